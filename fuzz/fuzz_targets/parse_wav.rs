@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rwav::wav::{parse_bytes, ParseLimits};
+
+// `parse_bytes` reads chunks through the same code as `WavFile`, so this covers both. Run with
+// `cargo +nightly fuzz run parse_wav` from the repository root
+fuzz_target!(|data: &[u8]| {
+    let limits = ParseLimits {
+        max_chunk_size: 1 << 20,
+        max_chunk_count: 1024,
+        max_metadata_size: 1 << 20,
+    };
+    if let Ok((header, chunks)) = parse_bytes(data, &limits) {
+        let _ = header.chunks_offset();
+        for chunk in &chunks {
+            let _ = chunk.to_bytes();
+        }
+    }
+});
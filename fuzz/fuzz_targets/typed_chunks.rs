@@ -0,0 +1,46 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rwav::chunks::{
+    acid::AcidChunk,
+    adm::{AxmlChunk, ChnaChunk},
+    adtl::AdtlChunk,
+    cart::CartChunk,
+    cue::CueChunk,
+    levl::LevlChunk,
+    smpl::SmplChunk,
+    TypedChunk,
+};
+
+// The first byte picks the parser, the rest is the chunk data
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    match selector % 8 {
+        0 => {
+            let _ = AcidChunk::parse(data).map(|acid| acid.to_bytes());
+        }
+        1 => {
+            let _ = LevlChunk::parse(data).map(|levl| levl.channel_peaks(0));
+        }
+        2 => {
+            let _ = CartChunk::parse(data).map(|cart| cart.validate());
+        }
+        3 => {
+            let _ = ChnaChunk::parse(data).map(|chna| chna.to_bytes());
+        }
+        4 => {
+            let _ = CueChunk::parse(data).map(|cue| cue.to_bytes());
+        }
+        5 => {
+            let _ = SmplChunk::parse(data).map(|smpl| smpl.to_bytes());
+        }
+        6 => {
+            let _ = AdtlChunk::parse(data).map(|adtl| adtl.to_bytes());
+        }
+        _ => {
+            let _ = AxmlChunk::parse(data).map(|axml| axml.parse_adm());
+        }
+    }
+});
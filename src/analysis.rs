@@ -0,0 +1,2 @@
+pub mod loudness;
+pub mod stats;
@@ -0,0 +1,408 @@
+use std::{collections::VecDeque, path::Path};
+
+use thiserror::Error;
+
+use crate::{
+    channels::{ChannelMask, Speaker},
+    chunks::{
+        bext::{BextChunk, LOUDNESS_NOT_SET},
+        ChunkError, TypedChunk,
+    },
+    dsp::{
+        convert::BLOCK_FRAMES,
+        filter::Biquad,
+        linear_to_db,
+        resample::{kaiser, sinc},
+    },
+    pipeline::{PipelineError, Sink, StreamFormat},
+    samples::{open_with_metadata, rewrite_audio, SampleError, SampleReader},
+    wav::{WavError, WavFile},
+};
+
+/// Momentary loudness and gating blocks are 400 ms long and start every 100 ms
+const HOPS_PER_BLOCK: usize = 4;
+/// Short-term loudness is measured over 3 s, also every 100 ms
+const HOPS_PER_SHORT_TERM: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// EBU Tech 3342 gates the short-term values of the loudness range 20 LU below their mean
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+const RANGE_LOW_PERCENTILE: f64 = 0.10;
+const RANGE_HIGH_PERCENTILE: f64 = 0.95;
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+/// Taps per phase of the true peak interpolator, as in the BS.1770 annex 2 example filter
+const TRUE_PEAK_TAPS: usize = 12;
+const TRUE_PEAK_KAISER_BETA: f64 = 5.0;
+
+#[derive(Error, Debug)]
+pub enum LoudnessError {
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoudnessReport {
+    /// Gated integrated loudness in LUFS, `None` if the whole file is below the absolute gate
+    pub integrated: Option<f64>,
+    /// Highest momentary (400 ms) loudness in LUFS, `None` if the file is silent or shorter
+    /// than one window
+    pub momentary_max: Option<f64>,
+    /// Highest short-term (3 s) loudness in LUFS, `None` if the file is silent or shorter than
+    /// one window
+    pub short_term_max: Option<f64>,
+    /// EBU Tech 3342 loudness range in LU, `None` if no short-term value passes the gates
+    pub loudness_range: Option<f64>,
+    /// Maximum of the 4x oversampled signal in dBTP
+    pub true_peak: f64,
+    /// Maximum absolute sample value in dBFS
+    pub sample_peak: f64,
+}
+
+/// ITU-R BS.1770-4 loudness meter. Samples are K-weighted per channel and reduced to one
+/// weighted mean square per 100 ms hop as they arrive, so only the window powers are kept.
+pub struct LoudnessMeter {
+    num_channels: usize,
+    sample_rate: u32,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    hop_len: usize,
+    hop_fill: usize,
+    hop_sums: Vec<f64>,
+    /// Weighted mean squares of the most recent hops, newest last
+    recent_hops: VecDeque<f64>,
+    /// Mean square of every 400 ms gating block
+    block_powers: Vec<f64>,
+    /// Mean square of every 3 s short-term window
+    short_term_powers: Vec<f64>,
+    true_peak: TruePeak,
+    sample_peak_max: f64,
+}
+
+impl LoudnessMeter {
+    /// Files without a channel mask are weighted as the standard layout for their channel count
+    pub fn new(num_channels: u16, sample_rate: u32, channel_mask: Option<u32>) -> Self {
+        let speakers = channel_mask
+            .map(ChannelMask)
+            .or(ChannelMask::default_for(num_channels))
+            .map(|mask| mask.speakers())
+            .unwrap_or_default();
+        let weights = (0..num_channels as usize)
+            .map(|channel| match speakers.get(channel) {
+                Some(Speaker::LowFrequency) => 0.0,
+                Some(
+                    Speaker::BackLeft | Speaker::BackRight | Speaker::SideLeft | Speaker::SideRight,
+                ) => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+
+        LoudnessMeter {
+            num_channels: num_channels as usize,
+            sample_rate,
+            weights,
+            filters: vec![k_weighting(sample_rate as f64); num_channels as usize],
+            hop_len: (sample_rate as usize / 10).max(1),
+            hop_fill: 0,
+            hop_sums: vec![0.0; num_channels as usize],
+            recent_hops: VecDeque::new(),
+            block_powers: Vec::new(),
+            short_term_powers: Vec::new(),
+            true_peak: TruePeak::new(num_channels as usize),
+            sample_peak_max: 0.0,
+        }
+    }
+
+    /// Feeds interleaved samples
+    pub fn process(&mut self, samples: &[f64]) {
+        for frame in samples.chunks_exact(self.num_channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.sample_peak_max = self.sample_peak_max.max(sample.abs());
+                self.true_peak.process_sample(channel, sample);
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process_sample(shelf.process_sample(sample));
+                self.hop_sums[channel] += weighted * weighted;
+            }
+            self.hop_fill += 1;
+            if self.hop_fill == self.hop_len {
+                self.end_hop();
+            }
+        }
+    }
+
+    fn end_hop(&mut self) {
+        let power = self
+            .hop_sums
+            .iter()
+            .zip(&self.weights)
+            .map(|(sum, weight)| weight * sum / self.hop_len as f64)
+            .sum();
+        self.hop_sums.fill(0.0);
+        self.hop_fill = 0;
+
+        self.recent_hops.push_back(power);
+        if self.recent_hops.len() > HOPS_PER_SHORT_TERM {
+            self.recent_hops.pop_front();
+        }
+        if self.recent_hops.len() >= HOPS_PER_BLOCK {
+            let block = self.recent_hops.iter().rev().take(HOPS_PER_BLOCK);
+            self.block_powers
+                .push(block.sum::<f64>() / HOPS_PER_BLOCK as f64);
+        }
+        if self.recent_hops.len() == HOPS_PER_SHORT_TERM {
+            self.short_term_powers
+                .push(self.recent_hops.iter().sum::<f64>() / HOPS_PER_SHORT_TERM as f64);
+        }
+    }
+
+    /// Gated integrated loudness of everything processed so far
+    pub fn integrated(&self) -> Option<f64> {
+        let above_absolute: Vec<f64> = self
+            .block_powers
+            .iter()
+            .copied()
+            .filter(|&power| loudness(power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness(mean(&above_absolute)) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = above_absolute
+            .into_iter()
+            .filter(|&power| loudness(power) > relative_gate)
+            .collect();
+        Some(loudness(mean(&gated)))
+    }
+
+    /// Loudness range of everything processed so far: the spread between the 10th and 95th
+    /// percentile of the gated short-term loudness values
+    pub fn loudness_range(&self) -> Option<f64> {
+        let above_absolute: Vec<f64> = self
+            .short_term_powers
+            .iter()
+            .copied()
+            .filter(|&power| loudness(power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness(mean(&above_absolute)) + RANGE_RELATIVE_GATE_LU;
+        let mut gated: Vec<f64> = above_absolute
+            .into_iter()
+            .map(loudness)
+            .filter(|&loudness| loudness > relative_gate)
+            .collect();
+        gated.sort_by(f64::total_cmp);
+        let percentile =
+            |fraction: f64| gated[((gated.len() - 1) as f64 * fraction).round() as usize];
+        Some(percentile(RANGE_HIGH_PERCENTILE) - percentile(RANGE_LOW_PERCENTILE))
+    }
+
+    /// Highest momentary loudness so far
+    pub fn momentary_max(&self) -> Option<f64> {
+        max_loudness(&self.block_powers)
+    }
+
+    /// Highest short-term loudness so far
+    pub fn short_term_max(&self) -> Option<f64> {
+        max_loudness(&self.short_term_powers)
+    }
+
+    /// Flushes the true peak interpolator and returns the measurements
+    pub fn finish(mut self) -> LoudnessReport {
+        self.true_peak.flush();
+        LoudnessReport {
+            integrated: self.integrated(),
+            momentary_max: self.momentary_max(),
+            short_term_max: self.short_term_max(),
+            loudness_range: self.loudness_range(),
+            true_peak: linear_to_db(self.true_peak.max),
+            sample_peak: linear_to_db(self.sample_peak_max),
+        }
+    }
+}
+
+/// 4x oversampling peak detector. A fixed polyphase windowed-sinc interpolator, cheaper than a
+/// general `Resampler` since only the largest magnitude it produces is kept.
+#[derive(Debug, Clone)]
+struct TruePeak {
+    /// Coefficients of each output phase, applied to the history oldest first
+    phases: [[f64; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING],
+    /// Most recent samples of each channel, newest last
+    history: Vec<[f64; TRUE_PEAK_TAPS]>,
+    max: f64,
+}
+
+impl TruePeak {
+    fn new(num_channels: usize) -> Self {
+        let half_width = (TRUE_PEAK_TAPS / 2) as f64;
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING];
+        for (phase, coefficients) in phases.iter_mut().enumerate() {
+            // Each phase interpolates a point between the two middle samples of the history
+            let position = half_width - 1.0 + phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            for (tap, coefficient) in coefficients.iter_mut().enumerate() {
+                let distance = tap as f64 - position;
+                *coefficient =
+                    sinc(distance) * kaiser(distance / half_width, TRUE_PEAK_KAISER_BETA);
+            }
+            let sum: f64 = coefficients.iter().sum();
+            coefficients
+                .iter_mut()
+                .for_each(|coefficient| *coefficient /= sum);
+        }
+        TruePeak {
+            phases,
+            history: vec![[0.0; TRUE_PEAK_TAPS]; num_channels],
+            max: 0.0,
+        }
+    }
+
+    fn process_sample(&mut self, channel: usize, sample: f64) {
+        let history = &mut self.history[channel];
+        history.copy_within(1.., 0);
+        history[TRUE_PEAK_TAPS - 1] = sample;
+        for coefficients in &self.phases {
+            let interpolated: f64 = coefficients
+                .iter()
+                .zip(history.iter())
+                .map(|(coefficient, sample)| coefficient * sample)
+                .sum();
+            self.max = self.max.max(interpolated.abs());
+        }
+    }
+
+    /// Pushes the last samples through the middle of the history
+    fn flush(&mut self) {
+        for channel in 0..self.history.len() {
+            for _ in 0..TRUE_PEAK_TAPS / 2 {
+                self.process_sample(channel, 0.0);
+            }
+        }
+    }
+}
+
+/// Loudness in LUFS of a weighted mean square
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn max_loudness(powers: &[f64]) -> Option<f64> {
+    let max = powers.iter().copied().fold(0.0, f64::max);
+    (max > 0.0).then(|| loudness(max))
+}
+
+/// The two K-weighting stages, a high shelf modelling the head and the RLB high pass, designed
+/// for any sample rate from the analog prototypes of BS.1770
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let shelf = {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    };
+    let high_pass = {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (std::f64::consts::PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    };
+    [shelf, high_pass]
+}
+
+impl LoudnessReport {
+    /// Stores the measurements in the version 2 loudness fields of `bext`, upgrading older
+    /// chunks to version 2. Values that couldn't be measured are marked as not set.
+    pub fn write_to_bext(&self, bext: &mut BextChunk) {
+        let hundredths = |value: Option<f64>| {
+            value
+                .filter(|value| value.is_finite())
+                .map(|value| {
+                    (value * 100.0)
+                        .round()
+                        .clamp(i16::MIN as f64, (LOUDNESS_NOT_SET - 1) as f64)
+                        as i16
+                })
+                .unwrap_or(LOUDNESS_NOT_SET)
+        };
+        bext.version = bext.version.max(2);
+        bext.loudness_value = hundredths(self.integrated);
+        bext.loudness_range = hundredths(self.loudness_range);
+        bext.max_true_peak_level = hundredths(Some(self.true_peak));
+        bext.max_momentary_loudness = hundredths(self.momentary_max);
+        bext.max_short_term_loudness = hundredths(self.short_term_max);
+    }
+}
+
+impl Sink for LoudnessMeter {
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError> {
+        format.check("loudness meter", self.num_channels as u16, self.sample_rate)
+    }
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        self.process(samples);
+        Ok(())
+    }
+}
+
+/// Measures a whole file
+pub fn measure_file(path: &Path) -> Result<LoudnessReport, SampleError> {
+    let mut wav_file = WavFile::open(path)?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    let mut meter =
+        LoudnessMeter::new(reader.num_channels, reader.sample_rate, reader.channel_mask);
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        meter.process(&samples);
+    }
+    Ok(meter.finish())
+}
+
+/// Measures `input` and writes it to `output` with the results in its bext chunk, adding a
+/// bext chunk if it has none. The audio is streamed into a temporary file that replaces
+/// `output`, so `output` may be `input`.
+pub fn write_file_loudness(input: &Path, output: &Path) -> Result<LoudnessReport, LoudnessError> {
+    let report = measure_file(input)?;
+    let (reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    match leading
+        .iter_mut()
+        .chain(trailing.iter_mut())
+        .find(|chunk| chunk.chunk_header.chunk_id == BextChunk::ID)
+    {
+        Some(chunk) => {
+            let mut bext = BextChunk::from_chunk(chunk)?;
+            report.write_to_bext(&mut bext);
+            *chunk = bext.to_chunk();
+        }
+        None => {
+            let mut bext = BextChunk::default();
+            report.write_to_bext(&mut bext);
+            leading.push(bext.to_chunk());
+        }
+    }
+    rewrite_audio(reader, output, &leading, &trailing)?;
+    Ok(report)
+}
@@ -0,0 +1,263 @@
+use std::path::Path;
+
+use crate::{
+    dsp::{convert::BLOCK_FRAMES, db_to_linear, linear_to_db},
+    pipeline::{PipelineError, Sink, StreamFormat},
+    samples::{SampleError, SampleFormat, SampleReader},
+    wav::WavFile,
+};
+
+/// Clipped runs past this many per channel are counted but their positions aren't kept
+pub const MAX_REPORTED_CLIP_RUNS: usize = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StatsSettings {
+    /// Frames where every channel stays below this level in dBFS are silent
+    pub silence_threshold_db: f64,
+    /// Shortest internal silence reported, in seconds. Leading and trailing silence is always
+    /// reported.
+    pub min_silence: f64,
+    /// Fewest consecutive full scale samples that count as a clipped run
+    pub min_clip_run: u64,
+}
+
+impl Default for StatsSettings {
+    fn default() -> Self {
+        StatsSettings {
+            silence_threshold_db: -60.0,
+            min_silence: 0.5,
+            min_clip_run: 3,
+        }
+    }
+}
+
+/// Consecutive full scale samples of the same sign on one channel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClipRun {
+    pub start_frame: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SilenceKind {
+    Leading,
+    Internal,
+    Trailing,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SilenceSpan {
+    pub kind: SilenceKind,
+    pub start_frame: u64,
+    /// Exclusive
+    pub end_frame: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStats {
+    /// Highest absolute sample value in dBFS
+    pub peak: f64,
+    pub peak_frame: u64,
+    /// RMS level in dBFS, a full scale square wave reads 0
+    pub rms: f64,
+    /// Mean sample value as a fraction of full scale
+    pub dc_offset: f64,
+    /// Peak to RMS ratio in dB, `None` for a silent channel
+    pub crest_factor: Option<f64>,
+    /// Number of clipped runs, which may exceed the positions kept in `clipped_runs`
+    pub clipped_run_count: u64,
+    pub clipped_runs: Vec<ClipRun>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsReport {
+    pub frames: u64,
+    pub channels: Vec<ChannelStats>,
+    /// In file order. A file that's silent throughout has a single leading span.
+    pub silences: Vec<SilenceSpan>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChannelState {
+    peak: f64,
+    peak_frame: u64,
+    sum: f64,
+    sum_squares: f64,
+    clip_run_start: u64,
+    clip_run_length: u64,
+    clip_run_negative: bool,
+    clipped_run_count: u64,
+    clipped_runs: Vec<ClipRun>,
+}
+
+impl ChannelState {
+    fn end_clip_run(&mut self, min_clip_run: u64) {
+        if self.clip_run_length >= min_clip_run && self.clip_run_length > 0 {
+            self.clipped_run_count += 1;
+            if self.clipped_runs.len() < MAX_REPORTED_CLIP_RUNS {
+                self.clipped_runs.push(ClipRun {
+                    start_frame: self.clip_run_start,
+                    length: self.clip_run_length,
+                });
+            }
+        }
+        self.clip_run_length = 0;
+    }
+}
+
+/// Streaming peak, RMS, DC offset, clipping and silence analysis. Memory doesn't grow with the
+/// length of the input beyond the reported spans.
+pub struct StatsMeter {
+    settings: StatsSettings,
+    sample_rate: u32,
+    channels: Vec<ChannelState>,
+    clip_level: f64,
+    silence_level: f64,
+    min_silence_frames: u64,
+    frame: u64,
+    /// Start of the silence the current frame is in, if it is in one
+    silence_start: Option<u64>,
+    silences: Vec<SilenceSpan>,
+}
+
+impl StatsMeter {
+    pub fn new(
+        num_channels: u16,
+        sample_rate: u32,
+        format: SampleFormat,
+        settings: StatsSettings,
+    ) -> Self {
+        StatsMeter {
+            settings,
+            sample_rate,
+            channels: vec![ChannelState::default(); num_channels as usize],
+            clip_level: clip_level(format),
+            silence_level: db_to_linear(settings.silence_threshold_db),
+            min_silence_frames: (settings.min_silence * sample_rate as f64).round() as u64,
+            frame: 0,
+            silence_start: Some(0),
+            silences: Vec::new(),
+        }
+    }
+
+    /// Feeds interleaved samples
+    pub fn process(&mut self, samples: &[f64]) {
+        for frame in samples.chunks_exact(self.channels.len()) {
+            let mut silent = true;
+            for (state, &sample) in self.channels.iter_mut().zip(frame) {
+                let magnitude = sample.abs();
+                if magnitude > state.peak {
+                    state.peak = magnitude;
+                    state.peak_frame = self.frame;
+                }
+                state.sum += sample;
+                state.sum_squares += sample * sample;
+                let negative = sample < 0.0;
+                if magnitude < self.clip_level || negative != state.clip_run_negative {
+                    state.end_clip_run(self.settings.min_clip_run);
+                }
+                if magnitude >= self.clip_level {
+                    if state.clip_run_length == 0 {
+                        state.clip_run_start = self.frame;
+                        state.clip_run_negative = negative;
+                    }
+                    state.clip_run_length += 1;
+                }
+                silent &= magnitude < self.silence_level;
+            }
+
+            match (silent, self.silence_start) {
+                (true, None) => self.silence_start = Some(self.frame),
+                (false, Some(start)) => {
+                    self.end_silence(start, false);
+                    self.silence_start = None;
+                }
+                _ => (),
+            }
+            self.frame += 1;
+        }
+    }
+
+    fn end_silence(&mut self, start_frame: u64, at_end: bool) {
+        let kind = match (start_frame, at_end) {
+            (0, _) => SilenceKind::Leading,
+            (_, true) => SilenceKind::Trailing,
+            (_, false) => SilenceKind::Internal,
+        };
+        let length = self.frame - start_frame;
+        if length > 0 && (kind != SilenceKind::Internal || length >= self.min_silence_frames) {
+            self.silences.push(SilenceSpan {
+                kind,
+                start_frame,
+                end_frame: self.frame,
+            });
+        }
+    }
+
+    pub fn finish(mut self) -> StatsReport {
+        if let Some(start) = self.silence_start {
+            self.end_silence(start, true);
+        }
+        let frames = self.frame;
+        let channels = self
+            .channels
+            .into_iter()
+            .map(|mut state| {
+                state.end_clip_run(self.settings.min_clip_run);
+                let rms = (state.sum_squares / frames.max(1) as f64).sqrt();
+                ChannelStats {
+                    peak: linear_to_db(state.peak),
+                    peak_frame: state.peak_frame,
+                    rms: linear_to_db(rms),
+                    dc_offset: state.sum / frames.max(1) as f64,
+                    crest_factor: (rms > 0.0).then(|| linear_to_db(state.peak / rms)),
+                    clipped_run_count: state.clipped_run_count,
+                    clipped_runs: state.clipped_runs,
+                }
+            })
+            .collect();
+        StatsReport {
+            frames,
+            channels,
+            silences: self.silences,
+        }
+    }
+}
+
+/// Lowest magnitude that counts as full scale: the most negative code for integers, whose
+/// positive limit is one step short of it, and 1.0 for floats
+fn clip_level(format: SampleFormat) -> f64 {
+    if format.is_float() {
+        1.0
+    } else {
+        (format.full_scale() - 1.0) / format.full_scale()
+    }
+}
+
+impl Sink for StatsMeter {
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError> {
+        format.check("stats meter", self.channels.len() as u16, self.sample_rate)
+    }
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        self.process(samples);
+        Ok(())
+    }
+}
+
+/// Analyzes a whole file, reading it a block at a time
+pub fn measure_file(path: &Path, settings: StatsSettings) -> Result<StatsReport, SampleError> {
+    let mut wav_file = WavFile::open(path)?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    let mut meter = StatsMeter::new(
+        reader.num_channels,
+        reader.sample_rate,
+        reader.format,
+        settings,
+    );
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        meter.process(&samples);
+    }
+    Ok(meter.finish())
+}
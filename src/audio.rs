@@ -14,6 +14,11 @@ use crate::{
     utils,
     utils::{create_cfstring_from_rust, get_cvoid_ptr, release_cfstring},
 };
+use crate::{
+    dsp::convert::{ConvertSettings, Dither, Requantizer},
+    pipeline::{PipelineError, Sink, StreamFormat},
+    samples::{SampleFormat, WAVE_FORMAT_PCM},
+};
 
 pub struct Audio(());
 
@@ -411,10 +416,90 @@ pub struct AudioSession {
     pub source_description: SourceDescription,
     pub state: SessionState,
     pub device: Option<AudioDeviceId>,
+    /// Frames waiting for the device, encoded as `source_description` describes. The device
+    /// takes it as one buffer, so it never grows past `MAX_QUEUED_BYTES`.
+    queue: Vec<u8>,
+    encoder: Option<(SampleFormat, Requantizer)>,
+    buffer: Vec<f64>,
 }
 
 impl AudioSession {
+    pub fn new(
+        id: u32,
+        source_description: SourceDescription,
+        device: Option<AudioDeviceId>,
+    ) -> Self {
+        AudioSession {
+            id,
+            source_description,
+            state: SessionState::Initialized,
+            device,
+            queue: Vec::new(),
+            encoder: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Interleaved integer PCM queued by the pipeline feeding the session
+    pub fn queued(&self) -> &[u8] {
+        &self.queue
+    }
+}
+
+/// Playback end of a pipeline. The stream must already be at the channel count and rate of
+/// the session's `SourceDescription`; it's requantized to its bit depth and queued.
+impl Sink for AudioSession {
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError> {
+        let description = &self.source_description;
+        format.check(
+            "playback",
+            description.num_channels,
+            description.sample_rate,
+        )?;
+        let sample_format = SampleFormat::from_parts(WAVE_FORMAT_PCM, description.bits_per_sample)?;
+        let mut settings = ConvertSettings::new(sample_format);
+        if !format.needs_dither(sample_format) {
+            settings.dither = Dither::None;
+        }
+        // Refuse a stream of known length that won't fit before any audio moves
+        let frame_size =
+            (sample_format.bytes_per_sample() * description.num_channels as usize) as u64;
+        if let Some(num_frames) = format.num_frames {
+            queue_limit(num_frames.saturating_mul(frame_size))?;
+        }
+        self.encoder = Some((
+            sample_format,
+            Requantizer::new(description.num_channels, settings),
+        ));
+        self.queue.clear();
+        Ok(())
+    }
 
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        if let Some((sample_format, requantizer)) = &mut self.encoder {
+            let size = self.queue.len() + samples.len() * sample_format.bytes_per_sample();
+            queue_limit(size as u64)?;
+            self.buffer.clear();
+            self.buffer.extend_from_slice(samples);
+            requantizer.process(&mut self.buffer);
+            sample_format.encode(&self.buffer, &mut self.queue);
+        }
+        Ok(())
+    }
+}
+
+/// Largest queue a session hands the device, whose buffer sizes are 32 bit
+pub const MAX_QUEUED_BYTES: u64 = u32::MAX as u64;
+
+/// Fails if a queue of `size` bytes couldn't be handed to the device
+fn queue_limit(size: u64) -> Result<(), PipelineError> {
+    if size > MAX_QUEUED_BYTES {
+        return Err(PipelineError::TooLong {
+            stage: "playback".to_string(),
+            limit: MAX_QUEUED_BYTES,
+        });
+    }
+    Ok(())
 }
 
 pub struct AudioManager {
@@ -422,10 +507,9 @@ pub struct AudioManager {
 }
 
 impl AudioManager {
-
     pub fn new() -> Self {
         Self {
-            sessions: Vec::new()
+            sessions: Vec::new(),
         }
     }
 }
@@ -0,0 +1,137 @@
+use std::fmt;
+
+/// Speaker positions of the WAVE_FORMAT_EXTENSIBLE channel mask, in bit order. Channels in
+/// the data chunk appear in this order for the bits that are set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontLeftOfCenter,
+    FrontRightOfCenter,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+}
+
+impl Speaker {
+    pub const ALL: [Speaker; 18] = [
+        Speaker::FrontLeft,
+        Speaker::FrontRight,
+        Speaker::FrontCenter,
+        Speaker::LowFrequency,
+        Speaker::BackLeft,
+        Speaker::BackRight,
+        Speaker::FrontLeftOfCenter,
+        Speaker::FrontRightOfCenter,
+        Speaker::BackCenter,
+        Speaker::SideLeft,
+        Speaker::SideRight,
+        Speaker::TopCenter,
+        Speaker::TopFrontLeft,
+        Speaker::TopFrontCenter,
+        Speaker::TopFrontRight,
+        Speaker::TopBackLeft,
+        Speaker::TopBackCenter,
+        Speaker::TopBackRight,
+    ];
+
+    pub fn bit(&self) -> u32 {
+        1 << *self as u32
+    }
+
+    pub fn from_label(label: &str) -> Option<Speaker> {
+        Speaker::ALL
+            .into_iter()
+            .find(|speaker| speaker.label().eq_ignore_ascii_case(label))
+    }
+
+    /// Short label as used in track names, e.g. "L", "LFE" or "Ls"
+    pub fn label(&self) -> &'static str {
+        match self {
+            Speaker::FrontLeft => "L",
+            Speaker::FrontRight => "R",
+            Speaker::FrontCenter => "C",
+            Speaker::LowFrequency => "LFE",
+            Speaker::BackLeft => "Lrs",
+            Speaker::BackRight => "Rrs",
+            Speaker::FrontLeftOfCenter => "Lc",
+            Speaker::FrontRightOfCenter => "Rc",
+            Speaker::BackCenter => "Cs",
+            Speaker::SideLeft => "Ls",
+            Speaker::SideRight => "Rs",
+            Speaker::TopCenter => "Tc",
+            Speaker::TopFrontLeft => "Tfl",
+            Speaker::TopFrontCenter => "Tfc",
+            Speaker::TopFrontRight => "Tfr",
+            Speaker::TopBackLeft => "Tbl",
+            Speaker::TopBackCenter => "Tbc",
+            Speaker::TopBackRight => "Tbr",
+        }
+    }
+}
+
+/// dwChannelMask of WAVE_FORMAT_EXTENSIBLE
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelMask(pub u32);
+
+impl ChannelMask {
+    pub const MONO: ChannelMask = ChannelMask(0x4);
+    pub const STEREO: ChannelMask = ChannelMask(0x3);
+    pub const QUAD: ChannelMask = ChannelMask(0x33);
+    /// L R C LFE Lrs Rrs, KSAUDIO_SPEAKER_5POINT1
+    pub const SURROUND_5_1: ChannelMask = ChannelMask(0x3F);
+    /// L R C LFE Ls Rs, the side speaker variant most DAWs write
+    pub const SURROUND_5_1_SIDE: ChannelMask = ChannelMask(0x60F);
+    /// L R C LFE Lrs Rrs Ls Rs
+    pub const SURROUND_7_1: ChannelMask = ChannelMask(0x63F);
+
+    /// Layout assumed for files without a channel mask
+    pub fn default_for(num_channels: u16) -> Option<ChannelMask> {
+        match num_channels {
+            1 => Some(ChannelMask::MONO),
+            2 => Some(ChannelMask::STEREO),
+            4 => Some(ChannelMask::QUAD),
+            6 => Some(ChannelMask::SURROUND_5_1),
+            8 => Some(ChannelMask::SURROUND_7_1),
+            _ => None,
+        }
+    }
+
+    pub fn from_speakers(speakers: &[Speaker]) -> ChannelMask {
+        ChannelMask(
+            speakers
+                .iter()
+                .fold(0, |mask, speaker| mask | speaker.bit()),
+        )
+    }
+
+    /// Speakers in channel order. Channels beyond these have no defined position.
+    pub fn speakers(&self) -> Vec<Speaker> {
+        Speaker::ALL
+            .into_iter()
+            .filter(|speaker| self.0 & speaker.bit() != 0)
+            .collect()
+    }
+
+    pub fn num_speakers(&self) -> u32 {
+        (self.0 & ((1 << Speaker::ALL.len()) - 1)).count_ones()
+    }
+}
+
+impl fmt::Display for ChannelMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels: Vec<&str> = self.speakers().iter().map(Speaker::label).collect();
+        write!(f, "{}", labels.join(" "))
+    }
+}
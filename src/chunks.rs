@@ -0,0 +1,159 @@
+pub mod acid;
+pub mod adm;
+pub mod adtl;
+pub mod bext;
+pub mod cart;
+pub mod cue;
+pub mod ixml;
+pub mod levl;
+pub mod md5;
+pub mod smpl;
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+use crate::wav::{Chunk, WavError, WavFile, WavWriter};
+
+/// A chunk with a typed representation that can be parsed from, and serialized back to, the raw
+/// bytes of a `Chunk`
+pub trait TypedChunk: Sized {
+    const ID: [u8; 4];
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError>;
+
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_chunk(chunk: &Chunk) -> Result<Self, ChunkError> {
+        if chunk.chunk_header.chunk_id != Self::ID {
+            return Err(ChunkError::UnexpectedId {
+                expected: Self::ID,
+                found: chunk.chunk_header.chunk_id,
+            });
+        }
+        Self::parse(&chunk.data)
+    }
+
+    fn to_chunk(&self) -> Chunk {
+        Chunk::new(Self::ID, self.to_bytes())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ChunkError {
+    #[error("Unexpected chunk id! Expected {expected:?}, found {found:?}")]
+    UnexpectedId { expected: [u8; 4], found: [u8; 4] },
+    #[error("Chunk too short! Expected at least {expected} bytes, found {found}")]
+    TooShort { expected: usize, found: usize },
+    #[error("Invalid chunk field: {0}")]
+    InvalidField(String),
+    #[error("IO error writing chunk: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// Rewrites `input` to `output` with `chunk` replacing any chunk with the same id. Every other
+/// chunk, including the audio, is copied unchanged.
+pub fn rewrite_with_chunk<T: TypedChunk>(
+    input: &Path,
+    output: &Path,
+    chunk: &T,
+) -> Result<(), WavError> {
+    let mut writer = WavWriter::from_wav_file(WavFile::open(input)?)?;
+    writer.set_chunk(chunk.to_chunk());
+    writer.write(output)?;
+    Ok(())
+}
+
+pub(crate) fn check_len(data: &[u8], expected: usize) -> Result<(), ChunkError> {
+    if data.len() < expected {
+        return Err(ChunkError::TooShort {
+            expected,
+            found: data.len(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Reads a fixed width, NUL padded text field
+pub(crate) fn read_fixed_str(data: &[u8], offset: usize, width: usize) -> String {
+    let field = &data[offset..offset + width];
+    let end = field.iter().position(|&b| b == 0).unwrap_or(width);
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// Appends `value` as a fixed width, NUL padded text field, truncating it if it doesn't fit
+pub(crate) fn write_fixed_str(bytes: &mut Vec<u8>, value: &str, width: usize) {
+    let value = value.as_bytes();
+    let len = value.len().min(width);
+    bytes.extend_from_slice(&value[..len]);
+    bytes.resize(bytes.len() + width - len, 0);
+}
+
+pub(crate) fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub(crate) fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// UTC wall clock time broken into calendar fields, for the timestamp fields of BWF chunks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UtcDateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+impl UtcDateTime {
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let secs = since_epoch.as_secs() as i64;
+        let days = secs.div_euclid(86400);
+        let secs_of_day = secs.rem_euclid(86400) as u32;
+
+        // Howard Hinnant's days_from_civil inverse
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        UtcDateTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            millisecond: since_epoch.subsec_millis(),
+        }
+    }
+}
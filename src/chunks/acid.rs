@@ -0,0 +1,173 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{ChunkError, TypedChunk};
+use crate::wav::FmtSubChunk;
+
+pub const ACID_ONE_SHOT: u32 = 1 << 0;
+pub const ACID_ROOT_NOTE_SET: u32 = 1 << 1;
+pub const ACID_STRETCH: u32 = 1 << 2;
+pub const ACID_DISK_BASED: u32 = 1 << 3;
+pub const ACID_HIGH_OCTAVE: u32 = 1 << 4;
+
+/// Tempo and beat metadata written by ACID and most loop libraries
+#[derive(Debug, Copy, Clone, Pod, Zeroable, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct AcidChunk {
+    pub flags: u32,
+    /// MIDI note number, only meaningful when `ACID_ROOT_NOTE_SET` is set
+    pub root_note: u16,
+    pub reserved_1: u16,
+    pub reserved_2: f32,
+    pub num_beats: u32,
+    pub meter_denominator: u16,
+    pub meter_numerator: u16,
+    /// Beats per minute
+    pub tempo: f32,
+}
+
+impl AcidChunk {
+    pub fn new_loop(tempo: f32, num_beats: u32) -> Self {
+        AcidChunk {
+            flags: ACID_STRETCH,
+            root_note: 60,
+            // ACID itself always writes these values
+            reserved_1: 0x8000,
+            reserved_2: 0.0,
+            num_beats,
+            meter_denominator: 4,
+            meter_numerator: 4,
+            tempo,
+        }
+    }
+
+    pub fn is_one_shot(&self) -> bool {
+        self.flags & ACID_ONE_SHOT != 0
+    }
+
+    pub fn set_one_shot(&mut self, one_shot: bool) {
+        self.set_flag(ACID_ONE_SHOT, one_shot);
+    }
+
+    pub fn is_stretch(&self) -> bool {
+        self.flags & ACID_STRETCH != 0
+    }
+
+    pub fn set_stretch(&mut self, stretch: bool) {
+        self.set_flag(ACID_STRETCH, stretch);
+    }
+
+    pub fn is_disk_based(&self) -> bool {
+        self.flags & ACID_DISK_BASED != 0
+    }
+
+    pub fn set_disk_based(&mut self, disk_based: bool) {
+        self.set_flag(ACID_DISK_BASED, disk_based);
+    }
+
+    pub fn root_note(&self) -> Option<u16> {
+        if self.flags & ACID_ROOT_NOTE_SET != 0 {
+            Some(self.root_note)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_root_note(&mut self, root_note: Option<u16>) {
+        self.set_flag(ACID_ROOT_NOTE_SET, root_note.is_some());
+        if let Some(note) = root_note {
+            self.root_note = note;
+        }
+    }
+
+    fn set_flag(&mut self, flag: u32, value: bool) {
+        if value {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// Tempo implied by the loop length, for files that carry a beat count but no tempo
+    pub fn tempo_from_length(&self, num_frames: u64, sample_rate: u32) -> Option<f32> {
+        if num_frames == 0 || self.num_beats == 0 {
+            return None;
+        }
+        let seconds = num_frames as f64 / sample_rate as f64;
+        Some((self.num_beats as f64 * 60.0 / seconds) as f32)
+    }
+}
+
+impl TypedChunk for AcidChunk {
+    const ID: [u8; 4] = *b"acid";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        let size = std::mem::size_of::<AcidChunk>();
+        if data.len() < size {
+            return Err(ChunkError::TooShort {
+                expected: size,
+                found: data.len(),
+            });
+        }
+        Ok(bytemuck::pod_read_unaligned(&data[..size]))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        bytemuck::bytes_of(self).to_vec()
+    }
+}
+
+/// Bar/beat grid derived from an acid chunk and the sample rate, in frames
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BeatGrid {
+    pub frames_per_beat: f64,
+    pub beats_per_bar: u16,
+    pub num_beats: u32,
+}
+
+impl BeatGrid {
+    pub fn new(acid: &AcidChunk, fmt: &FmtSubChunk) -> Option<Self> {
+        if acid.tempo <= 0.0 || !acid.tempo.is_finite() || fmt.sample_rate == 0 {
+            return None;
+        }
+        Some(BeatGrid {
+            frames_per_beat: fmt.sample_rate as f64 * 60.0 / acid.tempo as f64,
+            beats_per_bar: acid.meter_numerator.max(1),
+            num_beats: acid.num_beats,
+        })
+    }
+
+    pub fn frames_per_bar(&self) -> f64 {
+        self.frames_per_beat * self.beats_per_bar as f64
+    }
+
+    pub fn beat_frame(&self, beat: u32) -> u64 {
+        (beat as f64 * self.frames_per_beat).round() as u64
+    }
+
+    pub fn bar_frame(&self, bar: u32) -> u64 {
+        (bar as f64 * self.frames_per_bar()).round() as u64
+    }
+
+    /// Index of the beat nearest to `frame`
+    pub fn nearest_beat(&self, frame: u64) -> u32 {
+        (frame as f64 / self.frames_per_beat).round() as u32
+    }
+
+    /// Snaps `frame` to the nearest beat boundary
+    pub fn snap(&self, frame: u64) -> u64 {
+        self.beat_frame(self.nearest_beat(frame))
+    }
+
+    /// (bar, beat within bar) of `frame`, both zero based
+    pub fn bar_beat(&self, frame: u64) -> (u32, u16) {
+        let beat = (frame as f64 / self.frames_per_beat).floor() as u32;
+        let beats_per_bar = self.beats_per_bar as u32;
+        (beat / beats_per_bar, (beat % beats_per_bar) as u16)
+    }
+
+    /// Frame positions of every beat in the loop
+    pub fn beats(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.num_beats).map(|beat| self.beat_frame(beat))
+    }
+}
@@ -0,0 +1,342 @@
+use super::{check_len, read_fixed_str, write_fixed_str, xml_unescape, ChunkError, TypedChunk};
+use crate::wav::FmtSubChunk;
+
+pub const CHNA_HEADER_SIZE: usize = 4;
+pub const CHNA_AUDIO_ID_SIZE: usize = 40;
+const CHNA_UID_WIDTH: usize = 12;
+const CHNA_TRACK_REF_WIDTH: usize = 14;
+const CHNA_PACK_REF_WIDTH: usize = 11;
+
+/// One row of the chna table, mapping a track of the file to its ADM audioTrackUID
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChnaAudioId {
+    /// One based channel index into the data chunk, 0 for an unused slot
+    pub track_index: u16,
+    /// audioTrackUID, e.g. "ATU_00000001"
+    pub uid: String,
+    /// audioTrackFormatID (or audioChannelFormatID), e.g. "AT_00010001_01"
+    pub track_ref: String,
+    /// audioPackFormatID, e.g. "AP_00010002"
+    pub pack_ref: String,
+}
+
+impl ChnaAudioId {
+    pub fn is_used(&self) -> bool {
+        self.track_index != 0 || !self.uid.is_empty()
+    }
+}
+
+/// BW64 channel allocation chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChnaChunk {
+    pub num_tracks: u16,
+    /// Every slot of the table, including unused ones reserved for later edits
+    pub audio_ids: Vec<ChnaAudioId>,
+}
+
+impl ChnaChunk {
+    pub fn new(audio_ids: Vec<ChnaAudioId>) -> Self {
+        let mut chna = ChnaChunk {
+            num_tracks: 0,
+            audio_ids,
+        };
+        chna.num_tracks = chna.count_tracks();
+        chna
+    }
+
+    /// Number of distinct tracks referenced by the used slots
+    pub fn count_tracks(&self) -> u16 {
+        let mut tracks: Vec<u16> = self
+            .audio_ids
+            .iter()
+            .filter(|id| id.is_used())
+            .map(|id| id.track_index)
+            .collect();
+        tracks.sort_unstable();
+        tracks.dedup();
+        tracks.len() as u16
+    }
+
+    /// Checks that every used slot points at a channel that exists in the file
+    pub fn validate(&self, fmt: &FmtSubChunk) -> Result<(), ChunkError> {
+        let bad_slots: Vec<String> = self
+            .audio_ids
+            .iter()
+            .enumerate()
+            .filter(|(_, id)| {
+                id.is_used() && (id.track_index == 0 || id.track_index > fmt.num_channels)
+            })
+            .map(|(slot, id)| format!("slot {slot} ({}) track {}", id.uid, id.track_index))
+            .collect();
+        if !bad_slots.is_empty() {
+            return Err(ChunkError::InvalidField(format!(
+                "chna entries reference tracks outside 1..={}: {}",
+                fmt.num_channels,
+                bad_slots.join(", ")
+            )));
+        }
+        if self.num_tracks != self.count_tracks() {
+            return Err(ChunkError::InvalidField(format!(
+                "chna num_tracks is {}, the table references {}",
+                self.num_tracks,
+                self.count_tracks()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl TypedChunk for ChnaChunk {
+    const ID: [u8; 4] = *b"chna";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, CHNA_HEADER_SIZE)?;
+        let num_tracks = u16::from_le_bytes([data[0], data[1]]);
+        let num_uids = u16::from_le_bytes([data[2], data[3]]) as usize;
+        check_len(data, CHNA_HEADER_SIZE + num_uids * CHNA_AUDIO_ID_SIZE)?;
+
+        let audio_ids = data[CHNA_HEADER_SIZE..]
+            .chunks_exact(CHNA_AUDIO_ID_SIZE)
+            .take(num_uids)
+            .map(|entry| ChnaAudioId {
+                track_index: u16::from_le_bytes([entry[0], entry[1]]),
+                uid: read_fixed_str(entry, 2, CHNA_UID_WIDTH),
+                track_ref: read_fixed_str(entry, 2 + CHNA_UID_WIDTH, CHNA_TRACK_REF_WIDTH),
+                pack_ref: read_fixed_str(
+                    entry,
+                    2 + CHNA_UID_WIDTH + CHNA_TRACK_REF_WIDTH,
+                    CHNA_PACK_REF_WIDTH,
+                ),
+            })
+            .collect();
+
+        Ok(ChnaChunk {
+            num_tracks,
+            audio_ids,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(CHNA_HEADER_SIZE + self.audio_ids.len() * CHNA_AUDIO_ID_SIZE);
+        bytes.extend_from_slice(&self.num_tracks.to_le_bytes());
+        bytes.extend_from_slice(&(self.audio_ids.len() as u16).to_le_bytes());
+        for id in &self.audio_ids {
+            bytes.extend_from_slice(&id.track_index.to_le_bytes());
+            write_fixed_str(&mut bytes, &id.uid, CHNA_UID_WIDTH);
+            write_fixed_str(&mut bytes, &id.track_ref, CHNA_TRACK_REF_WIDTH);
+            write_fixed_str(&mut bytes, &id.pack_ref, CHNA_PACK_REF_WIDTH);
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
+/// ADM XML chunk. The XML is kept byte for byte so it round trips exactly; `parse_adm` pulls
+/// out the programmes, objects and pack formats.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AxmlChunk {
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "xml", with = "crate::metadata::lossy_str")
+    )]
+    pub raw: Vec<u8>,
+}
+
+impl AxmlChunk {
+    pub fn new(xml: &str) -> Self {
+        AxmlChunk {
+            raw: xml.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn xml(&self) -> String {
+        String::from_utf8_lossy(&self.raw)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+
+    pub fn parse_adm(&self) -> AdmDocument {
+        AdmDocument::parse(&self.xml())
+    }
+}
+
+impl TypedChunk for AxmlChunk {
+    const ID: [u8; 4] = *b"axml";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        Ok(AxmlChunk { raw: data.to_vec() })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdmProgramme {
+    pub id: String,
+    pub name: String,
+    pub content_refs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdmObject {
+    pub id: String,
+    pub name: String,
+    pub pack_format_refs: Vec<String>,
+    pub track_uid_refs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdmPackFormat {
+    pub id: String,
+    pub name: String,
+    pub type_label: String,
+    pub channel_format_refs: Vec<String>,
+}
+
+/// The parts of an ADM document needed to list what a deliverable contains. This is a light
+/// scan of the XML, not a validating parser.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdmDocument {
+    pub programmes: Vec<AdmProgramme>,
+    pub objects: Vec<AdmObject>,
+    pub pack_formats: Vec<AdmPackFormat>,
+}
+
+enum AdmElement {
+    Programme,
+    Object,
+    PackFormat,
+}
+
+impl AdmDocument {
+    pub fn parse(xml: &str) -> Self {
+        let mut document = AdmDocument::default();
+        let mut current: Option<AdmElement> = None;
+        let mut open_ref: Option<String> = None;
+        let mut rest = xml;
+
+        while let Some(start) = rest.find('<') {
+            let text = &rest[..start];
+            if let Some(ref_name) = open_ref.take() {
+                document.push_ref(&current, &ref_name, xml_unescape(text.trim()));
+            }
+            let Some(end) = rest[start..].find('>') else {
+                break;
+            };
+            let tag = &rest[start + 1..start + end];
+            rest = &rest[start + end + 1..];
+
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if let Some(closing) = tag.strip_prefix('/') {
+                match local_name(closing.trim()) {
+                    "audioProgramme" | "audioObject" | "audioPackFormat" => current = None,
+                    _ => (),
+                }
+                continue;
+            }
+
+            let self_closing = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name = local_name(tag.split_whitespace().next().unwrap_or(""));
+            match name {
+                "audioProgramme" => {
+                    document.programmes.push(AdmProgramme {
+                        id: attribute(tag, "audioProgrammeID"),
+                        name: attribute(tag, "audioProgrammeName"),
+                        ..Default::default()
+                    });
+                    current = Some(AdmElement::Programme);
+                }
+                "audioObject" => {
+                    document.objects.push(AdmObject {
+                        id: attribute(tag, "audioObjectID"),
+                        name: attribute(tag, "audioObjectName"),
+                        ..Default::default()
+                    });
+                    current = Some(AdmElement::Object);
+                }
+                "audioPackFormat" => {
+                    document.pack_formats.push(AdmPackFormat {
+                        id: attribute(tag, "audioPackFormatID"),
+                        name: attribute(tag, "audioPackFormatName"),
+                        type_label: attribute(tag, "typeLabel"),
+                        ..Default::default()
+                    });
+                    current = Some(AdmElement::PackFormat);
+                }
+                _ if name.ends_with("IDRef") && !self_closing => {
+                    open_ref = Some(name.to_string());
+                }
+                _ => (),
+            }
+            if self_closing && matches!(name, "audioProgramme" | "audioObject" | "audioPackFormat")
+            {
+                current = None;
+            }
+        }
+        document
+    }
+
+    fn push_ref(&mut self, current: &Option<AdmElement>, ref_name: &str, value: String) {
+        match (current, ref_name) {
+            (Some(AdmElement::Programme), "audioContentIDRef") => {
+                if let Some(programme) = self.programmes.last_mut() {
+                    programme.content_refs.push(value);
+                }
+            }
+            (Some(AdmElement::Object), "audioPackFormatIDRef") => {
+                if let Some(object) = self.objects.last_mut() {
+                    object.pack_format_refs.push(value);
+                }
+            }
+            (Some(AdmElement::Object), "audioTrackUIDRef") => {
+                if let Some(object) = self.objects.last_mut() {
+                    object.track_uid_refs.push(value);
+                }
+            }
+            (Some(AdmElement::PackFormat), "audioChannelFormatIDRef") => {
+                if let Some(pack_format) = self.pack_formats.last_mut() {
+                    pack_format.channel_format_refs.push(value);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Strips a namespace prefix such as `adm:`
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn attribute(tag: &str, name: &str) -> String {
+    let mut rest = tag;
+    while let Some(index) = rest.find(name) {
+        let preceded_by_space = rest[..index]
+            .chars()
+            .last()
+            .is_some_and(char::is_whitespace);
+        let after = rest[index + name.len()..].trim_start();
+        if preceded_by_space {
+            if let Some(value) = after.strip_prefix('=') {
+                let value = value.trim_start();
+                if let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                    let value = &value[1..];
+                    if let Some(end) = value.find(quote) {
+                        return xml_unescape(&value[..end]);
+                    }
+                }
+            }
+        }
+        rest = &rest[index + name.len()..];
+    }
+    String::new()
+}
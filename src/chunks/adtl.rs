@@ -0,0 +1,183 @@
+use super::{check_len, read_u32, ChunkError, TypedChunk};
+use crate::wav::Chunk;
+
+pub const LIST_TYPE_ADTL: [u8; 4] = *b"adtl";
+
+/// Text attached to a cue point by a `labl` or `note` entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CueText {
+    pub cue_id: u32,
+    pub text: String,
+}
+
+/// `ltxt` entry, which turns a cue point into a region `sample_length` frames long
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CueRegion {
+    pub cue_id: u32,
+    pub sample_length: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
+    pub purpose: [u8; 4],
+    pub country: u16,
+    pub language: u16,
+    pub dialect: u16,
+    pub code_page: u16,
+    pub text: String,
+}
+
+impl CueRegion {
+    pub fn new(cue_id: u32, sample_length: u32) -> Self {
+        CueRegion {
+            cue_id,
+            sample_length,
+            purpose: *b"rgn ",
+            country: 0,
+            language: 0,
+            dialect: 0,
+            code_page: 0,
+            text: String::new(),
+        }
+    }
+}
+
+/// Associated data list: the names, comments and lengths of the points in the cue chunk. It
+/// lives in a `LIST` chunk, so check `is_adtl` before parsing a `LIST` as one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdtlChunk {
+    pub labels: Vec<CueText>,
+    pub notes: Vec<CueText>,
+    pub regions: Vec<CueRegion>,
+    /// Sub-chunks rwav doesn't interpret, such as `file`, written back unchanged
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub other: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl AdtlChunk {
+    pub fn is_adtl(chunk: &Chunk) -> bool {
+        chunk.chunk_header.chunk_id == Self::ID && chunk.data.get(..4) == Some(&LIST_TYPE_ADTL)
+    }
+
+    pub fn label(&self, cue_id: u32) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|label| label.cue_id == cue_id)
+            .map(|label| label.text.as_str())
+    }
+
+    pub fn region(&self, cue_id: u32) -> Option<&CueRegion> {
+        self.regions.iter().find(|region| region.cue_id == cue_id)
+    }
+
+    /// Drops every entry whose cue point `keep` rejects
+    pub fn retain_cues(&mut self, keep: impl Fn(u32) -> bool) {
+        self.labels.retain(|label| keep(label.cue_id));
+        self.notes.retain(|note| keep(note.cue_id));
+        self.regions.retain(|region| keep(region.cue_id));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+            && self.notes.is_empty()
+            && self.regions.is_empty()
+            && self.other.is_empty()
+    }
+}
+
+impl TypedChunk for AdtlChunk {
+    const ID: [u8; 4] = *b"LIST";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, 4)?;
+        if data[..4] != LIST_TYPE_ADTL {
+            return Err(ChunkError::InvalidField(format!(
+                "LIST type {:?} is not adtl",
+                String::from_utf8_lossy(&data[..4])
+            )));
+        }
+        let mut adtl = AdtlChunk::default();
+        let mut offset = 4;
+        while offset + 8 <= data.len() {
+            let id: [u8; 4] = data[offset..offset + 4].try_into().unwrap();
+            let size = read_u32(data, offset + 4) as usize;
+            let start = offset + 8;
+            let end = (start + size).min(data.len());
+            let body = &data[start..end];
+            offset = end + (size & 1);
+
+            match &id {
+                b"labl" | b"note" => {
+                    check_len(body, 4)?;
+                    let entry = CueText {
+                        cue_id: read_u32(body, 0),
+                        text: zero_terminated(&body[4..]),
+                    };
+                    if &id == b"labl" {
+                        adtl.labels.push(entry);
+                    } else {
+                        adtl.notes.push(entry);
+                    }
+                }
+                b"ltxt" => {
+                    check_len(body, 20)?;
+                    let read_u16 = |at: usize| u16::from_le_bytes([body[at], body[at + 1]]);
+                    adtl.regions.push(CueRegion {
+                        cue_id: read_u32(body, 0),
+                        sample_length: read_u32(body, 4),
+                        purpose: body[8..12].try_into().unwrap(),
+                        country: read_u16(12),
+                        language: read_u16(14),
+                        dialect: read_u16(16),
+                        code_page: read_u16(18),
+                        text: zero_terminated(&body[20..]),
+                    });
+                }
+                _ => adtl.other.push((id, body.to_vec())),
+            }
+        }
+        Ok(adtl)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = LIST_TYPE_ADTL.to_vec();
+        let mut push = |id: &[u8; 4], body: Vec<u8>| {
+            bytes.extend_from_slice(&Chunk::new(*id, body).to_bytes());
+        };
+        for (id, entries) in [(b"labl", &self.labels), (b"note", &self.notes)] {
+            for entry in entries {
+                let mut body = entry.cue_id.to_le_bytes().to_vec();
+                body.extend_from_slice(entry.text.as_bytes());
+                body.push(0);
+                push(id, body);
+            }
+        }
+        for region in &self.regions {
+            let mut body = region.cue_id.to_le_bytes().to_vec();
+            body.extend_from_slice(&region.sample_length.to_le_bytes());
+            body.extend_from_slice(&region.purpose);
+            for value in [
+                region.country,
+                region.language,
+                region.dialect,
+                region.code_page,
+            ] {
+                body.extend_from_slice(&value.to_le_bytes());
+            }
+            if !region.text.is_empty() {
+                body.extend_from_slice(region.text.as_bytes());
+                body.push(0);
+            }
+            push(b"ltxt", body);
+        }
+        for (id, body) in &self.other {
+            push(id, body.clone());
+        }
+        bytes
+    }
+}
+
+fn zero_terminated(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
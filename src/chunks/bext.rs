@@ -0,0 +1,160 @@
+use super::{check_len, read_fixed_str, read_u32, write_fixed_str, ChunkError, TypedChunk};
+use crate::samples::SampleFormat;
+
+pub const BEXT_FIXED_SIZE: usize = 602;
+const BEXT_DESCRIPTION_WIDTH: usize = 256;
+const BEXT_ORIGINATOR_WIDTH: usize = 32;
+const BEXT_DATE_WIDTH: usize = 10;
+const BEXT_TIME_WIDTH: usize = 8;
+const BEXT_UMID_WIDTH: usize = 64;
+const BEXT_RESERVED_WIDTH: usize = 180;
+/// Offset of TimeReferenceLow
+const BEXT_TIME_REFERENCE_OFFSET: usize = 338;
+
+/// Value of a version 2 loudness field that wasn't measured
+pub const LOUDNESS_NOT_SET: i16 = 0x7FFF;
+
+/// EBU Tech 3285 broadcast audio extension chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BextChunk {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    /// yyyy-mm-dd
+    pub origination_date: String,
+    /// hh:mm:ss
+    pub origination_time: String,
+    /// First sample of the file counted in samples since midnight
+    pub time_reference: u64,
+    pub version: u16,
+    pub umid: Vec<u8>,
+    /// Version 2 loudness fields in hundredths of LUFS, LU or dBTP
+    pub loudness_value: i16,
+    pub loudness_range: i16,
+    pub max_true_peak_level: i16,
+    pub max_momentary_loudness: i16,
+    pub max_short_term_loudness: i16,
+    /// CR/LF terminated lines describing each process the audio has been through
+    pub coding_history: String,
+}
+
+impl Default for BextChunk {
+    fn default() -> Self {
+        BextChunk {
+            description: String::new(),
+            originator: String::new(),
+            originator_reference: String::new(),
+            origination_date: String::new(),
+            origination_time: String::new(),
+            time_reference: 0,
+            version: 2,
+            umid: vec![0; BEXT_UMID_WIDTH],
+            loudness_value: LOUDNESS_NOT_SET,
+            loudness_range: LOUDNESS_NOT_SET,
+            max_true_peak_level: LOUDNESS_NOT_SET,
+            max_momentary_loudness: LOUDNESS_NOT_SET,
+            max_short_term_loudness: LOUDNESS_NOT_SET,
+            coding_history: String::new(),
+        }
+    }
+}
+
+impl BextChunk {
+    /// Appends an EBU R98 coding history line such as
+    /// `A=PCM,F=48000,W=24,M=stereo,T=rwav gain -3.20 dB`
+    pub fn append_coding_history(
+        &mut self,
+        format: SampleFormat,
+        sample_rate: u32,
+        num_channels: u16,
+        text: &str,
+    ) {
+        let algorithm = if format.is_float() {
+            "PCM_FLOAT"
+        } else {
+            "PCM"
+        };
+        let mode = match num_channels {
+            1 => "mono".to_string(),
+            2 => "stereo".to_string(),
+            n => format!("multichannel {n}"),
+        };
+        if !self.coding_history.is_empty() && !self.coding_history.ends_with("\r\n") {
+            self.coding_history.push_str("\r\n");
+        }
+        self.coding_history.push_str(&format!(
+            "A={algorithm},F={sample_rate},W={},M={mode},T={text}\r\n",
+            format.bits_per_sample()
+        ));
+    }
+}
+
+impl TypedChunk for BextChunk {
+    const ID: [u8; 4] = *b"bext";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, BEXT_FIXED_SIZE)?;
+        let read_i16 = |offset: usize| i16::from_le_bytes([data[offset], data[offset + 1]]);
+        let time_reference = read_u32(data, BEXT_TIME_REFERENCE_OFFSET) as u64
+            | (read_u32(data, BEXT_TIME_REFERENCE_OFFSET + 4) as u64) << 32;
+        let version = u16::from_le_bytes([data[346], data[347]]);
+        let umid = data[348..348 + BEXT_UMID_WIDTH].to_vec();
+        // Version 0 and 1 files have reserved zeros where the loudness fields are
+        let loudness = |offset| {
+            if version >= 2 {
+                read_i16(offset)
+            } else {
+                LOUDNESS_NOT_SET
+            }
+        };
+
+        Ok(BextChunk {
+            description: read_fixed_str(data, 0, BEXT_DESCRIPTION_WIDTH),
+            originator: read_fixed_str(data, 256, BEXT_ORIGINATOR_WIDTH),
+            originator_reference: read_fixed_str(data, 288, BEXT_ORIGINATOR_WIDTH),
+            origination_date: read_fixed_str(data, 320, BEXT_DATE_WIDTH),
+            origination_time: read_fixed_str(data, 330, BEXT_TIME_WIDTH),
+            time_reference,
+            version,
+            umid,
+            loudness_value: loudness(412),
+            loudness_range: loudness(414),
+            max_true_peak_level: loudness(416),
+            max_momentary_loudness: loudness(418),
+            max_short_term_loudness: loudness(420),
+            coding_history: read_fixed_str(data, BEXT_FIXED_SIZE, data.len() - BEXT_FIXED_SIZE),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BEXT_FIXED_SIZE + self.coding_history.len());
+        write_fixed_str(&mut bytes, &self.description, BEXT_DESCRIPTION_WIDTH);
+        write_fixed_str(&mut bytes, &self.originator, BEXT_ORIGINATOR_WIDTH);
+        write_fixed_str(
+            &mut bytes,
+            &self.originator_reference,
+            BEXT_ORIGINATOR_WIDTH,
+        );
+        write_fixed_str(&mut bytes, &self.origination_date, BEXT_DATE_WIDTH);
+        write_fixed_str(&mut bytes, &self.origination_time, BEXT_TIME_WIDTH);
+        bytes.extend_from_slice(&self.time_reference.to_le_bytes());
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        let mut umid = self.umid.clone();
+        umid.resize(BEXT_UMID_WIDTH, 0);
+        bytes.extend_from_slice(&umid);
+        for value in [
+            self.loudness_value,
+            self.loudness_range,
+            self.max_true_peak_level,
+            self.max_momentary_loudness,
+            self.max_short_term_loudness,
+        ] {
+            let value = if self.version >= 2 { value } else { 0 };
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.resize(bytes.len() + BEXT_RESERVED_WIDTH, 0);
+        bytes.extend_from_slice(self.coding_history.as_bytes());
+        bytes
+    }
+}
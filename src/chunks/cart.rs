@@ -0,0 +1,307 @@
+use std::path::Path;
+
+use super::{
+    check_len, read_fixed_str, read_u32, rewrite_with_chunk, write_fixed_str, ChunkError,
+    TypedChunk,
+};
+
+pub const CART_FIXED_SIZE: usize = 2048;
+pub const CART_NUM_TIMERS: usize = 8;
+const CART_TEXT_WIDTH: usize = 64;
+const CART_DATE_WIDTH: usize = 10;
+const CART_TIME_WIDTH: usize = 8;
+const CART_RESERVED_WIDTH: usize = 276;
+const CART_URL_WIDTH: usize = 1024;
+
+/// A post timer marker, e.g. `SEG1` or `INT1`, positioned in audio frames
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartTimer {
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
+    pub usage: [u8; 4],
+    pub value: u32,
+}
+
+impl CartTimer {
+    pub fn is_used(&self) -> bool {
+        self.usage != [0; 4]
+    }
+}
+
+/// AES46 radio traffic data chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CartChunk {
+    pub version: String,
+    pub title: String,
+    pub artist: String,
+    pub cut_id: String,
+    pub client_id: String,
+    pub category: String,
+    pub classification: String,
+    pub out_cue: String,
+    /// YYYY-MM-DD
+    pub start_date: String,
+    /// hh:mm:ss
+    pub start_time: String,
+    pub end_date: String,
+    pub end_time: String,
+    pub producer_app_id: String,
+    pub producer_app_version: String,
+    pub user_def: String,
+    /// Sample value of the 0 dB reference level
+    pub level_reference: i32,
+    pub post_timers: [CartTimer; CART_NUM_TIMERS],
+    pub url: String,
+    pub tag_text: String,
+}
+
+impl Default for CartChunk {
+    fn default() -> Self {
+        CartChunk {
+            version: "0101".to_string(),
+            title: String::new(),
+            artist: String::new(),
+            cut_id: String::new(),
+            client_id: String::new(),
+            category: String::new(),
+            classification: String::new(),
+            out_cue: String::new(),
+            // AES46 defaults for a spot with no air date restrictions
+            start_date: "1900-01-01".to_string(),
+            start_time: "00:00:00".to_string(),
+            end_date: "9999-12-31".to_string(),
+            end_time: "23:59:59".to_string(),
+            producer_app_id: String::new(),
+            producer_app_version: String::new(),
+            user_def: String::new(),
+            level_reference: 0,
+            post_timers: [CartTimer::default(); CART_NUM_TIMERS],
+            url: String::new(),
+            tag_text: String::new(),
+        }
+    }
+}
+
+impl CartChunk {
+    fn text_fields(&self) -> [(&'static str, &str, usize); 16] {
+        [
+            ("version", &self.version, 4),
+            ("title", &self.title, CART_TEXT_WIDTH),
+            ("artist", &self.artist, CART_TEXT_WIDTH),
+            ("cut_id", &self.cut_id, CART_TEXT_WIDTH),
+            ("client_id", &self.client_id, CART_TEXT_WIDTH),
+            ("category", &self.category, CART_TEXT_WIDTH),
+            ("classification", &self.classification, CART_TEXT_WIDTH),
+            ("out_cue", &self.out_cue, CART_TEXT_WIDTH),
+            ("start_date", &self.start_date, CART_DATE_WIDTH),
+            ("start_time", &self.start_time, CART_TIME_WIDTH),
+            ("end_date", &self.end_date, CART_DATE_WIDTH),
+            ("end_time", &self.end_time, CART_TIME_WIDTH),
+            ("producer_app_id", &self.producer_app_id, CART_TEXT_WIDTH),
+            (
+                "producer_app_version",
+                &self.producer_app_version,
+                CART_TEXT_WIDTH,
+            ),
+            ("user_def", &self.user_def, CART_TEXT_WIDTH),
+            ("url", &self.url, CART_URL_WIDTH),
+        ]
+    }
+
+    /// Checks that every field is ASCII and fits its fixed width, and that the dates, times and
+    /// timer ids are well formed. Fields that don't fit would otherwise be truncated on write.
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        for (name, value, width) in self.text_fields() {
+            if !value.is_ascii() {
+                return Err(ChunkError::InvalidField(format!(
+                    "cart {name} is not ASCII"
+                )));
+            }
+            if value.len() > width {
+                return Err(ChunkError::InvalidField(format!(
+                    "cart {name} is {} bytes, the field holds {width}",
+                    value.len()
+                )));
+            }
+        }
+        if !self.tag_text.is_ascii() {
+            return Err(ChunkError::InvalidField(
+                "cart tag_text is not ASCII".to_string(),
+            ));
+        }
+        if self.version.len() != 4 || !self.version.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ChunkError::InvalidField(format!(
+                "cart version {:?} is not four digits",
+                self.version
+            )));
+        }
+        for (name, date) in [
+            ("start_date", &self.start_date),
+            ("end_date", &self.end_date),
+        ] {
+            if !date.is_empty() && !is_valid_date(date) {
+                return Err(ChunkError::InvalidField(format!(
+                    "cart {name} {date:?} is not YYYY-MM-DD"
+                )));
+            }
+        }
+        for (name, time) in [
+            ("start_time", &self.start_time),
+            ("end_time", &self.end_time),
+        ] {
+            if !time.is_empty() && !is_valid_time(time) {
+                return Err(ChunkError::InvalidField(format!(
+                    "cart {name} {time:?} is not hh:mm:ss"
+                )));
+            }
+        }
+        for (index, timer) in self.post_timers.iter().enumerate() {
+            if timer.is_used()
+                && !timer
+                    .usage
+                    .iter()
+                    .all(|&b| b.is_ascii_alphanumeric() || b == b' ')
+            {
+                return Err(ChunkError::InvalidField(format!(
+                    "cart post timer {index} usage {:?} is not a FOURCC",
+                    timer.usage
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Used post timers with their ids as text
+    pub fn timers(&self) -> impl Iterator<Item = (String, u32)> + '_ {
+        self.post_timers
+            .iter()
+            .filter(|timer| timer.is_used())
+            .map(|timer| {
+                (
+                    String::from_utf8_lossy(&timer.usage).to_string(),
+                    timer.value,
+                )
+            })
+    }
+}
+
+fn is_valid_date(date: &str) -> bool {
+    let bytes = date.as_bytes();
+    if bytes.len() != CART_DATE_WIDTH || bytes[4] != b'-' || bytes[7] != b'-' {
+        return false;
+    }
+    let (Ok(_year), Ok(month), Ok(day)) = (
+        date[0..4].parse::<u16>(),
+        date[5..7].parse::<u8>(),
+        date[8..10].parse::<u8>(),
+    ) else {
+        return false;
+    };
+    (1..=12).contains(&month) && (1..=31).contains(&day)
+}
+
+fn is_valid_time(time: &str) -> bool {
+    let bytes = time.as_bytes();
+    if bytes.len() != CART_TIME_WIDTH || bytes[2] != b':' || bytes[5] != b':' {
+        return false;
+    }
+    let (Ok(hour), Ok(minute), Ok(second)) = (
+        time[0..2].parse::<u8>(),
+        time[3..5].parse::<u8>(),
+        time[6..8].parse::<u8>(),
+    ) else {
+        return false;
+    };
+    hour < 24 && minute < 60 && second < 60
+}
+
+/// Validates `cart` and rewrites `input` to `output` with it, leaving the audio and every other
+/// chunk untouched
+pub fn write_cart(input: &Path, output: &Path, cart: &CartChunk) -> Result<(), ChunkError> {
+    cart.validate()?;
+    rewrite_with_chunk(input, output, cart)?;
+    Ok(())
+}
+
+impl TypedChunk for CartChunk {
+    const ID: [u8; 4] = *b"cart";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, CART_FIXED_SIZE)?;
+        let mut offset = 0;
+        let mut next_str = |width: usize| {
+            let value = read_fixed_str(data, offset, width);
+            offset += width;
+            value
+        };
+
+        let version = next_str(4);
+        let title = next_str(CART_TEXT_WIDTH);
+        let artist = next_str(CART_TEXT_WIDTH);
+        let cut_id = next_str(CART_TEXT_WIDTH);
+        let client_id = next_str(CART_TEXT_WIDTH);
+        let category = next_str(CART_TEXT_WIDTH);
+        let classification = next_str(CART_TEXT_WIDTH);
+        let out_cue = next_str(CART_TEXT_WIDTH);
+        let start_date = next_str(CART_DATE_WIDTH);
+        let start_time = next_str(CART_TIME_WIDTH);
+        let end_date = next_str(CART_DATE_WIDTH);
+        let end_time = next_str(CART_TIME_WIDTH);
+        let producer_app_id = next_str(CART_TEXT_WIDTH);
+        let producer_app_version = next_str(CART_TEXT_WIDTH);
+        let user_def = next_str(CART_TEXT_WIDTH);
+
+        let level_offset = 4 + CART_TEXT_WIDTH * 10 + (CART_DATE_WIDTH + CART_TIME_WIDTH) * 2;
+        let level_reference = read_u32(data, level_offset) as i32;
+        let mut post_timers = [CartTimer::default(); CART_NUM_TIMERS];
+        for (index, timer) in post_timers.iter_mut().enumerate() {
+            let timer_offset = level_offset + 4 + index * 8;
+            timer.usage = data[timer_offset..timer_offset + 4].try_into().unwrap();
+            timer.value = read_u32(data, timer_offset + 4);
+        }
+        let url_offset = level_offset + 4 + CART_NUM_TIMERS * 8 + CART_RESERVED_WIDTH;
+        let url = read_fixed_str(data, url_offset, CART_URL_WIDTH);
+        let tag_text = read_fixed_str(data, CART_FIXED_SIZE, data.len() - CART_FIXED_SIZE);
+
+        Ok(CartChunk {
+            version,
+            title,
+            artist,
+            cut_id,
+            client_id,
+            category,
+            classification,
+            out_cue,
+            start_date,
+            start_time,
+            end_date,
+            end_time,
+            producer_app_id,
+            producer_app_version,
+            user_def,
+            level_reference,
+            post_timers,
+            url,
+            tag_text,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CART_FIXED_SIZE + self.tag_text.len());
+        let text_fields = self.text_fields();
+        let (url_field, fixed_fields) = text_fields.split_last().unwrap();
+        for (_, value, width) in fixed_fields {
+            write_fixed_str(&mut bytes, value, *width);
+        }
+        bytes.extend_from_slice(&self.level_reference.to_le_bytes());
+        for timer in &self.post_timers {
+            bytes.extend_from_slice(&timer.usage);
+            bytes.extend_from_slice(&timer.value.to_le_bytes());
+        }
+        bytes.resize(bytes.len() + CART_RESERVED_WIDTH, 0);
+        write_fixed_str(&mut bytes, url_field.1, url_field.2);
+        bytes.extend_from_slice(self.tag_text.as_bytes());
+        bytes
+    }
+}
@@ -0,0 +1,80 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{check_len, read_u32, ChunkError, TypedChunk};
+
+/// A marker in the data chunk. rwav only writes uncompressed files with a single data chunk, so
+/// `sample_offset` is the frame the marker is at and the other position fields are zero.
+#[derive(Debug, Copy, Clone, Pod, Zeroable, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct CuePoint {
+    /// Referenced by the labels and regions of the adtl list and by smpl loops
+    pub id: u32,
+    /// Play order position, normally equal to `sample_offset`
+    pub position: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
+    pub data_chunk_id: [u8; 4],
+    pub chunk_start: u32,
+    pub block_start: u32,
+    pub sample_offset: u32,
+}
+
+impl CuePoint {
+    pub fn new(id: u32, frame: u32) -> Self {
+        CuePoint {
+            id,
+            position: frame,
+            data_chunk_id: *b"data",
+            chunk_start: 0,
+            block_start: 0,
+            sample_offset: frame,
+        }
+    }
+}
+
+/// Cue point chunk, the marker list of most editors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CueChunk {
+    pub points: Vec<CuePoint>,
+}
+
+impl CueChunk {
+    pub fn point(&self, id: u32) -> Option<&CuePoint> {
+        self.points.iter().find(|point| point.id == id)
+    }
+
+    /// One past the highest id in use, an error if that id is `u32::MAX`
+    pub fn next_id(&self) -> Result<u32, ChunkError> {
+        match self.points.iter().map(|point| point.id).max() {
+            Some(id) => id.checked_add(1).ok_or_else(|| {
+                ChunkError::InvalidField(format!("cue id {id} leaves no id for another cue point"))
+            }),
+            None => Ok(1),
+        }
+    }
+}
+
+impl TypedChunk for CueChunk {
+    const ID: [u8; 4] = *b"cue ";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, 4)?;
+        let count = read_u32(data, 0) as usize;
+        let size = std::mem::size_of::<CuePoint>();
+        check_len(data, 4 + count * size)?;
+        let points = data[4..4 + count * size]
+            .chunks_exact(size)
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+        Ok(CueChunk { points })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.points.len() as u32).to_le_bytes().to_vec();
+        for point in &self.points {
+            bytes.extend_from_slice(bytemuck::bytes_of(point));
+        }
+        bytes
+    }
+}
@@ -0,0 +1,148 @@
+use super::{xml_escape, xml_unescape, ChunkError, TypedChunk};
+
+/// Starting point for files that had no iXML chunk
+const EMPTY_IXML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n<IXML_VERSION>2.10</IXML_VERSION>\n</BWFXML>\n";
+
+/// One entry of the iXML TRACK_LIST
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IxmlTrack {
+    /// Input channel of the recorder the track came from, one based
+    pub channel_index: u16,
+    /// Position of the track in the file's interleaved data, one based
+    pub interleave_index: u16,
+    pub name: String,
+    pub function: String,
+}
+
+/// iXML production metadata chunk. Like axml the XML is kept byte for byte; only the track
+/// list is read and rewritten.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IxmlChunk {
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "xml", with = "crate::metadata::lossy_str")
+    )]
+    pub raw: Vec<u8>,
+}
+
+impl IxmlChunk {
+    pub fn new(xml: &str) -> Self {
+        IxmlChunk {
+            raw: xml.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn xml(&self) -> String {
+        String::from_utf8_lossy(&self.raw)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+
+    pub fn tracks(&self) -> Vec<IxmlTrack> {
+        let xml = self.xml();
+        let Some(track_list) = element_text(&xml, "TRACK_LIST") else {
+            return Vec::new();
+        };
+        let mut tracks = Vec::new();
+        let mut rest = track_list;
+        while let Some((track, after)) = next_element(rest, "TRACK") {
+            let index = |name| {
+                element_text(track, name)
+                    .and_then(|text| text.trim().parse().ok())
+                    .unwrap_or(0)
+            };
+            let text = |name| {
+                element_text(track, name)
+                    .map(|text| xml_unescape(text.trim()))
+                    .unwrap_or_default()
+            };
+            tracks.push(IxmlTrack {
+                channel_index: index("CHANNEL_INDEX"),
+                interleave_index: index("INTERLEAVE_INDEX"),
+                name: text("NAME"),
+                function: text("FUNCTION"),
+            });
+            rest = after;
+        }
+        tracks
+    }
+
+    /// Name of the track at a one based interleave index, if the track list names it
+    pub fn track_name(&self, interleave_index: u16) -> Option<String> {
+        self.tracks()
+            .into_iter()
+            .find(|track| track.interleave_index == interleave_index && !track.name.is_empty())
+            .map(|track| track.name)
+    }
+
+    /// Copy of the chunk with its TRACK_LIST replaced by `tracks`, or a minimal iXML document
+    /// holding just the track list if `ixml` is `None`
+    pub fn with_tracks(ixml: Option<&IxmlChunk>, tracks: &[IxmlTrack]) -> IxmlChunk {
+        let xml = ixml
+            .map(IxmlChunk::xml)
+            .unwrap_or_else(|| EMPTY_IXML.to_string());
+        let mut track_list = format!(
+            "<TRACK_LIST>\n<TRACK_COUNT>{}</TRACK_COUNT>\n",
+            tracks.len()
+        );
+        for track in tracks {
+            track_list.push_str(&format!(
+                "<TRACK>\n<CHANNEL_INDEX>{}</CHANNEL_INDEX>\n<INTERLEAVE_INDEX>{}</INTERLEAVE_INDEX>\n<NAME>{}</NAME>\n",
+                track.channel_index,
+                track.interleave_index,
+                xml_escape(&track.name)
+            ));
+            if !track.function.is_empty() {
+                track_list.push_str(&format!(
+                    "<FUNCTION>{}</FUNCTION>\n",
+                    xml_escape(&track.function)
+                ));
+            }
+            track_list.push_str("</TRACK>\n");
+        }
+        track_list.push_str("</TRACK_LIST>");
+
+        let xml = match (xml.find("<TRACK_LIST>"), xml.find("</TRACK_LIST>")) {
+            (Some(start), Some(end)) if start < end => {
+                format!(
+                    "{}{track_list}{}",
+                    &xml[..start],
+                    &xml[end + "</TRACK_LIST>".len()..]
+                )
+            }
+            _ => match xml.rfind("</BWFXML>") {
+                Some(end) => format!("{}{track_list}\n{}", &xml[..end], &xml[end..]),
+                None => format!("{xml}{track_list}"),
+            },
+        };
+        IxmlChunk::new(&xml)
+    }
+}
+
+impl TypedChunk for IxmlChunk {
+    const ID: [u8; 4] = *b"iXML";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        Ok(IxmlChunk { raw: data.to_vec() })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone()
+    }
+}
+
+/// Contents of the first `<name>` element. iXML elements are upper case and carry no
+/// attributes, so a plain search is enough.
+fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    next_element(xml, name).map(|(text, _)| text)
+}
+
+/// Contents of the first `<name>` element and the text after it
+fn next_element<'a>(xml: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{name}>");
+    let close = format!("</{name}>");
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some((&xml[start..end], &xml[end + close.len()..]))
+}
@@ -0,0 +1,260 @@
+use std::path::Path;
+
+use super::{check_len, read_u32, ChunkError, TypedChunk, UtcDateTime};
+use crate::{
+    samples::{SampleError, SampleReader},
+    wav::{WavFile, WavWriter},
+};
+
+pub const LEVL_HEADER_SIZE: usize = 120;
+pub const LEVL_TIMESTAMP_SIZE: usize = 28;
+/// Offset of the peak data from the start of the chunk, including the 8 byte chunk header
+pub const LEVL_OFFSET_TO_PEAKS: u32 = 128;
+pub const LEVL_NO_PEAK_OF_PEAKS: u32 = 0xFFFFFFFF;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PeakFormat {
+    U8 = 1,
+    U16 = 2,
+}
+
+impl PeakFormat {
+    pub fn full_scale(&self) -> u16 {
+        match self {
+            PeakFormat::U8 => 127,
+            PeakFormat::U16 => 32767,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PeakEnvelopeSettings {
+    pub format: PeakFormat,
+    /// 1 stores only the positive peak of each block, 2 stores the positive and negative peaks
+    pub points_per_value: u32,
+    /// Audio frames summarized by each peak frame
+    pub block_size: u32,
+}
+
+impl Default for PeakEnvelopeSettings {
+    fn default() -> Self {
+        PeakEnvelopeSettings {
+            format: PeakFormat::U16,
+            points_per_value: 2,
+            block_size: 256,
+        }
+    }
+}
+
+/// EBU Tech 3285 Supplement 3 peak envelope chunk
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LevlChunk {
+    pub version: u32,
+    pub format: PeakFormat,
+    pub points_per_value: u32,
+    pub block_size: u32,
+    pub peak_channels: u32,
+    pub num_peak_frames: u32,
+    /// Audio frame holding the loudest sample of the file, or `LEVL_NO_PEAK_OF_PEAKS`
+    pub pos_peak_of_peaks: u32,
+    pub offset_to_peaks: u32,
+    /// ASCII "YYYY:MM:DD:hh:mm:ss:uuu"
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fixed_str"))]
+    pub timestamp: [u8; LEVL_TIMESTAMP_SIZE],
+    /// Absolute peak values ordered by peak frame, then channel, then positive/negative point
+    pub peaks: Vec<u16>,
+}
+
+impl LevlChunk {
+    /// Builds the peak envelope by streaming the whole data chunk through `reader`
+    pub fn compute(
+        reader: &mut SampleReader,
+        settings: PeakEnvelopeSettings,
+    ) -> Result<Self, SampleError> {
+        let num_channels = reader.num_channels as usize;
+        let block_size = settings.block_size.max(1) as usize;
+        let points_per_value = settings.points_per_value.clamp(1, 2) as usize;
+        let full_scale = settings.format.full_scale() as f64;
+
+        let mut peaks = Vec::new();
+        let mut samples = Vec::new();
+        let mut peak_of_peaks = 0.0f64;
+        let mut pos_peak_of_peaks = LEVL_NO_PEAK_OF_PEAKS;
+        let mut frame_index = 0u64;
+        let mut block_peaks = vec![(0.0f64, 0.0f64); num_channels];
+
+        loop {
+            let num_frames = reader.read_frames(block_size, &mut samples)?;
+            if num_frames == 0 {
+                break;
+            }
+            block_peaks.fill((0.0, 0.0));
+            for (frame_offset, frame) in samples.chunks_exact(num_channels).enumerate() {
+                for (channel, &sample) in frame.iter().enumerate() {
+                    let (positive, negative) = &mut block_peaks[channel];
+                    *positive = positive.max(sample);
+                    *negative = negative.max(-sample);
+                    if sample.abs() > peak_of_peaks {
+                        peak_of_peaks = sample.abs();
+                        pos_peak_of_peaks = (frame_index + frame_offset as u64) as u32;
+                    }
+                }
+            }
+            frame_index += num_frames as u64;
+
+            for &(positive, negative) in &block_peaks {
+                if points_per_value == 1 {
+                    peaks.push(Self::quantize(positive.max(negative), full_scale));
+                } else {
+                    peaks.push(Self::quantize(positive, full_scale));
+                    peaks.push(Self::quantize(negative, full_scale));
+                }
+            }
+        }
+
+        let now = UtcDateTime::now();
+        let timestamp_str = format!(
+            "{:04}:{:02}:{:02}:{:02}:{:02}:{:02}:{:03}",
+            now.year, now.month, now.day, now.hour, now.minute, now.second, now.millisecond
+        );
+        let mut timestamp = [0u8; LEVL_TIMESTAMP_SIZE];
+        timestamp[..timestamp_str.len()].copy_from_slice(timestamp_str.as_bytes());
+
+        Ok(LevlChunk {
+            version: 1,
+            format: settings.format,
+            points_per_value: points_per_value as u32,
+            block_size: block_size as u32,
+            peak_channels: num_channels as u32,
+            num_peak_frames: (peaks.len() / (num_channels * points_per_value).max(1)) as u32,
+            pos_peak_of_peaks,
+            offset_to_peaks: LEVL_OFFSET_TO_PEAKS,
+            timestamp,
+            peaks,
+        })
+    }
+
+    fn quantize(peak: f64, full_scale: f64) -> u16 {
+        (peak * full_scale).round().clamp(0.0, full_scale) as u16
+    }
+
+    /// Positive and negative peaks of one peak frame and channel, normalized to 0.0..1.0. Files
+    /// with a single point per value report the same peak for both.
+    pub fn peak(&self, peak_frame: usize, channel: usize) -> Option<(f64, f64)> {
+        let points = self.points_per_value as usize;
+        let index = peak_frame
+            .checked_mul(self.peak_channels as usize)?
+            .checked_add(channel)?
+            .checked_mul(points)?;
+        let full_scale = self.format.full_scale() as f64;
+        let positive = *self.peaks.get(index)? as f64 / full_scale;
+        let negative = if points == 2 {
+            *self.peaks.get(index + 1)? as f64 / full_scale
+        } else {
+            positive
+        };
+        Some((positive, negative))
+    }
+
+    /// Every peak of one channel, normalized to 0.0..1.0
+    pub fn channel_peaks(&self, channel: usize) -> Vec<(f64, f64)> {
+        (0..self.num_peak_frames as usize)
+            .map_while(|peak_frame| self.peak(peak_frame, channel))
+            .collect()
+    }
+
+    pub fn timestamp_str(&self) -> String {
+        String::from_utf8_lossy(&self.timestamp)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+/// Rewrites `input` to `output` with a freshly computed peak envelope, replacing any existing one
+pub fn embed_peak_envelope(
+    input: &Path,
+    output: &Path,
+    settings: PeakEnvelopeSettings,
+) -> Result<LevlChunk, SampleError> {
+    let mut wav_file = WavFile::open(input)?;
+    let levl = LevlChunk::compute(&mut SampleReader::new(&mut wav_file)?, settings)?;
+    let mut writer = WavWriter::from_wav_file(wav_file)?;
+    writer.set_chunk(levl.to_chunk());
+    writer.write(output)?;
+    Ok(levl)
+}
+
+impl TypedChunk for LevlChunk {
+    const ID: [u8; 4] = *b"levl";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, LEVL_HEADER_SIZE)?;
+        let format = match read_u32(data, 4) {
+            1 => PeakFormat::U8,
+            2 => PeakFormat::U16,
+            other => return Err(ChunkError::InvalidField(format!("levl format {other}"))),
+        };
+        let points_per_value = read_u32(data, 8);
+        if !(1..=2).contains(&points_per_value) {
+            return Err(ChunkError::InvalidField(format!(
+                "levl points per value {points_per_value}"
+            )));
+        }
+        let offset_to_peaks = read_u32(data, 28);
+        // The offset counts the chunk header, which isn't part of `data`
+        let peaks_start = (offset_to_peaks as usize)
+            .saturating_sub(8)
+            .max(LEVL_HEADER_SIZE);
+        let peak_bytes = data.get(peaks_start..).unwrap_or(&[]);
+        let peaks = match format {
+            PeakFormat::U8 => peak_bytes.iter().map(|&peak| peak as u16).collect(),
+            PeakFormat::U16 => peak_bytes
+                .chunks_exact(2)
+                .map(|peak| u16::from_le_bytes([peak[0], peak[1]]))
+                .collect(),
+        };
+
+        Ok(LevlChunk {
+            version: read_u32(data, 0),
+            format,
+            points_per_value,
+            block_size: read_u32(data, 12),
+            peak_channels: read_u32(data, 16),
+            num_peak_frames: read_u32(data, 20),
+            pos_peak_of_peaks: read_u32(data, 24),
+            offset_to_peaks,
+            timestamp: data[32..32 + LEVL_TIMESTAMP_SIZE].try_into().unwrap(),
+            peaks,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(LEVL_HEADER_SIZE + self.peaks.len() * 2);
+        for field in [
+            self.version,
+            self.format as u32,
+            self.points_per_value,
+            self.block_size,
+            self.peak_channels,
+            self.num_peak_frames,
+            self.pos_peak_of_peaks,
+            LEVL_OFFSET_TO_PEAKS,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.timestamp);
+        bytes.resize(LEVL_HEADER_SIZE, 0);
+        match self.format {
+            PeakFormat::U8 => bytes.extend(self.peaks.iter().map(|&peak| peak.min(255) as u8)),
+            PeakFormat::U16 => {
+                for peak in &self.peaks {
+                    bytes.extend_from_slice(&peak.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt;
+
+use super::{check_len, ChunkError, TypedChunk};
+use crate::hash::to_hex;
+
+/// MD5 digest of the data chunk's contents, without its header or pad byte, as written by BWF
+/// MetaEdit and other archiving tools to check the audio's integrity
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Md5Chunk {
+    pub digest: [u8; 16],
+}
+
+impl TypedChunk for Md5Chunk {
+    const ID: [u8; 4] = *b"MD5 ";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        check_len(data, 16)?;
+        Ok(Md5Chunk {
+            digest: data[..16].try_into().unwrap(),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.digest.to_vec()
+    }
+}
+
+impl fmt::Display for Md5Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.digest))
+    }
+}
@@ -0,0 +1,84 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{check_len, read_u32, ChunkError, TypedChunk};
+
+pub const LOOP_FORWARD: u32 = 0;
+pub const LOOP_ALTERNATING: u32 = 1;
+pub const LOOP_BACKWARD: u32 = 2;
+
+/// Fixed fields of the smpl chunk, before the loops
+#[derive(Debug, Copy, Clone, Pod, Zeroable, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct SmplHeader {
+    pub manufacturer: u32,
+    pub product: u32,
+    /// Nanoseconds per sample
+    pub sample_period: u32,
+    pub midi_unity_note: u32,
+    pub midi_pitch_fraction: u32,
+    pub smpte_format: u32,
+    pub smpte_offset: u32,
+}
+
+#[derive(Debug, Copy, Clone, Pod, Zeroable, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct SampleLoop {
+    pub cue_point_id: u32,
+    /// One of the `LOOP_` constants
+    pub loop_type: u32,
+    /// First frame of the loop
+    pub start: u32,
+    /// Last frame of the loop, inclusive
+    pub end: u32,
+    pub fraction: u32,
+    /// 0 loops forever
+    pub play_count: u32,
+}
+
+/// Sampler chunk: MIDI tuning and sustain loops for samplers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SmplChunk {
+    pub header: SmplHeader,
+    pub loops: Vec<SampleLoop>,
+    /// Manufacturer specific data after the loops
+    pub sampler_data: Vec<u8>,
+}
+
+impl TypedChunk for SmplChunk {
+    const ID: [u8; 4] = *b"smpl";
+
+    fn parse(data: &[u8]) -> Result<Self, ChunkError> {
+        let header_size = std::mem::size_of::<SmplHeader>();
+        let loop_size = std::mem::size_of::<SampleLoop>();
+        check_len(data, header_size + 8)?;
+        let num_loops = read_u32(data, header_size) as usize;
+        let sampler_data_size = read_u32(data, header_size + 4) as usize;
+        let loops_start = header_size + 8;
+        let loops_end = loops_start + num_loops * loop_size;
+        check_len(data, loops_end)?;
+        let loops = data[loops_start..loops_end]
+            .chunks_exact(loop_size)
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+        let sampler_data_end = (loops_end + sampler_data_size).min(data.len());
+        Ok(SmplChunk {
+            header: bytemuck::pod_read_unaligned(&data[..header_size]),
+            loops,
+            sampler_data: data[loops_end..sampler_data_end].to_vec(),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = bytemuck::bytes_of(&self.header).to_vec();
+        bytes.extend_from_slice(&(self.loops.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.sampler_data.len() as u32).to_le_bytes());
+        for sample_loop in &self.loops {
+            bytes.extend_from_slice(bytemuck::bytes_of(sample_loop));
+        }
+        bytes.extend_from_slice(&self.sampler_data);
+        bytes
+    }
+}
@@ -1,13 +1,216 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{
+    dsp::{
+        dynamics::{CompressorSettings, GateSettings, LimiterSettings},
+        generator::Signal,
+        resample::ResampleQuality,
+    },
+    samples::SampleFormat,
+};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Wav file to operate on
-    #[arg(long, short)]
-    pub input: String,
+    #[arg(long, short, required = true)]
+    pub input: Option<String>,
 
     /// Output header information
-    #[arg(long, default_value="false")]
+    #[arg(long, default_value = "false")]
     pub header: bool,
+
+    /// Print the header, chunk index and parsed metadata chunks in this format, then exit
+    #[cfg(feature = "serde")]
+    #[arg(long, value_enum)]
+    pub dump_metadata: Option<MetadataFormat>,
+
+    /// Apply a metadata file written by --dump-metadata (and edited) to the input, writing
+    /// the result to --output
+    #[cfg(feature = "serde")]
+    #[arg(long, requires = "output")]
+    pub apply_metadata: Option<String>,
+
+    /// Measure integrated, momentary and short-term loudness, loudness range and true peak. With
+    /// --output the input is also written there with the results in its bext chunk
+    #[arg(long, default_value = "false")]
+    pub loudness: bool,
+
+    /// Print per channel peak, RMS, DC offset, crest factor and clipping, and the silent spans
+    #[arg(long, default_value = "false")]
+    pub stats: bool,
+
+    /// Convert to this sample rate. Written to --output if given, otherwise the audio is
+    /// converted before playback
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub sample_rate: Option<u32>,
+
+    /// Filter quality used by --sample-rate
+    #[arg(long, value_enum, default_value = "balanced")]
+    pub resample_quality: ResampleQualityArg,
+
+    /// Gate the input into --output. Takes optional settings such as
+    /// `threshold=-45,range=-20,attack=0.001,hold=0.05,release=0.1,link=off`
+    #[arg(long, requires = "output", num_args = 0..=1, default_missing_value = "")]
+    pub gate: Option<GateSettings>,
+
+    /// Compress the input into --output, after any gate. Takes optional settings such as
+    /// `threshold=-20,ratio=4,attack=0.01,release=0.1,knee=6,makeup=3,link=off`
+    #[arg(long, requires = "output", num_args = 0..=1, default_missing_value = "")]
+    pub compress: Option<CompressorSettings>,
+
+    /// Limit the input into --output, after any gate and compressor. Takes optional settings
+    /// such as `ceiling=-1,lookahead=0.005,release=0.05,link=off`
+    #[arg(long, requires = "output", num_args = 0..=1, default_missing_value = "")]
+    pub limit: Option<LimiterSettings>,
+
+    /// Wav file to write for operations that produce a new file
+    #[arg(long, short)]
+    pub output: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a file through a chain of effects, such as
+    /// `gain -3 highpass 80 norm -1 fade 0.5 0 1`. Options go before the input.
+    Process {
+        /// Print each effect with the format it produces and the output format, then exit
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+
+        /// Sample format to write, the input's if not given
+        #[arg(long, value_enum)]
+        format: Option<SampleFormatArg>,
+
+        input: String,
+
+        output: String,
+
+        /// Effects and their parameters, applied in order
+        #[arg(required = true, allow_hyphen_values = true)]
+        effects: Vec<String>,
+    },
+    /// Compare the headers, chunks and audio of two files. Exits with status 1 if they differ.
+    Diff {
+        a: String,
+
+        b: String,
+
+        /// Count sample differences at or below this level in dBFS as equal
+        #[arg(long, allow_hyphen_values = true)]
+        tolerance: Option<f64>,
+
+        /// Write the second file minus the first here, as 32 bit float
+        #[arg(long)]
+        difference: Option<String>,
+    },
+    /// Print a SHA-256 of the audio and its essential format fields that ignores every other
+    /// chunk, so files with the same audio hash the same
+    Hash {
+        input: String,
+
+        /// Also write the input here with an `MD5 ` chunk of its data chunk
+        #[arg(long, conflicts_with = "verify_md5")]
+        write_md5: Option<String>,
+
+        /// Also check the input's `MD5 ` chunk. Exits with status 1 if it's missing or wrong.
+        #[arg(long)]
+        verify_md5: bool,
+    },
+    /// Write a test signal to a wav file, or play it if no output is given
+    Generate {
+        /// `sine:<Hz>`, `multitone:<Hz>,<Hz>,...`, `sweep:<Hz>-<Hz>`, `white`, `pink`, `impulse`
+        /// or `silence`
+        signal: Signal,
+
+        output: Option<String>,
+
+        /// Length in seconds
+        #[arg(long, default_value = "1", value_parser = parse_duration)]
+        duration: f64,
+
+        /// Peak level of tones and impulses, RMS level of noise, in dBFS
+        #[arg(long, default_value = "-20", allow_hyphen_values = true)]
+        level: f64,
+
+        #[arg(long, default_value = "48000", value_parser = clap::value_parser!(u32).range(1..))]
+        sample_rate: u32,
+
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+        channels: u16,
+
+        /// Bits per sample of the integer PCM output
+        #[arg(long, default_value = "24", value_parser = parse_bits)]
+        bits: u16,
+    },
+}
+
+/// 8, 16, 24 or 32, the integer PCM depths rwav writes
+fn parse_bits(s: &str) -> Result<u16, String> {
+    match s.parse() {
+        Ok(bits @ (8 | 16 | 24 | 32)) => Ok(bits),
+        _ => Err("expected 8, 16, 24 or 32".to_string()),
+    }
+}
+
+/// A finite number of seconds above 0
+fn parse_duration(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(seconds),
+        _ => Err("expected a number of seconds above 0".to_string()),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MetadataFormat {
+    Json,
+    Yaml,
+}
+
+/// `ResampleQuality` for the command line, which keeps clap out of the library types
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ResampleQualityArg {
+    /// Around 60 dB of alias rejection, for previews and playback on slow machines
+    Fast,
+    /// Around 90 dB, transparent for 16 bit material
+    Balanced,
+    /// Around 120 dB with a steeper transition band, for mastering and 24 bit delivery
+    Best,
+}
+
+impl From<ResampleQualityArg> for ResampleQuality {
+    fn from(quality: ResampleQualityArg) -> Self {
+        match quality {
+            ResampleQualityArg::Fast => ResampleQuality::Fast,
+            ResampleQualityArg::Balanced => ResampleQuality::Balanced,
+            ResampleQualityArg::Best => ResampleQuality::Best,
+        }
+    }
+}
+
+/// `SampleFormat` for the command line
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SampleFormatArg {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+    F64,
+}
+
+impl From<SampleFormatArg> for SampleFormat {
+    fn from(format: SampleFormatArg) -> Self {
+        match format {
+            SampleFormatArg::U8 => SampleFormat::U8,
+            SampleFormatArg::I16 => SampleFormat::I16,
+            SampleFormatArg::I24 => SampleFormat::I24,
+            SampleFormatArg::I32 => SampleFormat::I32,
+            SampleFormatArg::F32 => SampleFormat::F32,
+            SampleFormatArg::F64 => SampleFormat::F64,
+        }
+    }
 }
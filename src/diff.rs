@@ -0,0 +1,276 @@
+use std::{fmt, path::Path};
+
+use thiserror::Error;
+
+use crate::{
+    dsp::{convert::BLOCK_FRAMES, db_to_linear, linear_to_db},
+    samples::{SampleError, SampleFormat, SampleReader, SampleWriter},
+    wav::{Chunk, WavError, WavFile},
+};
+
+/// A header field that differs between the two files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderDifference {
+    pub field: &'static str,
+    pub a: String,
+    pub b: String,
+}
+
+impl fmt::Display for HeaderDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} vs {}", self.field, self.a, self.b)
+    }
+}
+
+/// A chunk other than fmt and data that only one file has, or that has different contents.
+/// Chunks with the same id are paired up in the order they appear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkDifference {
+    OnlyInA([u8; 4]),
+    OnlyInB([u8; 4]),
+    Changed([u8; 4]),
+}
+
+impl fmt::Display for ChunkDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (id, what) = match self {
+            ChunkDifference::OnlyInA(id) => (id, "only in the first file"),
+            ChunkDifference::OnlyInB(id) => (id, "only in the second file"),
+            ChunkDifference::Changed(id) => (id, "has different contents"),
+        };
+        write!(f, "{:?} chunk {what}", String::from_utf8_lossy(id))
+    }
+}
+
+/// How far apart the audio of the two files is. A file shorter than the other is compared as
+/// if it were padded with silence.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioDifference {
+    pub frames: u64,
+    /// First frame with a sample differing by more than the tolerance
+    pub first_difference: Option<u64>,
+    /// Samples differing by more than the tolerance
+    pub differing_samples: u64,
+    /// Largest difference between two samples in dBFS, -inf if the audio is identical
+    pub max_difference: f64,
+    pub max_difference_frame: u64,
+    /// RMS of the difference signal in dBFS, -inf if the audio is identical
+    pub residual_rms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffReport {
+    pub header_differences: Vec<HeaderDifference>,
+    pub chunk_differences: Vec<ChunkDifference>,
+    /// `None` if the files have different channel counts, so their audio can't be compared
+    pub audio: Option<AudioDifference>,
+}
+
+impl DiffReport {
+    /// No differences in the headers or the chunks, and none in the audio beyond the tolerance
+    pub fn is_match(&self) -> bool {
+        self.header_differences.is_empty()
+            && self.chunk_differences.is_empty()
+            && self
+                .audio
+                .is_some_and(|audio| audio.first_difference.is_none())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DiffSettings {
+    /// Sample differences at or below this level in dBFS count as equal, e.g. to null test
+    /// audio that went through different dither. By default any difference counts.
+    pub tolerance_db: Option<f64>,
+}
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// Compares `a` and `b`'s headers, their other chunks and then their audio sample by sample.
+/// If `difference` is given, `b` minus `a` is written there as 32 bit float, so differences
+/// smaller than either file's LSB survive.
+pub fn diff_files(
+    a: &Path,
+    b: &Path,
+    settings: DiffSettings,
+    difference: Option<&Path>,
+) -> Result<DiffReport, DiffError> {
+    let mut file_a = WavFile::open(a)?;
+    let mut file_b = WavFile::open(b)?;
+    let mut reader_a = SampleReader::new(&mut file_a)?;
+    let mut reader_b = SampleReader::new(&mut file_b)?;
+
+    let mut header_differences = Vec::new();
+    let mut compare = |field: &'static str, a: String, b: String| {
+        if a != b {
+            header_differences.push(HeaderDifference { field, a, b });
+        }
+    };
+    let (fmt_a, fmt_b) = (file_a.header.fmt, file_b.header.fmt);
+    compare(
+        "audio format",
+        format!("0x{:04x}", { fmt_a.audio_format }),
+        format!("0x{:04x}", { fmt_b.audio_format }),
+    );
+    compare(
+        "sample format",
+        format!("{:?}", reader_a.format),
+        format!("{:?}", reader_b.format),
+    );
+    compare(
+        "channels",
+        reader_a.num_channels.to_string(),
+        reader_b.num_channels.to_string(),
+    );
+    compare(
+        "sample rate",
+        reader_a.sample_rate.to_string(),
+        reader_b.sample_rate.to_string(),
+    );
+    compare(
+        "channel mask",
+        format!("{:?}", reader_a.channel_mask),
+        format!("{:?}", reader_b.channel_mask),
+    );
+    compare(
+        "block align",
+        { fmt_a.block_align }.to_string(),
+        { fmt_b.block_align }.to_string(),
+    );
+    compare(
+        "byte rate",
+        { fmt_a.byte_rate }.to_string(),
+        { fmt_b.byte_rate }.to_string(),
+    );
+    compare(
+        "frames",
+        reader_a.num_frames.to_string(),
+        reader_b.num_frames.to_string(),
+    );
+
+    let chunk_differences = diff_chunks(&mut file_a, &mut file_b)?;
+
+    let audio = if reader_a.num_channels == reader_b.num_channels {
+        let mut writer = match difference {
+            Some(path) => Some(SampleWriter::create(
+                path,
+                SampleFormat::F32,
+                reader_a.num_channels,
+                reader_a.sample_rate,
+                reader_a.channel_mask,
+                &[],
+            )?),
+            None => None,
+        };
+        let audio = diff_audio(&mut reader_a, &mut reader_b, settings, writer.as_mut())?;
+        if let Some(writer) = writer {
+            writer.finish(&[])?;
+        }
+        Some(audio)
+    } else {
+        None
+    };
+
+    Ok(DiffReport {
+        header_differences,
+        chunk_differences,
+        audio,
+    })
+}
+
+/// Pairs up the chunks other than fmt and data by id and compares their contents
+fn diff_chunks(a: &mut WavFile, b: &mut WavFile) -> Result<Vec<ChunkDifference>, WavError> {
+    let read = |file: &mut WavFile| -> Result<Vec<Chunk>, WavError> {
+        let (mut leading, trailing) = file.metadata_chunks()?;
+        leading.extend(trailing);
+        Ok(leading)
+    };
+    let chunks_a = read(a)?;
+    let mut chunks_b: Vec<Option<Chunk>> = read(b)?.into_iter().map(Some).collect();
+
+    let mut differences = Vec::new();
+    for chunk in chunks_a {
+        let id = chunk.chunk_header.chunk_id;
+        let pair = chunks_b
+            .iter_mut()
+            .find(|other| matches!(other, Some(other) if other.chunk_header.chunk_id == id))
+            .and_then(Option::take);
+        match pair {
+            Some(other) if other.data != chunk.data => {
+                differences.push(ChunkDifference::Changed(id))
+            }
+            Some(_) => (),
+            None => differences.push(ChunkDifference::OnlyInA(id)),
+        }
+    }
+    differences.extend(
+        chunks_b
+            .into_iter()
+            .flatten()
+            .map(|chunk| ChunkDifference::OnlyInB(chunk.chunk_header.chunk_id)),
+    );
+    Ok(differences)
+}
+
+fn diff_audio(
+    a: &mut SampleReader,
+    b: &mut SampleReader,
+    settings: DiffSettings,
+    mut writer: Option<&mut SampleWriter>,
+) -> Result<AudioDifference, SampleError> {
+    let num_channels = a.num_channels as usize;
+    let tolerance = settings.tolerance_db.map_or(0.0, db_to_linear);
+    let mut report = AudioDifference {
+        frames: 0,
+        first_difference: None,
+        differing_samples: 0,
+        max_difference: 0.0,
+        max_difference_frame: 0,
+        residual_rms: 0.0,
+    };
+    let mut energy = 0.0;
+    let (mut samples_a, mut samples_b) = (Vec::new(), Vec::new());
+    loop {
+        let frames = a
+            .read_frames(BLOCK_FRAMES, &mut samples_a)?
+            .max(b.read_frames(BLOCK_FRAMES, &mut samples_b)?);
+        if frames == 0 {
+            break;
+        }
+        // Past the end of the shorter file, its side is silence
+        samples_a.resize(frames * num_channels, 0.0);
+        samples_b.resize(frames * num_channels, 0.0);
+        for (sample_b, sample_a) in samples_b.iter_mut().zip(&samples_a) {
+            *sample_b -= sample_a;
+        }
+        for (index, frame) in samples_b.chunks_exact(num_channels).enumerate() {
+            let position = report.frames + index as u64;
+            for &difference in frame {
+                let magnitude = difference.abs();
+                energy += difference * difference;
+                if magnitude > report.max_difference {
+                    report.max_difference = magnitude;
+                    report.max_difference_frame = position;
+                }
+                if magnitude > tolerance {
+                    report.differing_samples += 1;
+                    report.first_difference.get_or_insert(position);
+                }
+            }
+        }
+        if let Some(writer) = writer.as_mut() {
+            writer.write_frames(&samples_b)?;
+        }
+        report.frames += frames as u64;
+    }
+    report.residual_rms =
+        linear_to_db((energy / (report.frames as f64 * num_channels as f64).max(1.0)).sqrt());
+    report.max_difference = linear_to_db(report.max_difference);
+    Ok(report)
+}
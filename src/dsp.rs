@@ -0,0 +1,51 @@
+pub mod convert;
+pub mod dynamics;
+pub mod fade;
+pub mod filter;
+pub mod gain;
+pub mod generator;
+pub mod mix;
+pub mod resample;
+
+/// Converts a gain in decibels to a linear factor
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Converts a linear amplitude to decibels, -inf for silence
+pub fn linear_to_db(linear: f64) -> f64 {
+    20.0 * linear.abs().log10()
+}
+
+/// Small, seedable xorshift64* generator for dither and noise. Not for anything that needs
+/// statistical rigor beyond audio.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng {
+            // A zero state would only ever produce zeros
+            state: seed.max(1),
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [-1, 1)
+    pub fn next_bipolar(&mut self) -> f64 {
+        self.next_f64() * 2.0 - 1.0
+    }
+}
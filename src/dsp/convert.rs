@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use super::Rng;
+use crate::samples::{open_with_metadata, SampleError, SampleFormat, SampleWriter};
+
+/// Frames processed per block by the file conversions
+pub const BLOCK_FRAMES: usize = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dither {
+    None,
+    /// Triangular PDF dither of ±1 LSB, which decorrelates the quantization error from the signal
+    Tpdf,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoiseShaping {
+    None,
+    /// Simple error feedback that tilts the noise towards high frequencies
+    FirstOrder,
+    /// Lipshitz's 5 tap E-weighted filter, which moves noise out of the ear's most sensitive band
+    Lipshitz,
+}
+
+impl NoiseShaping {
+    fn coefficients(&self) -> &'static [f64] {
+        match self {
+            NoiseShaping::None => &[],
+            NoiseShaping::FirstOrder => &[1.0],
+            NoiseShaping::Lipshitz => &[2.033, -2.165, 1.959, -1.590, 0.6149],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConvertSettings {
+    pub format: SampleFormat,
+    pub dither: Dither,
+    pub noise_shaping: NoiseShaping,
+    /// Seed for the dither noise, so conversions are reproducible
+    pub seed: u64,
+}
+
+impl ConvertSettings {
+    pub fn new(format: SampleFormat) -> Self {
+        ConvertSettings {
+            format,
+            dither: Dither::Tpdf,
+            noise_shaping: NoiseShaping::None,
+            seed: 1,
+        }
+    }
+}
+
+/// Requantizes normalized samples onto the grid of a target format, with optional dither and
+/// noise shaping, and counts the samples that had to be clipped
+pub struct Requantizer {
+    pub format: SampleFormat,
+    dither: Dither,
+    coefficients: &'static [f64],
+    num_channels: usize,
+    /// Most recent quantization errors per channel, newest first
+    error_history: Vec<Vec<f64>>,
+    rng: Rng,
+    pub clipped_samples: u64,
+    pub peak: f64,
+}
+
+impl Requantizer {
+    pub fn new(num_channels: u16, settings: ConvertSettings) -> Self {
+        let coefficients = settings.noise_shaping.coefficients();
+        Requantizer {
+            format: settings.format,
+            dither: settings.dither,
+            coefficients,
+            num_channels: num_channels as usize,
+            error_history: vec![vec![0.0; coefficients.len()]; num_channels as usize],
+            rng: Rng::new(settings.seed),
+            clipped_samples: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Quantizes interleaved samples in place. Float targets aren't quantized or clipped, but
+    /// samples beyond full scale are still counted so overs can be caught before a later
+    /// integer conversion.
+    pub fn process(&mut self, samples: &mut [f64]) {
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                if self.format.is_float() {
+                    if sample.abs() > 1.0 {
+                        self.clipped_samples += 1;
+                    }
+                    continue;
+                }
+                *sample = self.quantize(channel, *sample);
+            }
+        }
+    }
+
+    fn quantize(&mut self, channel: usize, sample: f64) -> f64 {
+        let scale = self.format.full_scale();
+        let history = &mut self.error_history[channel];
+        let shaped = sample * scale
+            - self
+                .coefficients
+                .iter()
+                .zip(history.iter())
+                .map(|(coefficient, error)| coefficient * error)
+                .sum::<f64>();
+        let dither = match self.dither {
+            Dither::None => 0.0,
+            Dither::Tpdf => self.rng.next_f64() - self.rng.next_f64(),
+        };
+
+        let mut quantized = (shaped + dither).round();
+        let (min, max) = (-scale, scale - 1.0);
+        if quantized < min || quantized > max {
+            quantized = quantized.clamp(min, max);
+            self.clipped_samples += 1;
+        }
+
+        if !history.is_empty() {
+            history.rotate_right(1);
+            // Clipping errors are far larger than quantization noise, feeding them back would
+            // make the shaping filter ring
+            history[0] = (quantized - shaped).clamp(-1.0, 1.0);
+        }
+        quantized / scale
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ConversionReport {
+    pub frames: u64,
+    pub clipped_samples: u64,
+    /// Largest absolute sample value before requantization
+    pub peak: f64,
+}
+
+/// Converts `input` to the sample format in `settings`, carrying every other chunk over.
+/// Dither and noise shaping are only applied when the word length is reduced, so widening is
+/// lossless. Narrowing back is dithered again unless `settings` turns dither off, which gets
+/// the original file back bit for bit.
+pub fn convert_file(
+    input: &Path,
+    output: &Path,
+    settings: ConvertSettings,
+) -> Result<ConversionReport, SampleError> {
+    let (mut reader, leading, trailing) = open_with_metadata(input)?;
+
+    let mut settings = settings;
+    if !is_word_length_reduced(reader.format, settings.format) {
+        settings.dither = Dither::None;
+        settings.noise_shaping = NoiseShaping::None;
+    }
+    let mut requantizer = Requantizer::new(reader.num_channels, settings);
+    let mut writer = SampleWriter::create(
+        output,
+        settings.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        &leading,
+    )?;
+
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        requantizer.process(&mut samples);
+        writer.write_frames(&samples)?;
+    }
+    drop(reader);
+    let frames = writer.finish(&trailing)?;
+
+    Ok(ConversionReport {
+        frames,
+        clipped_samples: requantizer.clipped_samples,
+        peak: requantizer.peak,
+    })
+}
+
+/// True if `to` can't represent every value of `from` exactly
+pub fn is_word_length_reduced(from: SampleFormat, to: SampleFormat) -> bool {
+    match (from.is_float(), to.is_float()) {
+        (_, true) => from == SampleFormat::F64 && to == SampleFormat::F32,
+        (true, false) => true,
+        (false, false) => to.bits_per_sample() < from.bits_per_sample(),
+    }
+}
@@ -0,0 +1,600 @@
+use std::{collections::VecDeque, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+use super::{convert::ConversionReport, db_to_linear, linear_to_db};
+use crate::{
+    pipeline::{drop_stale_chunks, Pipeline, PipelineError, Processor, StreamFormat, WavSink},
+    samples::{open_with_metadata, SampleError},
+    wav::WavError,
+};
+
+/// Feed-forward compressor settings. Times are in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CompressorSettings {
+    /// Level in dBFS above which the gain is reduced
+    pub threshold_db: f64,
+    /// Input dB above the threshold per output dB above it
+    pub ratio: f64,
+    /// Time for the gain reduction to close most of the way to a new, higher target
+    pub attack: f64,
+    /// Time for the gain reduction to recover most of the way to a new, lower target
+    pub release: f64,
+    /// Width in dB of the soft knee centered on the threshold, 0 for a hard knee
+    pub knee_db: f64,
+    /// Gain in dB applied after compression
+    pub makeup_db: f64,
+    /// Derive one gain from the loudest channel and apply it to all, keeping the stereo image
+    pub link: bool,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        CompressorSettings {
+            threshold_db: -18.0,
+            ratio: 3.0,
+            attack: 0.01,
+            release: 0.1,
+            knee_db: 6.0,
+            makeup_db: 0.0,
+            link: true,
+        }
+    }
+}
+
+/// Look-ahead brickwall limiter settings. Times are in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LimiterSettings {
+    /// No sample leaves the limiter above this level in dBFS
+    pub ceiling_db: f64,
+    /// How far ahead peaks are seen, which is also the attack time and the delay the limiter adds
+    pub lookahead: f64,
+    pub release: f64,
+    pub link: bool,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        LimiterSettings {
+            ceiling_db: -1.0,
+            lookahead: 0.005,
+            release: 0.05,
+            link: true,
+        }
+    }
+}
+
+/// Noise gate settings. Times are in seconds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GateSettings {
+    /// The gate opens when the level reaches this many dBFS
+    pub threshold_db: f64,
+    /// Gain in dB while the gate is closed, -inf to mute
+    pub range_db: f64,
+    pub attack: f64,
+    /// How long the gate stays open after the level drops below the threshold
+    pub hold: f64,
+    pub release: f64,
+    /// Open and close every channel together when any of them crosses the threshold
+    pub link: bool,
+}
+
+impl Default for GateSettings {
+    fn default() -> Self {
+        GateSettings {
+            threshold_db: -50.0,
+            range_db: -80.0,
+            attack: 0.001,
+            hold: 0.05,
+            release: 0.1,
+            link: true,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DynamicsError {
+    #[error("Unknown setting {0:?}!")]
+    UnknownSetting(String),
+    #[error("Invalid value {value:?} for {key}!")]
+    InvalidValue { key: String, value: String },
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// One-pole smoothing coefficient for a time constant in seconds
+fn coefficient(seconds: f64, sample_rate: u32) -> f64 {
+    if seconds > 0.0 {
+        (-1.0 / (seconds * sample_rate as f64)).exp()
+    } else {
+        0.0
+    }
+}
+
+/// Number of detectors a processor needs, one per channel or one for all of them
+fn num_detectors(num_channels: u16, link: bool) -> usize {
+    if link {
+        1
+    } else {
+        num_channels as usize
+    }
+}
+
+/// Runs `gain_for` on the level each detector sees in `frame` and scales the frame by the
+/// gains it returns
+fn apply_detectors<T>(
+    frame: &mut [f64],
+    detectors: &mut [T],
+    mut gain_for: impl FnMut(&mut T, f64) -> f64,
+) {
+    if let [detector] = detectors {
+        let level = frame
+            .iter()
+            .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+        let gain = gain_for(detector, level);
+        for sample in frame {
+            *sample *= gain;
+        }
+    } else {
+        for (sample, detector) in frame.iter_mut().zip(detectors) {
+            *sample *= gain_for(detector, sample.abs());
+        }
+    }
+}
+
+/// Feed-forward compressor with a soft knee. The gain reduction is smoothed in dB, so attack
+/// and release behave the same at every level.
+pub struct Compressor {
+    settings: CompressorSettings,
+    num_channels: usize,
+    sample_rate: u32,
+    attack: f64,
+    release: f64,
+    makeup: f64,
+    /// Current gain reduction in dB of each detector, never positive
+    reduction: Vec<f64>,
+}
+
+impl Compressor {
+    pub fn new(num_channels: u16, sample_rate: u32, settings: CompressorSettings) -> Self {
+        Compressor {
+            settings,
+            num_channels: num_channels as usize,
+            sample_rate,
+            attack: coefficient(settings.attack, sample_rate),
+            release: coefficient(settings.release, sample_rate),
+            makeup: db_to_linear(settings.makeup_db),
+            reduction: vec![0.0; num_detectors(num_channels, settings.link)],
+        }
+    }
+
+    /// Static gain reduction in dB for a level in dBFS
+    pub fn gain_reduction_db(&self, level_db: f64) -> f64 {
+        let CompressorSettings {
+            threshold_db,
+            ratio,
+            knee_db,
+            ..
+        } = self.settings;
+        let slope = 1.0 / ratio.max(1.0) - 1.0;
+        let over = level_db - threshold_db;
+        if 2.0 * over <= -knee_db {
+            0.0
+        } else if 2.0 * over < knee_db {
+            slope * (over + knee_db / 2.0).powi(2) / (2.0 * knee_db)
+        } else {
+            slope * over
+        }
+    }
+
+    /// Compresses interleaved frames in place
+    pub fn process(&mut self, samples: &mut [f64]) {
+        let mut reduction = std::mem::take(&mut self.reduction);
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            apply_detectors(frame, &mut reduction, |current, level| {
+                let target = self.gain_reduction_db(linear_to_db(level));
+                let coefficient = if target < *current {
+                    self.attack
+                } else {
+                    self.release
+                };
+                *current = target + coefficient * (*current - target);
+                db_to_linear(*current) * self.makeup
+            });
+        }
+        self.reduction = reduction;
+    }
+}
+
+/// Gain state of one limiter detector
+#[derive(Clone)]
+struct LimiterDetector {
+    /// Frame indexes and required gains, increasing in both, whose front is the lowest gain
+    /// required over the look-ahead window
+    window: VecDeque<(u64, f64)>,
+    /// The window minimum with the release applied
+    released: f64,
+    /// The last `lookahead + 1` released gains, averaged to ramp the gain down ahead of a peak
+    ramp: VecDeque<f64>,
+    ramp_sum: f64,
+}
+
+/// Look-ahead brickwall limiter. The audio is delayed by the look-ahead, which lets the gain
+/// ramp down before each peak instead of clipping it. Call `flush` after the last block to get
+/// the delayed frames out.
+pub struct Limiter {
+    num_channels: usize,
+    sample_rate: u32,
+    ceiling: f64,
+    lookahead: usize,
+    release: f64,
+    detectors: Vec<LimiterDetector>,
+    /// Gain of each detector for the current frame
+    gains: Vec<f64>,
+    /// The last `lookahead` input frames, interleaved
+    delay: VecDeque<f64>,
+    frame: u64,
+    /// Output samples a pipeline still drops, so the limiter doesn't move the audio there
+    skip: usize,
+}
+
+impl Limiter {
+    pub fn new(num_channels: u16, sample_rate: u32, settings: LimiterSettings) -> Self {
+        let lookahead = (settings.lookahead.max(0.0) * sample_rate as f64).round() as usize;
+        let detector = LimiterDetector {
+            window: VecDeque::with_capacity(lookahead + 1),
+            released: 1.0,
+            ramp: VecDeque::from(vec![1.0; lookahead + 1]),
+            ramp_sum: (lookahead + 1) as f64,
+        };
+        let num_detectors = num_detectors(num_channels, settings.link);
+        Limiter {
+            num_channels: num_channels as usize,
+            sample_rate,
+            ceiling: db_to_linear(settings.ceiling_db),
+            lookahead,
+            release: coefficient(settings.release, sample_rate),
+            detectors: vec![detector; num_detectors],
+            gains: vec![1.0; num_detectors],
+            delay: VecDeque::from(vec![0.0; lookahead * num_channels as usize]),
+            frame: 0,
+            skip: lookahead * num_channels as usize,
+        }
+    }
+
+    /// Frames of delay the limiter adds
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Limits interleaved frames in place. The output lags the input by `latency` frames.
+    pub fn process(&mut self, samples: &mut [f64]) {
+        let linked = self.detectors.len() == 1;
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            if linked {
+                let level = frame
+                    .iter()
+                    .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+                self.gains[0] = self.next_gain(0, level);
+            } else {
+                for (channel, sample) in frame.iter().enumerate() {
+                    self.gains[channel] = self.next_gain(channel, sample.abs());
+                }
+            }
+            self.frame += 1;
+
+            self.delay.extend(frame.iter());
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let gain = self.gains[if linked { 0 } else { channel }];
+                // The ramp average carries rounding error, which mustn't let a peak through
+                *sample =
+                    (self.delay.pop_front().unwrap() * gain).clamp(-self.ceiling, self.ceiling);
+            }
+        }
+    }
+
+    /// Feeds `latency` frames of silence through, replacing the contents of `out` with the
+    /// input frames still in the delay line
+    pub fn flush(&mut self, out: &mut Vec<f64>) {
+        out.clear();
+        out.resize(self.lookahead * self.num_channels, 0.0);
+        self.process(out);
+    }
+
+    /// Gain for the frame leaving the delay line, given the level of the frame entering it
+    fn next_gain(&mut self, detector: usize, level: f64) -> f64 {
+        let required = if level > self.ceiling {
+            self.ceiling / level
+        } else {
+            1.0
+        };
+        let (frame, lookahead, release) = (self.frame, self.lookahead as u64, self.release);
+        let detector = &mut self.detectors[detector];
+        while detector
+            .window
+            .back()
+            .is_some_and(|&(_, gain)| gain >= required)
+        {
+            detector.window.pop_back();
+        }
+        detector.window.push_back((frame, required));
+        while detector
+            .window
+            .front()
+            .is_some_and(|&(start, _)| start + lookahead < frame)
+        {
+            detector.window.pop_front();
+        }
+        let minimum = detector.window.front().map_or(1.0, |&(_, gain)| gain);
+
+        detector.released = if minimum < detector.released {
+            minimum
+        } else {
+            minimum + release * (detector.released - minimum)
+        };
+        detector.ramp.push_back(detector.released);
+        detector.ramp_sum += detector.released - detector.ramp.pop_front().unwrap_or(1.0);
+        detector.ramp_sum / detector.ramp.len() as f64
+    }
+}
+
+/// Gate state of one detector
+struct GateDetector {
+    gain: f64,
+    /// Frames the gate stays open for without the level reaching the threshold
+    hold: u64,
+}
+
+/// Noise gate that attenuates by `range_db` while the level stays below the threshold
+pub struct Gate {
+    num_channels: usize,
+    sample_rate: u32,
+    threshold: f64,
+    floor: f64,
+    attack: f64,
+    hold: u64,
+    release: f64,
+    detectors: Vec<GateDetector>,
+}
+
+impl Gate {
+    pub fn new(num_channels: u16, sample_rate: u32, settings: GateSettings) -> Self {
+        let num_detectors = num_detectors(num_channels, settings.link);
+        Gate {
+            num_channels: num_channels as usize,
+            sample_rate,
+            threshold: db_to_linear(settings.threshold_db),
+            floor: db_to_linear(settings.range_db.min(0.0)),
+            attack: coefficient(settings.attack, sample_rate),
+            hold: (settings.hold.max(0.0) * sample_rate as f64).round() as u64,
+            release: coefficient(settings.release, sample_rate),
+            detectors: (0..num_detectors)
+                .map(|_| GateDetector { gain: 1.0, hold: 0 })
+                .collect(),
+        }
+    }
+
+    /// Gates interleaved frames in place
+    pub fn process(&mut self, samples: &mut [f64]) {
+        let mut detectors = std::mem::take(&mut self.detectors);
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            apply_detectors(frame, &mut detectors, |detector, level| {
+                let target = if level >= self.threshold {
+                    detector.hold = self.hold;
+                    1.0
+                } else if detector.hold > 0 {
+                    detector.hold -= 1;
+                    1.0
+                } else {
+                    self.floor
+                };
+                let coefficient = if target > detector.gain {
+                    self.attack
+                } else {
+                    self.release
+                };
+                detector.gain = target + coefficient * (detector.gain - target);
+                detector.gain
+            });
+        }
+        self.detectors = detectors;
+    }
+}
+
+/// A dynamics processor and its settings, for running several over a file in order
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dynamics {
+    Compressor(CompressorSettings),
+    Limiter(LimiterSettings),
+    Gate(GateSettings),
+}
+
+impl Processor for Compressor {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        input.check("compressor", self.num_channels as u16, self.sample_rate)?;
+        Ok(StreamFormat {
+            exact: false,
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Compressor::process(self, samples);
+    }
+}
+
+/// In a pipeline the limiter's delay is compensated: the first `latency` frames out are
+/// dropped and made up for by the ones `flush` gets out, so the audio doesn't move
+impl Processor for Limiter {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        input.check("limiter", self.num_channels as u16, self.sample_rate)?;
+        Ok(StreamFormat {
+            exact: false,
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Limiter::process(self, samples);
+        let skipped = self.skip.min(samples.len());
+        samples.drain(..skipped);
+        self.skip -= skipped;
+    }
+
+    fn flush(&mut self, out: &mut Vec<f64>) {
+        Limiter::flush(self, out);
+        let skipped = self.skip.min(out.len());
+        out.drain(..skipped);
+        self.skip -= skipped;
+    }
+}
+
+impl Processor for Gate {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        input.check("gate", self.num_channels as u16, self.sample_rate)?;
+        Ok(StreamFormat {
+            exact: false,
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Gate::process(self, samples);
+    }
+}
+
+/// Runs `input` through `processors` in order, keeping the sample format and the length. The
+/// delay a limiter adds is compensated, so the audio doesn't move. Metadata is carried over
+/// less what `drop_stale_chunks` removes.
+pub fn dynamics_file(
+    input: &Path,
+    output: &Path,
+    processors: &[Dynamics],
+) -> Result<ConversionReport, DynamicsError> {
+    let (reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    drop_stale_chunks(&mut leading, &mut trailing);
+    let (num_channels, sample_rate) = (reader.num_channels, reader.sample_rate);
+
+    let mut sink = WavSink::new(output, None, leading, trailing);
+    let mut pipeline = Pipeline::new(reader);
+    for dynamics in processors {
+        match *dynamics {
+            Dynamics::Compressor(settings) => {
+                pipeline.push(Compressor::new(num_channels, sample_rate, settings))?
+            }
+            Dynamics::Limiter(settings) => {
+                pipeline.push(Limiter::new(num_channels, sample_rate, settings))?
+            }
+            Dynamics::Gate(settings) => {
+                pipeline.push(Gate::new(num_channels, sample_rate, settings))?
+            }
+        };
+    }
+    pipeline.sink(&mut sink)?;
+    pipeline.run()?;
+    Ok(sink.report())
+}
+
+/// Splits `key=value` pairs separated by `,` and hands each to `set`. An empty string sets
+/// nothing, so every setting keeps its default.
+fn parse_settings(
+    s: &str,
+    mut set: impl FnMut(&str, &str) -> Result<(), DynamicsError>,
+) -> Result<(), DynamicsError> {
+    for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| DynamicsError::UnknownSetting(pair.to_string()))?;
+        set(key.trim(), value.trim())?;
+    }
+    Ok(())
+}
+
+fn invalid(key: &str, value: &str) -> DynamicsError {
+    DynamicsError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn number(key: &str, value: &str) -> Result<f64, DynamicsError> {
+    value.parse().map_err(|_| invalid(key, value))
+}
+
+fn switch(key: &str, value: &str) -> Result<bool, DynamicsError> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(invalid(key, value)),
+    }
+}
+
+/// Parses settings such as `threshold=-20,ratio=4,attack=0.005,link=off`. Keys left out keep
+/// their defaults; levels are in dB and times in seconds.
+impl FromStr for CompressorSettings {
+    type Err = DynamicsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = CompressorSettings::default();
+        parse_settings(s, |key, value| {
+            match key {
+                "threshold" => settings.threshold_db = number(key, value)?,
+                "ratio" => settings.ratio = number(key, value)?,
+                "attack" => settings.attack = number(key, value)?,
+                "release" => settings.release = number(key, value)?,
+                "knee" => settings.knee_db = number(key, value)?,
+                "makeup" => settings.makeup_db = number(key, value)?,
+                "link" => settings.link = switch(key, value)?,
+                _ => return Err(DynamicsError::UnknownSetting(key.to_string())),
+            }
+            Ok(())
+        })?;
+        Ok(settings)
+    }
+}
+
+/// Parses settings such as `ceiling=-0.3,lookahead=0.002`, like `CompressorSettings`
+impl FromStr for LimiterSettings {
+    type Err = DynamicsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = LimiterSettings::default();
+        parse_settings(s, |key, value| {
+            match key {
+                "ceiling" => settings.ceiling_db = number(key, value)?,
+                "lookahead" => settings.lookahead = number(key, value)?,
+                "release" => settings.release = number(key, value)?,
+                "link" => settings.link = switch(key, value)?,
+                _ => return Err(DynamicsError::UnknownSetting(key.to_string())),
+            }
+            Ok(())
+        })?;
+        Ok(settings)
+    }
+}
+
+/// Parses settings such as `threshold=-45,range=-20,hold=0.1`, like `CompressorSettings`
+impl FromStr for GateSettings {
+    type Err = DynamicsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = GateSettings::default();
+        parse_settings(s, |key, value| {
+            match key {
+                "threshold" => settings.threshold_db = number(key, value)?,
+                "range" => settings.range_db = number(key, value)?,
+                "attack" => settings.attack = number(key, value)?,
+                "hold" => settings.hold = number(key, value)?,
+                "release" => settings.release = number(key, value)?,
+                "link" => settings.link = switch(key, value)?,
+                _ => return Err(DynamicsError::UnknownSetting(key.to_string())),
+            }
+            Ok(())
+        })?;
+        Ok(settings)
+    }
+}
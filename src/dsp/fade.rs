@@ -0,0 +1,200 @@
+use std::{
+    f64::consts::{FRAC_PI_2, PI},
+    path::Path,
+};
+
+use super::convert::{ConvertSettings, Requantizer, BLOCK_FRAMES};
+use crate::{
+    pipeline::{drop_stale_chunks, PipelineError, Processor, StreamFormat},
+    samples::{open_with_metadata, SampleError, SampleWriter},
+};
+
+/// Lowest level of `FadeCurve::Logarithmic`, where it drops the rest of the way to silence
+const LOG_FLOOR_DB: f64 = -60.0;
+
+/// Shape of a fade, as the gain it applies over the fade's length
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// Constant slope in amplitude. Crossfades of correlated material keep a constant level.
+    Linear,
+    /// Quarter sine. Crossfades of uncorrelated material keep a constant power.
+    EqualPower,
+    /// Constant slope in decibels down to -60 dB, which sounds even to the ear
+    Logarithmic,
+    /// Raised cosine, gentle at both ends
+    SCurve,
+}
+
+impl FadeCurve {
+    /// Gain of a fade-in `progress` of the way through, from 0 at the start to 1 at the end. A
+    /// fade-out is the same curve backwards.
+    pub fn gain(&self, progress: f64) -> f64 {
+        let x = progress.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => x,
+            FadeCurve::EqualPower => (x * FRAC_PI_2).sin(),
+            FadeCurve::Logarithmic => {
+                let floor = super::db_to_linear(LOG_FLOOR_DB);
+                (super::db_to_linear(LOG_FLOOR_DB * (1.0 - x)) - floor) / (1.0 - floor)
+            }
+            FadeCurve::SCurve => (1.0 - (x * PI).cos()) / 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FadeLength {
+    Frames(u64),
+    Seconds(f64),
+}
+
+impl FadeLength {
+    pub fn frames(&self, sample_rate: u32) -> u64 {
+        match *self {
+            FadeLength::Frames(frames) => frames,
+            FadeLength::Seconds(seconds) => (seconds.max(0.0) * sample_rate as f64).round() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fade {
+    pub curve: FadeCurve,
+    pub length: FadeLength,
+}
+
+/// Applies a fade-in at the start and a fade-out at the end of a stream of known length. The
+/// first frame of a fade-in and the last frame of a fade-out are silent. Fades longer than the
+/// stream are cut short, and where the two overlap their gains multiply.
+pub struct Fader {
+    num_channels: usize,
+    num_frames: u64,
+    fade_in: Option<(FadeCurve, u64)>,
+    fade_out: Option<(FadeCurve, u64)>,
+    /// Frame the next call to `process` starts at
+    position: u64,
+}
+
+impl Fader {
+    pub fn new(
+        num_channels: u16,
+        sample_rate: u32,
+        num_frames: u64,
+        fade_in: Option<Fade>,
+        fade_out: Option<Fade>,
+    ) -> Self {
+        let resolve = |fade: Option<Fade>| {
+            fade.map(|fade| (fade.curve, fade.length.frames(sample_rate)))
+                .filter(|&(_, length)| length > 0)
+        };
+        Fader {
+            num_channels: num_channels as usize,
+            num_frames,
+            fade_in: resolve(fade_in),
+            fade_out: resolve(fade_out),
+            position: 0,
+        }
+    }
+
+    /// Gain of frame `frame`
+    pub fn gain(&self, frame: u64) -> f64 {
+        let mut gain = 1.0;
+        if let Some((curve, length)) = self.fade_in {
+            if frame < length {
+                gain *= curve.gain(frame as f64 / length as f64);
+            }
+        }
+        if let Some((curve, length)) = self.fade_out {
+            let remaining = self.num_frames.saturating_sub(frame + 1);
+            if remaining < length {
+                gain *= curve.gain(remaining as f64 / length as f64);
+            }
+        }
+        gain
+    }
+
+    /// Fades the next block of interleaved frames in place
+    pub fn process(&mut self, samples: &mut [f64]) {
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            let gain = self.gain(self.position);
+            if gain != 1.0 {
+                for sample in frame {
+                    *sample *= gain;
+                }
+            }
+            self.position += 1;
+        }
+    }
+}
+
+impl Processor for Fader {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        if input.num_channels as usize != self.num_channels
+            || input.num_frames != Some(self.num_frames)
+        {
+            return Err(PipelineError::Incompatible {
+                stage: "fade".to_string(),
+                reason: format!(
+                    "it was set up for {} channels and {} frames, not {input}",
+                    self.num_channels, self.num_frames
+                ),
+            });
+        }
+        Ok(StreamFormat {
+            exact: input.exact && self.fade_in.is_none() && self.fade_out.is_none(),
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Fader::process(self, samples);
+    }
+}
+
+/// Writes `input` to `output` with the given fades applied, keeping the metadata less what
+/// `drop_stale_chunks` removes, and returns the number of frames written. Only the faded frames are requantized, so the rest of
+/// the audio stays bit exact.
+pub fn fade_file(
+    input: &Path,
+    output: &Path,
+    fade_in: Option<Fade>,
+    fade_out: Option<Fade>,
+) -> Result<u64, SampleError> {
+    let (mut reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    drop_stale_chunks(&mut leading, &mut trailing);
+    let fader = Fader::new(
+        reader.num_channels,
+        reader.sample_rate,
+        reader.num_frames,
+        fade_in,
+        fade_out,
+    );
+    let mut requantizer =
+        Requantizer::new(reader.num_channels, ConvertSettings::new(reader.format));
+    let mut writer = SampleWriter::create(
+        output,
+        reader.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        &leading,
+    )?;
+
+    let mut samples = Vec::new();
+    let mut position = 0;
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        for frame in samples.chunks_exact_mut(reader.num_channels as usize) {
+            let gain = fader.gain(position);
+            if gain != 1.0 {
+                for sample in frame.iter_mut() {
+                    *sample *= gain;
+                }
+                requantizer.process(frame);
+            }
+            position += 1;
+        }
+        writer.write_frames(&samples)?;
+    }
+    drop(reader);
+    writer.finish(&trailing)
+}
@@ -0,0 +1,351 @@
+use std::{
+    f64::consts::{FRAC_1_SQRT_2, PI},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use super::convert::{ConversionReport, ConvertSettings, Requantizer, BLOCK_FRAMES};
+use crate::{
+    pipeline::{drop_stale_chunks, PipelineError, Processor, StreamFormat},
+    samples::{open_with_metadata, SampleError, SampleWriter},
+    wav::WavError,
+};
+
+/// Second order IIR section in transposed direct form II, normalized so a0 is 1
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Biquad {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    pub fn process_sample(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.s1;
+        self.s1 = self.b1 * x - self.a1 * y + self.s2;
+        self.s2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+/// A filter response from the RBJ audio EQ cookbook. Frequencies are in Hz and must be below
+/// Nyquist; `q` sets the bandwidth, or for the shelves the steepness of the transition, where
+/// `FRAC_1_SQRT_2` is the steepest without overshoot.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FilterBand {
+    LowPass {
+        frequency: f64,
+        q: f64,
+    },
+    HighPass {
+        frequency: f64,
+        q: f64,
+    },
+    /// Unity gain at `frequency`
+    BandPass {
+        frequency: f64,
+        q: f64,
+    },
+    Notch {
+        frequency: f64,
+        q: f64,
+    },
+    Peaking {
+        frequency: f64,
+        q: f64,
+        gain_db: f64,
+    },
+    LowShelf {
+        frequency: f64,
+        q: f64,
+        gain_db: f64,
+    },
+    HighShelf {
+        frequency: f64,
+        q: f64,
+        gain_db: f64,
+    },
+}
+
+impl FilterBand {
+    /// Second order high pass at 80 Hz, which removes handling noise, wind and rumble
+    pub const RUMBLE: FilterBand = FilterBand::HighPass {
+        frequency: 80.0,
+        q: FRAC_1_SQRT_2,
+    };
+
+    /// Notches at the mains frequency, usually 50 or 60 Hz, and its harmonics up to
+    /// `harmonics` times it. Harmonics at or above Nyquist are left out.
+    pub fn dehum(mains_frequency: f64, harmonics: u32, q: f64, sample_rate: u32) -> Vec<Self> {
+        (1..=harmonics)
+            .map(|harmonic| mains_frequency * harmonic as f64)
+            .take_while(|&frequency| frequency < sample_rate as f64 / 2.0)
+            .map(|frequency| FilterBand::Notch { frequency, q })
+            .collect()
+    }
+
+    pub fn frequency(&self) -> f64 {
+        match *self {
+            FilterBand::LowPass { frequency, .. }
+            | FilterBand::HighPass { frequency, .. }
+            | FilterBand::BandPass { frequency, .. }
+            | FilterBand::Notch { frequency, .. }
+            | FilterBand::Peaking { frequency, .. }
+            | FilterBand::LowShelf { frequency, .. }
+            | FilterBand::HighShelf { frequency, .. } => frequency,
+        }
+    }
+
+    pub fn q(&self) -> f64 {
+        match *self {
+            FilterBand::LowPass { q, .. }
+            | FilterBand::HighPass { q, .. }
+            | FilterBand::BandPass { q, .. }
+            | FilterBand::Notch { q, .. }
+            | FilterBand::Peaking { q, .. }
+            | FilterBand::LowShelf { q, .. }
+            | FilterBand::HighShelf { q, .. } => q,
+        }
+    }
+
+    /// Coefficients of the band at `sample_rate`
+    pub fn design(&self, sample_rate: u32) -> Result<Biquad, FilterError> {
+        let frequency = self.frequency();
+        if !(frequency > 0.0 && frequency < sample_rate as f64 / 2.0) {
+            return Err(FilterError::Frequency {
+                frequency,
+                sample_rate,
+            });
+        }
+        let q = self.q();
+        if !(q > 0.0 && q.is_finite()) {
+            return Err(FilterError::Q(q));
+        }
+
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        // Numerator and denominator before normalizing by a0
+        let (b, a) = match *self {
+            FilterBand::LowPass { .. } => (
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterBand::HighPass { .. } => (
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterBand::BandPass { .. } => {
+                ([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+            }
+            FilterBand::Notch { .. } => (
+                [1.0, -2.0 * cos, 1.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterBand::Peaking { gain_db, .. } => {
+                let amplitude = 10f64.powf(gain_db / 40.0);
+                (
+                    [1.0 + alpha * amplitude, -2.0 * cos, 1.0 - alpha * amplitude],
+                    [1.0 + alpha / amplitude, -2.0 * cos, 1.0 - alpha / amplitude],
+                )
+            }
+            FilterBand::LowShelf { gain_db, .. } => {
+                let amplitude = 10f64.powf(gain_db / 40.0);
+                let root = 2.0 * amplitude.sqrt() * alpha;
+                let (plus, minus) = (amplitude + 1.0, amplitude - 1.0);
+                (
+                    [
+                        amplitude * (plus - minus * cos + root),
+                        2.0 * amplitude * (minus - plus * cos),
+                        amplitude * (plus - minus * cos - root),
+                    ],
+                    [
+                        plus + minus * cos + root,
+                        -2.0 * (minus + plus * cos),
+                        plus + minus * cos - root,
+                    ],
+                )
+            }
+            FilterBand::HighShelf { gain_db, .. } => {
+                let amplitude = 10f64.powf(gain_db / 40.0);
+                let root = 2.0 * amplitude.sqrt() * alpha;
+                let (plus, minus) = (amplitude + 1.0, amplitude - 1.0);
+                (
+                    [
+                        amplitude * (plus + minus * cos + root),
+                        -2.0 * amplitude * (minus + plus * cos),
+                        amplitude * (plus + minus * cos - root),
+                    ],
+                    [
+                        plus - minus * cos + root,
+                        2.0 * (minus - plus * cos),
+                        plus - minus * cos - root,
+                    ],
+                )
+            }
+        };
+        Ok(Biquad::new(
+            b[0] / a[0],
+            b[1] / a[0],
+            b[2] / a[0],
+            a[1] / a[0],
+            a[2] / a[0],
+        ))
+    }
+}
+
+impl Biquad {
+    /// Gain of the filter at `frequency` in dB
+    pub fn response_db(&self, frequency: f64, sample_rate: u32) -> f64 {
+        let w = 2.0 * PI * frequency / sample_rate as f64;
+        // H(z) at z = e^jw, with z^-1 = cos w - j sin w and z^-2 = cos 2w - j sin 2w
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2.0 * w).sin_cos();
+        let magnitude = |c0: f64, c1: f64, c2: f64| {
+            let re = c0 + c1 * cos1 + c2 * cos2;
+            let im = -c1 * sin1 - c2 * sin2;
+            re.hypot(im)
+        };
+        let numerator = magnitude(self.b0, self.b1, self.b2);
+        let denominator = magnitude(1.0, self.a1, self.a2);
+        super::linear_to_db(numerator / denominator)
+    }
+}
+
+/// A cascade of biquads run over every channel, each channel with its own filter state
+pub struct Equalizer {
+    num_channels: usize,
+    sample_rate: u32,
+    /// The designed bands, copied for each channel
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl Equalizer {
+    pub fn new(
+        num_channels: u16,
+        sample_rate: u32,
+        bands: &[FilterBand],
+    ) -> Result<Self, FilterError> {
+        let cascade = bands
+            .iter()
+            .map(|band| band.design(sample_rate))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Equalizer {
+            num_channels: num_channels as usize,
+            sample_rate,
+            filters: vec![cascade; num_channels as usize],
+        })
+    }
+
+    /// Gain of the whole cascade at `frequency` in dB
+    pub fn response_db(&self, frequency: f64, sample_rate: u32) -> f64 {
+        self.filters.first().map_or(0.0, |cascade| {
+            cascade
+                .iter()
+                .map(|biquad| biquad.response_db(frequency, sample_rate))
+                .sum()
+        })
+    }
+
+    /// Filters interleaved frames in place, carrying the filter state over to the next call
+    pub fn process(&mut self, samples: &mut [f64]) {
+        for frame in samples.chunks_exact_mut(self.num_channels) {
+            for (sample, cascade) in frame.iter_mut().zip(&mut self.filters) {
+                for biquad in cascade.iter_mut() {
+                    *sample = biquad.process_sample(*sample);
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for biquad in self.filters.iter_mut().flatten() {
+            biquad.reset();
+        }
+    }
+}
+
+impl Processor for Equalizer {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        input.check("equalizer", self.num_channels as u16, self.sample_rate)?;
+        Ok(StreamFormat {
+            exact: false,
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Equalizer::process(self, samples);
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("Filter frequency {frequency} Hz isn't between 0 and Nyquist at {sample_rate} Hz!")]
+    Frequency { frequency: f64, sample_rate: u32 },
+    #[error("Filter Q {0} must be positive!")]
+    Q(f64),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// Runs `input` through the bands in order, designed for its sample rate, keeping the sample
+/// format and the metadata, less what `drop_stale_chunks` removes.
+pub fn filter_file(
+    input: &Path,
+    output: &Path,
+    bands: &[FilterBand],
+) -> Result<ConversionReport, FilterError> {
+    let (mut reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    drop_stale_chunks(&mut leading, &mut trailing);
+    let mut equalizer = Equalizer::new(reader.num_channels, reader.sample_rate, bands)?;
+    let mut requantizer =
+        Requantizer::new(reader.num_channels, ConvertSettings::new(reader.format));
+    let mut writer = SampleWriter::create(
+        output,
+        reader.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        &leading,
+    )?;
+
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        equalizer.process(&mut samples);
+        requantizer.process(&mut samples);
+        writer.write_frames(&samples)?;
+    }
+    drop(reader);
+    let frames = writer.finish(&trailing)?;
+
+    Ok(ConversionReport {
+        frames,
+        clipped_samples: requantizer.clipped_samples,
+        peak: requantizer.peak,
+    })
+}
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::{
+    convert::{ConvertSettings, Dither, Requantizer, BLOCK_FRAMES},
+    db_to_linear, linear_to_db,
+};
+use crate::{
+    analysis::loudness,
+    chunks::{
+        bext::{BextChunk, LOUDNESS_NOT_SET},
+        ChunkError, TypedChunk,
+    },
+    pipeline::{drop_stale_chunks, PipelineError, Processor, StreamFormat},
+    samples::{open_with_metadata, SampleError, SampleReader, SampleWriter},
+    wav::{WavError, WavFile},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GainMode {
+    /// Apply a fixed gain in dB
+    Fixed { db: f64 },
+    /// Bring the highest sample to `target_dbfs`
+    Peak { target_dbfs: f64 },
+    /// Bring the integrated loudness to `target_lufs`, unless that would push the true peak
+    /// over `true_peak_ceiling` dBTP, in which case the gain stops at the ceiling
+    Loudness {
+        target_lufs: f64,
+        true_peak_ceiling: f64,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GainReport {
+    pub gain_db: f64,
+    /// The loudness target couldn't be reached without exceeding the true peak ceiling
+    pub limited_by_true_peak: bool,
+    pub frames: u64,
+    pub clipped_samples: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum GainError {
+    #[error("File is silent, there's nothing to normalize!")]
+    Silent,
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// Scales samples by a fixed gain
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gain {
+    pub linear: f64,
+}
+
+impl Gain {
+    pub fn from_db(db: f64) -> Self {
+        Gain {
+            linear: db_to_linear(db),
+        }
+    }
+
+    pub fn process(&self, samples: &mut [f64]) {
+        for sample in samples {
+            *sample *= self.linear;
+        }
+    }
+}
+
+impl Processor for Gain {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        Ok(StreamFormat {
+            exact: input.exact && self.linear == 1.0,
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        Gain::process(self, samples);
+    }
+}
+
+/// First pass: works out the gain `mode` calls for on `input`, and whether the true peak
+/// ceiling limited it
+pub fn measure_gain(input: &Path, mode: GainMode) -> Result<(f64, bool), GainError> {
+    match mode {
+        GainMode::Fixed { db } => Ok((db, false)),
+        GainMode::Peak { target_dbfs } => {
+            let mut wav_file = WavFile::open(input)?;
+            let mut reader = SampleReader::new(&mut wav_file)?;
+            let mut samples = Vec::new();
+            let mut peak = 0.0f64;
+            while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+                peak = samples
+                    .iter()
+                    .fold(peak, |peak, sample| peak.max(sample.abs()));
+            }
+            if peak == 0.0 {
+                return Err(GainError::Silent);
+            }
+            Ok((target_dbfs - linear_to_db(peak), false))
+        }
+        GainMode::Loudness {
+            target_lufs,
+            true_peak_ceiling,
+        } => {
+            let report = loudness::measure_file(input)?;
+            let integrated = report.integrated.ok_or(GainError::Silent)?;
+            let gain = target_lufs - integrated;
+            let headroom = true_peak_ceiling - report.true_peak;
+            if gain > headroom {
+                Ok((headroom, true))
+            } else {
+                Ok((gain, false))
+            }
+        }
+    }
+}
+
+/// Measures `input` as `mode` requires, then writes it to `output` with the gain applied. The
+/// metadata is kept less what `drop_stale_chunks` removes; a bext chunk gets a coding history
+/// line for the gain and has any version 2 loudness values shifted by it.
+pub fn apply_gain(input: &Path, output: &Path, mode: GainMode) -> Result<GainReport, GainError> {
+    let (gain_db, limited_by_true_peak) = measure_gain(input, mode)?;
+
+    let (mut reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    drop_stale_chunks(&mut leading, &mut trailing);
+    for chunk in leading.iter_mut().chain(trailing.iter_mut()) {
+        if chunk.chunk_header.chunk_id == BextChunk::ID {
+            let mut bext = BextChunk::from_chunk(chunk)?;
+            shift_loudness(&mut bext, gain_db);
+            bext.append_coding_history(
+                reader.format,
+                reader.sample_rate,
+                reader.num_channels,
+                &format!("rwav gain {gain_db:+.2} dB"),
+            );
+            *chunk = bext.to_chunk();
+        }
+    }
+
+    let gain = Gain::from_db(gain_db);
+    let mut settings = ConvertSettings::new(reader.format);
+    if gain.linear == 1.0 {
+        settings.dither = Dither::None;
+    }
+    let mut requantizer = Requantizer::new(reader.num_channels, settings);
+    let mut writer = SampleWriter::create(
+        output,
+        reader.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        &leading,
+    )?;
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        gain.process(&mut samples);
+        requantizer.process(&mut samples);
+        writer.write_frames(&samples)?;
+    }
+    drop(reader);
+    let frames = writer.finish(&trailing)?;
+
+    Ok(GainReport {
+        gain_db,
+        limited_by_true_peak,
+        frames,
+        clipped_samples: requantizer.clipped_samples,
+    })
+}
+
+/// A gain moves every loudness and peak level by the same amount, loudness range is unchanged
+fn shift_loudness(bext: &mut BextChunk, gain_db: f64) {
+    let shift = (gain_db * 100.0).round() as i32;
+    for value in [
+        &mut bext.loudness_value,
+        &mut bext.max_true_peak_level,
+        &mut bext.max_momentary_loudness,
+        &mut bext.max_short_term_loudness,
+    ] {
+        if *value != LOUDNESS_NOT_SET && bext.version >= 2 {
+            *value =
+                (*value as i32 + shift).clamp(i16::MIN as i32, LOUDNESS_NOT_SET as i32 - 1) as i16;
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::{f64::consts::TAU, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+use super::{convert::ConversionReport, db_to_linear, Rng};
+use crate::{
+    audio::SourceDescription,
+    pipeline::{Pipeline, PipelineError, Source, StreamFormat, WavSink},
+    samples::{SampleError, SampleFormat, WAVE_FORMAT_PCM},
+};
+
+/// Pole and input gain of each one-pole section of Paul Kellet's refined pink noise filter
+const PINK_POLES: [(f64, f64); 6] = [
+    (0.99886, 0.0555179),
+    (0.99332, 0.0750759),
+    (0.96900, 0.1538520),
+    (0.86650, 0.3104856),
+    (0.55000, 0.5329522),
+    (-0.7616, -0.0168980),
+];
+/// Gain of the filter's direct path and of its one sample delay
+const PINK_DIRECT: f64 = 0.5362;
+const PINK_DELAYED: f64 = 0.115926;
+
+/// Test signals. Tones and impulses are generated at a peak level, noise at an RMS level.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Sine {
+        frequency: f64,
+    },
+    /// Sines of equal amplitude whose sum peaks at most at the level
+    Multitone {
+        frequencies: Vec<f64>,
+    },
+    /// Exponential sine sweep, which spends the same time on every octave
+    Sweep {
+        start: f64,
+        end: f64,
+    },
+    /// Uniform white noise, independent on every channel
+    WhiteNoise,
+    /// Noise falling 3 dB per octave, independent on every channel
+    PinkNoise,
+    /// A single sample at the start, then silence
+    Impulse,
+    /// Digital silence
+    Silence,
+}
+
+#[derive(Error, Debug)]
+pub enum GeneratorError {
+    #[error("Frequency {frequency} Hz isn't between 0 and Nyquist at {sample_rate} Hz!")]
+    Frequency { frequency: f64, sample_rate: u32 },
+    #[error("Can't generate {num_channels} channels at {sample_rate} Hz, both must be above 0!")]
+    EmptyFormat { num_channels: u16, sample_rate: u32 },
+    #[error("Unknown signal {0:?}!")]
+    UnknownSignal(String),
+    #[error("Invalid {signal} parameters {parameters:?}!")]
+    InvalidParameters { signal: String, parameters: String },
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+}
+
+/// Parses `sine:1000`, `multitone:100,1000,10000`, `sweep:20-20000`, `white`, `pink`,
+/// `impulse` or `silence`. Frequencies are in Hz.
+impl FromStr for Signal {
+    type Err = GeneratorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameters) = s.split_once(':').unwrap_or((s, ""));
+        let invalid = || GeneratorError::InvalidParameters {
+            signal: name.to_string(),
+            parameters: parameters.to_string(),
+        };
+        let frequency = |frequency: &str| frequency.trim().parse::<f64>().map_err(|_| invalid());
+        let no_parameters = |signal: Signal| match parameters {
+            "" => Ok(signal),
+            _ => Err(invalid()),
+        };
+        match name {
+            "sine" => Ok(Signal::Sine {
+                frequency: frequency(parameters)?,
+            }),
+            "multitone" => Ok(Signal::Multitone {
+                frequencies: parameters
+                    .split(',')
+                    .map(frequency)
+                    .collect::<Result<_, _>>()?,
+            }),
+            "sweep" => {
+                let (start, end) = parameters.split_once('-').ok_or_else(invalid)?;
+                Ok(Signal::Sweep {
+                    start: frequency(start)?,
+                    end: frequency(end)?,
+                })
+            }
+            "white" => no_parameters(Signal::WhiteNoise),
+            "pink" => no_parameters(Signal::PinkNoise),
+            "impulse" => no_parameters(Signal::Impulse),
+            "silence" => no_parameters(Signal::Silence),
+            _ => Err(GeneratorError::UnknownSignal(name.to_string())),
+        }
+    }
+}
+
+/// Source of a test signal in a `SourceDescription`'s format, the same on every channel
+/// except for noise. Noise is seeded, so the same settings always give the same audio.
+pub struct Generator {
+    signal: Signal,
+    format: StreamFormat,
+    /// Peak level for tones and impulses, RMS level for noise
+    amplitude: f64,
+    /// Frame the next call to `read` starts at
+    position: u64,
+    rng: Rng,
+    /// Pink noise filter state per channel: the one-pole sections, then the delayed input
+    pink: Vec<[f64; 7]>,
+    /// Scales pink noise from unit RMS white noise to unit RMS
+    pink_gain: f64,
+}
+
+impl Generator {
+    /// A generator for `duration` seconds of `signal` at `level_db` dBFS, which is the peak of
+    /// a tone or impulse and the RMS of noise
+    pub fn new(
+        signal: Signal,
+        description: &SourceDescription,
+        duration: f64,
+        level_db: f64,
+    ) -> Result<Self, GeneratorError> {
+        let sample_format = SampleFormat::from_parts(WAVE_FORMAT_PCM, description.bits_per_sample)?;
+        let sample_rate = description.sample_rate;
+        if description.num_channels == 0 || sample_rate == 0 {
+            return Err(GeneratorError::EmptyFormat {
+                num_channels: description.num_channels,
+                sample_rate,
+            });
+        }
+        let frequencies = match &signal {
+            Signal::Sine { frequency } => vec![*frequency],
+            Signal::Multitone { frequencies } => frequencies.clone(),
+            Signal::Sweep { start, end } => vec![*start, *end],
+            _ => Vec::new(),
+        };
+        for frequency in frequencies {
+            if !(frequency > 0.0 && frequency < sample_rate as f64 / 2.0) {
+                return Err(GeneratorError::Frequency {
+                    frequency,
+                    sample_rate,
+                });
+            }
+        }
+
+        let mut amplitude = db_to_linear(level_db);
+        if signal == Signal::Impulse && !sample_format.is_float() {
+            // On the grid, so the impulse is written without dither smearing it
+            let full_scale = sample_format.full_scale();
+            amplitude = (amplitude * full_scale).round().min(full_scale - 1.0) / full_scale;
+        }
+        Ok(Generator {
+            format: StreamFormat {
+                num_channels: description.num_channels,
+                sample_rate,
+                channel_mask: None,
+                sample_format,
+                exact: matches!(signal, Signal::Impulse | Signal::Silence),
+                num_frames: Some((duration.max(0.0) * sample_rate as f64).round() as u64),
+            },
+            signal,
+            amplitude,
+            position: 0,
+            rng: Rng::new(1),
+            pink: vec![[0.0; 7]; description.num_channels as usize],
+            pink_gain: 1.0 / pink_filter_rms(),
+        })
+    }
+
+    /// Next sample of noise on `channel`, at unit RMS
+    fn noise(&mut self, channel: usize) -> f64 {
+        // Uniform noise has an RMS of 1 / sqrt(3) of its peak
+        let white = self.rng.next_bipolar() * 3f64.sqrt();
+        if self.signal == Signal::WhiteNoise {
+            return white;
+        }
+        let state = &mut self.pink[channel];
+        let mut pink = white * PINK_DIRECT + state[6];
+        for (section, (pole, gain)) in state.iter_mut().zip(PINK_POLES) {
+            *section = pole * *section + white * gain;
+            pink += *section;
+        }
+        state[6] = white * PINK_DELAYED;
+        pink * self.pink_gain
+    }
+
+    /// Sample of a tone or impulse at `frame`, the same on every channel
+    fn tone(&self, frame: u64) -> f64 {
+        let sample_rate = self.format.sample_rate as f64;
+        let time = frame as f64 / sample_rate;
+        match &self.signal {
+            Signal::Sine { frequency } => (TAU * frequency * time).sin(),
+            Signal::Multitone { frequencies } => {
+                frequencies
+                    .iter()
+                    .map(|frequency| (TAU * frequency * time).sin())
+                    .sum::<f64>()
+                    / frequencies.len() as f64
+            }
+            Signal::Sweep { start, end } => {
+                let duration = self.format.num_frames.unwrap_or(0) as f64 / sample_rate;
+                let rate = (end / start).ln() / duration;
+                if rate == 0.0 {
+                    return (TAU * start * time).sin();
+                }
+                (TAU * start * ((rate * time).exp() - 1.0) / rate).sin()
+            }
+            Signal::Impulse if frame == 0 => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+impl Source for Generator {
+    fn format(&self) -> StreamFormat {
+        StreamFormat {
+            num_frames: self.format.num_frames.map(|frames| frames - self.position),
+            ..self.format
+        }
+    }
+
+    fn read(&mut self, max_frames: usize, out: &mut Vec<f64>) -> Result<usize, PipelineError> {
+        let remaining = self.format.num_frames.unwrap_or(0) - self.position;
+        let frames = (max_frames as u64).min(remaining) as usize;
+        let num_channels = self.format.num_channels as usize;
+        out.clear();
+        for frame in self.position..self.position + frames as u64 {
+            if let Signal::WhiteNoise | Signal::PinkNoise = self.signal {
+                for channel in 0..num_channels {
+                    let sample = self.noise(channel);
+                    out.push(sample * self.amplitude);
+                }
+            } else {
+                let sample = self.tone(frame) * self.amplitude;
+                out.extend(std::iter::repeat_n(sample, num_channels));
+            }
+        }
+        self.position += frames as u64;
+        Ok(frames)
+    }
+}
+
+/// RMS of the pink noise filter's output for unit RMS white noise: the root of the summed
+/// squares of its impulse response
+fn pink_filter_rms() -> f64 {
+    // Every one-pole section reacts to an impulse with gain * pole^n. Summed over n, the
+    // products of two sections' responses form a geometric series.
+    let mut energy = 0.0;
+    for (pole_a, gain_a) in PINK_POLES {
+        for (pole_b, gain_b) in PINK_POLES {
+            energy += gain_a * gain_b / (1.0 - pole_a * pole_b);
+        }
+    }
+    // The direct and delayed paths add to the first two samples of the response
+    let first: f64 = PINK_POLES.iter().map(|(_, gain)| gain).sum();
+    let second: f64 = PINK_POLES.iter().map(|(pole, gain)| pole * gain).sum();
+    energy += (first + PINK_DIRECT).powi(2) - first.powi(2);
+    energy += (second + PINK_DELAYED).powi(2) - second.powi(2);
+    energy.sqrt()
+}
+
+/// Writes `duration` seconds of `signal` at `level_db` dBFS to a new wav file in
+/// `description`'s format, dithered unless the signal is digital silence or an impulse
+pub fn generate_file(
+    path: &Path,
+    signal: Signal,
+    description: &SourceDescription,
+    duration: f64,
+    level_db: f64,
+) -> Result<ConversionReport, GeneratorError> {
+    let generator = Generator::new(signal, description, duration, level_db)?;
+    let mut sink = WavSink::new(path, None, Vec::new(), Vec::new());
+    let mut pipeline = Pipeline::new(generator);
+    pipeline.sink(&mut sink)?;
+    pipeline.run()?;
+    Ok(sink.report())
+}
@@ -0,0 +1,256 @@
+use std::{path::Path, str::FromStr};
+
+use thiserror::Error;
+
+use super::convert::{ConversionReport, ConvertSettings, Dither, Requantizer, BLOCK_FRAMES};
+use crate::{
+    channels::{ChannelMask, Speaker},
+    chunks::{adm::ChnaChunk, levl::LevlChunk, TypedChunk},
+    pipeline::{PipelineError, Processor, StreamFormat},
+    samples::{open_with_metadata, SampleError, SampleWriter},
+    wav::WavError,
+};
+
+/// -3 dB, the ITU-R BS.775 coefficient for center and surround channels
+const MINUS_3_DB: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MixPreset {
+    /// ITU-R BS.775 downmix of any layout to stereo, LFE is dropped
+    Stereo,
+    /// The stereo downmix summed to mono at -3 dB
+    Mono,
+    /// Mono copied to both channels at unity gain
+    MonoToStereo,
+}
+
+/// Gains from every input channel to every output channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixMatrix {
+    /// One row per output channel, one gain per input channel
+    pub gains: Vec<Vec<f64>>,
+    /// Speaker positions of the outputs
+    pub output_mask: Option<ChannelMask>,
+}
+
+impl MixMatrix {
+    /// Checks the matrix is rectangular and `output_mask` has a speaker for every output. The
+    /// mask defaults to the standard layout for the number of outputs.
+    pub fn new(gains: Vec<Vec<f64>>, output_mask: Option<ChannelMask>) -> Result<Self, MixError> {
+        let num_inputs = gains.first().map(Vec::len).unwrap_or(0);
+        if num_inputs == 0 || gains.iter().any(|row| row.len() != num_inputs) {
+            return Err(MixError::InvalidMatrix(
+                "every row needs the same, non-zero number of gains".to_string(),
+            ));
+        }
+        let output_mask = output_mask.or(ChannelMask::default_for(gains.len() as u16));
+        if let Some(mask) = output_mask {
+            if mask.num_speakers() as usize != gains.len() {
+                return Err(MixError::InvalidMatrix(format!(
+                    "channel mask {mask} doesn't have {} speakers",
+                    gains.len()
+                )));
+            }
+        }
+        Ok(MixMatrix { gains, output_mask })
+    }
+
+    /// Builds a preset for an input layout. Files without a channel mask are assumed to use
+    /// the standard layout for their channel count.
+    pub fn preset(
+        preset: MixPreset,
+        num_channels: u16,
+        input_mask: Option<ChannelMask>,
+    ) -> Result<Self, MixError> {
+        let speakers = match input_mask.or(ChannelMask::default_for(num_channels)) {
+            Some(mask) if mask.num_speakers() == num_channels as u32 => mask.speakers(),
+            _ => return Err(MixError::UnknownLayout(num_channels)),
+        };
+        match preset {
+            MixPreset::Stereo => {
+                let (left, right) = speakers.iter().map(stereo_gains).unzip();
+                MixMatrix::new(vec![left, right], Some(ChannelMask::STEREO))
+            }
+            MixPreset::Mono => {
+                let mono = speakers
+                    .iter()
+                    .map(|speaker| {
+                        let (left, right) = stereo_gains(speaker);
+                        (left + right) * MINUS_3_DB
+                    })
+                    .collect();
+                MixMatrix::new(vec![mono], Some(ChannelMask::MONO))
+            }
+            MixPreset::MonoToStereo if num_channels == 1 => {
+                MixMatrix::new(vec![vec![1.0], vec![1.0]], Some(ChannelMask::STEREO))
+            }
+            MixPreset::MonoToStereo => Err(MixError::ChannelCount {
+                matrix: 1,
+                file: num_channels,
+            }),
+        }
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.gains[0].len()
+    }
+
+    pub fn num_outputs(&self) -> usize {
+        self.gains.len()
+    }
+
+    /// True if every output is a copy of at most one input, so no new sample values are made
+    pub fn is_routing(&self) -> bool {
+        self.gains.iter().all(|row| {
+            row.iter().all(|&gain| gain == 0.0 || gain == 1.0)
+                && row.iter().filter(|&&gain| gain != 0.0).count() <= 1
+        })
+    }
+
+    /// Mixes interleaved `input` frames, replacing the contents of `output`
+    pub fn process(&self, input: &[f64], output: &mut Vec<f64>) {
+        output.clear();
+        for frame in input.chunks_exact(self.num_inputs()) {
+            output.extend(self.gains.iter().map(|row| {
+                row.iter()
+                    .zip(frame)
+                    .map(|(gain, sample)| gain * sample)
+                    .sum::<f64>()
+            }));
+        }
+    }
+}
+
+/// Parses rows separated by `;` of gains separated by `,`, e.g. `1,0,0.707;0,1,0.707`
+impl FromStr for MixMatrix {
+    type Err = MixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let gains = s
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|gain| gain.trim().parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| MixError::InvalidMatrix(err.to_string()))?;
+        MixMatrix::new(gains, None)
+    }
+}
+
+impl Processor for MixMatrix {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        if input.num_channels as usize != self.num_inputs() {
+            return Err(PipelineError::Incompatible {
+                stage: "mix".to_string(),
+                reason: format!(
+                    "it was set up for {} channels, not {input}",
+                    self.num_inputs()
+                ),
+            });
+        }
+        Ok(StreamFormat {
+            num_channels: self.num_outputs() as u16,
+            channel_mask: self.output_mask.map(|mask| mask.0),
+            exact: input.exact && self.is_routing(),
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        let input = std::mem::take(samples);
+        MixMatrix::process(self, &input, samples);
+    }
+}
+
+/// Contribution of a speaker to the left and right channels of an ITU-R BS.775 downmix
+fn stereo_gains(speaker: &Speaker) -> (f64, f64) {
+    match speaker {
+        Speaker::FrontLeft | Speaker::FrontLeftOfCenter => (1.0, 0.0),
+        Speaker::FrontRight | Speaker::FrontRightOfCenter => (0.0, 1.0),
+        Speaker::FrontCenter | Speaker::TopCenter | Speaker::TopFrontCenter => {
+            (MINUS_3_DB, MINUS_3_DB)
+        }
+        Speaker::BackCenter | Speaker::TopBackCenter => (0.5, 0.5),
+        Speaker::BackLeft | Speaker::SideLeft | Speaker::TopFrontLeft | Speaker::TopBackLeft => {
+            (MINUS_3_DB, 0.0)
+        }
+        Speaker::BackRight
+        | Speaker::SideRight
+        | Speaker::TopFrontRight
+        | Speaker::TopBackRight => (0.0, MINUS_3_DB),
+        Speaker::LowFrequency => (0.0, 0.0),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MixError {
+    #[error("Mix matrix has {matrix} inputs but the file has {file} channels!")]
+    ChannelCount { matrix: usize, file: u16 },
+    #[error("No channel mask and no standard layout for {0} channels!")]
+    UnknownLayout(u16),
+    #[error("Invalid mix matrix! {0}")]
+    InvalidMatrix(String),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// Remixes `input` through `matrix`, keeping the sample format. The output is written as
+/// WAVE_FORMAT_EXTENSIBLE with the matrix's channel mask if it has more than two channels or
+/// the input was extensible. Chunks describing individual channels (levl, chna) no longer
+/// apply and are dropped, everything else is carried over.
+pub fn mix_file(
+    input: &Path,
+    output: &Path,
+    matrix: &MixMatrix,
+) -> Result<ConversionReport, MixError> {
+    let (mut reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    for chunks in [&mut leading, &mut trailing] {
+        chunks.retain(|chunk| {
+            let chunk_id = chunk.chunk_header.chunk_id;
+            chunk_id != LevlChunk::ID && chunk_id != ChnaChunk::ID
+        });
+    }
+    if reader.num_channels as usize != matrix.num_inputs() {
+        return Err(MixError::ChannelCount {
+            matrix: matrix.num_inputs(),
+            file: reader.num_channels,
+        });
+    }
+
+    let num_outputs = matrix.num_outputs() as u16;
+    let channel_mask = (num_outputs > 2 || reader.channel_mask.is_some())
+        .then(|| matrix.output_mask.map(|mask| mask.0).unwrap_or(0));
+    let mut settings = ConvertSettings::new(reader.format);
+    if matrix.is_routing() {
+        settings.dither = Dither::None;
+    }
+    let mut requantizer = Requantizer::new(num_outputs, settings);
+    let mut writer = SampleWriter::create(
+        output,
+        reader.format,
+        num_outputs,
+        reader.sample_rate,
+        channel_mask,
+        &leading,
+    )?;
+
+    let mut samples = Vec::new();
+    let mut mixed = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        matrix.process(&samples, &mut mixed);
+        requantizer.process(&mut mixed);
+        writer.write_frames(&mixed)?;
+    }
+    drop(reader);
+    let frames = writer.finish(&trailing)?;
+
+    Ok(ConversionReport {
+        frames,
+        clipped_samples: requantizer.clipped_samples,
+        peak: requantizer.peak,
+    })
+}
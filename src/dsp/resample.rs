@@ -0,0 +1,309 @@
+use std::path::Path;
+
+use thiserror::Error;
+
+use super::convert::ConversionReport;
+use crate::{
+    chunks::ChunkError,
+    edit::{shift_markers, FrameMap},
+    pipeline::{Pipeline, PipelineError, Processor, StreamFormat, WavSink},
+    samples::{open_with_metadata, SampleError},
+};
+
+/// Trade-off between speed and stopband rejection. Every preset is linear phase.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Around 60 dB of alias rejection, for previews and playback on slow machines
+    Fast,
+    /// Around 90 dB, transparent for 16 bit material
+    Balanced,
+    /// Around 120 dB with a steeper transition band, for mastering and 24 bit delivery
+    Best,
+}
+
+impl ResampleQuality {
+    /// Zero crossings of the sinc on each side, the fraction of the lower Nyquist frequency
+    /// that is passed, the Kaiser window beta, and the kernel table resolution per sample
+    fn parameters(&self) -> (usize, f64, f64, usize) {
+        match self {
+            ResampleQuality::Fast => (8, 0.90, 6.0, 256),
+            ResampleQuality::Balanced => (24, 0.94, 9.0, 1024),
+            ResampleQuality::Best => (64, 0.97, 12.0, 4096),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ResampleError {
+    #[error("Can't resample from {from_rate} Hz to {to_rate} Hz, rates must be above 0!")]
+    ZeroRate { from_rate: u32, to_rate: u32 },
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// Streaming windowed-sinc sample rate converter. Input is fed in blocks of any size and only
+/// the kernel's width of input is kept between calls, so memory use doesn't grow with the
+/// length of the file. Output sample `n` is centered on input time `n * from / to`, so the
+/// converter adds no delay.
+pub struct Resampler {
+    pub num_channels: usize,
+    pub from_rate: u32,
+    pub to_rate: u32,
+    /// Reduced ratio, one output sample advances `step / phases` input samples
+    phases: u64,
+    step: u64,
+    /// Kernel sampled `resolution` times per input sample from 0 to `taps`
+    table: Vec<f64>,
+    resolution: usize,
+    /// Input samples used on each side of an output sample
+    taps: usize,
+    /// Interleaved input frames still needed, starting at input frame `buffer_start`
+    buffer: Vec<f64>,
+    buffer_start: i64,
+    input_frames: u64,
+    output_frames: u64,
+    weights: Vec<f64>,
+}
+
+impl Resampler {
+    pub fn new(
+        num_channels: u16,
+        from_rate: u32,
+        to_rate: u32,
+        quality: ResampleQuality,
+    ) -> Result<Self, ResampleError> {
+        // A zero rate would make the kernel infinitely wide
+        if from_rate == 0 || to_rate == 0 {
+            return Err(ResampleError::ZeroRate { from_rate, to_rate });
+        }
+        let divisor = gcd(from_rate as u64, to_rate as u64);
+        let phases = to_rate as u64 / divisor;
+        let step = from_rate as u64 / divisor;
+        let (zero_crossings, rolloff, beta, resolution) = quality.parameters();
+
+        // When downsampling the cutoff drops to the output's Nyquist frequency and the kernel
+        // widens in proportion
+        let cutoff = rolloff * (phases as f64 / step as f64).min(1.0);
+        let half_width = zero_crossings as f64 / cutoff;
+        let taps = half_width.ceil() as usize;
+        let table = (0..=taps * resolution + 1)
+            .map(|index| {
+                let x = index as f64 / resolution as f64;
+                if x > half_width {
+                    return 0.0;
+                }
+                cutoff * sinc(cutoff * x) * kaiser(x / half_width, beta)
+            })
+            .collect();
+
+        let num_channels = num_channels as usize;
+        Ok(Resampler {
+            num_channels,
+            from_rate,
+            to_rate,
+            phases,
+            step,
+            table,
+            resolution,
+            taps,
+            // Silence before the first sample, so the first outputs can be centered on it
+            buffer: vec![0.0; taps * num_channels],
+            buffer_start: -(taps as i64),
+            input_frames: 0,
+            output_frames: 0,
+            weights: vec![0.0; taps * 2],
+        })
+    }
+
+    fn is_passthrough(&self) -> bool {
+        self.phases == self.step
+    }
+
+    /// Number of output frames produced for `input_frames` frames once flushed
+    pub fn output_len(&self, input_frames: u64) -> u64 {
+        (input_frames as u128 * self.phases as u128).div_ceil(self.step as u128) as u64
+    }
+
+    /// Converts interleaved `input`, replacing the contents of `output` with every output frame
+    /// that can be computed so far
+    pub fn process(&mut self, input: &[f64], output: &mut Vec<f64>) {
+        output.clear();
+        self.input_frames += (input.len() / self.num_channels) as u64;
+        if self.is_passthrough() {
+            output.extend_from_slice(input);
+            self.output_frames = self.input_frames;
+            return;
+        }
+        self.buffer.extend_from_slice(input);
+        self.render(u64::MAX, output);
+    }
+
+    /// Feeds silence past the end of the input to compute the remaining output frames, which
+    /// replace the contents of `output`. The resampler can be reused for a new stream after.
+    pub fn flush(&mut self, output: &mut Vec<f64>) {
+        output.clear();
+        if !self.is_passthrough() {
+            let total = self.output_len(self.input_frames);
+            self.buffer
+                .resize(self.buffer.len() + (self.taps + 1) * self.num_channels, 0.0);
+            self.render(total, output);
+        }
+        self.buffer.clear();
+        self.buffer.resize(self.taps * self.num_channels, 0.0);
+        self.buffer_start = -(self.taps as i64);
+        self.input_frames = 0;
+        self.output_frames = 0;
+    }
+
+    fn render(&mut self, max_output_frames: u64, output: &mut Vec<f64>) {
+        let channels = self.num_channels;
+        let taps = self.taps as i64;
+        let buffer_end = self.buffer_start + (self.buffer.len() / channels) as i64;
+        while self.output_frames < max_output_frames {
+            let position = self.output_frames as u128 * self.step as u128;
+            let center = (position / self.phases as u128) as i64;
+            let fraction = (position % self.phases as u128) as f64 / self.phases as f64;
+            if center + taps >= buffer_end {
+                break;
+            }
+
+            let first = center - taps + 1;
+            for (k, weight) in self.weights.iter_mut().enumerate() {
+                let distance = (first + k as i64 - center) as f64 - fraction;
+                *weight = kernel(&self.table, self.resolution, distance.abs());
+            }
+            let offset = (first - self.buffer_start) as usize * channels;
+            for channel in 0..channels {
+                let sum: f64 = self
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| weight * self.buffer[offset + k * channels + channel])
+                    .sum();
+                output.push(sum);
+            }
+            self.output_frames += 1;
+        }
+
+        // Keep only what the next output frame reaches back to
+        let position = self.output_frames as u128 * self.step as u128;
+        let next_first = (position / self.phases as u128) as i64 - taps + 1;
+        let consumed = (next_first - self.buffer_start).clamp(0, buffer_end - self.buffer_start);
+        self.buffer.drain(..consumed as usize * channels);
+        self.buffer_start += consumed;
+    }
+}
+
+/// Linearly interpolated kernel value at a distance in input samples
+fn kernel(table: &[f64], resolution: usize, distance: f64) -> f64 {
+    let index = distance * resolution as f64;
+    let whole = index as usize;
+    if whole + 1 >= table.len() {
+        return 0.0;
+    }
+    let fraction = index - whole as f64;
+    table[whole] + (table[whole + 1] - table[whole]) * fraction
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Kaiser window at `x` in [-1, 1]
+pub(crate) fn kaiser(x: f64, beta: f64) -> f64 {
+    bessel_i0(beta * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(beta)
+}
+
+/// Zeroth order modified Bessel function of the first kind, by its power series
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..64 {
+        term *= half / k as f64;
+        sum += term * term;
+        if term * term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
+}
+
+impl Processor for Resampler {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        input.check("resampler", self.num_channels as u16, self.from_rate)?;
+        Ok(StreamFormat {
+            sample_rate: self.to_rate,
+            exact: input.exact && self.is_passthrough(),
+            num_frames: input.num_frames.map(|frames| self.output_len(frames)),
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        let input = std::mem::take(samples);
+        Resampler::process(self, &input, samples);
+    }
+
+    fn flush(&mut self, out: &mut Vec<f64>) {
+        Resampler::flush(self, out);
+    }
+}
+
+/// Resamples a whole interleaved buffer, e.g. a data chunk loaded for playback
+pub fn resample_samples(
+    samples: &[f64],
+    num_channels: u16,
+    from_rate: u32,
+    to_rate: u32,
+    quality: ResampleQuality,
+) -> Result<Vec<f64>, ResampleError> {
+    let mut resampler = Resampler::new(num_channels, from_rate, to_rate, quality)?;
+    let mut output = Vec::new();
+    let mut tail = Vec::new();
+    resampler.process(samples, &mut output);
+    resampler.flush(&mut tail);
+    output.extend_from_slice(&tail);
+    Ok(output)
+}
+
+/// Converts `input` to `to_rate`, keeping its sample format and other chunks, with markers
+/// moved to the same time at the new rate by `shift_markers`. Integer formats are requantized
+/// with TPDF dither and samples pushed over full scale by the filter's ripple are clipped and
+/// counted.
+pub fn resample_file(
+    input: &Path,
+    output: &Path,
+    to_rate: u32,
+    quality: ResampleQuality,
+) -> Result<ConversionReport, ResampleError> {
+    let (reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    let resampler = Resampler::new(reader.num_channels, reader.sample_rate, to_rate, quality)?;
+    let map = FrameMap::Scale {
+        from_rate: reader.sample_rate,
+        to_rate,
+    };
+    shift_markers(&mut leading, &mut trailing, map)?;
+    let mut sink = WavSink::new(output, None, leading, trailing);
+    let mut pipeline = Pipeline::new(reader);
+    pipeline.push(resampler)?.sink(&mut sink)?;
+    pipeline.run()?;
+    Ok(sink.report())
+}
@@ -0,0 +1,540 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::{
+    chunks::{
+        adtl::AdtlChunk,
+        bext::BextChunk,
+        cart::CartChunk,
+        cue::CueChunk,
+        smpl::{SampleLoop, SmplChunk},
+        ChunkError, TypedChunk,
+    },
+    dsp::{
+        convert::{ConvertSettings, Requantizer, BLOCK_FRAMES},
+        fade::{Fade, FadeCurve},
+    },
+    pipeline::drop_stale_chunks,
+    samples::{SampleError, SampleReader, SampleWriter},
+    wav::{Chunk, WavError, WavFile},
+};
+
+#[derive(Error, Debug)]
+pub enum EditError {
+    #[error("Frames {start}..{end} aren't within the file's {num_frames} frames!")]
+    OutOfRange {
+        start: u64,
+        end: u64,
+        num_frames: u64,
+    },
+    #[error("Nothing to concatenate!")]
+    NoInputs,
+    #[error("File has no cue markers to split at!")]
+    NoMarkers,
+    #[error("{path:?} is too short for a {frames} frame crossfade!")]
+    TooShortForCrossfade { path: PathBuf, frames: u64 },
+    #[error("{path:?} doesn't have the same format, channels and sample rate as the first file!")]
+    FormatMismatch { path: PathBuf },
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// Where the frames of a file end up after an edit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameMap {
+    /// Only frames `start..end` are kept
+    Keep { start: u64, end: u64 },
+    /// Frames `start..end` are removed and the rest close up
+    Remove { start: u64, end: u64 },
+    /// Every frame moves later by this many frames
+    Offset(u64),
+    /// The audio is resampled, so frame positions scale from one rate to the other
+    Scale { from_rate: u32, to_rate: u32 },
+}
+
+impl FrameMap {
+    /// New position of `frame`, `None` if the edit removes it
+    pub fn map(&self, frame: u64) -> Option<u64> {
+        match *self {
+            FrameMap::Keep { start, end } => (start..end).contains(&frame).then(|| frame - start),
+            FrameMap::Remove { start, .. } if frame < start => Some(frame),
+            FrameMap::Remove { start, end } => (frame >= end).then(|| frame - (end - start)),
+            FrameMap::Offset(offset) => Some(frame + offset),
+            FrameMap::Scale { .. } => Some(self.scale(frame)),
+        }
+    }
+
+    /// New position of the span `start..end`, shortened to the frames that survive. `None` if
+    /// none do.
+    pub fn map_span(&self, span_start: u64, span_end: u64) -> Option<(u64, u64)> {
+        let (new_start, new_end) = match *self {
+            FrameMap::Keep { start, end } => (
+                span_start.clamp(start, end) - start,
+                span_end.clamp(start, end) - start,
+            ),
+            FrameMap::Remove { start, end } => {
+                let close_up = |frame: u64| {
+                    if frame <= start {
+                        frame
+                    } else {
+                        frame.max(end) - (end - start)
+                    }
+                };
+                (close_up(span_start), close_up(span_end))
+            }
+            FrameMap::Offset(offset) => (span_start + offset, span_end + offset),
+            FrameMap::Scale { .. } => (self.scale(span_start), self.scale(span_end)),
+        };
+        (new_start < new_end).then_some((new_start, new_end))
+    }
+
+    /// Frames removed ahead of the first frame of the output
+    pub fn leading_frames(&self) -> u64 {
+        match *self {
+            FrameMap::Keep { start, .. } => start,
+            FrameMap::Remove { start: 0, end } => end,
+            _ => 0,
+        }
+    }
+
+    /// New bext time reference, a count of frames since midnight at the file's rate
+    pub fn map_time_reference(&self, time_reference: u64) -> u64 {
+        match *self {
+            FrameMap::Scale { .. } => self.scale(time_reference),
+            _ => time_reference + self.leading_frames(),
+        }
+    }
+
+    /// `frames` at the rate a `Scale` map converts to, rounded to the nearest frame
+    fn scale(&self, frames: u64) -> u64 {
+        match *self {
+            FrameMap::Scale { from_rate, to_rate } => {
+                let (from_rate, to_rate) = (from_rate as u128, to_rate as u128);
+                ((frames as u128 * to_rate + from_rate / 2) / from_rate) as u64
+            }
+            _ => frames,
+        }
+    }
+}
+
+/// Moves the markers in the leading and trailing metadata chunks of a file to where `map` puts their frames:
+/// - cue points, with their labels, notes and region lengths in the adtl list
+/// - smpl loops, which are dropped unless they survive whole, and the smpl sample period
+/// - cart timers, which are cleared when their frame is removed
+/// - the bext time reference, which follows the first frame of the output
+///
+/// Markers on removed frames are dropped and regions are shortened to what's left of them.
+/// Chunks that summarize the audio go through `drop_stale_chunks`.
+pub fn shift_markers(
+    leading: &mut Vec<Chunk>,
+    trailing: &mut Vec<Chunk>,
+    map: FrameMap,
+) -> Result<(), ChunkError> {
+    let mut adtl = match leading
+        .iter()
+        .chain(trailing.iter())
+        .find(|chunk| AdtlChunk::is_adtl(chunk))
+    {
+        Some(chunk) => Some(AdtlChunk::from_chunk(chunk)?),
+        None => None,
+    };
+    drop_stale_chunks(leading, trailing);
+
+    for chunk in leading.iter_mut().chain(trailing.iter_mut()) {
+        let chunk_id = chunk.chunk_header.chunk_id;
+        if chunk_id == CueChunk::ID {
+            let mut cue = CueChunk::from_chunk(chunk)?;
+            cue.points.retain_mut(|point| {
+                let start = point.sample_offset as u64;
+                let region = adtl
+                    .as_mut()
+                    .and_then(|adtl| adtl.regions.iter_mut().find(|r| r.cue_id == point.id));
+                let new_start = match region {
+                    Some(region) if region.sample_length > 0 => {
+                        let end = start + region.sample_length as u64;
+                        map.map_span(start, end).map(|(new_start, new_end)| {
+                            region.sample_length = (new_end - new_start) as u32;
+                            new_start
+                        })
+                    }
+                    _ => map.map(start),
+                };
+                match new_start.and_then(|frame| u32::try_from(frame).ok()) {
+                    Some(frame) => {
+                        point.sample_offset = frame;
+                        point.position = frame;
+                        true
+                    }
+                    None => false,
+                }
+            });
+            if let Some(adtl) = adtl.as_mut() {
+                adtl.retain_cues(|id| cue.point(id).is_some());
+            }
+            *chunk = cue.to_chunk();
+        } else if chunk_id == SmplChunk::ID {
+            let mut smpl = SmplChunk::from_chunk(chunk)?;
+            smpl.loops.retain_mut(|sample_loop| {
+                match (
+                    map.map(sample_loop.start as u64),
+                    map.map(sample_loop.end as u64),
+                ) {
+                    // Resampling scales a loop rather than cutting into it
+                    (Some(start), Some(end))
+                        if end - start == (sample_loop.end - sample_loop.start) as u64
+                            || matches!(map, FrameMap::Scale { .. }) =>
+                    {
+                        sample_loop.start = start as u32;
+                        sample_loop.end = end as u32;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+            if let FrameMap::Scale { to_rate, .. } = map {
+                smpl.header.sample_period = (1_000_000_000 / to_rate as u64) as u32;
+            }
+            *chunk = smpl.to_chunk();
+        } else if chunk_id == CartChunk::ID {
+            let mut cart = CartChunk::from_chunk(chunk)?;
+            for timer in cart.post_timers.iter_mut().filter(|timer| timer.is_used()) {
+                match map.map(timer.value as u64) {
+                    Some(frame) => timer.value = frame as u32,
+                    None => *timer = Default::default(),
+                }
+            }
+            *chunk = cart.to_chunk();
+        } else if chunk_id == BextChunk::ID {
+            let mut bext = BextChunk::from_chunk(chunk)?;
+            bext.time_reference = map.map_time_reference(bext.time_reference);
+            *chunk = bext.to_chunk();
+        }
+    }
+    // Written last, since the cue chunk can come either side of it
+    if let Some(adtl) = adtl {
+        if let Some(chunk) = leading
+            .iter_mut()
+            .chain(trailing.iter_mut())
+            .find(|chunk| AdtlChunk::is_adtl(chunk))
+        {
+            *chunk = adtl.to_chunk();
+        }
+    }
+    Ok(())
+}
+
+/// Writes frames `start..end` of `input` to `output`
+pub fn trim(input: &Path, output: &Path, start: u64, end: u64) -> Result<u64, EditError> {
+    edit(input, output, start, end, FrameMap::Keep { start, end })
+}
+
+/// Writes `input` to `output` without frames `start..end`
+pub fn cut(input: &Path, output: &Path, start: u64, end: u64) -> Result<u64, EditError> {
+    edit(input, output, start, end, FrameMap::Remove { start, end })
+}
+
+/// Copies the frames `map` keeps byte for byte, so the audio is untouched and every edit
+/// point falls on a `block_align` boundary, then shifts the markers to match
+fn edit(
+    input: &Path,
+    output: &Path,
+    start: u64,
+    end: u64,
+    map: FrameMap,
+) -> Result<u64, EditError> {
+    let mut wav_file = WavFile::open(input)?;
+    let (mut leading, mut trailing) = wav_file.metadata_chunks()?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    if start > end || end > reader.num_frames {
+        return Err(EditError::OutOfRange {
+            start,
+            end,
+            num_frames: reader.num_frames,
+        });
+    }
+    shift_markers(&mut leading, &mut trailing, map)?;
+
+    let mut writer = SampleWriter::create(
+        output,
+        reader.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        &leading,
+    )?;
+    let ranges = match map {
+        FrameMap::Keep { .. } => vec![(start, end)],
+        _ => vec![(0, start), (end, reader.num_frames)],
+    };
+    for (range_start, range_end) in ranges {
+        copy_frames(&mut reader, &mut writer, range_start, range_end)?;
+    }
+    Ok(writer.finish(&trailing)?)
+}
+
+/// Copies frames `start..end` of `reader` to `writer` without decoding them
+pub(crate) fn copy_frames(
+    reader: &mut SampleReader,
+    writer: &mut SampleWriter,
+    start: u64,
+    end: u64,
+) -> Result<(), SampleError> {
+    let mut bytes = Vec::new();
+    reader.seek_frame(start);
+    let mut remaining = end.saturating_sub(start);
+    while remaining > 0 {
+        let num_frames =
+            reader.read_raw_frames(BLOCK_FRAMES.min(remaining as usize), &mut bytes)?;
+        if num_frames == 0 {
+            break;
+        }
+        writer.write_raw_frames(&bytes)?;
+        remaining -= num_frames as u64;
+    }
+    Ok(())
+}
+
+/// Joins `inputs` end to end into `output`, byte for byte. Every input must have the same
+/// format, channel layout and sample rate. Metadata comes from the first input; the cue points,
+/// labels, regions and smpl loops of the others are moved to where their audio lands, with
+/// their cue ids renumbered to stay unique.
+pub fn concat(inputs: &[&Path], output: &Path) -> Result<u64, EditError> {
+    join(inputs, output, None)
+}
+
+/// Like `concat`, but each input overlaps the next by the fade's length, fading out while the
+/// next fades in. Only the overlaps are requantized, the rest of the audio is copied byte for
+/// byte. Markers of later inputs move back by the overlaps before them.
+pub fn crossfade(inputs: &[&Path], output: &Path, fade: Fade) -> Result<u64, EditError> {
+    join(inputs, output, Some(fade))
+}
+
+fn join(inputs: &[&Path], output: &Path, fade: Option<Fade>) -> Result<u64, EditError> {
+    let (first, rest) = inputs.split_first().ok_or(EditError::NoInputs)?;
+    let mut wav_file = WavFile::open(first)?;
+    let (mut leading, mut trailing) = wav_file.metadata_chunks()?;
+    let first_reader = SampleReader::new(&mut wav_file)?;
+    let mut markers = Markers::read(leading.iter().chain(&trailing))?;
+    let overlap = fade.map_or(0, |fade| fade.length.frames(first_reader.sample_rate));
+
+    let mut offset = first_reader.num_frames;
+    let mut readers = vec![first_reader];
+    for path in rest {
+        let mut wav_file = WavFile::open(path)?;
+        let (mut other_leading, mut other_trailing) = wav_file.metadata_chunks()?;
+        let reader = SampleReader::new(&mut wav_file)?;
+        let first = &readers[0];
+        if reader.format != first.format
+            || reader.num_channels != first.num_channels
+            || reader.sample_rate != first.sample_rate
+            || reader.channel_mask != first.channel_mask
+        {
+            return Err(EditError::FormatMismatch {
+                path: path.to_path_buf(),
+            });
+        }
+        offset = offset.saturating_sub(overlap);
+        shift_markers(
+            &mut other_leading,
+            &mut other_trailing,
+            FrameMap::Offset(offset),
+        )?;
+        markers.append(Markers::read(other_leading.iter().chain(&other_trailing))?)?;
+        offset += reader.num_frames;
+        readers.push(reader);
+    }
+
+    // Every input but the ends is overlapped at both ends, and the overlaps can't meet
+    let last = readers.len() - 1;
+    for (index, (reader, path)) in readers.iter().zip(inputs).enumerate() {
+        let overlaps = (index > 0) as u64 + (index < last) as u64;
+        if reader.num_frames < overlap * overlaps {
+            return Err(EditError::TooShortForCrossfade {
+                path: path.to_path_buf(),
+                frames: overlap,
+            });
+        }
+    }
+
+    drop_stale_chunks(&mut leading, &mut trailing);
+    markers.write(&mut leading, &mut trailing);
+
+    let first = &readers[0];
+    let mut requantizer = Requantizer::new(first.num_channels, ConvertSettings::new(first.format));
+    let mut writer = SampleWriter::create(
+        output,
+        first.format,
+        first.num_channels,
+        first.sample_rate,
+        first.channel_mask,
+        &leading,
+    )?;
+    for index in 0..readers.len() {
+        let head = if index > 0 { overlap } else { 0 };
+        let tail = if index < last { overlap } else { 0 };
+        let reader = &mut readers[index];
+        let num_frames = reader.num_frames;
+        copy_frames(reader, &mut writer, head, num_frames - tail)?;
+        if let (Some(fade), true) = (fade, tail > 0) {
+            let (outgoing, incoming) = readers.split_at_mut(index + 1);
+            write_crossfade(
+                &mut outgoing[index],
+                &mut incoming[0],
+                &mut writer,
+                &mut requantizer,
+                fade.curve,
+                overlap,
+            )?;
+        }
+    }
+    Ok(writer.finish(&trailing)?)
+}
+
+/// Mixes the last `length` frames of `outgoing` with the first `length` of `incoming` into
+/// `writer`. The two gains are the curve sampled at the middle of each frame, so a linear
+/// crossfade sums to exactly one.
+fn write_crossfade(
+    outgoing: &mut SampleReader,
+    incoming: &mut SampleReader,
+    writer: &mut SampleWriter,
+    requantizer: &mut Requantizer,
+    curve: FadeCurve,
+    length: u64,
+) -> Result<(), SampleError> {
+    let num_channels = outgoing.num_channels as usize;
+    outgoing.seek_frame(outgoing.num_frames - length);
+    incoming.seek_frame(0);
+    let (mut mixed, mut incoming_samples) = (Vec::new(), Vec::new());
+    let mut position = 0;
+    while position < length {
+        let block = BLOCK_FRAMES.min((length - position) as usize);
+        outgoing.read_frames(block, &mut mixed)?;
+        incoming.read_frames(block, &mut incoming_samples)?;
+        let frames = mixed
+            .chunks_exact_mut(num_channels)
+            .zip(incoming_samples.chunks_exact(num_channels));
+        for (frame, (out_frame, in_frame)) in frames.enumerate() {
+            let progress = ((position + frame as u64) as f64 + 0.5) / length as f64;
+            let (out_gain, in_gain) = (curve.gain(1.0 - progress), curve.gain(progress));
+            for (sample, in_sample) in out_frame.iter_mut().zip(in_frame) {
+                *sample = *sample * out_gain + in_sample * in_gain;
+            }
+        }
+        requantizer.process(&mut mixed);
+        writer.write_frames(&mixed)?;
+        position += block as u64;
+    }
+    Ok(())
+}
+
+/// The marker chunks `concat` merges across files
+#[derive(Default)]
+struct Markers {
+    cue: Option<CueChunk>,
+    adtl: Option<AdtlChunk>,
+    loops: Vec<SampleLoop>,
+}
+
+impl Markers {
+    fn read<'a>(chunks: impl Iterator<Item = &'a Chunk>) -> Result<Self, ChunkError> {
+        let mut markers = Markers::default();
+        for chunk in chunks {
+            if chunk.chunk_header.chunk_id == CueChunk::ID {
+                markers.cue = Some(CueChunk::from_chunk(chunk)?);
+            } else if AdtlChunk::is_adtl(chunk) {
+                markers.adtl = Some(AdtlChunk::from_chunk(chunk)?);
+            } else if chunk.chunk_header.chunk_id == SmplChunk::ID {
+                markers.loops = SmplChunk::from_chunk(chunk)?.loops;
+            }
+        }
+        Ok(markers)
+    }
+
+    /// Adds the markers of a later file, renumbering its cue ids past the ones in use
+    fn append(&mut self, mut other: Markers) -> Result<(), ChunkError> {
+        let base = match &self.cue {
+            Some(cue) => cue.next_id()? - 1,
+            None => 0,
+        };
+        let renumber = |id: u32| {
+            id.checked_add(base).ok_or_else(|| {
+                ChunkError::InvalidField(format!("cue id {id} can't be renumbered past {base}"))
+            })
+        };
+        if let Some(other_cue) = other.cue.take() {
+            let cue = self.cue.get_or_insert_with(Default::default);
+            for mut point in other_cue.points {
+                point.id = renumber(point.id)?;
+                cue.points.push(point);
+            }
+        }
+        if let Some(other_adtl) = other.adtl.take() {
+            let adtl = self.adtl.get_or_insert_with(Default::default);
+            for mut label in other_adtl.labels {
+                label.cue_id = renumber(label.cue_id)?;
+                adtl.labels.push(label);
+            }
+            for mut note in other_adtl.notes {
+                note.cue_id = renumber(note.cue_id)?;
+                adtl.notes.push(note);
+            }
+            for mut region in other_adtl.regions {
+                region.cue_id = renumber(region.cue_id)?;
+                adtl.regions.push(region);
+            }
+        }
+        for mut sample_loop in other.loops {
+            if sample_loop.cue_point_id != 0 {
+                sample_loop.cue_point_id = renumber(sample_loop.cue_point_id)?;
+            }
+            self.loops.push(sample_loop);
+        }
+        Ok(())
+    }
+
+    /// Replaces the marker chunks in `leading` and `trailing`, adding any that are new to
+    /// `leading`
+    fn write(self, leading: &mut Vec<Chunk>, trailing: &mut [Chunk]) {
+        let smpl = leading
+            .iter()
+            .chain(trailing.iter())
+            .find(|chunk| chunk.chunk_header.chunk_id == SmplChunk::ID)
+            .and_then(|chunk| SmplChunk::from_chunk(chunk).ok())
+            .map(|smpl| SmplChunk {
+                loops: self.loops.clone(),
+                ..smpl
+            })
+            .or_else(|| {
+                (!self.loops.is_empty()).then(|| SmplChunk {
+                    loops: self.loops.clone(),
+                    ..Default::default()
+                })
+            });
+        let replacements = [
+            self.cue.map(|cue| cue.to_chunk()),
+            self.adtl.map(|adtl| adtl.to_chunk()),
+            smpl.map(|smpl| smpl.to_chunk()),
+        ];
+        for chunk in replacements.into_iter().flatten() {
+            let is_same = |existing: &Chunk| {
+                existing.chunk_header.chunk_id == chunk.chunk_header.chunk_id
+                    && (chunk.chunk_header.chunk_id != AdtlChunk::ID
+                        || AdtlChunk::is_adtl(existing))
+            };
+            if let Some(existing) = leading
+                .iter_mut()
+                .chain(trailing.iter_mut())
+                .find(|existing| is_same(existing))
+            {
+                *existing = chunk;
+            } else {
+                leading.push(chunk);
+            }
+        }
+    }
+}
@@ -0,0 +1,782 @@
+use std::{f64::consts::FRAC_1_SQRT_2, fmt, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{
+    channels::ChannelMask,
+    chunks::{adm::ChnaChunk, ChunkError, TypedChunk},
+    dsp::{
+        convert::ConversionReport,
+        dynamics::{Compressor, CompressorSettings, Gate, GateSettings, Limiter, LimiterSettings},
+        fade::{Fade, FadeCurve, FadeLength, Fader},
+        filter::{Equalizer, FilterBand, FilterError},
+        gain::Gain,
+        linear_to_db,
+        mix::{MixMatrix, MixPreset},
+        resample::{ResampleQuality, Resampler},
+    },
+    edit::{shift_markers, FrameMap},
+    pipeline::{
+        drop_stale_chunks, Pipeline, PipelineError, Processor, Sink, Source, StreamFormat, WavSink,
+    },
+    samples::{open_with_metadata, SampleError, SampleFormat, SampleReader},
+    wav::{Chunk, WavError, WavFile},
+};
+
+/// Harmonics of the mains frequency `dehum` notches out, the fundamental included
+const DEHUM_HARMONICS: u32 = 5;
+/// Narrow enough to leave the music between the harmonics alone
+const DEHUM_Q: f64 = 30.0;
+
+/// Effect names, which also end the parameter list of the effect before them
+const EFFECT_NAMES: [&str; 17] = [
+    "gain",
+    "norm",
+    "highpass",
+    "lowpass",
+    "bandpass",
+    "bandreject",
+    "equalizer",
+    "bass",
+    "treble",
+    "dehum",
+    "fade",
+    "rate",
+    "channels",
+    "mix",
+    "compress",
+    "limit",
+    "gate",
+];
+
+/// One effect of a chain, its parameters checked but not yet fitted to a stream. Names and
+/// parameters follow SoX where rwav has the same effect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// `gain <dB>`
+    Gain { db: f64 },
+    /// `norm [<dBFS>]`: the gain that brings the sample peak of the audio reaching it to the
+    /// level, 0 dBFS by default. The audio is measured in a first pass.
+    Norm { dbfs: f64 },
+    /// `highpass`, `lowpass`, `bandpass` and `bandreject <Hz> [<Q>]`, `equalizer <Hz> <Q> <dB>`,
+    /// `bass` and `treble <dB> [<Hz>]`
+    Filter(FilterBand),
+    /// `dehum [<Hz>]`: notches at the mains frequency, 50 Hz by default, and its harmonics
+    Dehum { mains_frequency: f64 },
+    /// `fade [t|q|h|l] <in> [<stop> [<out>]]`. The curve is linear (t), quarter sine (q), half
+    /// sine (h) or logarithmic (l), which is the default as in SoX. Lengths are in seconds, or
+    /// in frames with an `s` suffix. Audio after `stop` is dropped, a stop of 0 being the end,
+    /// and `out` defaults to `in`.
+    Fade {
+        curve: FadeCurve,
+        fade_in: FadeLength,
+        stop: Option<FadeLength>,
+        fade_out: Option<FadeLength>,
+    },
+    /// `rate [-q|-m|-h] <Hz>`, with fast, balanced (the default) or best quality
+    Rate {
+        sample_rate: u32,
+        quality: ResampleQuality,
+    },
+    /// `channels <1|2>`: the standard downmix to mono or stereo, or mono copied to stereo
+    Channels(u16),
+    /// `mix <matrix>`: a gain matrix such as `0.5,0.5` or `1,0;0,1;0.5,0.5`, one row of input
+    /// gains per output channel
+    Mix(MixMatrix),
+    /// `compress [<settings>]`, with settings as `CompressorSettings` parses them
+    Compress(CompressorSettings),
+    /// `limit [<settings>]`, with settings as `LimiterSettings` parses them
+    Limit(LimiterSettings),
+    /// `gate [<settings>]`, with settings as `GateSettings` parses them
+    Gate(GateSettings),
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Gain { .. } => "gain",
+            Effect::Norm { .. } => "norm",
+            Effect::Filter(FilterBand::HighPass { .. }) => "highpass",
+            Effect::Filter(FilterBand::LowPass { .. }) => "lowpass",
+            Effect::Filter(FilterBand::BandPass { .. }) => "bandpass",
+            Effect::Filter(FilterBand::Notch { .. }) => "bandreject",
+            Effect::Filter(FilterBand::Peaking { .. }) => "equalizer",
+            Effect::Filter(FilterBand::LowShelf { .. }) => "bass",
+            Effect::Filter(FilterBand::HighShelf { .. }) => "treble",
+            Effect::Dehum { .. } => "dehum",
+            Effect::Fade { .. } => "fade",
+            Effect::Rate { .. } => "rate",
+            Effect::Channels(_) => "channels",
+            Effect::Mix(_) => "mix",
+            Effect::Compress(_) => "compress",
+            Effect::Limit(_) => "limit",
+            Effect::Gate(_) => "gate",
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let length = |length: &FadeLength| match length {
+            FadeLength::Frames(frames) => format!("{frames} frames"),
+            FadeLength::Seconds(seconds) => format!("{seconds} s"),
+        };
+        match self {
+            Effect::Gain { db } => write!(f, "gain {db} dB"),
+            Effect::Norm { dbfs } => write!(f, "norm to a {dbfs} dBFS peak"),
+            Effect::Filter(band) => {
+                write!(
+                    f,
+                    "{} at {} Hz, Q {:.3}",
+                    self.name(),
+                    band.frequency(),
+                    band.q()
+                )?;
+                match band {
+                    FilterBand::Peaking { gain_db, .. }
+                    | FilterBand::LowShelf { gain_db, .. }
+                    | FilterBand::HighShelf { gain_db, .. } => write!(f, ", {gain_db} dB"),
+                    _ => Ok(()),
+                }
+            }
+            Effect::Dehum { mains_frequency } => write!(
+                f,
+                "dehum at {mains_frequency} Hz and {} harmonics",
+                DEHUM_HARMONICS - 1
+            ),
+            Effect::Fade {
+                curve,
+                fade_in,
+                stop,
+                fade_out,
+            } => {
+                write!(f, "fade {curve:?} in over {}", length(fade_in))?;
+                if let Some(stop) = stop {
+                    write!(f, ", stop at {}", length(stop))?;
+                }
+                if let Some(fade_out) = fade_out {
+                    write!(f, ", out over {}", length(fade_out))?;
+                }
+                Ok(())
+            }
+            Effect::Rate {
+                sample_rate,
+                quality,
+            } => write!(f, "rate {sample_rate} Hz, {quality:?} quality"),
+            Effect::Channels(num_channels) => write!(f, "channels {num_channels}"),
+            Effect::Mix(matrix) => write!(
+                f,
+                "mix {} channels to {}",
+                matrix.num_inputs(),
+                matrix.num_outputs()
+            ),
+            Effect::Compress(settings) => write!(f, "compress {settings:?}"),
+            Effect::Limit(settings) => write!(f, "limit {settings:?}"),
+            Effect::Gate(settings) => write!(f, "gate {settings:?}"),
+        }
+    }
+}
+
+/// Errors in a chain carry the position of the token they're about, for `point_at`
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("Unknown effect {token:?}!")]
+    UnknownEffect { position: usize, token: String },
+    #[error("{effect} needs {expected}!")]
+    MissingParameter {
+        position: usize,
+        effect: &'static str,
+        expected: &'static str,
+    },
+    #[error("{effect} expects {expected}, not {token:?}!")]
+    InvalidParameter {
+        position: usize,
+        effect: &'static str,
+        expected: &'static str,
+        token: String,
+    },
+    #[error("Invalid {effect} settings {token:?}: {reason}")]
+    InvalidSettings {
+        position: usize,
+        effect: &'static str,
+        token: String,
+        reason: String,
+    },
+    /// The parameters don't suit the audio reaching the effect
+    #[error("Can't apply {effect}: {reason}!")]
+    Unsuitable {
+        position: usize,
+        effect: &'static str,
+        reason: String,
+    },
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+impl ChainError {
+    /// Index of the offending token, or the number of tokens if one is missing at the end
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            ChainError::UnknownEffect { position, .. }
+            | ChainError::MissingParameter { position, .. }
+            | ChainError::InvalidParameter { position, .. }
+            | ChainError::InvalidSettings { position, .. }
+            | ChainError::Unsuitable { position, .. } => Some(position),
+            _ => None,
+        }
+    }
+
+    /// The chain on one line with a caret under the offending token on the next
+    pub fn point_at(&self, tokens: &[String]) -> Option<String> {
+        let position = self.position()?;
+        let offset: usize = tokens[..position.min(tokens.len())]
+            .iter()
+            .map(|token| token.chars().count() + 1)
+            .sum();
+        let width = tokens
+            .get(position)
+            .map_or(1, |token| token.chars().count().max(1));
+        Some(format!(
+            "{}\n{}{}",
+            tokens.join(" "),
+            " ".repeat(offset),
+            "^".repeat(width)
+        ))
+    }
+}
+
+/// Effects parsed from tokens such as `gain -3 highpass 80 norm -1 fade 0.5 0 1`, applied in
+/// order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectChain {
+    /// Each effect with the position of the token naming it
+    effects: Vec<(usize, Effect)>,
+}
+
+impl EffectChain {
+    /// Parses the effects and checks every parameter. Whether they suit the audio is only
+    /// known once the chain is fitted to a stream, by `resolve` or `process_file`.
+    pub fn parse(tokens: &[String]) -> Result<Self, ChainError> {
+        let mut tokens = Tokens { tokens, next: 0 };
+        let mut effects = Vec::new();
+        while let Some((position, token)) = tokens.next_token() {
+            let Some(&name) = EFFECT_NAMES.iter().find(|&&name| name == token) else {
+                return Err(ChainError::UnknownEffect {
+                    position,
+                    token: token.to_string(),
+                });
+            };
+            let effect = match name {
+                "gain" => Effect::Gain {
+                    db: tokens.require("gain", "a gain in dB", level)?,
+                },
+                "norm" => Effect::Norm {
+                    dbfs: tokens
+                        .parse("norm", "a level at or below 0 dBFS", |token| {
+                            level(token).filter(|&dbfs| dbfs <= 0.0)
+                        })?
+                        .unwrap_or(0.0),
+                },
+                "highpass" | "lowpass" | "bandpass" | "bandreject" => {
+                    let frequency = tokens.require(name, "a frequency in Hz", positive)?;
+                    let q = tokens
+                        .parse(name, "a positive Q", positive)?
+                        .unwrap_or(FRAC_1_SQRT_2);
+                    Effect::Filter(match name {
+                        "highpass" => FilterBand::HighPass { frequency, q },
+                        "lowpass" => FilterBand::LowPass { frequency, q },
+                        "bandpass" => FilterBand::BandPass { frequency, q },
+                        _ => FilterBand::Notch { frequency, q },
+                    })
+                }
+                "equalizer" => Effect::Filter(FilterBand::Peaking {
+                    frequency: tokens.require("equalizer", "a frequency in Hz", positive)?,
+                    q: tokens.require("equalizer", "a positive Q", positive)?,
+                    gain_db: tokens.require("equalizer", "a gain in dB", level)?,
+                }),
+                "bass" => {
+                    let gain_db = tokens.require("bass", "a gain in dB", level)?;
+                    Effect::Filter(FilterBand::LowShelf {
+                        frequency: tokens
+                            .parse("bass", "a frequency in Hz", positive)?
+                            .unwrap_or(100.0),
+                        q: FRAC_1_SQRT_2,
+                        gain_db,
+                    })
+                }
+                "treble" => {
+                    let gain_db = tokens.require("treble", "a gain in dB", level)?;
+                    Effect::Filter(FilterBand::HighShelf {
+                        frequency: tokens
+                            .parse("treble", "a frequency in Hz", positive)?
+                            .unwrap_or(3000.0),
+                        q: FRAC_1_SQRT_2,
+                        gain_db,
+                    })
+                }
+                "dehum" => Effect::Dehum {
+                    mains_frequency: tokens
+                        .parse("dehum", "a mains frequency in Hz", positive)?
+                        .unwrap_or(50.0),
+                },
+                "fade" => {
+                    let curve = tokens.parse_if(|token| match token {
+                        "t" => Some(FadeCurve::Linear),
+                        "q" => Some(FadeCurve::EqualPower),
+                        "h" => Some(FadeCurve::SCurve),
+                        "l" => Some(FadeCurve::Logarithmic),
+                        _ => None,
+                    });
+                    let expected = "a length in seconds, or in frames ending in s";
+                    let fade_in = tokens.require("fade", expected, length)?;
+                    let stop = tokens.parse("fade", expected, length)?;
+                    let fade_out = match stop {
+                        Some(_) => Some(tokens.parse("fade", expected, length)?.unwrap_or(fade_in)),
+                        None => None,
+                    };
+                    Effect::Fade {
+                        curve: curve.unwrap_or(FadeCurve::Logarithmic),
+                        fade_in,
+                        // A stop position of 0 is the end
+                        stop: stop.filter(|&stop| {
+                            stop != FadeLength::Frames(0) && stop != FadeLength::Seconds(0.0)
+                        }),
+                        fade_out,
+                    }
+                }
+                "rate" => {
+                    let quality = tokens.parse_if(|token| match token {
+                        "-q" => Some(ResampleQuality::Fast),
+                        "-m" => Some(ResampleQuality::Balanced),
+                        "-h" | "-v" => Some(ResampleQuality::Best),
+                        _ => None,
+                    });
+                    Effect::Rate {
+                        sample_rate: tokens.require("rate", "a sample rate in Hz", |token| {
+                            token.parse().ok().filter(|&rate: &u32| rate > 0)
+                        })?,
+                        quality: quality.unwrap_or(ResampleQuality::Balanced),
+                    }
+                }
+                "channels" => Effect::Channels(tokens.require("channels", "1 or 2", |token| {
+                    token
+                        .parse()
+                        .ok()
+                        .filter(|num_channels| (1..=2).contains(num_channels))
+                })?),
+                "mix" => Effect::Mix(tokens.require_settings("mix")?),
+                "compress" => Effect::Compress(tokens.settings("compress")?),
+                "limit" => Effect::Limit(tokens.settings("limit")?),
+                "gate" => Effect::Gate(tokens.settings("gate")?),
+                _ => unreachable!("{name} has no parser"),
+            };
+            effects.push((position, effect));
+        }
+        Ok(EffectChain { effects })
+    }
+
+    pub fn effects(&self) -> impl Iterator<Item = &Effect> {
+        self.effects.iter().map(|(_, effect)| effect)
+    }
+
+    /// Fits the chain to a stream in `input` without running it, returning the format after
+    /// each effect. The level `norm` measures is only known once the audio runs, so here it's
+    /// taken as unity gain.
+    pub fn resolve(&self, input: StreamFormat) -> Result<Vec<StreamFormat>, ChainError> {
+        let mut pipeline = Pipeline::new(Unread(input));
+        let mut formats = Vec::new();
+        for (position, effect) in &self.effects {
+            push_effect(&mut pipeline, *position, effect, 0.0)?;
+            formats.push(pipeline.format());
+        }
+        Ok(formats)
+    }
+
+    /// Pushes the first `norm_gains.len()` effects, each `norm` with the gain in dB at its index
+    fn build(&self, pipeline: &mut Pipeline<'_>, norm_gains: &[f64]) -> Result<(), ChainError> {
+        for ((position, effect), &norm_gain) in self.effects.iter().zip(norm_gains) {
+            push_effect(pipeline, *position, effect, norm_gain)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the chain from a single string, split at whitespace
+impl FromStr for EffectChain {
+    type Err = ChainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<String> = s.split_whitespace().map(str::to_string).collect();
+        EffectChain::parse(&tokens)
+    }
+}
+
+/// Cursor over the tokens of a chain
+struct Tokens<'a> {
+    tokens: &'a [String],
+    next: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next_token(&mut self) -> Option<(usize, &'a str)> {
+        let token = self.tokens.get(self.next)?;
+        self.next += 1;
+        Some((self.next - 1, token))
+    }
+
+    /// The next token if it's a parameter rather than the name of the next effect
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens
+            .get(self.next)
+            .map(String::as_str)
+            .filter(|token| !EFFECT_NAMES.contains(token))
+    }
+
+    /// Takes the next parameter if `parse` accepts it, for flags and curve letters
+    fn parse_if<T>(&mut self, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+        let value = parse(self.peek()?)?;
+        self.next += 1;
+        Some(value)
+    }
+
+    /// Takes an optional parameter, which has to be `expected` if it's there
+    fn parse<T>(
+        &mut self,
+        effect: &'static str,
+        expected: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Option<T>, ChainError> {
+        let Some(token) = self.peek() else {
+            return Ok(None);
+        };
+        let value = parse(token).ok_or_else(|| ChainError::InvalidParameter {
+            position: self.next,
+            effect,
+            expected,
+            token: token.to_string(),
+        })?;
+        self.next += 1;
+        Ok(Some(value))
+    }
+
+    fn require<T>(
+        &mut self,
+        effect: &'static str,
+        expected: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, ChainError> {
+        self.parse(effect, expected, parse)?
+            .ok_or(ChainError::MissingParameter {
+                position: self.next,
+                effect,
+                expected,
+            })
+    }
+
+    /// Takes an optional `key=value,...` settings parameter, defaults if there's none
+    fn settings<T>(&mut self, effect: &'static str) -> Result<T, ChainError>
+    where
+        T: FromStr + Default,
+        T::Err: fmt::Display,
+    {
+        match self.peek() {
+            Some(_) => self.require_settings(effect),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn require_settings<T>(&mut self, effect: &'static str) -> Result<T, ChainError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let token = self.peek().ok_or(ChainError::MissingParameter {
+            position: self.next,
+            effect,
+            expected: "its settings",
+        })?;
+        let value = token
+            .parse()
+            .map_err(|err: T::Err| ChainError::InvalidSettings {
+                position: self.next,
+                effect,
+                token: token.to_string(),
+                reason: err.to_string(),
+            })?;
+        self.next += 1;
+        Ok(value)
+    }
+}
+
+fn level(token: &str) -> Option<f64> {
+    token.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+fn positive(token: &str) -> Option<f64> {
+    level(token).filter(|&value| value > 0.0)
+}
+
+/// Seconds, or frames with an `s` suffix like SoX
+fn length(token: &str) -> Option<FadeLength> {
+    match token.strip_suffix('s') {
+        Some(frames) => frames.parse().ok().map(FadeLength::Frames),
+        None => level(token)
+            .filter(|&seconds| seconds >= 0.0)
+            .map(FadeLength::Seconds),
+    }
+}
+
+/// Turns an effect into processors fitted to the format leaving the end of `pipeline` and
+/// pushes them
+fn push_effect(
+    pipeline: &mut Pipeline<'_>,
+    position: usize,
+    effect: &Effect,
+    norm_gain: f64,
+) -> Result<(), ChainError> {
+    let format = pipeline.format();
+    let (num_channels, sample_rate) = (format.num_channels, format.sample_rate);
+    let unsuitable = |reason: String| ChainError::Unsuitable {
+        position,
+        effect: effect.name(),
+        reason,
+    };
+    let equalizer = |bands: &[FilterBand]| {
+        Equalizer::new(num_channels, sample_rate, bands).map_err(|err| match err {
+            FilterError::Frequency { frequency, .. } => unsuitable(format!(
+                "{frequency} Hz isn't below Nyquist at {sample_rate} Hz"
+            )),
+            err => unsuitable(err.to_string()),
+        })
+    };
+    let processor: Box<dyn Processor> = match effect {
+        Effect::Gain { db } => Box::new(Gain::from_db(*db)),
+        Effect::Norm { .. } => Box::new(Gain::from_db(norm_gain)),
+        Effect::Filter(band) => Box::new(equalizer(&[*band])?),
+        Effect::Dehum { mains_frequency } => Box::new(equalizer(&FilterBand::dehum(
+            *mains_frequency,
+            DEHUM_HARMONICS,
+            DEHUM_Q,
+            sample_rate,
+        ))?),
+        Effect::Fade {
+            curve,
+            fade_in,
+            stop,
+            fade_out,
+        } => {
+            let num_frames = format
+                .num_frames
+                .ok_or_else(|| unsuitable("the length of the audio isn't known".to_string()))?;
+            let end = stop.map_or(num_frames, |stop| stop.frames(sample_rate).min(num_frames));
+            if end < num_frames {
+                push(pipeline, position, effect, Truncate::new(num_channels, end))?;
+            }
+            let fade = |length: FadeLength| Fade {
+                curve: *curve,
+                length,
+            };
+            Box::new(Fader::new(
+                num_channels,
+                sample_rate,
+                end,
+                Some(fade(*fade_in)),
+                fade_out.map(fade),
+            ))
+        }
+        Effect::Rate {
+            sample_rate: to_rate,
+            quality,
+        } => Box::new(
+            Resampler::new(num_channels, sample_rate, *to_rate, *quality)
+                .map_err(|err| unsuitable(err.to_string().trim_end_matches('!').into()))?,
+        ),
+        Effect::Channels(to_channels) => {
+            let preset = match (*to_channels, num_channels) {
+                (1, _) => MixPreset::Mono,
+                (_, 1) => MixPreset::MonoToStereo,
+                _ => MixPreset::Stereo,
+            };
+            let input_mask = format.channel_mask.map(ChannelMask);
+            Box::new(
+                MixMatrix::preset(preset, num_channels, input_mask)
+                    .map_err(|err| unsuitable(err.to_string().trim_end_matches('!').into()))?,
+            )
+        }
+        Effect::Mix(matrix) => Box::new(matrix.clone()),
+        Effect::Compress(settings) => {
+            Box::new(Compressor::new(num_channels, sample_rate, *settings))
+        }
+        Effect::Limit(settings) => Box::new(Limiter::new(num_channels, sample_rate, *settings)),
+        Effect::Gate(settings) => Box::new(Gate::new(num_channels, sample_rate, *settings)),
+    };
+    push(pipeline, position, effect, processor)
+}
+
+/// Pushes a processor, blaming `effect` if it can't take the stream
+fn push(
+    pipeline: &mut Pipeline<'_>,
+    position: usize,
+    effect: &Effect,
+    processor: impl Processor + 'static,
+) -> Result<(), ChainError> {
+    match pipeline.push(processor) {
+        Ok(_) => Ok(()),
+        Err(PipelineError::Incompatible { reason, .. }) => Err(ChainError::Unsuitable {
+            position,
+            effect: effect.name(),
+            reason,
+        }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Drops every frame after the first `end`, for a fade with a stop position
+struct Truncate {
+    num_channels: usize,
+    /// Frames still to let through
+    remaining: u64,
+}
+
+impl Truncate {
+    fn new(num_channels: u16, end: u64) -> Self {
+        Truncate {
+            num_channels: num_channels as usize,
+            remaining: end,
+        }
+    }
+}
+
+impl Processor for Truncate {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        Ok(StreamFormat {
+            num_frames: input.num_frames.map(|frames| frames.min(self.remaining)),
+            ..input
+        })
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        let frames = (samples.len() / self.num_channels) as u64;
+        let kept = frames.min(self.remaining);
+        samples.truncate(kept as usize * self.num_channels);
+        self.remaining -= kept;
+    }
+}
+
+/// Stands in for the source of a chain that's only resolved
+struct Unread(StreamFormat);
+
+impl Source for Unread {
+    fn format(&self) -> StreamFormat {
+        self.0
+    }
+
+    fn read(&mut self, _max_frames: usize, out: &mut Vec<f64>) -> Result<usize, PipelineError> {
+        out.clear();
+        Ok(0)
+    }
+}
+
+/// Sample peak of everything written to it, for `norm`
+#[derive(Default)]
+struct PeakMeter {
+    peak: f64,
+}
+
+impl Sink for PeakMeter {
+    fn configure(&mut self, _format: StreamFormat) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        self.peak = samples
+            .iter()
+            .fold(self.peak, |peak, sample| peak.max(sample.abs()));
+        Ok(())
+    }
+}
+
+/// Runs `input` through the chain into `output`, in `format` or the input's sample format.
+/// Every `norm` first measures the audio reaching it, in a pass through the effects before it.
+/// Metadata is carried over less what `drop_stale_chunks` removes, with markers moved along
+/// by `rate` and a fade's stop, and without the chna channel list once the channels change.
+pub fn process_file(
+    input: &Path,
+    output: &Path,
+    chain: &EffectChain,
+    format: Option<SampleFormat>,
+) -> Result<ConversionReport, ChainError> {
+    let mut norm_gains = Vec::new();
+    for (position, effect) in &chain.effects {
+        let Effect::Norm { dbfs } = *effect else {
+            norm_gains.push(0.0);
+            continue;
+        };
+        let mut wav_file = WavFile::open(input)?;
+        let mut meter = PeakMeter::default();
+        let mut pipeline = Pipeline::new(SampleReader::new(&mut wav_file)?);
+        chain.build(&mut pipeline, &norm_gains)?;
+        pipeline.sink(&mut meter)?;
+        pipeline.run()?;
+        if meter.peak == 0.0 {
+            return Err(ChainError::Unsuitable {
+                position: *position,
+                effect: effect.name(),
+                reason: "the audio reaching it is silent".to_string(),
+            });
+        }
+        norm_gains.push(dbfs - linear_to_db(meter.peak));
+    }
+
+    let (reader, mut leading, mut trailing) = open_with_metadata(input)?;
+    let input_format = Source::format(&reader);
+    let formats = chain.resolve(input_format)?;
+    shift_chain_markers(&mut leading, &mut trailing, input_format, &formats)?;
+    let mut sink = WavSink::new(output, format, leading, trailing);
+    let mut pipeline = Pipeline::new(reader);
+    chain.build(&mut pipeline, &norm_gains)?;
+    pipeline.sink(&mut sink)?;
+    pipeline.run()?;
+    Ok(sink.report())
+}
+
+/// Moves the markers in the leading and trailing metadata chunks along with the frames of a
+/// stream in `input` that leaves each effect in the matching entry of `formats`, and drops the
+/// chunks the chain leaves stale
+fn shift_chain_markers(
+    leading: &mut Vec<Chunk>,
+    trailing: &mut Vec<Chunk>,
+    input: StreamFormat,
+    formats: &[StreamFormat],
+) -> Result<(), ChunkError> {
+    drop_stale_chunks(leading, trailing);
+    let mut before = input;
+    for &after in formats {
+        let map = match (before.num_frames, after.num_frames) {
+            _ if after.sample_rate != before.sample_rate => Some(FrameMap::Scale {
+                from_rate: before.sample_rate,
+                to_rate: after.sample_rate,
+            }),
+            (Some(num_frames), Some(end)) if end < num_frames => {
+                Some(FrameMap::Keep { start: 0, end })
+            }
+            _ => None,
+        };
+        if let Some(map) = map {
+            shift_markers(leading, trailing, map)?;
+        }
+        if after.num_channels != before.num_channels {
+            for chunks in [&mut *leading, &mut *trailing] {
+                chunks.retain(|chunk| chunk.chunk_header.chunk_id != ChnaChunk::ID);
+            }
+        }
+        before = after;
+    }
+    Ok(())
+}
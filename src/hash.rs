@@ -0,0 +1,145 @@
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    channels::ChannelMask,
+    chunks::{md5::Md5Chunk, ChunkError, TypedChunk},
+    dsp::convert::BLOCK_FRAMES,
+    samples::{open_with_metadata, rewrite_audio, SampleError, SampleReader},
+    wav::{WavError, WavFile},
+};
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// SHA-256 of a file's audio that doesn't depend on its metadata chunks, chunk order or how
+/// its fmt chunk is spelled, so identical audio hashes the same across files
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ContentHash(pub [u8; 32]);
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", to_hex(&self.0))
+    }
+}
+
+/// Result of checking an `MD5 ` chunk against the audio
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Md5Check {
+    Match(Md5Chunk),
+    Mismatch {
+        stored: Md5Chunk,
+        computed: Md5Chunk,
+    },
+    Missing,
+}
+
+#[derive(Error, Debug)]
+pub enum HashError {
+    #[error("IO error reading audio: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// Hashes `path`'s audio with SHA-256. The hash covers, in this order and little endian:
+/// the format tag (1 for PCM or 3 for float, WAVE_FORMAT_EXTENSIBLE is resolved through its
+/// sub format), bits per sample and channels as u16, sample rate and channel mask as u32, the
+/// number of frames as u64, then the frames as they're stored. Files without a channel mask
+/// count as having the default one for their channel count, 0 if there is none.
+pub fn content_hash(path: &Path) -> Result<ContentHash, HashError> {
+    let mut wav_file = WavFile::open(path)?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    let channel_mask = reader
+        .channel_mask
+        .or_else(|| ChannelMask::default_for(reader.num_channels).map(|mask| mask.0))
+        .unwrap_or(0);
+
+    let mut sha256 = Sha256::new();
+    sha256.update(reader.format.audio_format().to_le_bytes());
+    sha256.update(reader.format.bits_per_sample().to_le_bytes());
+    sha256.update(reader.num_channels.to_le_bytes());
+    sha256.update(reader.sample_rate.to_le_bytes());
+    sha256.update(channel_mask.to_le_bytes());
+    sha256.update(reader.num_frames.to_le_bytes());
+    let mut frames = Vec::new();
+    while reader.read_raw_frames(BLOCK_FRAMES, &mut frames)? > 0 {
+        sha256.update(&frames);
+    }
+    Ok(ContentHash(sha256.finalize().into()))
+}
+
+/// MD5 of the data chunk's contents, the digest an `MD5 ` chunk holds
+pub fn data_md5(wav_file: &mut WavFile) -> Result<Md5Chunk, HashError> {
+    let (data_offset, data_size) = wav_file
+        .find_chunk(b"data")
+        .ok_or(SampleError::MissingDataChunk)?;
+    let mut handle = wav_file.handle.try_clone()?;
+    handle.seek(SeekFrom::Start(data_offset))?;
+    let mut data = handle.take(data_size);
+    let mut md5 = Md5::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let num_bytes = data.read(&mut buffer)?;
+        if num_bytes == 0 {
+            break;
+        }
+        md5.update(&buffer[..num_bytes]);
+    }
+    Ok(Md5Chunk {
+        digest: md5.finalize().into(),
+    })
+}
+
+/// Writes `input` to `output` with an `MD5 ` chunk of its audio, replacing any there was. The
+/// audio is read once and digested as it's written. `output` may be `input`.
+pub fn write_md5(input: &Path, output: &Path) -> Result<Md5Chunk, HashError> {
+    let (reader, mut leading, trailing) = open_with_metadata(input)?;
+    // `open_with_metadata` puts any `MD5 ` chunk first, and `SampleWriter` fills in the digest
+    if !leading
+        .iter()
+        .any(|chunk| chunk.chunk_header.chunk_id == Md5Chunk::ID)
+    {
+        leading.push(Md5Chunk::default().to_chunk());
+    }
+    Ok(rewrite_audio(reader, output, &leading, &trailing)?
+        .expect("Leading chunks hold an MD5 chunk!"))
+}
+
+/// Checks `path`'s `MD5 ` chunk against its audio
+pub fn verify_md5(path: &Path) -> Result<Md5Check, HashError> {
+    let mut wav_file = WavFile::open(path)?;
+    let Some(stored) = stored_md5(&mut wav_file)? else {
+        return Ok(Md5Check::Missing);
+    };
+    let computed = data_md5(&mut wav_file)?;
+    if stored == computed {
+        Ok(Md5Check::Match(stored))
+    } else {
+        Ok(Md5Check::Mismatch { stored, computed })
+    }
+}
+
+/// The file's `MD5 ` chunk, if it has one
+fn stored_md5(wav_file: &mut WavFile) -> Result<Option<Md5Chunk>, HashError> {
+    let entry = wav_file
+        .chunk_index()?
+        .into_iter()
+        .find(|entry| entry.chunk_header.chunk_id == Md5Chunk::ID);
+    match entry {
+        Some(entry) => Ok(Some(Md5Chunk::from_chunk(&wav_file.read_chunk(&entry)?)?)),
+        None => Ok(None),
+    }
+}
@@ -1,8 +1,24 @@
 #![feature(strict_provenance)]
 // pub mod bindings;
+pub mod analysis;
 pub mod audio;
+pub mod channels;
+pub mod chunks;
 pub mod cli;
+pub mod diff;
+pub mod dsp;
+pub mod edit;
+pub mod effects;
+pub mod hash;
+#[cfg(feature = "serde")]
+pub mod metadata;
+pub mod pipeline;
+pub mod poly;
+pub mod repair;
+pub mod samples;
+pub mod split;
 pub mod tests;
+pub mod validate;
 pub mod wav;
 
 pub mod utils {
@@ -3,6 +3,8 @@ use std::{os::raw::c_void, path::Path, ptr};
 
 use clap::Parser;
 use rwav::{
+    analysis::{loudness, stats},
+    audio::{AudioSession, SourceDescription},
     bindings::{
         self,
         flags::{kAudioFormatFlagIsPacked, kAudioFormatFlagIsSignedInteger},
@@ -10,49 +12,121 @@ use rwav::{
         AudioQueueEnqueueBuffer, AudioQueueRef, AudioQueueStart, AudioStreamBasicDescription,
         CFRunLoopGetCurrent, CFRunLoopRun,
     },
-    cli::Cli,
+    cli::{Cli, Command},
+    diff::{self, DiffSettings},
+    dsp::{
+        dynamics::{self, Dynamics},
+        generator::{self, Generator, Signal},
+        linear_to_db,
+        resample::{self, Resampler},
+    },
+    effects::{self, ChainError, EffectChain},
+    hash::{self, Md5Check},
+    pipeline::{Pipeline, Source},
+    samples::{SampleFormat, SampleReader},
     utils::{self, TestData},
-    wav::{Chunk, WavFile},
+    wav::WavFile,
 };
 
-fn main() {
-    let cli = Cli::parse();
-    let file_path = Path::new(&(*cli.input));
-    let wav_file = WavFile::new(file_path);
-    let header = wav_file.header;
-    let mut data_chunk: Option<Chunk> = None;
-    print!("{header:?}");
+#[cfg(feature = "serde")]
+fn run_metadata_commands(cli: &Cli) -> bool {
+    use rwav::{cli::MetadataFormat, metadata::WavMetadata};
 
-    let device_ids = rwav::audio::Audio::get_device_ids().unwrap();
-    let name = rwav::audio::Audio::get_device_name(&device_ids[1]);
+    let file_path = Path::new(cli.input.as_deref().expect("--input is required!"));
+    if let Some(format) = cli.dump_metadata {
+        let mut wav_file = WavFile::open(file_path).expect("Unable to read file!");
+        let metadata = WavMetadata::read(&mut wav_file).expect("Unable to read metadata!");
+        let dump = match format {
+            MetadataFormat::Json => metadata.to_json(),
+            MetadataFormat::Yaml => metadata.to_yaml(),
+        };
+        println!("{}", dump.expect("Unable to serialize metadata!"));
+        return true;
+    }
+    if let Some(metadata_path) = &cli.apply_metadata {
+        let text = std::fs::read_to_string(metadata_path).expect("Unable to read metadata file!");
+        let metadata = if metadata_path.ends_with(".yaml") || metadata_path.ends_with(".yml") {
+            WavMetadata::from_yaml(&text)
+        } else {
+            WavMetadata::from_json(&text)
+        }
+        .expect("Unable to parse metadata file!");
+        let output = cli.output.as_deref().expect("--output is required!");
+        metadata
+            .apply(file_path, Path::new(output))
+            .expect("Unable to apply metadata!");
+        return true;
+    }
+    false
+}
 
-    wav_file.for_each(|chunk| {
-        // let chunk_id: &str = chunk.chunk_header.chunk_id.as_ascii().unwrap();
-        let chunk_id = String::from_utf8(chunk.chunk_header.chunk_id.to_vec()).unwrap();
-        match chunk_id.as_str() {
-            "info" => {
-                println!("Found INFO block!");
-            }
-            "data" => {
-                println!("Found DATA block!");
-                data_chunk = Some(chunk);
-            }
-            _ => (),
+/// Prints a chain error with a pointer to the token it's about and exits
+fn exit_with_chain_error(err: ChainError, tokens: &[String]) -> ! {
+    eprintln!("{err}");
+    if let Some(pointer) = err.point_at(tokens) {
+        eprintln!("{pointer}");
+    }
+    std::process::exit(1);
+}
+
+fn process(
+    input: &str,
+    output: &str,
+    tokens: &[String],
+    format: Option<SampleFormat>,
+    dry_run: bool,
+) {
+    let chain = EffectChain::parse(tokens).unwrap_or_else(|err| exit_with_chain_error(err, tokens));
+    if dry_run {
+        let mut wav_file = WavFile::open(Path::new(input)).expect("Unable to read file!");
+        let reader = SampleReader::new(&mut wav_file).expect("Unsupported sample format!");
+        let input_format = reader.format();
+        let formats = chain
+            .resolve(input_format)
+            .unwrap_or_else(|err| exit_with_chain_error(err, tokens));
+        println!("Input: {input_format}");
+        for (effect, stream) in chain.effects().zip(&formats) {
+            println!("  {effect}: {stream}");
         }
-        // println!("{chunk_id:?}");
-    });
+        let last = formats.last().copied().unwrap_or(input_format);
+        let sample_format = format.unwrap_or(last.sample_format);
+        println!(
+            "Output: {output} as {sample_format:?}, {} channels at {} Hz{}",
+            last.num_channels,
+            last.sample_rate,
+            if last.needs_dither(sample_format) {
+                " with TPDF dither"
+            } else {
+                ""
+            }
+        );
+        return;
+    }
+    let report = effects::process_file(Path::new(input), Path::new(output), &chain, format)
+        .unwrap_or_else(|err| exit_with_chain_error(err, tokens));
+    println!(
+        "Wrote {} frames, peak {:.2} dBFS, {} samples clipped",
+        report.frames,
+        linear_to_db(report.peak),
+        report.clipped_samples
+    );
+}
 
-    let bytes_per_frame = ((header.fmt.num_channels * header.fmt.bits_per_sample) / 8) as u32;
+/// Plays the audio queued in `session` on the default output
+fn play(session: &AudioSession) {
+    let source = &session.source_description;
+    let queued = session.queued();
+    let bytes_per_frame = ((source.num_channels * source.bits_per_sample) / 8) as u32;
 
     let description = AudioStreamBasicDescription {
-        mSampleRate: header.fmt.sample_rate as f64,
+        mSampleRate: source.sample_rate as f64,
         mFormatID: rwav::utils::ascii_str_transmute_u32_be("lpcm").expect("Unable to transmute!"),
         mFormatFlags: kAudioFormatFlagIsPacked | kAudioFormatFlagIsSignedInteger,
         mBytesPerPacket: bytes_per_frame,
         mFramesPerPacket: 1u32,
         mBytesPerFrame: bytes_per_frame,
-        mChannelsPerFrame: header.fmt.num_channels as u32,
-        mBitsPerChannel: header.fmt.bits_per_sample as u32,
+        mChannelsPerFrame: source.num_channels as u32,
+        mBitsPerChannel: source.bits_per_sample as u32,
         mReserved: 0,
     };
 
@@ -75,18 +149,15 @@ fn main() {
             &mut audio_queue,
         );
 
-        let chunk = data_chunk.unwrap();
-        let _alloc_status = AudioQueueAllocateBuffer(
-            audio_queue,
-            chunk.chunk_header.chunk_size,
-            &mut audio_buffer,
-        );
-        (*audio_buffer).mAudioDataByteSize = chunk.chunk_header.chunk_size;
+        // The session refuses to queue more than a buffer can hold
+        let size = u32::try_from(queued.len()).expect("Queued audio is too long to play!");
+        let _alloc_status = AudioQueueAllocateBuffer(audio_queue, size, &mut audio_buffer);
+        (*audio_buffer).mAudioDataByteSize = size;
 
-        let raw_data_ptr: *const c_void = chunk.data.as_ptr() as *const c_void;
+        let raw_data_ptr: *const c_void = queued.as_ptr() as *const c_void;
         (*audio_buffer)
             .mAudioData
-            .copy_from(raw_data_ptr, chunk.chunk_header.chunk_size as usize);
+            .copy_from(raw_data_ptr, queued.len());
 
         let _enqueue_status = AudioQueueEnqueueBuffer(audio_queue, audio_buffer, 0, ptr::null());
         let _start_status = AudioQueueStart(audio_queue, ptr::null());
@@ -103,3 +174,297 @@ fn main() {
         // println!("{:?}", *audio_queue);
     }
 }
+
+fn generate(
+    signal: Signal,
+    output: Option<&str>,
+    description: SourceDescription,
+    duration: f64,
+    level_db: f64,
+) {
+    if let Some(output) = output {
+        let report =
+            generator::generate_file(Path::new(output), signal, &description, duration, level_db)
+                .expect("Unable to generate file!");
+        println!(
+            "Wrote {} frames, peak {:.2} dBFS, {} samples clipped",
+            report.frames,
+            linear_to_db(report.peak),
+            report.clipped_samples
+        );
+        return;
+    }
+    let generator = Generator::new(signal, &description, duration, level_db)
+        .expect("Unable to generate signal!");
+    let mut session = AudioSession::new(0, description, None);
+    let mut pipeline = Pipeline::new(generator);
+    pipeline.sink(&mut session).expect("Unable to play signal!");
+    pipeline.run().expect("Unable to play signal!");
+    play(&session);
+}
+
+fn diff(a: &str, b: &str, settings: DiffSettings, difference: Option<&str>) {
+    let report = diff::diff_files(
+        Path::new(a),
+        Path::new(b),
+        settings,
+        difference.map(Path::new),
+    )
+    .expect("Unable to compare files!");
+    for header_difference in &report.header_differences {
+        println!("Header {header_difference}");
+    }
+    for chunk_difference in &report.chunk_differences {
+        println!("{chunk_difference}");
+    }
+    match report.audio {
+        None => println!("Audio not compared, the channel counts differ"),
+        Some(audio) => match audio.first_difference {
+            None => println!(
+                "Audio matches over {} frames, max difference {:.2} dBFS",
+                audio.frames, audio.max_difference
+            ),
+            Some(frame) => println!(
+                "Audio differs from frame {frame}: {} samples differ, max difference {:.2} dBFS at frame {}, residual RMS {:.2} dBFS",
+                audio.differing_samples,
+                audio.max_difference,
+                audio.max_difference_frame,
+                audio.residual_rms
+            ),
+        },
+    }
+    if !report.is_match() {
+        std::process::exit(1);
+    }
+}
+
+fn hash(input: &str, write_md5: Option<&str>, verify_md5: bool) {
+    let input = Path::new(input);
+    let content_hash = hash::content_hash(input).expect("Unable to hash audio!");
+    println!("{content_hash}  {}", input.display());
+    if let Some(output) = write_md5 {
+        let md5 = hash::write_md5(input, Path::new(output)).expect("Unable to write MD5 chunk!");
+        println!("Wrote MD5 chunk {md5} to {output}");
+    }
+    if verify_md5 {
+        match hash::verify_md5(input).expect("Unable to verify MD5 chunk!") {
+            Md5Check::Match(md5) => println!("MD5 chunk {md5} matches the audio"),
+            Md5Check::Mismatch { stored, computed } => {
+                println!("MD5 chunk {stored} doesn't match the audio's {computed}");
+                std::process::exit(1);
+            }
+            Md5Check::Missing => {
+                println!("File has no MD5 chunk");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    if let Some(Command::Process {
+        dry_run,
+        format,
+        input,
+        output,
+        effects,
+    }) = &cli.command
+    {
+        process(input, output, effects, format.map(Into::into), *dry_run);
+        return;
+    }
+    if let Some(Command::Diff {
+        a,
+        b,
+        tolerance,
+        difference,
+    }) = &cli.command
+    {
+        let settings = DiffSettings {
+            tolerance_db: *tolerance,
+        };
+        diff(a, b, settings, difference.as_deref());
+        return;
+    }
+    if let Some(Command::Hash {
+        input,
+        write_md5,
+        verify_md5,
+    }) = &cli.command
+    {
+        hash(input, write_md5.as_deref(), *verify_md5);
+        return;
+    }
+    if let Some(Command::Generate {
+        signal,
+        output,
+        duration,
+        level,
+        sample_rate,
+        channels,
+        bits,
+    }) = &cli.command
+    {
+        let description = SourceDescription {
+            bits_per_sample: *bits,
+            num_channels: *channels,
+            sample_rate: *sample_rate,
+        };
+        generate(
+            signal.clone(),
+            output.as_deref(),
+            description,
+            *duration,
+            *level,
+        );
+        return;
+    }
+
+    #[cfg(feature = "serde")]
+    if run_metadata_commands(&cli) {
+        return;
+    }
+
+    let file_path = Path::new(cli.input.as_deref().expect("--input is required!"));
+    if cli.loudness {
+        let report = match &cli.output {
+            Some(output) => loudness::write_file_loudness(file_path, Path::new(output)),
+            None => loudness::measure_file(file_path).map_err(Into::into),
+        }
+        .expect("Unable to measure loudness!");
+        let level = |value: Option<f64>, unit: &str| match value {
+            Some(value) => format!("{value:.1} {unit}"),
+            None => "not measurable".to_string(),
+        };
+        println!("Integrated loudness: {}", level(report.integrated, "LUFS"));
+        println!("Momentary max: {}", level(report.momentary_max, "LUFS"));
+        println!("Short-term max: {}", level(report.short_term_max, "LUFS"));
+        println!("Loudness range: {}", level(report.loudness_range, "LU"));
+        println!("True peak: {:.1} dBTP", report.true_peak);
+        return;
+    }
+
+    if cli.stats {
+        let report =
+            stats::measure_file(file_path, Default::default()).expect("Unable to analyze file!");
+        println!("{} frames", report.frames);
+        for (index, channel) in report.channels.iter().enumerate() {
+            println!(
+                "Channel {}: peak {:.2} dBFS at frame {}, RMS {:.2} dBFS, DC offset {:.6}, crest factor {}, {} clipped runs",
+                index + 1,
+                channel.peak,
+                channel.peak_frame,
+                channel.rms,
+                channel.dc_offset,
+                channel
+                    .crest_factor
+                    .map(|crest_factor| format!("{crest_factor:.2} dB"))
+                    .unwrap_or_else(|| "n/a".to_string()),
+                channel.clipped_run_count
+            );
+            for run in &channel.clipped_runs {
+                println!(
+                    "  Clipped from frame {} for {}",
+                    run.start_frame, run.length
+                );
+            }
+        }
+        for silence in &report.silences {
+            println!(
+                "{:?} silence from frame {} to {}",
+                silence.kind, silence.start_frame, silence.end_frame
+            );
+        }
+        return;
+    }
+
+    let processors: Vec<Dynamics> = [
+        cli.gate.map(Dynamics::Gate),
+        cli.compress.map(Dynamics::Compressor),
+        cli.limit.map(Dynamics::Limiter),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !processors.is_empty() {
+        let output = cli.output.as_deref().expect("--output is required!");
+        let report = dynamics::dynamics_file(file_path, Path::new(output), &processors)
+            .expect("Unable to process file!");
+        println!(
+            "Wrote {} frames, peak {:.2} dBFS, {} samples clipped",
+            report.frames,
+            linear_to_db(report.peak),
+            report.clipped_samples
+        );
+        return;
+    }
+
+    if let (Some(sample_rate), Some(output)) = (cli.sample_rate, &cli.output) {
+        let report = resample::resample_file(
+            file_path,
+            Path::new(output),
+            sample_rate,
+            cli.resample_quality.into(),
+        )
+        .expect("Unable to resample file!");
+        println!(
+            "Wrote {} frames at {sample_rate} Hz, {} samples clipped",
+            report.frames, report.clipped_samples
+        );
+        return;
+    }
+
+    let wav_file = WavFile::new(file_path);
+    let header = wav_file.header;
+    print!("{header:?}");
+
+    let device_ids = rwav::audio::Audio::get_device_ids().unwrap();
+    let name = rwav::audio::Audio::get_device_name(&device_ids[1]);
+
+    wav_file.for_each(|chunk| {
+        // let chunk_id: &str = chunk.chunk_header.chunk_id.as_ascii().unwrap();
+        let chunk_id = String::from_utf8(chunk.chunk_header.chunk_id.to_vec()).unwrap();
+        match chunk_id.as_str() {
+            "info" => {
+                println!("Found INFO block!");
+            }
+            "data" => {
+                println!("Found DATA block!");
+            }
+            _ => (),
+        }
+        // println!("{chunk_id:?}");
+    });
+
+    // The device gets the file's own channels and bit depth, at --sample-rate if it can't play
+    // the file's rate
+    let sample_rate = cli.sample_rate.unwrap_or(header.fmt.sample_rate);
+    let mut session = AudioSession::new(
+        0,
+        SourceDescription {
+            bits_per_sample: header.fmt.bits_per_sample,
+            num_channels: header.fmt.num_channels,
+            sample_rate,
+        },
+        device_ids.get(1).copied(),
+    );
+    let mut wav_file = WavFile::open(file_path).expect("Unable to read file!");
+    let reader = SampleReader::new(&mut wav_file).expect("Unsupported sample format!");
+    let mut pipeline = Pipeline::new(reader);
+    if sample_rate != header.fmt.sample_rate {
+        let resampler = Resampler::new(
+            header.fmt.num_channels,
+            header.fmt.sample_rate,
+            sample_rate,
+            cli.resample_quality.into(),
+        )
+        .expect("Unable to resample file!");
+        pipeline.push(resampler).expect("Unable to resample file!");
+    }
+    pipeline.sink(&mut session).expect("Unable to play file!");
+    pipeline.run().expect("Unable to play file!");
+
+    play(&session);
+}
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    chunks::{
+        acid::AcidChunk,
+        adm::{AxmlChunk, ChnaChunk},
+        adtl::AdtlChunk,
+        bext::BextChunk,
+        cart::CartChunk,
+        cue::CueChunk,
+        ixml::IxmlChunk,
+        levl::LevlChunk,
+        md5::Md5Chunk,
+        smpl::SmplChunk,
+        ChunkError, TypedChunk,
+    },
+    wav::{ChunkIndexEntry, WavError, WavFile, WavHeader, WavWriter},
+};
+
+/// Everything rwav knows about a file apart from the audio itself, in a form that can be
+/// exported to JSON or YAML and applied back onto the file after editing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WavMetadata {
+    /// Informational, `apply` never changes the format of the audio
+    pub header: WavHeader,
+    /// Informational, `apply` keeps the chunk order of the target file
+    pub chunks: Vec<ChunkIndexEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bext: Option<BextChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acid: Option<AcidChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cart: Option<CartChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cue: Option<CueChunk>,
+    /// Labels, notes and regions of the cue points. Sub-chunks rwav doesn't interpret are
+    /// left out, and `apply` keeps the file's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adtl: Option<AdtlChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smpl: Option<SmplChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub levl: Option<LevlChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chna: Option<ChnaChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub axml: Option<AxmlChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ixml: Option<IxmlChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<Md5Chunk>,
+}
+
+impl WavMetadata {
+    /// Reads the header, chunk index and every typed chunk, without loading the audio
+    pub fn read(wav_file: &mut WavFile) -> Result<Self, MetadataError> {
+        let chunks = wav_file.chunk_index()?;
+        let mut metadata = WavMetadata {
+            header: wav_file.header,
+            chunks: chunks.clone(),
+            bext: None,
+            acid: None,
+            cart: None,
+            cue: None,
+            adtl: None,
+            smpl: None,
+            levl: None,
+            chna: None,
+            axml: None,
+            ixml: None,
+            md5: None,
+        };
+        for entry in &chunks {
+            let chunk_id = entry.chunk_header.chunk_id;
+            if chunk_id == BextChunk::ID {
+                metadata.bext = Some(BextChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == AcidChunk::ID {
+                metadata.acid = Some(AcidChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == CartChunk::ID {
+                metadata.cart = Some(CartChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == CueChunk::ID {
+                metadata.cue = Some(CueChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == AdtlChunk::ID {
+                // Only one of the LIST chunks, the rest being INFO and the like
+                let chunk = wav_file.read_chunk(entry)?;
+                if AdtlChunk::is_adtl(&chunk) {
+                    metadata.adtl = Some(AdtlChunk::from_chunk(&chunk)?);
+                }
+            } else if chunk_id == SmplChunk::ID {
+                metadata.smpl = Some(SmplChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == LevlChunk::ID {
+                metadata.levl = Some(LevlChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == ChnaChunk::ID {
+                metadata.chna = Some(ChnaChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == AxmlChunk::ID {
+                metadata.axml = Some(AxmlChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == IxmlChunk::ID {
+                metadata.ixml = Some(IxmlChunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            } else if chunk_id == Md5Chunk::ID {
+                metadata.md5 = Some(Md5Chunk::from_chunk(&wav_file.read_chunk(entry)?)?);
+            }
+        }
+        Ok(metadata)
+    }
+
+    pub fn to_json(&self) -> Result<String, MetadataError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, MetadataError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, MetadataError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, MetadataError> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Rewrites `input` to `output` with every typed chunk present in `self`. Chunks missing
+    /// from `self` are left as they are in the file rather than removed.
+    pub fn apply(&self, input: &Path, output: &Path) -> Result<(), MetadataError> {
+        let mut wav_file = WavFile::open(input)?;
+        let mut writer = WavWriter::new(wav_file.header.fmt);
+        writer.fmt_extension = wav_file.fmt_extension()?;
+        while let Some(chunk) = wav_file.next_chunk()? {
+            writer.chunks.push(chunk);
+        }
+        if let Some(bext) = &self.bext {
+            writer.set_chunk(bext.to_chunk());
+        }
+        if let Some(acid) = &self.acid {
+            writer.set_chunk(acid.to_chunk());
+        }
+        if let Some(cart) = &self.cart {
+            cart.validate()?;
+            writer.set_chunk(cart.to_chunk());
+        }
+        if let Some(cue) = &self.cue {
+            writer.set_chunk(cue.to_chunk());
+        }
+        if let Some(adtl) = &self.adtl {
+            // `set_chunk` would replace whichever LIST chunk comes first
+            let mut adtl = adtl.clone();
+            match writer
+                .chunks
+                .iter_mut()
+                .find(|chunk| AdtlChunk::is_adtl(chunk))
+            {
+                Some(chunk) => {
+                    adtl.other = AdtlChunk::from_chunk(chunk)?.other;
+                    *chunk = adtl.to_chunk();
+                }
+                None => writer.chunks.push(adtl.to_chunk()),
+            }
+        }
+        if let Some(smpl) = &self.smpl {
+            writer.set_chunk(smpl.to_chunk());
+        }
+        if let Some(levl) = &self.levl {
+            writer.set_chunk(levl.to_chunk());
+        }
+        if let Some(chna) = &self.chna {
+            chna.validate(&wav_file.header.fmt)?;
+            writer.set_chunk(chna.to_chunk());
+        }
+        if let Some(axml) = &self.axml {
+            writer.set_chunk(axml.to_chunk());
+        }
+        if let Some(ixml) = &self.ixml {
+            writer.set_chunk(ixml.to_chunk());
+        }
+        if let Some(md5) = &self.md5 {
+            writer.set_chunk(md5.to_chunk());
+        }
+        writer.write(output)?;
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MetadataError {
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+/// Serializes four character codes such as chunk ids as strings
+pub mod fourcc {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(id: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(id))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+        let id = String::deserialize(deserializer)?;
+        id.as_bytes()
+            .try_into()
+            .map_err(|_| D::Error::custom(format!("{id:?} is not a four character code")))
+    }
+}
+
+/// Serializes fixed width, NUL padded text fields as strings
+pub mod fixed_str {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<const N: usize, S: Serializer>(
+        field: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let end = field.iter().position(|&b| b == 0).unwrap_or(N);
+        serializer.serialize_str(&String::from_utf8_lossy(&field[..end]))
+    }
+
+    pub fn deserialize<'de, const N: usize, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value.len() > N {
+            return Err(D::Error::custom(format!(
+                "{value:?} doesn't fit in {N} bytes"
+            )));
+        }
+        let mut field = [0u8; N];
+        field[..value.len()].copy_from_slice(value.as_bytes());
+        Ok(field)
+    }
+}
+
+/// Serializes text stored as raw bytes, such as XML, as a string
+pub mod lossy_str {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(String::deserialize(deserializer)?.into_bytes())
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{
+    chunks::{levl::LevlChunk, TypedChunk},
+    dsp::convert::{
+        is_word_length_reduced, ConversionReport, ConvertSettings, Dither, Requantizer,
+        BLOCK_FRAMES,
+    },
+    samples::{open_with_metadata, SampleError, SampleFormat, SampleReader, SampleWriter},
+    wav::{Chunk, WavError},
+};
+
+/// What flows between two stages of a pipeline: blocks of interleaved, normalized frames in
+/// this layout
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub num_channels: u16,
+    pub sample_rate: u32,
+    /// Speaker positions, for files written as WAVE_FORMAT_EXTENSIBLE
+    pub channel_mask: Option<u32>,
+    /// Format the audio came in as, which sinks write unless told otherwise
+    pub sample_format: SampleFormat,
+    /// Every sample is still on the grid of `sample_format`, so it can be written back without
+    /// dither
+    pub exact: bool,
+    /// Frames the stream will carry, if known up front
+    pub num_frames: Option<u64>,
+}
+
+impl StreamFormat {
+    /// Checks a stage built for `num_channels` at `sample_rate` can take this stream
+    pub fn check(
+        &self,
+        stage: &str,
+        num_channels: u16,
+        sample_rate: u32,
+    ) -> Result<(), PipelineError> {
+        if self.num_channels != num_channels || self.sample_rate != sample_rate {
+            return Err(PipelineError::Incompatible {
+                stage: stage.to_string(),
+                reason: format!(
+                    "it was set up for {num_channels} channels at {sample_rate} Hz, not {self}"
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether writing the stream as `to` calls for dither: an integer target the samples
+    /// aren't already on the grid of
+    pub fn needs_dither(&self, to: SampleFormat) -> bool {
+        !to.is_float() && (!self.exact || is_word_length_reduced(self.sample_format, to))
+    }
+}
+
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} channels at {} Hz from {:?}",
+            self.num_channels, self.sample_rate, self.sample_format
+        )?;
+        if let Some(num_frames) = self.num_frames {
+            write!(f, ", {num_frames} frames")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PipelineError {
+    #[error("Can't connect {stage}: {reason}!")]
+    Incompatible { stage: String, reason: String },
+    #[error("Pipeline has nowhere to send its output!")]
+    NoSink,
+    #[error("Stream is too long for {stage}, which holds at most {limit} bytes!")]
+    TooLong { stage: String, limit: u64 },
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// Where a pipeline's audio comes from: a file being read, a generator
+pub trait Source {
+    fn format(&self) -> StreamFormat;
+
+    /// Replaces the contents of `out` with up to `max_frames` frames and returns how many it
+    /// read, 0 once the source is exhausted
+    fn read(&mut self, max_frames: usize, out: &mut Vec<f64>) -> Result<usize, PipelineError>;
+}
+
+/// A stage that transforms the stream: gain, filters, dynamics, resampling, mixing
+pub trait Processor {
+    /// Checks the processor can take `input` and returns the format it produces from it
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError>;
+
+    /// Processes a block in place. The output can hold a different number of frames than the
+    /// input, for processors that change the rate or hold audio back.
+    fn process(&mut self, samples: &mut Vec<f64>);
+
+    /// Called once after the last block, replacing the contents of `out` with any frames the
+    /// processor still holds
+    fn flush(&mut self, out: &mut Vec<f64>) {
+        out.clear();
+    }
+}
+
+/// Where a pipeline's audio ends up: a file, a playback device, a meter
+pub trait Sink {
+    /// Checks the sink can take `format` and gets ready to receive it
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError>;
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError>;
+
+    /// Called once after the last block
+    fn close(&mut self) -> Result<(), PipelineError> {
+        Ok(())
+    }
+}
+
+impl<P: Processor + ?Sized> Processor for Box<P> {
+    fn configure(&mut self, input: StreamFormat) -> Result<StreamFormat, PipelineError> {
+        (**self).configure(input)
+    }
+
+    fn process(&mut self, samples: &mut Vec<f64>) {
+        (**self).process(samples)
+    }
+
+    fn flush(&mut self, out: &mut Vec<f64>) {
+        (**self).flush(out)
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError> {
+        (**self).configure(format)
+    }
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        (**self).write(samples)
+    }
+
+    fn close(&mut self) -> Result<(), PipelineError> {
+        (**self).close()
+    }
+}
+
+/// A source feeding a chain of processors whose output goes to every sink. Formats are
+/// negotiated as stages are added, so a chain that can't work fails before any audio moves,
+/// and `run` streams the source through in blocks of `BLOCK_FRAMES`.
+pub struct Pipeline<'a> {
+    source: Box<dyn Source + 'a>,
+    processors: Vec<Box<dyn Processor + 'a>>,
+    sinks: Vec<Box<dyn Sink + 'a>>,
+    format: StreamFormat,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(source: impl Source + 'a) -> Self {
+        let format = source.format();
+        Pipeline {
+            source: Box::new(source),
+            processors: Vec::new(),
+            sinks: Vec::new(),
+            format,
+        }
+    }
+
+    /// Format leaving the last processor, which the next stage added will receive
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Appends a processor to the chain
+    pub fn push(&mut self, mut processor: impl Processor + 'a) -> Result<&mut Self, PipelineError> {
+        self.format = processor.configure(self.format)?;
+        self.processors.push(Box::new(processor));
+        Ok(self)
+    }
+
+    /// Adds a sink receiving the output of the whole chain. Pass `&mut sink` to keep hold of a
+    /// meter and read it after `run`.
+    pub fn sink(&mut self, mut sink: impl Sink + 'a) -> Result<&mut Self, PipelineError> {
+        sink.configure(self.format)?;
+        self.sinks.push(Box::new(sink));
+        Ok(self)
+    }
+
+    /// Streams the whole source through and closes the sinks. Returns the number of frames
+    /// each sink received.
+    pub fn run(self) -> Result<u64, PipelineError> {
+        let Pipeline {
+            mut source,
+            mut processors,
+            mut sinks,
+            format,
+        } = self;
+        if sinks.is_empty() {
+            return Err(PipelineError::NoSink);
+        }
+        let num_channels = format.num_channels as usize;
+        let mut frames = 0;
+        let mut samples = Vec::new();
+        while source.read(BLOCK_FRAMES, &mut samples)? > 0 {
+            frames += run_block(&mut processors, &mut sinks, num_channels, &mut samples)?;
+        }
+        // Closing a sink can move its file over the source's, which Windows refuses while the
+        // source still has it open
+        drop(source);
+        // A processor's held back frames still go through the rest of the chain
+        for index in 0..processors.len() {
+            processors[index].flush(&mut samples);
+            frames += run_block(
+                &mut processors[index + 1..],
+                &mut sinks,
+                num_channels,
+                &mut samples,
+            )?;
+        }
+        for sink in &mut sinks {
+            sink.close()?;
+        }
+        Ok(frames)
+    }
+}
+
+/// Sends a block through `processors` and into `sinks`, returning the number of frames that
+/// came out
+fn run_block(
+    processors: &mut [Box<dyn Processor + '_>],
+    sinks: &mut [Box<dyn Sink + '_>],
+    num_channels: usize,
+    samples: &mut Vec<f64>,
+) -> Result<u64, PipelineError> {
+    for processor in processors {
+        if samples.is_empty() {
+            break;
+        }
+        processor.process(samples);
+    }
+    if samples.is_empty() {
+        return Ok(0);
+    }
+    for sink in sinks {
+        sink.write(samples)?;
+    }
+    Ok((samples.len() / num_channels) as u64)
+}
+
+impl Source for SampleReader {
+    fn format(&self) -> StreamFormat {
+        StreamFormat {
+            num_channels: self.num_channels,
+            sample_rate: self.sample_rate,
+            channel_mask: self.channel_mask,
+            sample_format: self.format,
+            exact: true,
+            num_frames: Some(self.num_frames - self.frames_read),
+        }
+    }
+
+    fn read(&mut self, max_frames: usize, out: &mut Vec<f64>) -> Result<usize, PipelineError> {
+        Ok(self.read_frames(max_frames, out)?)
+    }
+}
+
+/// Writes the stream to a new wav file, requantizing to its sample format. Dither is only
+/// added when the samples aren't already on the target's grid.
+pub struct WavSink {
+    path: PathBuf,
+    format: Option<SampleFormat>,
+    leading: Vec<Chunk>,
+    trailing: Vec<Chunk>,
+    writer: Option<SampleWriter>,
+    requantizer: Option<Requantizer>,
+    /// Requantized copy of the block being written
+    buffer: Vec<f64>,
+    frames: u64,
+}
+
+impl WavSink {
+    /// A sink writing `path` in `format`, or the stream's own sample format if `None`, with
+    /// `leading` chunks before the data chunk and `trailing` ones after
+    pub fn new(
+        path: &Path,
+        format: Option<SampleFormat>,
+        leading: Vec<Chunk>,
+        trailing: Vec<Chunk>,
+    ) -> Self {
+        WavSink {
+            path: path.to_path_buf(),
+            format,
+            leading,
+            trailing,
+            writer: None,
+            requantizer: None,
+            buffer: Vec::new(),
+            frames: 0,
+        }
+    }
+
+    /// Frames written, clipping and peak level, complete once the pipeline has run
+    pub fn report(&self) -> ConversionReport {
+        let requantizer = self.requantizer.as_ref();
+        ConversionReport {
+            frames: self.frames,
+            clipped_samples: requantizer.map_or(0, |requantizer| requantizer.clipped_samples),
+            peak: requantizer.map_or(0.0, |requantizer| requantizer.peak),
+        }
+    }
+}
+
+impl Sink for WavSink {
+    fn configure(&mut self, format: StreamFormat) -> Result<(), PipelineError> {
+        let sample_format = self.format.unwrap_or(format.sample_format);
+        let mut settings = ConvertSettings::new(sample_format);
+        if !format.needs_dither(sample_format) {
+            settings.dither = Dither::None;
+        }
+        self.requantizer = Some(Requantizer::new(format.num_channels, settings));
+        self.writer = Some(SampleWriter::create(
+            &self.path,
+            sample_format,
+            format.num_channels,
+            format.sample_rate,
+            format.channel_mask,
+            &self.leading,
+        )?);
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f64]) -> Result<(), PipelineError> {
+        let (Some(writer), Some(requantizer)) = (&mut self.writer, &mut self.requantizer) else {
+            return Ok(());
+        };
+        self.buffer.clear();
+        self.buffer.extend_from_slice(samples);
+        requantizer.process(&mut self.buffer);
+        Ok(writer.write_frames(&self.buffer)?)
+    }
+
+    fn close(&mut self) -> Result<(), PipelineError> {
+        if let Some(writer) = self.writer.take() {
+            self.frames = writer.finish(&self.trailing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens `input` for reading and a `WavSink` writing `output` with all of its metadata, the
+/// two ends of most file to file pipelines
+pub fn open_file_pipeline(
+    input: &Path,
+    output: &Path,
+    format: Option<SampleFormat>,
+) -> Result<(SampleReader, WavSink), PipelineError> {
+    let (reader, leading, trailing) = open_with_metadata(input)?;
+    Ok((reader, WavSink::new(output, format, leading, trailing)))
+}
+
+/// Drops the metadata chunks summarizing audio a file operation has changed, so the output
+/// doesn't carry them over stale. That's the levl peak envelope.
+pub fn drop_stale_chunks(leading: &mut Vec<Chunk>, trailing: &mut Vec<Chunk>) {
+    for chunks in [leading, trailing] {
+        chunks.retain(|chunk| chunk.chunk_header.chunk_id != LevlChunk::ID);
+    }
+}
@@ -0,0 +1,307 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::{
+    channels::{ChannelMask, Speaker},
+    chunks::{
+        adm::ChnaChunk,
+        ixml::{IxmlChunk, IxmlTrack},
+        levl::LevlChunk,
+        ChunkError, TypedChunk,
+    },
+    dsp::convert::BLOCK_FRAMES,
+    samples::{SampleError, SampleFormat, SampleReader, SampleWriter},
+    wav::{Chunk, WavError, WavFile},
+};
+
+#[derive(Error, Debug)]
+pub enum PolyError {
+    #[error("Nothing to merge!")]
+    NoInputs,
+    #[error("{path:?} is {found} Hz, expected {expected} Hz!")]
+    RateMismatch {
+        path: PathBuf,
+        expected: u32,
+        found: u32,
+    },
+    #[error("{path:?} is {found} frames long, expected {expected}!")]
+    LengthMismatch {
+        path: PathBuf,
+        expected: u64,
+        found: u64,
+    },
+    #[error("{path:?} is {found:?}, expected {expected:?}!")]
+    FormatMismatch {
+        path: PathBuf,
+        expected: SampleFormat,
+        found: SampleFormat,
+    },
+    #[error(transparent)]
+    Sample(#[from] SampleError),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+}
+
+/// Metadata chunks of a poly or mono file, with the ones that describe individual channels
+/// (levl, chna) dropped and iXML pulled out so its track list can be rewritten
+struct SharedChunks {
+    leading: Vec<Chunk>,
+    trailing: Vec<Chunk>,
+    ixml: Option<IxmlChunk>,
+}
+
+impl SharedChunks {
+    fn read(wav_file: &mut WavFile) -> Result<Self, PolyError> {
+        let (mut leading, mut trailing) = wav_file.metadata_chunks()?;
+        let mut ixml = None;
+        for chunks in [&mut leading, &mut trailing] {
+            for chunk in chunks.iter() {
+                if chunk.chunk_header.chunk_id == IxmlChunk::ID {
+                    ixml = Some(IxmlChunk::from_chunk(chunk)?);
+                }
+            }
+            chunks.retain(|chunk| {
+                let chunk_id = chunk.chunk_header.chunk_id;
+                chunk_id != IxmlChunk::ID && chunk_id != LevlChunk::ID && chunk_id != ChnaChunk::ID
+            });
+        }
+        Ok(SharedChunks {
+            leading,
+            trailing,
+            ixml,
+        })
+    }
+
+    /// Leading chunks with an iXML chunk listing `tracks` added
+    fn leading_with_tracks(&self, tracks: &[IxmlTrack]) -> Vec<Chunk> {
+        let mut leading = self.leading.clone();
+        leading.push(IxmlChunk::with_tracks(self.ixml.as_ref(), tracks).to_chunk());
+        leading
+    }
+}
+
+/// Splits a poly file into one mono file per channel in `output_dir`, named
+/// `<stem>_<track>.wav`. Track names come from the iXML track list, then the speaker labels of
+/// the channel mask (or the standard layout for the channel count), then the channel number.
+/// bext, iXML and the other metadata chunks are copied to every file, with the iXML track list
+/// narrowed to the file's own track.
+pub fn split(input: &Path, output_dir: &Path) -> Result<Vec<PathBuf>, PolyError> {
+    let mut wav_file = WavFile::open(input)?;
+    let shared = SharedChunks::read(&mut wav_file)?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    let num_channels = reader.num_channels as usize;
+
+    let names = track_names(&reader, shared.ixml.as_ref());
+    let tracks = shared
+        .ixml
+        .as_ref()
+        .map(IxmlChunk::tracks)
+        .unwrap_or_default();
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut paths = Vec::with_capacity(num_channels);
+    let mut writers = Vec::with_capacity(num_channels);
+    for (index, name) in names.iter().enumerate() {
+        let interleave_index = index as u16 + 1;
+        let track = tracks
+            .iter()
+            .find(|track| track.interleave_index == interleave_index)
+            .cloned()
+            .unwrap_or(IxmlTrack {
+                channel_index: interleave_index,
+                name: name.clone(),
+                ..Default::default()
+            });
+        let path = output_dir.join(format!("{stem}_{}.wav", file_name_safe(name)));
+        writers.push(SampleWriter::create(
+            &path,
+            reader.format,
+            1,
+            reader.sample_rate,
+            None,
+            &shared.leading_with_tracks(&[IxmlTrack {
+                interleave_index: 1,
+                ..track
+            }]),
+        )?);
+        paths.push(path);
+    }
+
+    let mut samples = Vec::new();
+    let mut channel = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        for (index, writer) in writers.iter_mut().enumerate() {
+            channel.clear();
+            channel.extend(samples.iter().skip(index).step_by(num_channels));
+            writer.write_frames(&channel)?;
+        }
+    }
+    for writer in writers {
+        writer.finish(&shared.trailing)?;
+    }
+    Ok(paths)
+}
+
+/// Interleaves `inputs` into one poly file, in order. Every input must have the same sample
+/// rate, format and length. Metadata comes from the first input, with an iXML track list naming
+/// each channel after its input's own iXML track name or file stem. If those names are speaker
+/// labels in channel mask order, e.g. the output of `split`, the file gets that channel mask.
+pub fn merge(inputs: &[&Path], output: &Path) -> Result<u64, PolyError> {
+    if inputs.is_empty() {
+        return Err(PolyError::NoInputs);
+    }
+    let mut shared = None;
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut tracks = Vec::new();
+    for path in inputs {
+        let mut wav_file = WavFile::open(path)?;
+        let chunks = SharedChunks::read(&mut wav_file)?;
+        let ixml = chunks.ixml.clone();
+        shared.get_or_insert(chunks);
+        let reader = SampleReader::new(&mut wav_file)?;
+        if let Some(expected) = readers.first() {
+            check_matches(path, expected, &reader)?;
+        }
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let names = track_names(&reader, ixml.as_ref());
+        for (index, name) in names.into_iter().enumerate() {
+            let interleave_index = tracks.len() as u16 + 1;
+            let ixml_name = ixml
+                .as_ref()
+                .and_then(|ixml| ixml.track_name(index as u16 + 1));
+            let name = match (reader.num_channels, ixml_name) {
+                (_, Some(ixml_name)) => ixml_name,
+                (1, None) => stem.clone(),
+                (_, None) => format!("{stem} {name}"),
+            };
+            tracks.push(IxmlTrack {
+                channel_index: interleave_index,
+                interleave_index,
+                name,
+                function: String::new(),
+            });
+        }
+        readers.push(reader);
+    }
+
+    let shared = shared.ok_or(PolyError::NoInputs)?;
+    let format = readers[0].format;
+    let sample_rate = readers[0].sample_rate;
+    let num_channels = tracks.len() as u16;
+    let mut writer = SampleWriter::create(
+        output,
+        format,
+        num_channels,
+        sample_rate,
+        speaker_mask(&tracks).map(|mask| mask.0),
+        &shared.leading_with_tracks(&tracks),
+    )?;
+
+    let mut blocks: Vec<Vec<f64>> = vec![Vec::new(); readers.len()];
+    let mut interleaved = Vec::new();
+    loop {
+        let mut num_frames = 0;
+        for (reader, block) in readers.iter_mut().zip(blocks.iter_mut()) {
+            num_frames = reader.read_frames(BLOCK_FRAMES, block)?;
+        }
+        if num_frames == 0 {
+            break;
+        }
+        interleaved.clear();
+        for frame in 0..num_frames {
+            for (reader, block) in readers.iter().zip(&blocks) {
+                let channels = reader.num_channels as usize;
+                interleaved.extend_from_slice(&block[frame * channels..(frame + 1) * channels]);
+            }
+        }
+        writer.write_frames(&interleaved)?;
+    }
+    Ok(writer.finish(&shared.trailing)?)
+}
+
+fn check_matches(
+    path: &Path,
+    expected: &SampleReader,
+    reader: &SampleReader,
+) -> Result<(), PolyError> {
+    if reader.sample_rate != expected.sample_rate {
+        return Err(PolyError::RateMismatch {
+            path: path.to_path_buf(),
+            expected: expected.sample_rate,
+            found: reader.sample_rate,
+        });
+    }
+    if reader.format != expected.format {
+        return Err(PolyError::FormatMismatch {
+            path: path.to_path_buf(),
+            expected: expected.format,
+            found: reader.format,
+        });
+    }
+    if reader.num_frames != expected.num_frames {
+        return Err(PolyError::LengthMismatch {
+            path: path.to_path_buf(),
+            expected: expected.num_frames,
+            found: reader.num_frames,
+        });
+    }
+    Ok(())
+}
+
+/// A unique name per channel, see `split`
+fn track_names(reader: &SampleReader, ixml: Option<&IxmlChunk>) -> Vec<String> {
+    let speakers = reader
+        .channel_mask
+        .map(ChannelMask)
+        .or(ChannelMask::default_for(reader.num_channels))
+        .map(|mask| mask.speakers())
+        .unwrap_or_default();
+    let mut names: Vec<String> = Vec::with_capacity(reader.num_channels as usize);
+    for index in 0..reader.num_channels as usize {
+        let mut name = ixml
+            .and_then(|ixml| ixml.track_name(index as u16 + 1))
+            .or_else(|| {
+                speakers
+                    .get(index)
+                    .map(|speaker| speaker.label().to_string())
+            })
+            .unwrap_or_else(|| (index + 1).to_string());
+        if names.contains(&name) {
+            name = format!("{name}_{}", index + 1);
+        }
+        names.push(name);
+    }
+    names
+}
+
+/// Channel mask spelled out by the track names, if every name is a speaker label and they're
+/// in channel mask order
+fn speaker_mask(tracks: &[IxmlTrack]) -> Option<ChannelMask> {
+    let speakers = tracks
+        .iter()
+        .map(|track| Speaker::from_label(&track.name))
+        .collect::<Option<Vec<Speaker>>>()?;
+    let in_order = speakers
+        .windows(2)
+        .all(|pair| pair[0].bit() < pair[1].bit());
+    (in_order && speakers.len() > 2).then(|| ChannelMask::from_speakers(&speakers))
+}
+
+pub(crate) fn file_name_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,282 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use thiserror::Error;
+
+use crate::{
+    samples::temp_path_for,
+    wav::{ChunkHeader, WavError, WavFile},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepairChange {
+    RiffSize {
+        from: u32,
+        to: u32,
+    },
+    DataSize {
+        from: u32,
+        to: u32,
+    },
+    /// Bytes of an incomplete last frame cut from the end of the data chunk
+    TrimmedPartialFrame {
+        bytes: u64,
+    },
+    /// Bytes of audio cut from the end because a RIFF file can't hold more than 4 GiB
+    TruncatedToRiffLimit {
+        bytes: u64,
+    },
+    /// Fewer than 8 bytes left at the end of the file, too short for a chunk header
+    DroppedPartialHeader {
+        offset: u64,
+        bytes: u64,
+    },
+    /// A chunk other than data that claims more bytes than the file holds
+    DroppedTruncatedChunk {
+        chunk_id: [u8; 4],
+        offset: u64,
+    },
+}
+
+impl fmt::Display for RepairChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepairChange::RiffSize { from, to } => write!(f, "RIFF size {from} -> {to}"),
+            RepairChange::DataSize { from, to } => write!(f, "data size {from} -> {to}"),
+            RepairChange::TrimmedPartialFrame { bytes } => {
+                write!(f, "trimmed {bytes} bytes of a partial frame")
+            }
+            RepairChange::TruncatedToRiffLimit { bytes } => {
+                write!(f, "cut {bytes} bytes of audio past the 4 GiB RIFF limit")
+            }
+            RepairChange::DroppedPartialHeader { offset, bytes } => {
+                write!(f, "dropped {bytes} byte partial chunk header at {offset}")
+            }
+            RepairChange::DroppedTruncatedChunk { chunk_id, offset } => write!(
+                f,
+                "dropped truncated {:?} chunk at {offset}",
+                String::from_utf8_lossy(chunk_id)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub changes: Vec<RepairChange>,
+}
+
+impl RepairReport {
+    /// True if the file was already consistent and was copied unchanged
+    pub fn is_unchanged(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RepairError {
+    #[error("Not a RIFF/WAVE file with a leading fmt chunk!")]
+    NotAWavFile,
+    #[error("fmt block_align is 0, frame size can't be recovered!")]
+    NoBlockAlign,
+    #[error("No data chunk found to recover!")]
+    MissingDataChunk,
+    #[error("IO error during repair: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+}
+
+/// A chunk to copy to the repaired file, with its possibly corrected size
+struct PlannedChunk {
+    chunk_header: ChunkHeader,
+    data_offset: u64,
+}
+
+/// Rebuilds the RIFF and data sizes of an interrupted recording from the real file length.
+/// The data chunk is trimmed to whole frames, trailing partial chunks are dropped, and the
+/// result is written to `output` atomically. `output` may be the same path as `input`.
+pub fn repair(input: &Path, output: &Path) -> Result<RepairReport, RepairError> {
+    let wav_file = WavFile::open(input)?;
+    let header = wav_file.header;
+    if &header.chunk_id != b"RIFF"
+        || &header.format != b"WAVE"
+        || &header.fmt.subchunk_1_id != b"fmt "
+    {
+        return Err(RepairError::NotAWavFile);
+    }
+    let block_align = header.fmt.block_align as u64;
+    if block_align == 0 {
+        return Err(RepairError::NoBlockAlign);
+    }
+
+    let mut source = wav_file.handle;
+    let file_len = source.metadata()?.len();
+    let prefix_len = header.chunks_offset();
+    if prefix_len > file_len {
+        return Err(RepairError::NotAWavFile);
+    }
+
+    let mut report = RepairReport::default();
+    let mut plan: Vec<PlannedChunk> = Vec::new();
+    let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+    let mut offset = prefix_len;
+    while offset < file_len {
+        let remaining = file_len - offset;
+        if remaining < header_size {
+            report.changes.push(RepairChange::DroppedPartialHeader {
+                offset,
+                bytes: remaining,
+            });
+            break;
+        }
+        let mut info_buff = [0u8; std::mem::size_of::<ChunkHeader>()];
+        source.seek(SeekFrom::Start(offset))?;
+        source.read_exact(&mut info_buff)?;
+        let mut chunk_header: ChunkHeader = bytemuck::pod_read_unaligned(&info_buff);
+        let data_offset = offset + header_size;
+        let available = file_len - data_offset;
+        let chunk_size = chunk_header.chunk_size as u64;
+
+        if &chunk_header.chunk_id == b"data" {
+            // A recorder that never finalized the header leaves the size at 0 or past the end,
+            // and the audio runs to the end of the file. An empty data chunk can be genuine
+            // though, if the file goes on with more chunks.
+            let unfinalized = chunk_size > available
+                || (chunk_size == 0 && !chunk_follows(&mut source, data_offset, file_len)?);
+            let size = if unfinalized { available } else { chunk_size };
+            let partial_frame = size % block_align;
+            let mut recovered_size = size - partial_frame;
+            if partial_frame > 0 {
+                report.changes.push(RepairChange::TrimmedPartialFrame {
+                    bytes: partial_frame,
+                });
+            }
+            // The RIFF size also counts everything before the audio, and a possible pad byte
+            let limit = u32::MAX as u64 - (data_offset - 8) - 1;
+            let limit = limit - limit % block_align;
+            if recovered_size > limit {
+                report.changes.push(RepairChange::TruncatedToRiffLimit {
+                    bytes: recovered_size - limit,
+                });
+                recovered_size = limit;
+            }
+            if recovered_size != chunk_size {
+                report.changes.push(RepairChange::DataSize {
+                    from: chunk_header.chunk_size,
+                    to: recovered_size as u32,
+                });
+            }
+            chunk_header.chunk_size = recovered_size as u32;
+            plan.push(PlannedChunk {
+                chunk_header,
+                data_offset,
+            });
+            if unfinalized {
+                break;
+            }
+            offset = data_offset + chunk_size + (chunk_size & 1);
+            continue;
+        }
+        if chunk_size > available {
+            report.changes.push(RepairChange::DroppedTruncatedChunk {
+                chunk_id: chunk_header.chunk_id,
+                offset,
+            });
+            break;
+        }
+        plan.push(PlannedChunk {
+            chunk_header,
+            data_offset,
+        });
+        offset = data_offset + chunk_size + (chunk_size & 1);
+    }
+
+    if !plan
+        .iter()
+        .any(|chunk| &chunk.chunk_header.chunk_id == b"data")
+    {
+        return Err(RepairError::MissingDataChunk);
+    }
+
+    let chunks_len: u64 = plan
+        .iter()
+        .map(|chunk| {
+            let size = chunk.chunk_header.chunk_size as u64;
+            header_size + size + (size & 1)
+        })
+        .sum();
+    let riff_size = (prefix_len + chunks_len - 8) as u32;
+    if riff_size != header.chunk_size {
+        report.changes.push(RepairChange::RiffSize {
+            from: header.chunk_size,
+            to: riff_size,
+        });
+    }
+
+    let temp_path = temp_path_for(output, "repair");
+    let result = write_repaired(&mut source, &temp_path, prefix_len, riff_size, &plan);
+    // Windows can't replace a file that is still open, and `output` may be `input`
+    drop(source);
+    let result = result.and_then(|_| fs::rename(&temp_path, output));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+    Ok(report)
+}
+
+fn write_repaired(
+    source: &mut File,
+    temp_path: &Path,
+    prefix_len: u64,
+    riff_size: u32,
+    plan: &[PlannedChunk],
+) -> Result<(), io::Error> {
+    let mut writer = BufWriter::new(File::create(temp_path)?);
+
+    // RIFF header and fmt chunk, including any fmt extension, with the corrected size
+    let mut prefix = vec![0u8; prefix_len as usize];
+    source.seek(SeekFrom::Start(0))?;
+    source.read_exact(&mut prefix)?;
+    prefix[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    writer.write_all(&prefix)?;
+
+    for chunk in plan {
+        let chunk_size = chunk.chunk_header.chunk_size as u64;
+        writer.write_all(bytemuck::bytes_of(&chunk.chunk_header))?;
+        source.seek(SeekFrom::Start(chunk.data_offset))?;
+        let copied = io::copy(&mut (&mut *source).take(chunk_size), &mut writer)?;
+        if copied != chunk_size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        if chunk_size % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+    }
+
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()
+}
+
+/// Whether a plausible chunk header starts at `offset`: a printable id and a size that fits in
+/// the file. Audio after an unfinalized data header almost never looks like one.
+fn chunk_follows(source: &mut File, offset: u64, file_len: u64) -> Result<bool, io::Error> {
+    let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+    if file_len.saturating_sub(offset) < header_size {
+        return Ok(false);
+    }
+    let mut info_buff = [0u8; std::mem::size_of::<ChunkHeader>()];
+    source.seek(SeekFrom::Start(offset))?;
+    source.read_exact(&mut info_buff)?;
+    let chunk_header: ChunkHeader = bytemuck::pod_read_unaligned(&info_buff);
+    Ok(chunk_header
+        .chunk_id
+        .iter()
+        .all(|byte| (0x20..=0x7e).contains(byte))
+        && chunk_header.chunk_size as u64 <= file_len - offset - header_size)
+}
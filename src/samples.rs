@@ -0,0 +1,516 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::{
+    chunks::{md5::Md5Chunk, TypedChunk},
+    dsp::convert::BLOCK_FRAMES,
+    wav::{Chunk, ChunkHeader, FmtExtension, FmtSubChunk, WavError, WavFile, WavHeader},
+};
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+/// The real format tag is in the sub format GUID of the fmt extension
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Sample encodings rwav can decode to and encode from normalized `f64` samples
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+    F64,
+}
+
+impl SampleFormat {
+    pub fn from_fmt(fmt: &FmtSubChunk) -> Result<Self, SampleError> {
+        Self::from_parts(fmt.audio_format, fmt.bits_per_sample)
+    }
+
+    /// Like `from_fmt`, resolving WAVE_FORMAT_EXTENSIBLE through its sub format
+    pub fn from_extensible(
+        fmt: &FmtSubChunk,
+        extension: Option<&FmtExtension>,
+    ) -> Result<Self, SampleError> {
+        match extension.and_then(FmtExtension::audio_format) {
+            Some(audio_format) if fmt.audio_format == WAVE_FORMAT_EXTENSIBLE => {
+                Self::from_parts(audio_format, fmt.bits_per_sample)
+            }
+            _ => Self::from_fmt(fmt),
+        }
+    }
+
+    pub fn from_parts(audio_format: u16, bits_per_sample: u16) -> Result<Self, SampleError> {
+        match (audio_format, bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => Ok(SampleFormat::U8),
+            (WAVE_FORMAT_PCM, 16) => Ok(SampleFormat::I16),
+            (WAVE_FORMAT_PCM, 24) => Ok(SampleFormat::I24),
+            (WAVE_FORMAT_PCM, 32) => Ok(SampleFormat::I32),
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => Ok(SampleFormat::F32),
+            (WAVE_FORMAT_IEEE_FLOAT, 64) => Ok(SampleFormat::F64),
+            _ => Err(SampleError::UnsupportedFormat {
+                audio_format,
+                bits_per_sample,
+            }),
+        }
+    }
+
+    /// fmt chunk for interleaved audio in this format
+    pub fn fmt(&self, num_channels: u16, sample_rate: u32) -> FmtSubChunk {
+        let block_align = num_channels * self.bytes_per_sample() as u16;
+        FmtSubChunk {
+            subchunk_1_id: *b"fmt ",
+            subchunk_1_size: 16,
+            audio_format: self.audio_format(),
+            num_channels,
+            sample_rate,
+            byte_rate: sample_rate * block_align as u32,
+            block_align,
+            bits_per_sample: self.bits_per_sample(),
+        }
+    }
+
+    pub fn audio_format(&self) -> u16 {
+        if self.is_float() {
+            WAVE_FORMAT_IEEE_FLOAT
+        } else {
+            WAVE_FORMAT_PCM
+        }
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::U8 => 8,
+            SampleFormat::I16 => 16,
+            SampleFormat::I24 => 24,
+            SampleFormat::I32 | SampleFormat::F32 => 32,
+            SampleFormat::F64 => 64,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        self.bits_per_sample() as usize / 8
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, SampleFormat::F32 | SampleFormat::F64)
+    }
+
+    /// Value of a full scale integer sample, used to normalize to -1.0..1.0
+    pub fn full_scale(&self) -> f64 {
+        match self {
+            SampleFormat::U8 => 128.0,
+            SampleFormat::I16 => 32768.0,
+            SampleFormat::I24 => 8388608.0,
+            SampleFormat::I32 => 2147483648.0,
+            SampleFormat::F32 | SampleFormat::F64 => 1.0,
+        }
+    }
+
+    /// Decodes little endian samples, appending them to `out` as normalized `f64`
+    pub fn decode(&self, bytes: &[u8], out: &mut Vec<f64>) {
+        let scale = self.full_scale();
+        let samples = bytes.chunks_exact(self.bytes_per_sample());
+        out.reserve(samples.len());
+        match self {
+            SampleFormat::U8 => out.extend(samples.map(|b| (b[0] as f64 - 128.0) / scale)),
+            SampleFormat::I16 => {
+                out.extend(samples.map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / scale))
+            }
+            SampleFormat::I24 => out.extend(
+                samples.map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / scale),
+            ),
+            SampleFormat::I32 => out.extend(
+                samples.map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / scale),
+            ),
+            SampleFormat::F32 => {
+                out.extend(samples.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64))
+            }
+            SampleFormat::F64 => {
+                out.extend(samples.map(|b| f64::from_le_bytes(b.try_into().unwrap())))
+            }
+        }
+    }
+
+    /// Encodes normalized samples as little endian bytes appended to `out`. Integer formats are
+    /// rounded and clamped to their range.
+    pub fn encode(&self, samples: &[f64], out: &mut Vec<u8>) {
+        let scale = self.full_scale();
+        out.reserve(samples.len() * self.bytes_per_sample());
+        for &sample in samples {
+            match self {
+                SampleFormat::U8 => {
+                    let value = (sample * scale).round().clamp(-128.0, 127.0) as i16 + 128;
+                    out.push(value as u8);
+                }
+                SampleFormat::I16 => {
+                    let value = (sample * scale).round().clamp(-scale, scale - 1.0) as i16;
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                SampleFormat::I24 => {
+                    let value = (sample * scale).round().clamp(-scale, scale - 1.0) as i32;
+                    out.extend_from_slice(&value.to_le_bytes()[..3]);
+                }
+                SampleFormat::I32 => {
+                    let value = (sample * scale).round().clamp(-scale, scale - 1.0) as i32;
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                SampleFormat::F32 => out.extend_from_slice(&(sample as f32).to_le_bytes()),
+                SampleFormat::F64 => out.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+    }
+}
+
+/// Streams interleaved, normalized samples out of a file's data chunk a block at a time, so
+/// the data chunk never has to be held in memory
+pub struct SampleReader {
+    /// Shares its cursor with the `WavFile` handle, so every read seeks to `position` first
+    handle: File,
+    position: u64,
+    data_offset: u64,
+    pub format: SampleFormat,
+    pub num_channels: u16,
+    pub sample_rate: u32,
+    /// Speaker positions from WAVE_FORMAT_EXTENSIBLE files
+    pub channel_mask: Option<u32>,
+    pub num_frames: u64,
+    pub frames_read: u64,
+    buffer: Vec<u8>,
+}
+
+impl SampleReader {
+    pub fn new(wav_file: &mut WavFile) -> Result<Self, SampleError> {
+        let fmt = wav_file.header.fmt;
+        let extension = wav_file.fmt_extension()?;
+        let format = SampleFormat::from_extensible(&fmt, extension.as_ref())?;
+        let (data_offset, data_size) = wav_file
+            .find_chunk(b"data")
+            .ok_or(SampleError::MissingDataChunk)?;
+
+        let handle = wav_file.handle.try_clone()?;
+        // Truncated recordings can claim more data than the file holds
+        let file_len = handle.metadata()?.len();
+        let data_size = data_size.min(file_len.saturating_sub(data_offset));
+        let frame_size = (format.bytes_per_sample() * fmt.num_channels as usize) as u64;
+        if frame_size == 0 {
+            return Err(SampleError::UnsupportedFormat {
+                audio_format: fmt.audio_format,
+                bits_per_sample: fmt.bits_per_sample,
+            });
+        }
+
+        Ok(SampleReader {
+            handle,
+            position: data_offset,
+            data_offset,
+            format,
+            num_channels: fmt.num_channels,
+            sample_rate: fmt.sample_rate,
+            channel_mask: extension.map(|extension| extension.channel_mask),
+            num_frames: data_size / frame_size,
+            frames_read: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.format.bytes_per_sample() * self.num_channels as usize
+    }
+
+    /// Replaces the contents of `out` with up to `max_frames` interleaved frames and returns the
+    /// number of frames read. Returns 0 once the data chunk is exhausted.
+    pub fn read_frames(
+        &mut self,
+        max_frames: usize,
+        out: &mut Vec<f64>,
+    ) -> Result<usize, SampleError> {
+        out.clear();
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let num_frames = self.read_raw_frames(max_frames, &mut buffer)?;
+        self.format.decode(&buffer, out);
+        self.buffer = buffer;
+        Ok(num_frames)
+    }
+
+    /// Like `read_frames`, but replaces the contents of `out` with the frames' bytes as they
+    /// are in the file
+    pub fn read_raw_frames(
+        &mut self,
+        max_frames: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, SampleError> {
+        let remaining = (self.num_frames - self.frames_read) as usize;
+        let num_frames = max_frames.min(remaining);
+        out.resize(num_frames * self.frame_size(), 0);
+        if num_frames == 0 {
+            return Ok(0);
+        }
+        self.handle.seek(SeekFrom::Start(self.position))?;
+        self.handle.read_exact(out)?;
+        self.position += out.len() as u64;
+        self.frames_read += num_frames as u64;
+        Ok(num_frames)
+    }
+
+    /// Moves to `frame`, clamped to the end of the data, so the next read starts there
+    pub fn seek_frame(&mut self, frame: u64) {
+        let frame = frame.min(self.num_frames);
+        self.position = self.data_offset + frame * self.frame_size() as u64;
+        self.frames_read = frame;
+    }
+}
+
+/// Streams interleaved, normalized samples into a new file's data chunk. The RIFF and data sizes
+/// are patched in by `finish`, so the file doesn't need to fit in memory. So is the digest of an
+/// `MD5 ` chunk among the leading chunks, which would otherwise no longer match. The audio is
+/// only digested when there is one.
+///
+/// The file is written next to its path and only moved there by `finish`, so the path may be
+/// the file the audio is being read from. A writer dropped before `finish` leaves nothing behind.
+pub struct SampleWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    temp_path: PathBuf,
+    finished: bool,
+    pub format: SampleFormat,
+    pub num_channels: u16,
+    pub sample_rate: u32,
+    pub frames_written: u64,
+    /// Offset of the data chunk header
+    data_offset: u64,
+    /// Offset of a leading `MD5 ` chunk's digest
+    md5_offset: Option<u64>,
+    /// Digest of the audio so far, kept only for a leading `MD5 ` chunk
+    md5: Option<Md5>,
+    buffer: Vec<u8>,
+}
+
+impl SampleWriter {
+    /// Starts the file for `path` with the header, `leading_chunks` and the start of the data
+    /// chunk. A `channel_mask` makes the fmt chunk WAVE_FORMAT_EXTENSIBLE.
+    pub fn create(
+        path: &Path,
+        format: SampleFormat,
+        num_channels: u16,
+        sample_rate: u32,
+        channel_mask: Option<u32>,
+        leading_chunks: &[Chunk],
+    ) -> Result<Self, SampleError> {
+        let temp_path = temp_path_for(path, "write");
+        let mut sample_writer = SampleWriter {
+            writer: BufWriter::new(File::create(&temp_path)?),
+            path: path.to_path_buf(),
+            temp_path,
+            finished: false,
+            format,
+            num_channels,
+            sample_rate,
+            frames_written: 0,
+            data_offset: 0,
+            md5_offset: None,
+            md5: None,
+            buffer: Vec::new(),
+        };
+        let writer = &mut sample_writer.writer;
+        let mut fmt = format.fmt(num_channels, sample_rate);
+        let extension = channel_mask.map(|channel_mask| {
+            let extension = FmtExtension::new(fmt.audio_format, fmt.bits_per_sample, channel_mask);
+            fmt.audio_format = WAVE_FORMAT_EXTENSIBLE;
+            fmt.subchunk_1_size += std::mem::size_of::<FmtExtension>() as u32;
+            extension
+        });
+        let header = WavHeader {
+            chunk_id: *b"RIFF",
+            chunk_size: 0,
+            format: *b"WAVE",
+            fmt,
+        };
+        writer.write_all(bytemuck::bytes_of(&header))?;
+        let mut data_offset = std::mem::size_of::<WavHeader>() as u64;
+        if let Some(extension) = &extension {
+            writer.write_all(bytemuck::bytes_of(extension))?;
+            data_offset += std::mem::size_of::<FmtExtension>() as u64;
+        }
+        let mut md5_offset = None;
+        for chunk in leading_chunks {
+            let mut chunk = chunk;
+            let placeholder;
+            if chunk.chunk_header.chunk_id == Md5Chunk::ID {
+                placeholder = Md5Chunk::default().to_chunk();
+                chunk = &placeholder;
+                md5_offset = Some(data_offset + 8);
+            }
+            writer.write_all(&chunk.to_bytes())?;
+            data_offset += chunk.padded_size();
+        }
+        writer.write_all(bytemuck::bytes_of(&ChunkHeader {
+            chunk_id: *b"data",
+            chunk_size: 0,
+        }))?;
+        sample_writer.data_offset = data_offset;
+        sample_writer.md5_offset = md5_offset;
+        sample_writer.md5 = md5_offset.map(|_| Md5::new());
+        Ok(sample_writer)
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.format.bytes_per_sample() * self.num_channels as usize
+    }
+
+    /// Encodes and appends interleaved frames. Integer formats are rounded and clamped, so
+    /// samples should already be requantized if dither is wanted.
+    pub fn write_frames(&mut self, samples: &[f64]) -> Result<(), SampleError> {
+        self.buffer.clear();
+        self.format.encode(samples, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        if let Some(md5) = &mut self.md5 {
+            md5.update(&self.buffer);
+        }
+        self.frames_written += (samples.len() / self.num_channels as usize) as u64;
+        Ok(())
+    }
+
+    /// Appends frames that are already encoded in this writer's format, for bit exact copies
+    pub fn write_raw_frames(&mut self, bytes: &[u8]) -> Result<(), SampleError> {
+        self.writer.write_all(bytes)?;
+        if let Some(md5) = &mut self.md5 {
+            md5.update(bytes);
+        }
+        self.frames_written += (bytes.len() / self.frame_size()) as u64;
+        Ok(())
+    }
+
+    /// Digest of the audio written so far, which `finish` puts in an `MD5 ` chunk. `None` unless
+    /// the leading chunks hold one.
+    pub fn md5(&self) -> Option<Md5Chunk> {
+        self.md5.as_ref().map(|md5| Md5Chunk {
+            digest: md5.clone().finalize().into(),
+        })
+    }
+
+    /// Writes `trailing_chunks` after the audio, patches the sizes and moves the file to its
+    /// path. Returns the number of frames written. Windows can't replace a file that is still
+    /// open, so readers of the path should be dropped first.
+    pub fn finish(mut self, trailing_chunks: &[Chunk]) -> Result<u64, SampleError> {
+        let data_size = self.frames_written * self.frame_size() as u64;
+        if data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let md5 = self.md5.take().map(|md5| Md5Chunk {
+            digest: md5.finalize().into(),
+        });
+        let mut riff_end = self.data_offset + 8 + data_size + (data_size & 1);
+        for chunk in trailing_chunks {
+            let mut chunk = chunk;
+            let updated;
+            if chunk.chunk_header.chunk_id == Md5Chunk::ID {
+                // Without a digest to put in it, it could only be stale
+                let Some(md5) = md5 else {
+                    continue;
+                };
+                updated = md5.to_chunk();
+                chunk = &updated;
+            }
+            self.writer.write_all(&chunk.to_bytes())?;
+            riff_end += chunk.padded_size();
+        }
+        if riff_end - 8 > u32::MAX as u64 {
+            return Err(SampleError::TooLarge(riff_end));
+        }
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&((riff_end - 8) as u32).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.data_offset + 4))?;
+        self.writer.write_all(&(data_size as u32).to_le_bytes())?;
+        if let (Some(md5_offset), Some(md5)) = (self.md5_offset, md5) {
+            self.writer.seek(SeekFrom::Start(md5_offset))?;
+            self.writer.write_all(&md5.digest)?;
+        }
+        self.writer.flush()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.finished = true;
+        Ok(self.frames_written)
+    }
+}
+
+impl Drop for SampleWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Opens `path` for its audio and the metadata chunks before and after the data chunk. The
+/// reader holds the only handle on the file, so dropping it closes the file.
+pub fn open_with_metadata(
+    path: &Path,
+) -> Result<(SampleReader, Vec<Chunk>, Vec<Chunk>), SampleError> {
+    let mut wav_file = WavFile::open(path)?;
+    let (leading, trailing) = wav_file.metadata_chunks()?;
+    let reader = SampleReader::new(&mut wav_file)?;
+    Ok((reader, leading, trailing))
+}
+
+/// Copies the rest of `reader`'s audio bit for bit into a new file at `path`, between
+/// `leading_chunks` and `trailing_chunks`. The reader is closed before the file is moved into
+/// place, so `path` may be the file being read. Returns the MD5 of the audio if the leading
+/// chunks hold an `MD5 ` chunk.
+pub fn rewrite_audio(
+    mut reader: SampleReader,
+    path: &Path,
+    leading_chunks: &[Chunk],
+    trailing_chunks: &[Chunk],
+) -> Result<Option<Md5Chunk>, SampleError> {
+    let mut writer = SampleWriter::create(
+        path,
+        reader.format,
+        reader.num_channels,
+        reader.sample_rate,
+        reader.channel_mask,
+        leading_chunks,
+    )?;
+    let mut frames = Vec::new();
+    while reader.read_raw_frames(BLOCK_FRAMES, &mut frames)? > 0 {
+        writer.write_raw_frames(&frames)?;
+    }
+    drop(reader);
+    let md5 = writer.md5();
+    writer.finish(trailing_chunks)?;
+    Ok(md5)
+}
+
+/// Sibling of `path`, so the final rename stays on one filesystem
+pub(crate) fn temp_path_for(path: &Path, purpose: &str) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{file_name}.rwav-{purpose}"))
+}
+
+#[derive(Error, Debug)]
+pub enum SampleError {
+    #[error("Unsupported sample format! audio_format: {audio_format}, bits_per_sample: {bits_per_sample}")]
+    UnsupportedFormat {
+        audio_format: u16,
+        bits_per_sample: u16,
+    },
+    #[error("File has no data chunk!")]
+    MissingDataChunk,
+    #[error("IO error reading samples: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Wav(#[from] WavError),
+    #[error("Output would be {0} bytes, too large for a RIFF file!")]
+    TooLarge(u64),
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    analysis::stats::{SilenceKind, StatsMeter, StatsSettings},
+    chunks::{adtl::AdtlChunk, cue::CueChunk, TypedChunk},
+    dsp::convert::BLOCK_FRAMES,
+    edit::{copy_frames, shift_markers, EditError, FrameMap},
+    poly::file_name_safe,
+    samples::{SampleReader, SampleWriter},
+    wav::{Chunk, WavFile},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SilenceSplit {
+    /// Frames where every channel stays below this level in dBFS are silent
+    pub threshold_db: f64,
+    /// Shortest silence to split at, in seconds
+    pub min_silence: f64,
+    /// Silence kept either side of each piece, in seconds. Never more than half of the silence,
+    /// so neighbouring pieces don't overlap.
+    pub padding: f64,
+}
+
+impl Default for SilenceSplit {
+    fn default() -> Self {
+        SilenceSplit {
+            threshold_db: -50.0,
+            min_silence: 1.0,
+            padding: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SplitMode {
+    /// One piece per region if the file has any, otherwise a piece from each cue point to the
+    /// next, and one for any audio before the first
+    Markers,
+    /// One piece per stretch of sound, with the silence between them dropped
+    Silence(SilenceSplit),
+}
+
+/// A span of the input written to its own file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPiece {
+    pub start: u64,
+    /// Exclusive
+    pub end: u64,
+    /// Label of the marker or region the piece starts at
+    pub label: Option<String>,
+}
+
+/// Splits `input` into pieces in `output_dir`, named `<stem>_<label>.wav` after the marker or
+/// region they start at, or `<stem>_<number>.wav` when it has no label. Every piece keeps the
+/// metadata of the input, with its markers and bext time reference moved as `trim` would.
+pub fn split(input: &Path, output_dir: &Path, mode: SplitMode) -> Result<Vec<PathBuf>, EditError> {
+    let mut wav_file = WavFile::open(input)?;
+    let (leading, trailing) = wav_file.metadata_chunks()?;
+    let mut reader = SampleReader::new(&mut wav_file)?;
+    let pieces = match mode {
+        SplitMode::Markers => marker_pieces(&leading, &trailing, reader.num_frames)?,
+        SplitMode::Silence(settings) => silence_pieces(&mut reader, settings)?,
+    };
+
+    let stem = input
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let width = pieces.len().to_string().len().max(2);
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(pieces.len());
+    for (index, piece) in pieces.iter().enumerate() {
+        let number = format!("{:0width$}", index + 1);
+        let name = match &piece.label {
+            Some(label) if !label.is_empty() => file_name_safe(label),
+            _ => number.clone(),
+        };
+        let mut path = output_dir.join(format!("{stem}_{name}.wav"));
+        if paths.contains(&path) {
+            path = output_dir.join(format!("{stem}_{name}_{number}.wav"));
+        }
+
+        let map = FrameMap::Keep {
+            start: piece.start,
+            end: piece.end,
+        };
+        let (mut piece_leading, mut piece_trailing) = (leading.clone(), trailing.clone());
+        shift_markers(&mut piece_leading, &mut piece_trailing, map)?;
+        let mut writer = SampleWriter::create(
+            &path,
+            reader.format,
+            reader.num_channels,
+            reader.sample_rate,
+            reader.channel_mask,
+            &piece_leading,
+        )?;
+        copy_frames(&mut reader, &mut writer, piece.start, piece.end)?;
+        writer.finish(&piece_trailing)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// The pieces `SplitMode::Markers` cuts a file with these metadata chunks into
+pub fn marker_pieces(
+    leading: &[Chunk],
+    trailing: &[Chunk],
+    num_frames: u64,
+) -> Result<Vec<SplitPiece>, EditError> {
+    let mut cue = None;
+    let mut adtl = AdtlChunk::default();
+    for chunk in leading.iter().chain(trailing) {
+        if chunk.chunk_header.chunk_id == CueChunk::ID {
+            cue = Some(CueChunk::from_chunk(chunk)?);
+        } else if AdtlChunk::is_adtl(chunk) {
+            adtl = AdtlChunk::from_chunk(chunk)?;
+        }
+    }
+    let mut points = cue.ok_or(EditError::NoMarkers)?.points;
+    points.retain(|point| (point.sample_offset as u64) < num_frames);
+    points.sort_by_key(|point| point.sample_offset);
+    if points.is_empty() {
+        return Err(EditError::NoMarkers);
+    }
+    let label = |id: u32| adtl.label(id).map(str::to_string);
+
+    let regions: Vec<SplitPiece> = points
+        .iter()
+        .filter_map(|point| {
+            let region = adtl
+                .region(point.id)
+                .filter(|region| region.sample_length > 0)?;
+            let start = point.sample_offset as u64;
+            Some(SplitPiece {
+                start,
+                end: (start + region.sample_length as u64).min(num_frames),
+                label: label(point.id),
+            })
+        })
+        .collect();
+    if !regions.is_empty() {
+        return Ok(regions);
+    }
+
+    let mut pieces = Vec::with_capacity(points.len() + 1);
+    let first = points[0].sample_offset as u64;
+    if first > 0 {
+        pieces.push(SplitPiece {
+            start: 0,
+            end: first,
+            label: None,
+        });
+    }
+    for (index, point) in points.iter().enumerate() {
+        let start = point.sample_offset as u64;
+        let end = points
+            .get(index + 1)
+            .map_or(num_frames, |next| next.sample_offset as u64);
+        if end > start {
+            pieces.push(SplitPiece {
+                start,
+                end,
+                label: label(point.id),
+            });
+        }
+    }
+    Ok(pieces)
+}
+
+/// First pass of `SplitMode::Silence`: the stretches of sound between silences, padded
+fn silence_pieces(
+    reader: &mut SampleReader,
+    settings: SilenceSplit,
+) -> Result<Vec<SplitPiece>, EditError> {
+    let mut meter = StatsMeter::new(
+        reader.num_channels,
+        reader.sample_rate,
+        reader.format,
+        StatsSettings {
+            silence_threshold_db: settings.threshold_db,
+            min_silence: settings.min_silence,
+            ..Default::default()
+        },
+    );
+    let mut samples = Vec::new();
+    while reader.read_frames(BLOCK_FRAMES, &mut samples)? > 0 {
+        meter.process(&samples);
+    }
+    let report = meter.finish();
+    let padding = (settings.padding * reader.sample_rate as f64).round() as u64;
+
+    let mut pieces = Vec::new();
+    // Unpadded start of the current stretch of sound, and the padding before it
+    let (mut start, mut start_pad) = (0, 0);
+    for silence in &report.silences {
+        let length = silence.end_frame - silence.start_frame;
+        let pad = match silence.kind {
+            SilenceKind::Internal => padding.min(length / 2),
+            _ => padding.min(length),
+        };
+        if silence.start_frame > start {
+            pieces.push(SplitPiece {
+                start: start - start_pad,
+                end: silence.start_frame + pad,
+                label: None,
+            });
+        }
+        (start, start_pad) = (silence.end_frame, pad);
+    }
+    if report.frames > start {
+        pieces.push(SplitPiece {
+            start: start - start_pad,
+            end: report.frames,
+            label: None,
+        });
+    }
+    Ok(pieces)
+}
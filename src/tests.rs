@@ -1,4 +1,93 @@
-use crate::audio::Audio;
+#![cfg(test)]
+
+use std::{
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+
+use crate::{
+    analysis::{
+        loudness::{self, LoudnessMeter},
+        stats::{self, ClipRun, SilenceKind, SilenceSpan, StatsMeter, StatsSettings},
+    },
+    audio::{Audio, AudioSession, SourceDescription},
+    channels::{ChannelMask, Speaker},
+    chunks::{
+        acid::{AcidChunk, BeatGrid},
+        adm::{AxmlChunk, ChnaAudioId, ChnaChunk},
+        adtl::{AdtlChunk, CueRegion, CueText},
+        bext::{BextChunk, LOUDNESS_NOT_SET},
+        cart::{self, CartChunk, CartTimer},
+        cue::{CueChunk, CuePoint},
+        ixml::{IxmlChunk, IxmlTrack},
+        levl::{self, LevlChunk, PeakEnvelopeSettings, PeakFormat},
+        md5::Md5Chunk,
+        smpl::{SampleLoop, SmplChunk, SmplHeader, LOOP_FORWARD},
+        ChunkError, TypedChunk,
+    },
+    diff::{self, ChunkDifference, DiffSettings},
+    dsp::{
+        convert::{self, ConvertSettings, Dither, NoiseShaping, Requantizer},
+        db_to_linear,
+        dynamics::{
+            self, Compressor, CompressorSettings, Dynamics, DynamicsError, Gate, GateSettings,
+            Limiter, LimiterSettings,
+        },
+        fade::{self, Fade, FadeCurve, FadeLength},
+        filter::{self, Equalizer, FilterBand, FilterError},
+        gain::{self, Gain, GainMode},
+        generator::{self, Generator, GeneratorError, Signal},
+        linear_to_db,
+        mix::{self, MixError, MixMatrix, MixPreset},
+        resample::{self, ResampleError, ResampleQuality, Resampler},
+    },
+    edit::{self, EditError},
+    effects::{self, ChainError, Effect, EffectChain},
+    hash::{self, Md5Check},
+    pipeline::{self, Pipeline, PipelineError, Sink, Source, StreamFormat},
+    poly::{self, PolyError},
+    repair::{self, RepairChange, RepairError},
+    samples::{SampleError, SampleFormat, SampleReader, SampleWriter},
+    split::{self, SilenceSplit, SplitMode},
+    validate::{FindingKind, Severity},
+    wav::{self, Chunk, FmtSubChunk, ParseLimits, WavError, WavFile, WavWriter},
+};
+
+fn test_fmt(num_channels: u16, sample_rate: u32, bits_per_sample: u16) -> FmtSubChunk {
+    let block_align = num_channels * bits_per_sample / 8;
+    FmtSubChunk {
+        subchunk_1_id: *b"fmt ",
+        subchunk_1_size: 16,
+        audio_format: 1,
+        num_channels,
+        sample_rate,
+        byte_rate: sample_rate * block_align as u32,
+        block_align,
+        bits_per_sample,
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rwav_{}_{name}", std::process::id()))
+}
+
+fn write_test_wav(path: &Path, fmt: FmtSubChunk, samples: &[f64]) {
+    let mut data = Vec::new();
+    SampleFormat::from_fmt(&fmt)
+        .unwrap()
+        .encode(samples, &mut data);
+    let mut writer = WavWriter::new(fmt);
+    writer.set_chunk(Chunk::new(*b"data", data));
+    writer.write(path).unwrap();
+}
+
+fn patch_file(path: &Path, offset: u64, bytes: &[u8]) {
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
 
 #[test]
 pub fn test_get_num_devices() {
@@ -58,3 +147,3017 @@ pub fn get_devices() {
     println!("DEVICES: {:#?}", devices);
     assert!(devices.len() > 0);
 }
+
+#[test]
+pub fn acid_chunk_round_trip() {
+    let mut acid = AcidChunk::new_loop(120.0, 8);
+    acid.set_root_note(Some(57));
+    acid.set_one_shot(false);
+
+    let path = temp_path("acid.wav");
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 16));
+    writer.set_chunk(Chunk::new(*b"data", vec![0u8; 16]));
+    writer.set_chunk(acid.to_chunk());
+    writer.write(&path).unwrap();
+
+    let wav_file = WavFile::new(&path);
+    let chunks: Vec<Chunk> = wav_file.collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(chunks[0].chunk_header.chunk_id, *b"acid");
+    assert_eq!(chunks[1].chunk_header.chunk_id, *b"data");
+
+    let parsed = AcidChunk::from_chunk(&chunks[0]).unwrap();
+    assert_eq!(parsed, acid);
+    assert_eq!(parsed.root_note(), Some(57));
+    assert!(parsed.is_stretch());
+    assert!(!parsed.is_one_shot());
+}
+
+#[test]
+pub fn acid_beat_grid() {
+    let acid = AcidChunk::new_loop(120.0, 8);
+    let grid = BeatGrid::new(&acid, &test_fmt(2, 48000, 16)).unwrap();
+    assert_eq!(grid.frames_per_beat, 24000.0);
+    assert_eq!(grid.bar_frame(1), 96000);
+    assert_eq!(grid.snap(35000), 24000);
+    assert_eq!(grid.snap(37000), 48000);
+    assert_eq!(grid.bar_beat(100000), (1, 0));
+    assert_eq!(grid.beats().count(), 8);
+    assert_eq!(acid.tempo_from_length(192000, 48000), Some(120.0));
+}
+
+#[test]
+pub fn sample_formats_round_trip() {
+    let samples = [0.0, 0.5, -0.5, -1.0, 0.25];
+    for format in [
+        SampleFormat::I16,
+        SampleFormat::I24,
+        SampleFormat::I32,
+        SampleFormat::F32,
+        SampleFormat::F64,
+    ] {
+        let mut bytes = Vec::new();
+        format.encode(&samples, &mut bytes);
+        assert_eq!(bytes.len(), samples.len() * format.bytes_per_sample());
+        let mut decoded = Vec::new();
+        format.decode(&bytes, &mut decoded);
+        assert_eq!(decoded, samples, "{format:?}");
+    }
+}
+
+#[test]
+pub fn levl_compute_and_read_back() {
+    let input = temp_path("levl_in.wav");
+    let output = temp_path("levl_out.wav");
+    // Two channels, three blocks of two frames
+    let samples = [
+        0.5, -0.25, -1.0, 0.0, 0.1, 0.2, 0.0, -0.3, 0.0, 0.0, 0.0, 0.0,
+    ];
+    write_test_wav(&input, test_fmt(2, 48000, 16), &samples);
+
+    let settings = PeakEnvelopeSettings {
+        format: PeakFormat::U16,
+        points_per_value: 2,
+        block_size: 2,
+    };
+    let levl = levl::embed_peak_envelope(&input, &output, settings).unwrap();
+    assert_eq!(levl.num_peak_frames, 3);
+    assert_eq!(levl.pos_peak_of_peaks, 1);
+
+    let mut wav_file = WavFile::new(&output);
+    let (offset, size) = wav_file.find_chunk(b"levl").unwrap();
+    assert!(offset > 0 && size > 0);
+    let chunk = wav_file
+        .find(|chunk| chunk.chunk_header.chunk_id == *b"levl")
+        .unwrap();
+    let parsed = LevlChunk::from_chunk(&chunk).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert!(matches!(
+        levl::embed_peak_envelope(&input, &output, settings),
+        Err(SampleError::Wav(WavError::Io(_)))
+    ));
+
+    assert_eq!(parsed, levl);
+    let (positive, negative) = parsed.peak(0, 0).unwrap();
+    assert!((positive - 0.5).abs() < 1e-4 && negative == 1.0);
+    let (positive, negative) = parsed.peak(1, 1).unwrap();
+    assert!((positive - 0.2).abs() < 1e-4 && (negative - 0.3).abs() < 1e-4);
+    assert_eq!(parsed.channel_peaks(0).len(), 3);
+    assert_eq!(parsed.timestamp_str().len(), 23);
+}
+
+#[test]
+pub fn sample_reader_streams_blocks() {
+    let path = temp_path("reader.wav");
+    let samples: Vec<f64> = (0..10).map(|i| i as f64 / 16.0).collect();
+    write_test_wav(&path, test_fmt(1, 8000, 24), &samples);
+    let mut reader = SampleReader::new(&mut WavFile::new(&path)).unwrap();
+    let mut block = Vec::new();
+    let mut read = Vec::new();
+    while reader.read_frames(4, &mut block).unwrap() > 0 {
+        read.extend_from_slice(&block);
+    }
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reader.num_frames, 10);
+    assert_eq!(read, samples);
+}
+
+#[test]
+pub fn cart_chunk_round_trip_preserves_audio() {
+    let input = temp_path("cart_in.wav");
+    let output = temp_path("cart_out.wav");
+    let samples: Vec<f64> = (0..32).map(|i| (i as f64 / 32.0) - 0.5).collect();
+    write_test_wav(&input, test_fmt(2, 44100, 24), &samples);
+
+    let mut cart = CartChunk {
+        title: "Spring Sale".to_string(),
+        artist: "Acme".to_string(),
+        cut_id: "10042".to_string(),
+        start_date: "2024-03-01".to_string(),
+        end_date: "2024-03-31".to_string(),
+        tag_text: "Read live after the news\r\n".to_string(),
+        ..Default::default()
+    };
+    cart.post_timers[0] = CartTimer {
+        usage: *b"SEG1",
+        value: 12,
+    };
+    cart::write_cart(&input, &output, &cart).unwrap();
+
+    let original: Vec<Chunk> = WavFile::new(&input).collect();
+    let rewritten: Vec<Chunk> = WavFile::new(&output).collect();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert!(cart::write_cart(&input, &output, &cart).is_err());
+
+    let cart_chunk = rewritten
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == *b"cart")
+        .unwrap();
+    let parsed = CartChunk::from_chunk(cart_chunk).unwrap();
+    assert_eq!(parsed, cart);
+    assert_eq!(
+        parsed.timers().collect::<Vec<_>>(),
+        [("SEG1".to_string(), 12)]
+    );
+    let data = |chunks: &[Chunk]| {
+        chunks
+            .iter()
+            .find(|chunk| chunk.chunk_header.chunk_id == *b"data")
+            .unwrap()
+            .data
+            .clone()
+    };
+    assert_eq!(data(&original), data(&rewritten));
+}
+
+#[test]
+pub fn cart_chunk_validation() {
+    assert!(CartChunk::default().validate().is_ok());
+    let too_long = CartChunk {
+        title: "x".repeat(65),
+        ..Default::default()
+    };
+    assert!(too_long.validate().is_err());
+    let bad_date = CartChunk {
+        start_date: "2024-13-01".to_string(),
+        ..Default::default()
+    };
+    assert!(bad_date.validate().is_err());
+    let bad_time = CartChunk {
+        end_time: "24:00:00".to_string(),
+        ..Default::default()
+    };
+    assert!(bad_time.validate().is_err());
+    let non_ascii = CartChunk {
+        artist: "Motörhead".to_string(),
+        ..Default::default()
+    };
+    assert!(non_ascii.validate().is_err());
+    assert_eq!(CartChunk::default().to_bytes().len(), cart::CART_FIXED_SIZE);
+}
+
+const TEST_ADM_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ebuCoreMain xmlns="urn:ebu:metadata-schema:ebuCore_2014">
+  <coreMetadata><format><audioFormatExtended>
+    <audioProgramme audioProgrammeID="APR_1001" audioProgrammeName="Main &amp; Mix">
+      <audioContentIDRef>ACO_1001</audioContentIDRef>
+    </audioProgramme>
+    <audioObject audioObjectID="AO_1001" audioObjectName="Bed">
+      <audioPackFormatIDRef>AP_00010002</audioPackFormatIDRef>
+      <audioTrackUIDRef>ATU_00000001</audioTrackUIDRef>
+      <audioTrackUIDRef>ATU_00000002</audioTrackUIDRef>
+    </audioObject>
+    <audioPackFormat audioPackFormatID="AP_00010002" audioPackFormatName="stereo" typeLabel="0001">
+      <audioChannelFormatIDRef>AC_00010001</audioChannelFormatIDRef>
+      <audioChannelFormatIDRef>AC_00010002</audioChannelFormatIDRef>
+    </audioPackFormat>
+  </audioFormatExtended></format></coreMetadata>
+</ebuCoreMain>
+"#;
+
+fn test_chna() -> ChnaChunk {
+    let audio_id = |track_index: u16| ChnaAudioId {
+        track_index,
+        uid: format!("ATU_0000000{track_index}"),
+        track_ref: format!("AT_0001000{track_index}_01"),
+        pack_ref: "AP_00010002".to_string(),
+    };
+    ChnaChunk::new(vec![audio_id(1), audio_id(2)])
+}
+
+#[test]
+pub fn adm_chunks_round_trip() {
+    let path = temp_path("adm.wav");
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 24));
+    writer.set_chunk(Chunk::new(*b"data", vec![0u8; 12]));
+    writer.set_chunk(test_chna().to_chunk());
+    writer.set_chunk(AxmlChunk::new(TEST_ADM_XML).to_chunk());
+    writer.write(&path).unwrap();
+
+    let chunks: Vec<Chunk> = WavFile::new(&path).collect();
+    std::fs::remove_file(&path).unwrap();
+    let chna = ChnaChunk::from_chunk(&chunks[0]).unwrap();
+    let axml = AxmlChunk::from_chunk(&chunks[1]).unwrap();
+    assert_eq!(chna, test_chna());
+    assert_eq!(chna.num_tracks, 2);
+    assert_eq!(chna.to_bytes().len(), 4 + 2 * 40);
+    assert_eq!(axml.xml(), TEST_ADM_XML);
+
+    let adm = axml.parse_adm();
+    assert_eq!(adm.programmes[0].name, "Main & Mix");
+    assert_eq!(adm.programmes[0].content_refs, ["ACO_1001"]);
+    assert_eq!(adm.objects[0].id, "AO_1001");
+    assert_eq!(adm.objects[0].pack_format_refs, ["AP_00010002"]);
+    assert_eq!(adm.objects[0].track_uid_refs.len(), 2);
+    assert_eq!(adm.pack_formats[0].type_label, "0001");
+    assert_eq!(adm.pack_formats[0].channel_format_refs.len(), 2);
+}
+
+#[test]
+pub fn chna_validation_checks_track_indices() {
+    let chna = test_chna();
+    assert!(chna.validate(&test_fmt(2, 48000, 24)).is_ok());
+    assert!(chna.validate(&test_fmt(1, 48000, 24)).is_err());
+
+    let mut with_spare_slot = test_chna();
+    with_spare_slot.audio_ids.push(ChnaAudioId::default());
+    assert!(with_spare_slot.validate(&test_fmt(2, 48000, 24)).is_ok());
+
+    let mut wrong_count = test_chna();
+    wrong_count.num_tracks = 3;
+    assert!(wrong_count.validate(&test_fmt(2, 48000, 24)).is_err());
+}
+
+#[test]
+pub fn validate_clean_file() {
+    let path = temp_path("validate_clean.wav");
+    write_test_wav(&path, test_fmt(2, 48000, 16), &[0.0; 8]);
+    let report = WavFile::new(&path).validate().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(report.is_clean(), "{:?}", report.findings);
+}
+
+#[test]
+pub fn validate_header_checks() {
+    // (field offset, replacement bytes, expected finding)
+    let cases: [(u64, &[u8], FindingKind); 10] = [
+        (0, b"RIFX", FindingKind::RiffId),
+        (8, b"AVI ", FindingKind::WaveId),
+        (12, b"JUNK", FindingKind::FmtId),
+        (16, &14u32.to_le_bytes(), FindingKind::FmtSize),
+        (20, &2u16.to_le_bytes(), FindingKind::UnsupportedFormat),
+        (22, &0u16.to_le_bytes(), FindingKind::NoChannels),
+        (24, &0u32.to_le_bytes(), FindingKind::NoSampleRate),
+        (28, &1u32.to_le_bytes(), FindingKind::ByteRate),
+        (32, &3u16.to_le_bytes(), FindingKind::BlockAlign),
+        (4, &1000u32.to_le_bytes(), FindingKind::RiffSizeMismatch),
+    ];
+    for (offset, bytes, kind) in cases {
+        let path = temp_path("validate_header.wav");
+        write_test_wav(&path, test_fmt(2, 48000, 16), &[0.0; 8]);
+        patch_file(&path, offset, bytes);
+        let report = WavFile::new(&path).validate().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let finding = report
+            .findings
+            .iter()
+            .find(|finding| finding.kind == kind)
+            .unwrap_or_else(|| panic!("{kind:?} not reported: {:?}", report.findings));
+        assert_eq!(finding.offset, offset, "{kind:?}");
+    }
+}
+
+#[test]
+pub fn validate_trailing_bytes_are_info() {
+    let path = temp_path("validate_trailing.wav");
+    write_test_wav(&path, test_fmt(1, 8000, 16), &[0.0; 4]);
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.extend_from_slice(&[0, 0, 0]);
+    std::fs::write(&path, bytes).unwrap();
+    let report = WavFile::new(&path).validate().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report.findings.len(), 1);
+    assert_eq!(report.findings[0].kind, FindingKind::RiffSizeMismatch);
+    assert_eq!(report.findings[0].severity, Severity::Info);
+}
+
+#[test]
+pub fn validate_chunk_checks() {
+    let fmt = test_fmt(2, 48000, 16);
+    let validate = |writer: WavWriter| {
+        let path = temp_path("validate_chunks.wav");
+        writer.write(&path).unwrap();
+        let report = WavFile::new(&path).validate().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        report
+    };
+
+    let mut no_data = WavWriter::new(fmt);
+    no_data.set_chunk(Chunk::new(*b"LIST", vec![0u8; 4]));
+    let report = validate(no_data);
+    assert!(report.contains(FindingKind::MissingDataChunk) && report.has_errors());
+
+    let mut two_data = WavWriter::new(fmt);
+    two_data.chunks.push(Chunk::new(*b"data", vec![0u8; 4]));
+    two_data.chunks.push(Chunk::new(*b"data", vec![0u8; 4]));
+    let report = validate(two_data);
+    assert!(report.contains(FindingKind::DuplicateDataChunk));
+    assert_eq!(report.findings[0].offset, 48);
+
+    let mut unaligned = WavWriter::new(fmt);
+    unaligned.set_chunk(Chunk::new(*b"data", vec![0u8; 6]));
+    assert!(validate(unaligned).contains(FindingKind::DataNotFrameAligned));
+
+    let path = temp_path("validate_past_eof.wav");
+    write_test_wav(&path, fmt, &[0.0; 8]);
+    patch_file(&path, 40, &1000u32.to_le_bytes());
+    let report = WavFile::new(&path).validate().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let finding = report
+        .findings
+        .iter()
+        .find(|finding| finding.kind == FindingKind::ChunkPastEof)
+        .unwrap();
+    assert_eq!((finding.offset, finding.severity), (36, Severity::Error));
+
+    // A cut off fmt extension and a chunk list over the limit are findings, not errors
+    write_extensible_wav(&path, 2, ChannelMask::STEREO.0, &[0.0; 8]);
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..50]).unwrap();
+    let report = WavFile::open(&path).unwrap().validate().unwrap();
+    assert!(report.contains(FindingKind::Unreadable) && report.has_errors());
+    write_test_wav(&path, fmt, &[0.0; 8]);
+    let limits = ParseLimits {
+        max_chunk_count: 0,
+        ..Default::default()
+    };
+    let report = WavFile::open_with_limits(&path, limits)
+        .unwrap()
+        .validate()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let finding = report
+        .findings
+        .iter()
+        .find(|finding| finding.kind == FindingKind::Unreadable)
+        .unwrap();
+    assert_eq!((finding.offset, finding.severity), (36, Severity::Error));
+}
+
+fn read_test_samples(path: &Path) -> Vec<f64> {
+    let mut reader = SampleReader::new(&mut WavFile::new(path)).unwrap();
+    let mut samples = Vec::new();
+    reader
+        .read_frames(reader.num_frames as usize, &mut samples)
+        .unwrap();
+    samples
+}
+
+#[test]
+pub fn repair_crash_interrupted_recording() {
+    let path = temp_path("repair_crash.wav");
+    let samples: Vec<f64> = (0..20).map(|i| i as f64 / 64.0).collect();
+    write_test_wav(&path, test_fmt(2, 48000, 16), &samples);
+    // A recorder that lost power: sizes never written, half a sample of a new frame on disk
+    patch_file(&path, 4, &0u32.to_le_bytes());
+    patch_file(&path, 40, &0u32.to_le_bytes());
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.push(0x7f);
+    std::fs::write(&path, bytes).unwrap();
+
+    let report = repair::repair(&path, &path).unwrap();
+    assert_eq!(
+        report.changes,
+        [
+            RepairChange::TrimmedPartialFrame { bytes: 1 },
+            RepairChange::DataSize { from: 0, to: 40 },
+            RepairChange::RiffSize { from: 0, to: 76 },
+        ]
+    );
+    let validation = WavFile::new(&path).validate().unwrap();
+    assert!(validation.is_clean(), "{:?}", validation.findings);
+    assert_eq!(read_test_samples(&path), samples);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn repair_drops_dangling_chunks() {
+    let input = temp_path("repair_dangling_in.wav");
+    let output = temp_path("repair_dangling_out.wav");
+    let mut writer = WavWriter::new(test_fmt(1, 8000, 16));
+    writer.set_chunk(Chunk::new(*b"data", vec![1u8; 8]));
+    writer.chunks.push(Chunk::new(*b"LIST", vec![2u8; 4]));
+    writer.write(&input).unwrap();
+    // A chunk header whose data was never written, then a partial header
+    let mut bytes = std::fs::read(&input).unwrap();
+    bytes.extend_from_slice(b"junk");
+    bytes.extend_from_slice(&64u32.to_le_bytes());
+    std::fs::write(&input, &bytes).unwrap();
+
+    let report = repair::repair(&input, &output).unwrap();
+    assert_eq!(
+        report.changes[0],
+        RepairChange::DroppedTruncatedChunk {
+            chunk_id: *b"junk",
+            offset: 64,
+        }
+    );
+    assert_eq!(std::fs::read(&output).unwrap().len(), 64);
+
+    bytes.truncate(67);
+    std::fs::write(&input, &bytes).unwrap();
+    let report = repair::repair(&input, &output).unwrap();
+    assert_eq!(
+        report.changes[0],
+        RepairChange::DroppedPartialHeader {
+            offset: 64,
+            bytes: 3,
+        }
+    );
+    let chunks: Vec<Chunk> = WavFile::new(&output).collect();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[1].data, [2u8; 4]);
+}
+
+#[test]
+pub fn repair_trims_data_to_whole_frames_and_keeps_empty_data() {
+    let input = temp_path("repair_frames_in.wav");
+    let output = temp_path("repair_frames_out.wav");
+    // Half a 16 bit stereo frame at the end of a finalized data chunk, then a LIST chunk
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 16));
+    writer.set_chunk(Chunk::new(*b"data", vec![1u8; 38]));
+    writer.chunks.push(Chunk::new(*b"LIST", vec![2u8; 4]));
+    writer.write(&input).unwrap();
+    let report = repair::repair(&input, &output).unwrap();
+    assert_eq!(
+        report.changes,
+        [
+            RepairChange::TrimmedPartialFrame { bytes: 2 },
+            RepairChange::DataSize { from: 38, to: 36 },
+            RepairChange::RiffSize { from: 86, to: 84 },
+        ]
+    );
+    let chunks: Vec<Chunk> = WavFile::new(&output).collect();
+    assert_eq!(chunks[0].data, [1u8; 36]);
+    assert_eq!(chunks[1].data, [2u8; 4]);
+
+    // An empty data chunk followed by other chunks isn't an unfinalized recording
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 16));
+    writer.set_chunk(Chunk::new(*b"data", Vec::new()));
+    writer.chunks.push(Chunk::new(*b"LIST", vec![2u8; 4]));
+    writer.write(&input).unwrap();
+    let report = repair::repair(&input, &output).unwrap();
+    assert!(report.is_unchanged(), "{:?}", report.changes);
+    assert_eq!(
+        std::fs::read(&input).unwrap(),
+        std::fs::read(&output).unwrap()
+    );
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+pub fn repair_leaves_valid_files_unchanged() {
+    let input = temp_path("repair_valid_in.wav");
+    let output = temp_path("repair_valid_out.wav");
+    write_test_wav(&input, test_fmt(2, 44100, 24), &[0.25; 12]);
+    let report = repair::repair(&input, &output).unwrap();
+    assert!(report.is_unchanged());
+    assert_eq!(
+        std::fs::read(&input).unwrap(),
+        std::fs::read(&output).unwrap()
+    );
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    // Too short for a header, or missing, is an error rather than a panic
+    std::fs::write(&input, [0u8; 20]).unwrap();
+    let err = repair::repair(&input, &output).unwrap_err();
+    assert!(matches!(err, RepairError::Wav(WavError::TooShort(20))));
+    std::fs::remove_file(&input).unwrap();
+    let err = repair::repair(&input, &output).unwrap_err();
+    assert!(matches!(err, RepairError::Wav(WavError::Io(_))));
+    assert!(!output.exists());
+}
+
+#[test]
+pub fn parser_rejects_oversized_chunk_without_allocating() {
+    // 36 byte header, then a chunk header claiming 4 GiB and 6 bytes of data
+    let path = temp_path("parser_oversized.wav");
+    write_test_wav(&path, test_fmt(1, 8000, 16), &[]);
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.truncate(36);
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+    bytes.extend_from_slice(&[0u8; 6]);
+    assert_eq!(bytes.len(), 50);
+    std::fs::write(&path, &bytes).unwrap();
+
+    let mut wav_file = WavFile::open(&path).unwrap();
+    assert!(matches!(
+        wav_file.next_chunk(),
+        Err(WavError::ChunkPastEof {
+            size: 0xFFFFFFFF,
+            available: 6,
+            ..
+        })
+    ));
+    let mut wav_file = WavFile::open(&path).unwrap();
+    assert_eq!(wav_file.by_ref().count(), 0);
+    assert!(wav_file.error.is_some());
+    assert!(matches!(
+        WavWriter::from_wav_file(WavFile::open(&path).unwrap()),
+        Err(WavError::ChunkPastEof { .. })
+    ));
+    assert!(matches!(
+        wav::parse_bytes(&bytes, &ParseLimits::default()),
+        Err(WavError::ChunkPastEof { .. })
+    ));
+
+    std::fs::write(&path, &bytes[..20]).unwrap();
+    assert!(matches!(WavFile::open(&path), Err(WavError::TooShort(20))));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn parser_enforces_limits() {
+    let mut writer = WavWriter::new(test_fmt(1, 8000, 16));
+    for _ in 0..4 {
+        writer.chunks.push(Chunk::new(*b"LIST", vec![0u8; 100]));
+    }
+    writer.set_chunk(Chunk::new(*b"data", vec![0u8; 200]));
+    let mut bytes = Vec::new();
+    writer.write_to(&mut bytes).unwrap();
+
+    let parse = |limits: ParseLimits| wav::parse_bytes(&bytes, &limits);
+    assert_eq!(parse(ParseLimits::default()).unwrap().1.len(), 5);
+    // Only the file length bounds the data chunk
+    let limits = ParseLimits {
+        max_chunk_size: 150,
+        ..Default::default()
+    };
+    assert_eq!(parse(limits).unwrap().1.len(), 5);
+    assert!(matches!(
+        parse(ParseLimits {
+            max_chunk_size: 50,
+            ..Default::default()
+        }),
+        Err(WavError::ChunkTooLarge { size: 100, .. })
+    ));
+    assert!(matches!(
+        parse(ParseLimits {
+            max_chunk_count: 3,
+            ..Default::default()
+        }),
+        Err(WavError::TooManyChunks(3))
+    ));
+    assert!(matches!(
+        parse(ParseLimits {
+            max_metadata_size: 350,
+            ..Default::default()
+        }),
+        Err(WavError::MetadataTooLarge(350))
+    ));
+
+    // Chunks read one at a time from the index count toward the same budget
+    let path = temp_path("parser_limits.wav");
+    std::fs::write(&path, &bytes).unwrap();
+    let limits = ParseLimits {
+        max_metadata_size: 350,
+        ..Default::default()
+    };
+    let mut wav_file = WavFile::open_with_limits(&path, limits).unwrap();
+    assert!(matches!(
+        wav_file.metadata_chunks(),
+        Err(WavError::MetadataTooLarge(350))
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn parsers_survive_garbage() {
+    // xorshift, so failures reproduce
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut next_byte = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    };
+    let mut valid = Vec::new();
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 16));
+    writer.set_chunk(Chunk::new(*b"data", vec![0u8; 64]));
+    writer.write_to(&mut valid).unwrap();
+
+    for round in 0..2000 {
+        let mut bytes: Vec<u8> = if round % 2 == 0 {
+            (0..(round % 300)).map(|_| next_byte()).collect()
+        } else {
+            valid.clone()
+        };
+        // Corrupt a few bytes, which mostly lands on header and size fields of the valid file
+        for _ in 0..4 {
+            if !bytes.is_empty() {
+                let index = next_byte() as usize % bytes.len();
+                bytes[index] = next_byte();
+            }
+        }
+        let _ = wav::parse_bytes(&bytes, &ParseLimits::default());
+        let _ = AcidChunk::parse(&bytes);
+        let _ = LevlChunk::parse(&bytes).map(|levl| levl.channel_peaks(1));
+        let _ = CartChunk::parse(&bytes).map(|cart| cart.validate());
+        let _ = ChnaChunk::parse(&bytes).map(|chna| chna.validate(&test_fmt(2, 48000, 16)));
+        let _ = AxmlChunk::parse(&bytes).map(|axml| axml.parse_adm());
+        let _ = CueChunk::parse(&bytes).map(|cue| cue.to_bytes());
+        let _ = SmplChunk::parse(&bytes).map(|smpl| smpl.to_bytes());
+        let _ = AdtlChunk::parse(&bytes).map(|adtl| adtl.to_bytes());
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+pub fn metadata_json_round_trip_and_apply() {
+    use crate::metadata::WavMetadata;
+
+    let input = temp_path("metadata_in.wav");
+    let output = temp_path("metadata_out.wav");
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 24));
+    writer.set_chunk(Chunk::new(*b"data", vec![7u8; 24]));
+    writer.set_chunk(AcidChunk::new_loop(96.0, 4).to_chunk());
+    writer.set_chunk(test_chna().to_chunk());
+    writer.set_chunk(AxmlChunk::new(TEST_ADM_XML).to_chunk());
+    let (cue, mut adtl) = test_markers(&[(1, 2, "Intro")]);
+    adtl.other.push((*b"file", vec![1, 2, 3]));
+    writer.set_chunk(cue.to_chunk());
+    let info = Chunk::new(*b"LIST", b"INFOISFT\x04\0\0\0rwav".to_vec());
+    writer.chunks.insert(0, info.clone());
+    writer.chunks.push(adtl.to_chunk());
+    writer.set_chunk(Md5Chunk { digest: [5; 16] }.to_chunk());
+    writer.write(&input).unwrap();
+
+    let metadata = WavMetadata::read(&mut WavFile::open(&input).unwrap()).unwrap();
+    let json = metadata.to_json().unwrap();
+    assert!(json.contains(r#""chunk_id": "acid""#));
+    assert!(json.contains("audioProgrammeName"));
+    assert!(!json.contains(r#""cart""#));
+    assert!(json.contains(r#""text": "Intro""#));
+    assert_eq!(metadata.md5, Some(Md5Chunk { digest: [5; 16] }));
+
+    let yaml = metadata.to_yaml().unwrap();
+    let from_yaml = WavMetadata::from_yaml(&yaml).unwrap();
+    assert_eq!(from_yaml.chna, metadata.chna);
+    assert_eq!(from_yaml.axml, metadata.axml);
+
+    let edited = json.replace("96.0", "128.0").replace("Intro", "Verse");
+    let mut edited = WavMetadata::from_json(&edited).unwrap();
+    edited.cart = Some(CartChunk {
+        title: "Loop".to_string(),
+        ..Default::default()
+    });
+    edited.apply(&input, &output).unwrap();
+
+    let reread = WavMetadata::read(&mut WavFile::open(&output).unwrap()).unwrap();
+    let chunks: Vec<Chunk> = WavFile::new(&output).collect();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(reread.acid.unwrap().tempo, 128.0);
+    assert_eq!(reread.cart.unwrap().title, "Loop");
+    assert_eq!(reread.chna, metadata.chna);
+    assert_eq!(reread.md5, metadata.md5);
+    // The adtl list is edited in place, keeping its other sub-chunks and the INFO list
+    assert_eq!(reread.adtl.unwrap().label(1), Some("Verse"));
+    assert!(chunks.iter().any(|chunk| chunk.data == info.data));
+    let adtl_chunk = chunks.iter().find(|chunk| AdtlChunk::is_adtl(chunk));
+    assert_eq!(
+        AdtlChunk::from_chunk(adtl_chunk.unwrap()).unwrap().other,
+        adtl.other
+    );
+    assert_eq!(reread.header.fmt.sample_rate, 48000);
+    let data = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == *b"data")
+        .unwrap();
+    assert_eq!(data.data, [7u8; 24]);
+}
+
+#[test]
+pub fn convert_float_to_16_bit_counts_clips() {
+    let input = temp_path("convert_float.wav");
+    let output = temp_path("convert_16.wav");
+    let samples = [0.0, 0.25, -0.5, 1.5, -2.0, 0.999];
+    write_test_wav(&input, SampleFormat::F32.fmt(2, 44100), &samples);
+    let mut writer = WavWriter::from_wav_file(WavFile::new(&input)).unwrap();
+    writer.set_chunk(AcidChunk::new_loop(120.0, 4).to_chunk());
+    writer.write(&input).unwrap();
+
+    let mut settings = ConvertSettings::new(SampleFormat::I16);
+    settings.dither = Dither::None;
+    let report = convert::convert_file(&input, &output, settings).unwrap();
+    assert_eq!(report.frames, 3);
+    assert_eq!(report.clipped_samples, 2);
+    assert_eq!(report.peak, 2.0);
+
+    let mut wav_file = WavFile::new(&output);
+    assert_eq!(
+        bytemuck::bytes_of(&wav_file.header.fmt),
+        bytemuck::bytes_of(&test_fmt(2, 44100, 16))
+    );
+    assert!(wav_file.find_chunk(&AcidChunk::ID).is_some());
+    let converted = read_test_samples(&output);
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(&converted[..3], &[0.0, 0.25, -0.5]);
+    assert_eq!(converted[3], 32767.0 / 32768.0);
+    assert_eq!(converted[4], -1.0);
+}
+
+#[test]
+pub fn convert_widening_is_bit_exact() {
+    let input = temp_path("convert_widen_16.wav");
+    let wide = temp_path("convert_widen_24.wav");
+    let narrow = temp_path("convert_widen_back.wav");
+    let samples: Vec<f64> = (0..64).map(|i| (i as f64 - 32.0) / 37.0).collect();
+    write_test_wav(&input, test_fmt(1, 48000, 16), &samples);
+    let original = read_test_samples(&input);
+
+    let mut settings = ConvertSettings::new(SampleFormat::I24);
+    settings.noise_shaping = NoiseShaping::Lipshitz;
+    convert::convert_file(&input, &wide, settings).unwrap();
+    // Widening ignored the dither settings, so truncating back without dither is lossless
+    let report = convert::convert_file(
+        &wide,
+        &narrow,
+        ConvertSettings {
+            dither: Dither::None,
+            noise_shaping: NoiseShaping::None,
+            ..ConvertSettings::new(SampleFormat::I16)
+        },
+    )
+    .unwrap();
+    assert_eq!(report.clipped_samples, 0);
+    let round_trip = read_test_samples(&narrow);
+    for path in [&input, &wide, &narrow] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(round_trip, original);
+}
+
+#[test]
+pub fn file_operations_write_over_their_input() {
+    let path = temp_path("in_place.wav");
+    let leftover = path.with_file_name(format!(
+        ".{}.rwav-write",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    let samples: Vec<f64> = (0..4000).map(|i| (i as f64 * 0.05).sin() * 0.5).collect();
+    write_test_wav(&path, test_fmt(2, 48000, 16), &samples);
+
+    let settings = ConvertSettings::new(SampleFormat::I24);
+    assert_eq!(
+        convert::convert_file(&path, &path, settings)
+            .unwrap()
+            .frames,
+        2000
+    );
+    let gain = gain::apply_gain(&path, &path, GainMode::Fixed { db: -6.0 }).unwrap();
+    assert_eq!(gain.frames, 2000);
+    let bands = [FilterBand::HighPass {
+        frequency: 80.0,
+        q: std::f64::consts::FRAC_1_SQRT_2,
+    }];
+    assert_eq!(
+        filter::filter_file(&path, &path, &bands).unwrap().frames,
+        2000
+    );
+    let fade = Fade {
+        curve: FadeCurve::Linear,
+        length: FadeLength::Frames(100),
+    };
+    assert_eq!(
+        fade::fade_file(&path, &path, Some(fade), None).unwrap(),
+        2000
+    );
+    let matrix = MixMatrix::preset(MixPreset::Mono, 2, None).unwrap();
+    assert_eq!(mix::mix_file(&path, &path, &matrix).unwrap().frames, 2000);
+    let reader = SampleReader::new(&mut WavFile::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        (reader.format, reader.num_channels, reader.num_frames),
+        (SampleFormat::I24, 1, 2000)
+    );
+    assert!(!leftover.exists());
+
+    // A writer that is never finished leaves neither file behind
+    std::fs::remove_file(&path).unwrap();
+    let writer = SampleWriter::create(&path, SampleFormat::I16, 1, 48000, None, &[]).unwrap();
+    assert!(leftover.exists() && !path.exists());
+    drop(writer);
+    assert!(!leftover.exists() && !path.exists());
+}
+
+#[test]
+pub fn pipelines_write_over_their_input() {
+    let path = temp_path("pipeline_in_place.wav");
+    let samples: Vec<f64> = (0..4000).map(|i| (i as f64 * 0.05).sin() * 0.5).collect();
+    write_test_wav(&path, test_fmt(2, 48000, 16), &samples);
+
+    let report = resample::resample_file(&path, &path, 24000, ResampleQuality::Balanced).unwrap();
+    assert_eq!(report.frames, 1000);
+    let processors = [Dynamics::Limiter(LimiterSettings::default())];
+    assert_eq!(
+        dynamics::dynamics_file(&path, &path, &processors)
+            .unwrap()
+            .frames,
+        1000
+    );
+    let chain: EffectChain = "gain -3 channels 1".parse().unwrap();
+    let report = effects::process_file(&path, &path, &chain, None).unwrap();
+    assert_eq!(report.frames, 1000);
+    let reader = SampleReader::new(&mut WavFile::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        (reader.num_channels, reader.sample_rate, reader.num_frames),
+        (1, 24000, 1000)
+    );
+}
+
+#[test]
+pub fn requantizer_dither_error_is_bounded() {
+    for noise_shaping in [NoiseShaping::None, NoiseShaping::FirstOrder] {
+        let mut settings = ConvertSettings::new(SampleFormat::I16);
+        settings.noise_shaping = noise_shaping;
+        let mut requantizer = Requantizer::new(1, settings);
+        let input: Vec<f64> = (0..4096).map(|i| (i as f64 * 0.01).sin() * 0.3).collect();
+        let mut output = input.clone();
+        requantizer.process(&mut output);
+        let lsb = 1.0 / 32768.0;
+        let max_error = input
+            .iter()
+            .zip(&output)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(max_error <= 3.0 * lsb, "{noise_shaping:?}: {max_error}");
+        assert!(output.iter().all(|s| (s / lsb).fract() == 0.0));
+        assert_eq!(requantizer.clipped_samples, 0);
+    }
+}
+
+fn sine(frequency: f64, sample_rate: u32, num_frames: usize) -> Vec<f64> {
+    (0..num_frames)
+        .map(|i| (std::f64::consts::TAU * frequency * i as f64 / sample_rate as f64).sin() * 0.5)
+        .collect()
+}
+
+#[test]
+pub fn resampler_converts_sine_accurately() {
+    for (from_rate, to_rate) in [(44100, 48000), (48000, 44100), (96000, 44100)] {
+        let input = sine(1000.0, from_rate, from_rate as usize / 10);
+        let output =
+            resample::resample_samples(&input, 1, from_rate, to_rate, ResampleQuality::Balanced)
+                .unwrap();
+        let expected = sine(1000.0, to_rate, output.len());
+        assert_eq!(output.len(), to_rate as usize / 10);
+        // Ignore the edges, where the input starts and stops abruptly
+        let edge = 200;
+        let max_error = output[edge..output.len() - edge]
+            .iter()
+            .zip(&expected[edge..])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(max_error < 1e-4, "{from_rate} -> {to_rate}: {max_error}");
+    }
+    assert!(matches!(
+        Resampler::new(1, 0, 48000, ResampleQuality::Fast),
+        Err(ResampleError::ZeroRate { from_rate: 0, .. })
+    ));
+    assert!(Resampler::new(1, 48000, 0, ResampleQuality::Fast).is_err());
+}
+
+#[test]
+pub fn resampler_streams_in_any_block_size() {
+    let input: Vec<f64> = sine(440.0, 44100, 3000)
+        .into_iter()
+        .flat_map(|sample| [sample, -sample])
+        .collect();
+    let whole = resample::resample_samples(&input, 2, 44100, 48000, ResampleQuality::Fast).unwrap();
+
+    let mut resampler = Resampler::new(2, 44100, 48000, ResampleQuality::Fast).unwrap();
+    let mut streamed = Vec::new();
+    let mut block = Vec::new();
+    for frames in input.chunks(2 * 37) {
+        resampler.process(frames, &mut block);
+        streamed.extend_from_slice(&block);
+    }
+    resampler.flush(&mut block);
+    streamed.extend_from_slice(&block);
+    assert_eq!(streamed, whole);
+    assert_eq!(whole.len() as u64, 2 * resampler.output_len(3000));
+}
+
+#[test]
+pub fn resampler_rejects_aliases() {
+    // 30 kHz can't be represented at 44.1 kHz and must not fold back to 14.1 kHz
+    let input = sine(30000.0, 96000, 9600);
+    let output =
+        resample::resample_samples(&input, 1, 96000, 44100, ResampleQuality::Best).unwrap();
+    let middle = &output[500..output.len() - 500];
+    let peak = middle.iter().fold(0.0f64, |peak, s| peak.max(s.abs()));
+    assert!(peak < 0.5e-5, "{peak}");
+}
+
+#[test]
+pub fn resample_file_keeps_format_and_chunks() {
+    let input = temp_path("resample_in.wav");
+    let output = temp_path("resample_out.wav");
+    let samples: Vec<f64> = sine(1000.0, 48000, 4800)
+        .into_iter()
+        .flat_map(|sample| [sample, sample / 2.0])
+        .collect();
+    write_test_wav(&input, test_fmt(2, 48000, 24), &samples);
+    let mut writer = WavWriter::from_wav_file(WavFile::new(&input)).unwrap();
+    writer.set_chunk(AcidChunk::new_loop(120.0, 4).to_chunk());
+    writer.write(&input).unwrap();
+
+    let report =
+        resample::resample_file(&input, &output, 44100, ResampleQuality::Balanced).unwrap();
+    assert_eq!(report.frames, 4410);
+    assert_eq!(report.clipped_samples, 0);
+    let mut wav_file = WavFile::new(&output);
+    assert_eq!(
+        bytemuck::bytes_of(&wav_file.header.fmt),
+        bytemuck::bytes_of(&test_fmt(2, 44100, 24))
+    );
+    assert!(wav_file.find_chunk(&AcidChunk::ID).is_some());
+    let resampled = read_test_samples(&output);
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    let expected = sine(1000.0, 44100, 4410);
+    for (frame, expected) in resampled.chunks(2).zip(&expected).skip(100).take(4000) {
+        assert!((frame[0] - expected).abs() < 1e-4);
+        assert!((frame[1] - expected / 2.0).abs() < 1e-4);
+    }
+}
+
+#[test]
+pub fn resampling_moves_markers_to_the_new_rate() {
+    let input = temp_path("resample_markers_in.wav");
+    let output = temp_path("resample_markers_out.wav");
+    let (cue, mut adtl) = test_markers(&[(1, 480, "a")]);
+    adtl.regions.push(CueRegion::new(1, 96));
+    let smpl = SmplChunk {
+        header: SmplHeader {
+            sample_period: 20833,
+            ..Default::default()
+        },
+        loops: vec![SampleLoop {
+            cue_point_id: 0,
+            loop_type: LOOP_FORWARD,
+            start: 100,
+            end: 199,
+            fraction: 0,
+            play_count: 0,
+        }],
+        ..Default::default()
+    };
+    let bext = BextChunk {
+        time_reference: 48000 * 3600,
+        ..Default::default()
+    };
+    let mut writer = SampleWriter::create(
+        &input,
+        SampleFormat::I16,
+        1,
+        48000,
+        None,
+        &[bext.to_chunk(), cue.to_chunk(), smpl.to_chunk()],
+    )
+    .unwrap();
+    writer.write_frames(&[0.0; 4800]).unwrap();
+    writer.finish(&[adtl.to_chunk()]).unwrap();
+
+    let chain: EffectChain = "rate 44100".parse().unwrap();
+    let resample = |output: &Path| {
+        resample::resample_file(&input, output, 44100, ResampleQuality::Fast).unwrap();
+    };
+    let process = |output: &Path| {
+        effects::process_file(&input, output, &chain, None).unwrap();
+    };
+    for run in [&resample as &dyn Fn(&Path), &process] {
+        run(&output);
+        let (cue, adtl, chunks) = read_marker_chunks(&output);
+        assert_eq!(cue.points[0].sample_offset, 441);
+        assert_eq!(adtl.region(1).unwrap().sample_length, 88);
+        let find = |id: &[u8; 4]| {
+            chunks
+                .iter()
+                .find(|chunk| chunk.chunk_header.chunk_id == *id)
+                .unwrap()
+        };
+        let smpl = SmplChunk::from_chunk(find(b"smpl")).unwrap();
+        assert_eq!((smpl.loops[0].start, smpl.loops[0].end), (92, 183));
+        assert_eq!(smpl.header.sample_period, 22675);
+        let bext = BextChunk::from_chunk(find(b"bext")).unwrap();
+        assert_eq!(bext.time_reference, 44100 * 3600);
+    }
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+/// Writes `samples` as WAVE_FORMAT_EXTENSIBLE 24 bit audio with `channel_mask`
+fn write_extensible_wav(path: &Path, num_channels: u16, channel_mask: u32, samples: &[f64]) {
+    let mut writer = SampleWriter::create(
+        path,
+        SampleFormat::I24,
+        num_channels,
+        48000,
+        Some(channel_mask),
+        &[],
+    )
+    .unwrap();
+    writer.write_frames(samples).unwrap();
+    writer.finish(&[]).unwrap();
+}
+
+#[test]
+pub fn channel_mask_speakers() {
+    let mask = ChannelMask::SURROUND_5_1_SIDE;
+    assert_eq!(mask.to_string(), "L R C LFE Ls Rs");
+    assert_eq!(ChannelMask::from_speakers(&mask.speakers()), mask);
+    assert_eq!(ChannelMask::default_for(8), Some(ChannelMask::SURROUND_7_1));
+    assert_eq!(Speaker::LowFrequency.bit(), 0x8);
+}
+
+#[test]
+pub fn mix_5_1_to_stereo_and_mono() {
+    let input = temp_path("mix_51.wav");
+    let stereo = temp_path("mix_stereo.wav");
+    let mono = temp_path("mix_mono.wav");
+    // L R C LFE Ls Rs
+    let frame = [0.1, 0.2, 0.3, 0.9, 0.05, -0.05];
+    write_extensible_wav(
+        &input,
+        6,
+        ChannelMask::SURROUND_5_1_SIDE.0,
+        &frame.repeat(10),
+    );
+
+    let mut wav_file = WavFile::new(&input);
+    assert_eq!(
+        wav_file.fmt_extension().unwrap().unwrap().audio_format(),
+        Some(1)
+    );
+    assert!(wav_file.validate().unwrap().is_clean());
+    let reader = SampleReader::new(&mut wav_file).unwrap();
+    assert_eq!(reader.channel_mask, Some(0x60F));
+
+    let matrix =
+        MixMatrix::preset(MixPreset::Stereo, 6, Some(ChannelMask::SURROUND_5_1_SIDE)).unwrap();
+    let report = mix::mix_file(&input, &stereo, &matrix).unwrap();
+    assert_eq!(report.frames, 10);
+    let matrix =
+        MixMatrix::preset(MixPreset::Mono, 6, Some(ChannelMask::SURROUND_5_1_SIDE)).unwrap();
+    mix::mix_file(&input, &mono, &matrix).unwrap();
+
+    let mut stereo_file = WavFile::new(&stereo);
+    let extension = stereo_file.fmt_extension().unwrap().unwrap();
+    assert_eq!({ extension.channel_mask }, ChannelMask::STEREO.0);
+    assert_eq!(stereo_file.header.fmt.num_channels, 2);
+    let stereo_samples = read_test_samples(&stereo);
+    let mono_samples = read_test_samples(&mono);
+    for path in [&input, &stereo, &mono] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    let left = 0.1 + h * 0.3 + h * 0.05;
+    let right = 0.2 + h * 0.3 - h * 0.05;
+    let lsb = 2.0 / 8388608.0;
+    assert!((stereo_samples[0] - left).abs() < lsb);
+    assert!((stereo_samples[1] - right).abs() < lsb);
+    assert!((mono_samples[0] - h * (left + right)).abs() < lsb);
+}
+
+#[test]
+pub fn mix_user_matrix() {
+    let matrix: MixMatrix = "0,1;1,0".parse().unwrap();
+    assert_eq!(matrix.output_mask, Some(ChannelMask::STEREO));
+    assert!(matrix.is_routing());
+    let mut swapped = Vec::new();
+    matrix.process(&[0.25, -0.5, 1.0, 0.0], &mut swapped);
+    assert_eq!(swapped, [-0.5, 0.25, 0.0, 1.0]);
+
+    let input = temp_path("mix_user.wav");
+    let output = temp_path("mix_user_out.wav");
+    write_test_wav(&input, test_fmt(1, 48000, 16), &[0.5, -0.25]);
+    let err = mix::mix_file(&input, &output, &matrix).unwrap_err();
+    assert!(matches!(err, MixError::ChannelCount { matrix: 2, file: 1 }));
+
+    let matrix = MixMatrix::preset(MixPreset::MonoToStereo, 1, None).unwrap();
+    mix::mix_file(&input, &output, &matrix).unwrap();
+    // Plain stereo stays WAVE_FORMAT_PCM
+    assert!(WavFile::new(&output).fmt_extension().unwrap().is_none());
+    assert_eq!(read_test_samples(&output), [0.5, 0.5, -0.25, -0.25]);
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    assert!("1,0;1".parse::<MixMatrix>().is_err());
+    assert!(MixMatrix::new(vec![vec![1.0]; 3], Some(ChannelMask::STEREO)).is_err());
+}
+
+#[test]
+pub fn ixml_track_list() {
+    let ixml = IxmlChunk::new(
+        "<BWFXML><PROJECT>Scene 4</PROJECT><TRACK_LIST><TRACK_COUNT>2</TRACK_COUNT>\
+         <TRACK><CHANNEL_INDEX>3</CHANNEL_INDEX><INTERLEAVE_INDEX>1</INTERLEAVE_INDEX>\
+         <NAME>Boom &amp; Lav</NAME></TRACK><TRACK><CHANNEL_INDEX>4</CHANNEL_INDEX>\
+         <INTERLEAVE_INDEX>2</INTERLEAVE_INDEX><NAME>Plant</NAME></TRACK></TRACK_LIST></BWFXML>",
+    );
+    let tracks = ixml.tracks();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].name, "Boom & Lav");
+    assert_eq!(tracks[1].channel_index, 4);
+    assert_eq!(ixml.track_name(2).as_deref(), Some("Plant"));
+
+    let rewritten = IxmlChunk::with_tracks(Some(&ixml), &tracks[1..]);
+    assert!(rewritten.xml().contains("<PROJECT>Scene 4</PROJECT>"));
+    assert_eq!(rewritten.tracks(), &tracks[1..]);
+    let fresh = IxmlChunk::with_tracks(None, &tracks);
+    assert_eq!(fresh.tracks(), tracks);
+}
+
+#[test]
+pub fn split_and_merge_poly_file() {
+    let input = temp_path("poly.wav");
+    let merged = temp_path("poly_merged.wav");
+    let frames: Vec<f64> = (0..30).map(|i| i as f64 / 64.0).collect();
+    let mut writer = SampleWriter::create(
+        &input,
+        SampleFormat::I24,
+        3,
+        48000,
+        Some(0x7),
+        &[
+            Chunk::new(*b"bext", vec![b'x'; 602]),
+            IxmlChunk::with_tracks(
+                None,
+                &[IxmlTrack {
+                    channel_index: 1,
+                    interleave_index: 1,
+                    name: "Boom/Mix".to_string(),
+                    function: String::new(),
+                }],
+            )
+            .to_chunk(),
+        ],
+    )
+    .unwrap();
+    writer.write_frames(&frames).unwrap();
+    writer.finish(&[]).unwrap();
+
+    let output_dir = std::env::temp_dir();
+    let paths = poly::split(&input, &output_dir).unwrap();
+    let stem = input.file_stem().unwrap().to_string_lossy().to_string();
+    let names: Vec<String> = paths
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            format!("{stem}_Boom_Mix.wav"),
+            format!("{stem}_R.wav"),
+            format!("{stem}_C.wav")
+        ]
+    );
+    let mut mono = WavFile::new(&paths[1]);
+    assert_eq!(mono.header.fmt.num_channels, 1);
+    assert!(mono.find_chunk(b"bext").is_some());
+    let chunks: Vec<Chunk> = WavFile::new(&paths[1]).collect();
+    let ixml = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == IxmlChunk::ID)
+        .map(|chunk| IxmlChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    assert_eq!(ixml.tracks().len(), 1);
+    assert_eq!(ixml.track_name(1).as_deref(), Some("R"));
+    assert_eq!(
+        read_test_samples(&paths[2]),
+        frames
+            .iter()
+            .skip(2)
+            .step_by(3)
+            .copied()
+            .collect::<Vec<f64>>()
+    );
+
+    let inputs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    assert_eq!(poly::merge(&inputs, &merged).unwrap(), 10);
+    let mut merged_file = WavFile::new(&merged);
+    assert!(merged_file.find_chunk(b"bext").is_some());
+    // "Boom/Mix" isn't a speaker label, so the mask can't be rebuilt
+    assert!(merged_file.fmt_extension().unwrap().is_none());
+    assert_eq!(read_test_samples(&merged), frames);
+
+    write_test_wav(&merged, test_fmt(1, 44100, 24), &[0.0; 10]);
+    let err = poly::merge(&[inputs[1], &merged], &input).unwrap_err();
+    assert!(matches!(err, PolyError::RateMismatch { found: 44100, .. }));
+    for path in paths.iter().chain([&input, &merged]) {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn split_and_merge_rebuilds_channel_mask() {
+    let input = temp_path("poly_51.wav");
+    let merged = temp_path("poly_51_merged.wav");
+    let frames: Vec<f64> = (0..60).map(|i| i as f64 / 128.0).collect();
+    write_extensible_wav(&input, 6, ChannelMask::SURROUND_5_1_SIDE.0, &frames);
+
+    let output_dir = std::env::temp_dir().join(format!("rwav_{}_split", std::process::id()));
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let paths = poly::split(&input, &output_dir).unwrap();
+    assert!(paths[5].ends_with(format!(
+        "{}_Rs.wav",
+        input.file_stem().unwrap().to_string_lossy()
+    )));
+    let inputs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    poly::merge(&inputs, &merged).unwrap();
+
+    let extension = WavFile::new(&merged).fmt_extension().unwrap().unwrap();
+    let merged_samples = read_test_samples(&merged);
+    std::fs::remove_dir_all(&output_dir).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&merged).unwrap();
+    assert_eq!({ extension.channel_mask }, ChannelMask::SURROUND_5_1_SIDE.0);
+    assert_eq!(merged_samples, frames);
+}
+
+#[test]
+pub fn bext_round_trip() {
+    let mut bext = BextChunk {
+        description: "Take 4".to_string(),
+        originator: "rwav".to_string(),
+        origination_date: "2024-05-01".to_string(),
+        origination_time: "12:30:00".to_string(),
+        time_reference: 48000 * 3600 * 10,
+        loudness_value: -2300,
+        ..Default::default()
+    };
+    bext.append_coding_history(SampleFormat::I24, 48000, 2, "rwav gain +1.00 dB");
+    assert_eq!(
+        bext.coding_history,
+        "A=PCM,F=48000,W=24,M=stereo,T=rwav gain +1.00 dB\r\n"
+    );
+    let bytes = bext.to_bytes();
+    assert_eq!(bytes.len(), 602 + bext.coding_history.len());
+    assert_eq!(BextChunk::parse(&bytes).unwrap(), bext);
+
+    // Version 1 has reserved bytes where the loudness fields are
+    let mut version_1 = bytes.clone();
+    version_1[346] = 1;
+    let parsed = BextChunk::parse(&version_1).unwrap();
+    assert_eq!(parsed.loudness_value, LOUDNESS_NOT_SET);
+    assert!(parsed.to_bytes()[412..422].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+pub fn loudness_of_full_scale_sine() {
+    // A 997 Hz sine at 0 dBFS in one channel of a stereo file reads -3.01 LUFS
+    let path = temp_path("loudness_sine.wav");
+    let samples: Vec<f64> = sine(997.0, 48000, 48000 * 2)
+        .into_iter()
+        .flat_map(|sample| [sample * 2.0, 0.0])
+        .collect();
+    write_test_wav(&path, test_fmt(2, 48000, 24), &samples);
+    let report = loudness::measure_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let integrated = report.integrated.unwrap();
+    assert!((integrated + 3.01).abs() < 0.05, "{integrated}");
+    assert!(report.sample_peak.abs() < 0.01);
+    assert!(report.true_peak.abs() < 0.1, "{}", report.true_peak);
+}
+
+#[test]
+pub fn gain_fixed_and_peak() {
+    let input = temp_path("gain_in.wav");
+    let output = temp_path("gain_out.wav");
+    write_test_wav(&input, test_fmt(1, 48000, 32), &sine(1000.0, 48000, 4800));
+
+    let report = gain::apply_gain(&input, &output, GainMode::Fixed { db: -6.0 }).unwrap();
+    assert_eq!(report.frames, 4800);
+    let peak = read_test_samples(&output)
+        .iter()
+        .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+    assert!(
+        (peak - 0.5 * 10f64.powf(-6.0 / 20.0)).abs() < 1e-6,
+        "{peak}"
+    );
+
+    let report = gain::apply_gain(&input, &output, GainMode::Peak { target_dbfs: -1.0 }).unwrap();
+    assert!((report.gain_db - (6.0206 - 1.0)).abs() < 1e-3);
+    let peak = read_test_samples(&output)
+        .iter()
+        .fold(0.0f64, |peak, sample| peak.max(sample.abs()));
+    assert!((peak - 10f64.powf(-1.0 / 20.0)).abs() < 1e-6, "{peak}");
+
+    // The peak envelope of the louder audio isn't carried over, nor after a fade
+    let settings = PeakEnvelopeSettings {
+        format: PeakFormat::U16,
+        points_per_value: 1,
+        block_size: 256,
+    };
+    let has_levl = |path: &Path| WavFile::open(path).unwrap().find_chunk(b"levl").is_some();
+    levl::embed_peak_envelope(&input, &input, settings).unwrap();
+    assert!(has_levl(&input));
+    gain::apply_gain(&input, &output, GainMode::Fixed { db: -6.0 }).unwrap();
+    assert!(!has_levl(&output));
+    let fade = Fade {
+        curve: FadeCurve::Linear,
+        length: FadeLength::Frames(100),
+    };
+    fade::fade_file(&input, &output, Some(fade), None).unwrap();
+    assert!(!has_levl(&output));
+
+    write_test_wav(&input, test_fmt(1, 48000, 16), &[0.0; 100]);
+    let err = gain::apply_gain(&input, &output, GainMode::Peak { target_dbfs: -1.0 });
+    assert!(matches!(err, Err(gain::GainError::Silent)));
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+pub fn loudness_normalization_updates_bext() {
+    let input = temp_path("normalize_in.wav");
+    let output = temp_path("normalize_out.wav");
+    let samples: Vec<f64> = sine(1000.0, 48000, 48000 * 3)
+        .into_iter()
+        .flat_map(|sample| [sample * 0.1, sample * 0.1])
+        .collect();
+    let bext = BextChunk {
+        loudness_value: -2500,
+        ..Default::default()
+    };
+    let mut writer = SampleWriter::create(
+        &input,
+        SampleFormat::I24,
+        2,
+        48000,
+        None,
+        &[bext.to_chunk()],
+    )
+    .unwrap();
+    writer.write_frames(&samples).unwrap();
+    writer.finish(&[]).unwrap();
+
+    let mode = GainMode::Loudness {
+        target_lufs: -23.0,
+        true_peak_ceiling: -1.0,
+    };
+    let report = gain::apply_gain(&input, &output, mode).unwrap();
+    assert!(!report.limited_by_true_peak);
+    let integrated = loudness::measure_file(&output).unwrap().integrated.unwrap();
+    assert!((integrated + 23.0).abs() < 0.05, "{integrated}");
+
+    let chunks: Vec<Chunk> = WavFile::new(&output).collect();
+    let bext = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == BextChunk::ID)
+        .map(|chunk| BextChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    let shift = (report.gain_db * 100.0).round() as i16;
+    assert_eq!(bext.loudness_value, -2500 + shift);
+    assert_eq!(bext.max_true_peak_level, LOUDNESS_NOT_SET);
+    assert!(bext
+        .coding_history
+        .starts_with("A=PCM,F=48000,W=24,M=stereo,T=rwav gain +"));
+
+    // A stereo sine's loudness is within a dB of its peak level, so 0 LUFS would clip
+    let mode = GainMode::Loudness {
+        target_lufs: 0.0,
+        true_peak_ceiling: -1.0,
+    };
+    let report = gain::apply_gain(&input, &output, mode).unwrap();
+    assert!(report.limited_by_true_peak);
+    let true_peak = loudness::measure_file(&output).unwrap().true_peak;
+    assert!((true_peak + 1.0).abs() < 0.05, "{true_peak}");
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+/// Stereo 1 kHz sine with both channels at each `(dbfs, seconds)` level in turn, as used by
+/// the EBU Tech 3341 and 3342 test signals
+fn ebu_tone(levels: &[(f64, usize)]) -> Vec<f64> {
+    let mut samples = Vec::new();
+    let mut frame = 0;
+    for &(dbfs, seconds) in levels {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        for _ in 0..seconds * 48000 {
+            let sample =
+                amplitude * (std::f64::consts::TAU * 1000.0 * frame as f64 / 48000.0).sin();
+            samples.extend_from_slice(&[sample, sample]);
+            frame += 1;
+        }
+    }
+    samples
+}
+
+fn measure_samples(num_channels: u16, samples: &[f64]) -> loudness::LoudnessReport {
+    let mut meter = loudness::LoudnessMeter::new(num_channels, 48000, None);
+    for block in samples.chunks(4096 * num_channels as usize) {
+        meter.process(block);
+    }
+    meter.finish()
+}
+
+#[test]
+pub fn loudness_ebu_tech_3341_cases() {
+    // Cases 1 and 2: steady tones read the same momentary, short-term and integrated loudness
+    for dbfs in [-23.0, -33.0] {
+        let report = measure_samples(2, &ebu_tone(&[(dbfs, 5)]));
+        for value in [
+            report.integrated,
+            report.momentary_max,
+            report.short_term_max,
+        ] {
+            let value = value.unwrap();
+            assert!((value - dbfs).abs() < 0.1, "{dbfs}: {value}");
+        }
+    }
+
+    // Cases 3 to 5, shortened to keep the test quick: the quieter parts fall below the
+    // relative gate
+    for levels in [
+        [(-36.0, 2), (-23.0, 12), (-36.0, 2)],
+        [(-72.0, 2), (-36.0, 2), (-23.0, 12)],
+        [(-26.0, 4), (-20.0, 4), (-26.0, 4)],
+    ] {
+        let integrated = measure_samples(2, &ebu_tone(&levels)).integrated.unwrap();
+        assert!((integrated + 23.0).abs() < 0.1, "{levels:?}: {integrated}");
+    }
+
+    // Cases 16 to 19 style true peak: a quarter sample rate sine at 45 degrees only hits its
+    // peak between samples
+    let samples: Vec<f64> = (0..4800)
+        .map(|i| (std::f64::consts::FRAC_PI_2 * i as f64 + std::f64::consts::FRAC_PI_4).sin())
+        .collect();
+    let report = measure_samples(1, &samples);
+    assert!(
+        (report.sample_peak + 3.01).abs() < 0.01,
+        "{}",
+        report.sample_peak
+    );
+    assert!(
+        report.true_peak > -0.4 && report.true_peak < 0.2,
+        "{}",
+        report.true_peak
+    );
+}
+
+#[test]
+pub fn loudness_range_ebu_tech_3342_cases() {
+    // Cases 1 to 3, shortened like the Tech 3341 ones
+    for (levels, expected) in [
+        ([(-20.0, 10), (-30.0, 10)], 10.0),
+        ([(-20.0, 10), (-15.0, 10)], 5.0),
+        ([(-40.0, 10), (-20.0, 10)], 20.0),
+    ] {
+        let range = measure_samples(2, &ebu_tone(&levels))
+            .loudness_range
+            .unwrap();
+        assert!((range - expected).abs() < 1.0, "{levels:?}: {range}");
+    }
+    assert_eq!(
+        measure_samples(2, &vec![0.0; 48000 * 8]).loudness_range,
+        None
+    );
+}
+
+#[test]
+pub fn loudness_written_to_bext() {
+    let path = temp_path("loudness_bext.wav");
+    write_test_wav(&path, test_fmt(2, 48000, 24), &ebu_tone(&[(-23.0, 5)]));
+    let samples = read_test_samples(&path);
+    let report = loudness::write_file_loudness(&path, &path).unwrap();
+    assert_eq!(read_test_samples(&path), samples);
+    let chunks: Vec<Chunk> = WavFile::new(&path).collect();
+    std::fs::remove_file(&path).unwrap();
+    let bext = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == BextChunk::ID)
+        .map(|chunk| BextChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    assert_eq!(bext.version, 2);
+    assert!(
+        (bext.loudness_value + 2300).abs() <= 10,
+        "{}",
+        bext.loudness_value
+    );
+    assert_eq!(
+        bext.max_true_peak_level,
+        (report.true_peak * 100.0).round() as i16
+    );
+    assert_ne!(bext.loudness_range, LOUDNESS_NOT_SET);
+    assert_ne!(bext.max_short_term_loudness, LOUDNESS_NOT_SET);
+    assert!(chunks.last().unwrap().chunk_header.chunk_id == *b"data");
+}
+
+#[test]
+pub fn stats_report_levels_clipping_and_silence() {
+    let path = temp_path("stats.wav");
+    // 0.1 s of silence, 1 s of sine with a DC offset, 0.6 s of silence, 0.4 s of clipped
+    // square wave, then 0.05 s of silence
+    let mut mono = vec![0.0; 4800];
+    mono.extend(sine(1000.0, 48000, 48000).iter().map(|s| s + 0.1));
+    mono.extend(vec![0.0; 28800]);
+    mono.extend((0..19200).map(|i| if i / 24 % 2 == 0 { 1.5 } else { -1.5 }));
+    mono.extend(vec![0.0; 2400]);
+    let samples: Vec<f64> = mono.iter().flat_map(|&s| [s, s * 0.25]).collect();
+
+    for bits in [8, 16, 24, 32] {
+        write_test_wav(&path, test_fmt(2, 48000, bits), &samples);
+        let report = stats::measure_file(&path, StatsSettings::default()).unwrap();
+        assert_eq!(report.frames, mono.len() as u64);
+        assert_eq!(
+            report.silences,
+            [
+                SilenceSpan {
+                    kind: SilenceKind::Leading,
+                    start_frame: 0,
+                    end_frame: 4800
+                },
+                SilenceSpan {
+                    kind: SilenceKind::Internal,
+                    start_frame: 52800,
+                    end_frame: 81600
+                },
+                SilenceSpan {
+                    kind: SilenceKind::Trailing,
+                    start_frame: 100800,
+                    end_frame: 103200
+                },
+            ],
+            "{bits} bit"
+        );
+
+        let left = &report.channels[0];
+        assert!(left.peak.abs() < 1e-3, "{bits} bit: {}", left.peak);
+        // Integer formats reach full scale on the negative side first
+        assert_eq!(left.peak_frame, 81624);
+        // Every half cycle of the square wave clips, 800 runs of 24 samples
+        assert_eq!(left.clipped_run_count, 800, "{bits} bit");
+        assert_eq!(
+            left.clipped_runs[0],
+            ClipRun {
+                start_frame: 81600,
+                length: 24
+            }
+        );
+        let expected_dc = (0.1 * 48000.0) / mono.len() as f64;
+        assert!(
+            (left.dc_offset - expected_dc).abs() < 1e-3,
+            "{}",
+            left.dc_offset
+        );
+
+        let right = &report.channels[1];
+        assert_eq!(right.clipped_run_count, 0);
+        assert!(
+            (right.peak - 20.0 * 0.375f64.log10()).abs() < 0.05,
+            "{}",
+            right.peak
+        );
+        let expected_rms = (samples
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|s| s * s)
+            .sum::<f64>()
+            / mono.len() as f64)
+            .sqrt();
+        assert!(
+            (right.rms - 20.0 * expected_rms.log10()).abs() < 0.05,
+            "{}",
+            right.rms
+        );
+        let crest_factor = right.crest_factor.unwrap();
+        assert!((crest_factor - (right.peak - right.rms)).abs() < 1e-9);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn stats_of_silent_and_float_files() {
+    let path = temp_path("stats_float.wav");
+    write_test_wav(&path, test_fmt(1, 48000, 16), &[0.0; 1000]);
+    let report = stats::measure_file(&path, StatsSettings::default()).unwrap();
+    assert_eq!(report.silences.len(), 1);
+    assert_eq!(report.silences[0].kind, SilenceKind::Leading);
+    assert_eq!(report.channels[0].crest_factor, None);
+
+    let mut fmt = test_fmt(1, 48000, 32);
+    fmt.audio_format = 3;
+    // Float samples can go past 1.0. Runs shorter than the minimum or changing sign don't count.
+    write_test_wav(
+        &path,
+        fmt,
+        &[0.5, 1.0, 1.0, -1.0, 0.2, -1.25, -1.5, -1.1, 0.0],
+    );
+    let report = stats::measure_file(&path, StatsSettings::default()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let channel = &report.channels[0];
+    assert_eq!(
+        channel.clipped_runs,
+        [ClipRun {
+            start_frame: 5,
+            length: 3
+        }]
+    );
+    assert!((channel.peak - 20.0 * 1.5f64.log10()).abs() < 1e-6);
+    assert_eq!(channel.peak_frame, 6);
+    assert_eq!(
+        report.silences,
+        [SilenceSpan {
+            kind: SilenceKind::Trailing,
+            start_frame: 8,
+            end_frame: 9
+        }]
+    );
+}
+
+fn test_markers(points: &[(u32, u32, &str)]) -> (CueChunk, AdtlChunk) {
+    let cue = CueChunk {
+        points: points
+            .iter()
+            .map(|&(id, frame, _)| CuePoint::new(id, frame))
+            .collect(),
+    };
+    let adtl = AdtlChunk {
+        labels: points
+            .iter()
+            .map(|&(id, _, label)| CueText {
+                cue_id: id,
+                text: label.to_string(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    (cue, adtl)
+}
+
+fn read_marker_chunks(path: &Path) -> (CueChunk, AdtlChunk, Vec<Chunk>) {
+    let chunks: Vec<Chunk> = WavFile::new(path).collect();
+    let cue = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == CueChunk::ID)
+        .map(|chunk| CueChunk::from_chunk(chunk).unwrap())
+        .unwrap_or_default();
+    let adtl = chunks
+        .iter()
+        .find(|chunk| AdtlChunk::is_adtl(chunk))
+        .map(|chunk| AdtlChunk::from_chunk(chunk).unwrap())
+        .unwrap_or_default();
+    (cue, adtl, chunks)
+}
+
+fn data_bytes(path: &Path) -> Vec<u8> {
+    WavFile::new(path)
+        .find(|chunk| chunk.chunk_header.chunk_id == *b"data")
+        .unwrap()
+        .data
+}
+
+#[test]
+pub fn marker_chunks_round_trip() {
+    let (cue, mut adtl) = test_markers(&[(1, 10, "Verse"), (2, 480, "Chorus")]);
+    adtl.notes.push(CueText {
+        cue_id: 2,
+        text: "louder".to_string(),
+    });
+    adtl.regions.push(CueRegion::new(2, 96));
+    adtl.other.push((*b"file", vec![1, 2, 3]));
+    let smpl = SmplChunk {
+        loops: vec![SampleLoop {
+            cue_point_id: 0,
+            loop_type: LOOP_FORWARD,
+            start: 100,
+            end: 199,
+            fraction: 0,
+            play_count: 0,
+        }],
+        sampler_data: vec![9; 3],
+        ..Default::default()
+    };
+
+    assert_eq!(CueChunk::parse(&cue.to_bytes()).unwrap(), cue);
+    assert_eq!(cue.next_id().unwrap(), 3);
+    let full = CueChunk {
+        points: vec![CuePoint::new(u32::MAX, 0)],
+    };
+    assert!(matches!(full.next_id(), Err(ChunkError::InvalidField(_))));
+    let adtl_chunk = adtl.to_chunk();
+    assert!(AdtlChunk::is_adtl(&adtl_chunk));
+    let parsed = AdtlChunk::from_chunk(&adtl_chunk).unwrap();
+    assert_eq!(parsed, adtl);
+    assert_eq!(parsed.label(2), Some("Chorus"));
+    assert_eq!(parsed.region(2).unwrap().sample_length, 96);
+    assert_eq!(SmplChunk::parse(&smpl.to_bytes()).unwrap(), smpl);
+    let info = Chunk::new(*b"LIST", b"INFOINAM".to_vec());
+    assert!(!AdtlChunk::is_adtl(&info));
+    assert!(AdtlChunk::from_chunk(&info).is_err());
+}
+
+#[test]
+pub fn trim_and_cut_shift_markers() {
+    let input = temp_path("edit_in.wav");
+    let output = temp_path("edit_out.wav");
+    let frames: Vec<f64> = (0..200).map(|i| (i as f64 - 100.0) / 128.0).collect();
+    let (cue, mut adtl) = test_markers(&[(1, 10, "a"), (2, 40, "b"), (3, 50, "c"), (4, 90, "d")]);
+    adtl.regions.push(CueRegion::new(2, 30));
+    let smpl = SmplChunk {
+        loops: vec![SampleLoop {
+            cue_point_id: 0,
+            loop_type: LOOP_FORWARD,
+            start: 60,
+            end: 79,
+            fraction: 0,
+            play_count: 0,
+        }],
+        ..Default::default()
+    };
+    let bext = BextChunk {
+        time_reference: 1000,
+        ..Default::default()
+    };
+    let mut writer = SampleWriter::create(
+        &input,
+        SampleFormat::I24,
+        2,
+        48000,
+        None,
+        &[bext.to_chunk(), cue.to_chunk(), smpl.to_chunk()],
+    )
+    .unwrap();
+    writer.write_frames(&frames).unwrap();
+    writer.finish(&[adtl.to_chunk()]).unwrap();
+    let original = data_bytes(&input);
+
+    assert_eq!(edit::trim(&input, &output, 20, 80).unwrap(), 60);
+    assert_eq!(data_bytes(&output), original[20 * 6..80 * 6]);
+    let (cue, adtl, chunks) = read_marker_chunks(&output);
+    let positions: Vec<(u32, u32)> = cue
+        .points
+        .iter()
+        .map(|point| (point.id, point.sample_offset))
+        .collect();
+    assert_eq!(positions, [(2, 20), (3, 30)]);
+    assert_eq!(adtl.region(2).unwrap().sample_length, 30);
+    assert_eq!(adtl.label(1), None);
+    assert_eq!(adtl.label(3), Some("c"));
+    let find = |id: &[u8; 4]| {
+        chunks
+            .iter()
+            .find(|chunk| chunk.chunk_header.chunk_id == *id)
+            .unwrap()
+    };
+    let smpl = SmplChunk::from_chunk(find(b"smpl")).unwrap();
+    assert_eq!((smpl.loops[0].start, smpl.loops[0].end), (40, 59));
+    let bext = BextChunk::from_chunk(find(b"bext")).unwrap();
+    assert_eq!(bext.time_reference, 1020);
+
+    assert_eq!(edit::cut(&input, &output, 30, 60).unwrap(), 70);
+    assert_eq!(
+        data_bytes(&output),
+        [&original[..30 * 6], &original[60 * 6..]].concat()
+    );
+    let (cue, adtl, chunks) = read_marker_chunks(&output);
+    let positions: Vec<(u32, u32)> = cue
+        .points
+        .iter()
+        .map(|point| (point.id, point.sample_offset))
+        .collect();
+    // The region starting at 40 loses its first 20 frames, the marker at 50 goes
+    assert_eq!(positions, [(1, 10), (2, 30), (4, 60)]);
+    assert_eq!(adtl.region(2).unwrap().sample_length, 10);
+    let smpl = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == SmplChunk::ID)
+        .map(|chunk| SmplChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    assert_eq!((smpl.loops[0].start, smpl.loops[0].end), (30, 49));
+
+    // A loop split by the cut is dropped rather than shortened
+    edit::cut(&input, &output, 70, 71).unwrap();
+    let (_, _, chunks) = read_marker_chunks(&output);
+    let smpl = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == SmplChunk::ID)
+        .map(|chunk| SmplChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    assert!(smpl.loops.is_empty());
+
+    let err = edit::trim(&input, &output, 50, 500).unwrap_err();
+    assert!(matches!(
+        err,
+        EditError::OutOfRange {
+            num_frames: 100,
+            ..
+        }
+    ));
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+pub fn concat_merges_markers() {
+    let first = temp_path("concat_a.wav");
+    let second = temp_path("concat_b.wav");
+    let output = temp_path("concat_out.wav");
+    let (cue, adtl) = test_markers(&[(1, 3, "A")]);
+    let mut writer = SampleWriter::create(
+        &first,
+        SampleFormat::I16,
+        1,
+        44100,
+        None,
+        &[cue.to_chunk(), adtl.to_chunk()],
+    )
+    .unwrap();
+    writer.write_frames(&[0.25; 10]).unwrap();
+    writer.finish(&[]).unwrap();
+    let (cue, adtl) = test_markers(&[(1, 5, "B")]);
+    let mut writer = SampleWriter::create(&second, SampleFormat::I16, 1, 44100, None, &[]).unwrap();
+    writer.write_frames(&[-0.5; 7]).unwrap();
+    writer.finish(&[cue.to_chunk(), adtl.to_chunk()]).unwrap();
+
+    assert_eq!(edit::concat(&[&first, &second], &output).unwrap(), 17);
+    assert_eq!(
+        data_bytes(&output),
+        [data_bytes(&first), data_bytes(&second)].concat()
+    );
+    let (cue, adtl, _) = read_marker_chunks(&output);
+    assert_eq!(cue.points, [CuePoint::new(1, 3), CuePoint::new(2, 15)]);
+    assert_eq!(adtl.label(2), Some("B"));
+
+    // The first file's markers are optional
+    edit::concat(&[&second, &second], &output).unwrap();
+    let (cue, _, _) = read_marker_chunks(&output);
+    assert_eq!(cue.points, [CuePoint::new(1, 5), CuePoint::new(2, 12)]);
+
+    // Renumbering the second file's ids past the first's can't wrap around
+    let (cue, adtl) = test_markers(&[(u32::MAX, 5, "B")]);
+    let mut writer = SampleWriter::create(&second, SampleFormat::I16, 1, 44100, None, &[]).unwrap();
+    writer.write_frames(&[-0.5; 7]).unwrap();
+    writer.finish(&[cue.to_chunk(), adtl.to_chunk()]).unwrap();
+    let err = edit::concat(&[&first, &second], &output).unwrap_err();
+    assert!(matches!(err, EditError::Chunk(ChunkError::InvalidField(_))));
+
+    write_test_wav(&second, test_fmt(1, 44100, 24), &[0.0; 4]);
+    let err = edit::concat(&[&first, &second], &output).unwrap_err();
+    assert!(matches!(err, EditError::FormatMismatch { .. }));
+    for path in [&first, &second, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn fade_curves_and_fade_file() {
+    let half = |curve: FadeCurve| curve.gain(0.5);
+    assert_eq!(half(FadeCurve::Linear), 0.5);
+    assert!((half(FadeCurve::EqualPower) - 0.5f64.sqrt()).abs() < 1e-12);
+    assert!((half(FadeCurve::SCurve) - 0.5).abs() < 1e-12);
+    assert!((half(FadeCurve::Logarithmic) - 0.0307).abs() < 1e-3);
+    for curve in [
+        FadeCurve::Linear,
+        FadeCurve::EqualPower,
+        FadeCurve::Logarithmic,
+        FadeCurve::SCurve,
+    ] {
+        assert_eq!(curve.gain(0.0), 0.0);
+        assert!((curve.gain(1.0) - 1.0).abs() < 1e-12);
+    }
+
+    let input = temp_path("fade_in.wav");
+    let output = temp_path("fade_out.wav");
+    let mut fmt = test_fmt(1, 1000, 32);
+    fmt.audio_format = 3;
+    write_test_wav(&input, fmt, &[0.5; 1000]);
+    let fade_in = Fade {
+        curve: FadeCurve::Linear,
+        length: FadeLength::Frames(100),
+    };
+    let fade_out = Fade {
+        curve: FadeCurve::EqualPower,
+        length: FadeLength::Seconds(0.2),
+    };
+    let frames = fade::fade_file(&input, &output, Some(fade_in), Some(fade_out)).unwrap();
+    assert_eq!(frames, 1000);
+    let samples = read_test_samples(&output);
+    assert_eq!(samples[0], 0.0);
+    assert_eq!(samples[50], 0.25);
+    assert_eq!(samples[100..800], [0.5; 700]);
+    assert!((samples[899] - 0.5 * 0.5f64.sqrt()).abs() < 1e-6);
+    assert_eq!(samples[999], 0.0);
+
+    // Integer files are only requantized where the gain isn't one
+    let samples: Vec<f64> = (0..1000).map(|i| (i as f64 * 0.1).sin() * 0.5).collect();
+    write_test_wav(&input, test_fmt(2, 1000, 24), &samples);
+    fade::fade_file(&input, &output, Some(fade_in), None).unwrap();
+    assert_eq!(data_bytes(&output)[600..], data_bytes(&input)[600..]);
+    assert_ne!(data_bytes(&output)[..600], data_bytes(&input)[..600]);
+    for path in [&input, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn crossfade_overlaps_files_and_moves_markers() {
+    let first = temp_path("crossfade_a.wav");
+    let second = temp_path("crossfade_b.wav");
+    let output = temp_path("crossfade_out.wav");
+    let (cue, adtl) = test_markers(&[(1, 20, "B")]);
+    let mut writer = SampleWriter::create(&first, SampleFormat::I16, 1, 1000, None, &[]).unwrap();
+    writer.write_frames(&[0.5; 100]).unwrap();
+    writer.finish(&[]).unwrap();
+    let mut writer = SampleWriter::create(
+        &second,
+        SampleFormat::I16,
+        1,
+        1000,
+        None,
+        &[cue.to_chunk(), adtl.to_chunk()],
+    )
+    .unwrap();
+    writer.write_frames(&[-0.25; 60]).unwrap();
+    writer.finish(&[]).unwrap();
+
+    let fade = Fade {
+        curve: FadeCurve::Linear,
+        length: FadeLength::Seconds(0.04),
+    };
+    assert_eq!(
+        edit::crossfade(&[&first, &second], &output, fade).unwrap(),
+        120
+    );
+    let (first_data, second_data) = (data_bytes(&first), data_bytes(&second));
+    let data = data_bytes(&output);
+    assert_eq!(data[..120], first_data[..120]);
+    assert_eq!(data[200..], second_data[80..]);
+    let samples = read_test_samples(&output);
+    for (frame, &sample) in samples[60..100].iter().enumerate() {
+        let progress = (frame as f64 + 0.5) / 40.0;
+        let expected = 0.5 * (1.0 - progress) - 0.25 * progress;
+        assert!((sample - expected).abs() <= 1.5 / 32768.0);
+    }
+    let (cue, adtl, _) = read_marker_chunks(&output);
+    assert_eq!(cue.points, [CuePoint::new(1, 80)]);
+    assert_eq!(adtl.label(1), Some("B"));
+
+    // The middle file is overlapped at both ends
+    let err = edit::crossfade(&[&first, &second, &first], &output, fade).unwrap_err();
+    assert!(matches!(
+        err,
+        EditError::TooShortForCrossfade { frames: 40, .. }
+    ));
+    for path in [&first, &second, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn filter_band_responses() {
+    let response = |band: FilterBand, frequency: f64| {
+        band.design(48000).unwrap().response_db(frequency, 48000)
+    };
+    let close = |value: f64, expected: f64| (value - expected).abs() < 0.05;
+
+    let low_pass = FilterBand::LowPass {
+        frequency: 1000.0,
+        q: std::f64::consts::FRAC_1_SQRT_2,
+    };
+    assert!(close(response(low_pass, 1000.0), -3.01));
+    assert!(close(response(low_pass, 10.0), 0.0));
+    assert!(response(low_pass, 10000.0) < -38.0);
+    assert!(close(response(FilterBand::RUMBLE, 80.0), -3.01));
+    assert!(response(FilterBand::RUMBLE, 20.0) < -23.0);
+    assert!(close(response(FilterBand::RUMBLE, 2000.0), 0.0));
+    let band_pass = FilterBand::BandPass {
+        frequency: 500.0,
+        q: 2.0,
+    };
+    assert!(close(response(band_pass, 500.0), 0.0));
+    assert!(response(band_pass, 5000.0) < -20.0);
+    let notch = FilterBand::Notch {
+        frequency: 50.0,
+        q: 10.0,
+    };
+    assert!(response(notch, 50.0) < -100.0);
+    assert!(close(response(notch, 1000.0), 0.0));
+    let peaking = FilterBand::Peaking {
+        frequency: 3000.0,
+        q: 1.0,
+        gain_db: -6.0,
+    };
+    assert!(close(response(peaking, 3000.0), -6.0));
+    assert!(close(response(peaking, 30.0), 0.0));
+    let low_shelf = FilterBand::LowShelf {
+        frequency: 200.0,
+        q: std::f64::consts::FRAC_1_SQRT_2,
+        gain_db: 6.0,
+    };
+    assert!(close(response(low_shelf, 10.0), 6.0));
+    assert!(close(response(low_shelf, 200.0), 3.0));
+    assert!(close(response(low_shelf, 10000.0), 0.0));
+    let high_shelf = FilterBand::HighShelf {
+        frequency: 8000.0,
+        q: std::f64::consts::FRAC_1_SQRT_2,
+        gain_db: -4.0,
+    };
+    assert!(close(response(high_shelf, 23000.0), -4.0));
+    assert!(close(response(high_shelf, 100.0), 0.0));
+
+    let dehum = FilterBand::dehum(60.0, 5, 30.0, 48000);
+    assert_eq!(dehum.len(), 5);
+    assert_eq!(dehum[4].frequency(), 300.0);
+    let equalizer = Equalizer::new(2, 48000, &dehum).unwrap();
+    for harmonic in 1..=5 {
+        assert!(equalizer.response_db(60.0 * harmonic as f64, 48000) < -100.0);
+    }
+    assert!(close(equalizer.response_db(1000.0, 48000), 0.0));
+    assert_eq!(FilterBand::dehum(50.0, 10, 30.0, 200).len(), 1);
+
+    let err = FilterBand::LowPass {
+        frequency: 30000.0,
+        q: 1.0,
+    }
+    .design(48000)
+    .unwrap_err();
+    assert!(matches!(err, FilterError::Frequency { .. }));
+    let err = Equalizer::new(
+        1,
+        48000,
+        &[FilterBand::Notch {
+            frequency: 50.0,
+            q: 0.0,
+        }],
+    );
+    assert!(matches!(err, Err(FilterError::Q(_))));
+}
+
+#[test]
+pub fn filter_file_per_channel() {
+    let input = temp_path("filter_in.wav");
+    let output = temp_path("filter_out.wav");
+    let sine = |frequency: f64, frame: usize| {
+        (2.0 * std::f64::consts::PI * frequency * frame as f64 / 48000.0).sin() * 0.5
+    };
+    // Hum on the left, a tone on the right
+    let samples: Vec<f64> = (0..48000)
+        .flat_map(|frame| [sine(50.0, frame), sine(1000.0, frame)])
+        .collect();
+    let mut writer = SampleWriter::create(&input, SampleFormat::F32, 2, 48000, None, &[]).unwrap();
+    writer.write_frames(&samples).unwrap();
+    writer.finish(&[]).unwrap();
+
+    let bands = FilterBand::dehum(50.0, 3, 10.0, 48000);
+    let report = filter::filter_file(&input, &output, &bands).unwrap();
+    assert_eq!(report.frames, 48000);
+    assert_eq!(report.clipped_samples, 0);
+    let filtered = read_test_samples(&output);
+    // Past the notches' settling time
+    let peak = |channel: usize| {
+        filtered[24000 * 2..]
+            .iter()
+            .skip(channel)
+            .step_by(2)
+            .fold(0.0f64, |peak, sample| peak.max(sample.abs()))
+    };
+    assert!(peak(0) < 0.001);
+    assert!((peak(1) - 0.5).abs() < 0.01);
+    for path in [&input, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn compressor_curve_and_stereo_link() {
+    let hard_knee = CompressorSettings {
+        threshold_db: -18.0,
+        ratio: 3.0,
+        knee_db: 0.0,
+        ..Default::default()
+    };
+    let compressor = Compressor::new(1, 48000, hard_knee);
+    assert_eq!(compressor.gain_reduction_db(-30.0), 0.0);
+    assert!((compressor.gain_reduction_db(-6.0) + 8.0).abs() < 1e-12);
+    let soft_knee = Compressor::new(1, 48000, CompressorSettings::default());
+    assert!((soft_knee.gain_reduction_db(-18.0) + 0.5).abs() < 1e-12);
+    assert_eq!(soft_knee.gain_reduction_db(-21.0), 0.0);
+
+    // A loud left channel and a quiet right one, settled after a second
+    let samples: Vec<f64> = [0.5, 0.01].repeat(48000);
+    let mut compressed = samples.clone();
+    Compressor::new(2, 48000, hard_knee).process(&mut compressed);
+    let gain = gain::Gain::from_db(compressor.gain_reduction_db(linear_to_db(0.5))).linear;
+    assert!((compressed[95998] - 0.5 * gain).abs() < 1e-6);
+    assert!((compressed[95999] - 0.01 * gain).abs() < 1e-6);
+    let mut compressed = samples.clone();
+    let unlinked = CompressorSettings {
+        link: false,
+        makeup_db: -compressor.gain_reduction_db(linear_to_db(0.5)),
+        ..hard_knee
+    };
+    Compressor::new(2, 48000, unlinked).process(&mut compressed);
+    assert!((compressed[95998] - 0.5).abs() < 1e-6);
+    assert!((compressed[95999] - 0.01 / gain).abs() < 1e-6);
+}
+
+#[test]
+pub fn limiter_holds_the_ceiling() {
+    let settings = LimiterSettings::default();
+    let ceiling = gain::Gain::from_db(settings.ceiling_db).linear;
+    let mut samples: Vec<f64> = (0..9600)
+        .map(|frame| (frame as f64 * 0.05).sin() * 0.8)
+        .collect();
+    samples[4000] = 1.8;
+    samples[4001] = -1.5;
+    let mut limited = samples.clone();
+    let mut limiter = Limiter::new(1, 48000, settings);
+    assert_eq!(limiter.latency(), 240);
+    limiter.process(&mut limited);
+    let mut tail = Vec::new();
+    limiter.flush(&mut tail);
+    let limited = [&limited[240..], &tail[..]].concat();
+    assert_eq!(limited.len(), samples.len());
+    assert!(limited.iter().all(|sample| sample.abs() <= ceiling));
+    // The gain ramps down ahead of the spike rather than clipping it
+    assert!((limited[4000] - ceiling).abs() < 1e-12);
+    assert!(limited[3999].abs() < samples[3999].abs());
+    assert_eq!(limited[..3700], samples[..3700]);
+
+    // Quiet material passes untouched and in place
+    let input = temp_path("limiter_in.wav");
+    let output = temp_path("limiter_out.wav");
+    let quiet: Vec<f64> = samples.iter().map(|sample| sample * 0.25).collect();
+    let mut writer = SampleWriter::create(&input, SampleFormat::F32, 2, 48000, None, &[]).unwrap();
+    writer.write_frames(&quiet).unwrap();
+    writer.finish(&[]).unwrap();
+    let processors = [
+        Dynamics::Compressor(CompressorSettings {
+            threshold_db: 0.0,
+            ..Default::default()
+        }),
+        Dynamics::Limiter(settings),
+    ];
+    let report = dynamics::dynamics_file(&input, &output, &processors).unwrap();
+    assert_eq!(report.frames, 4800);
+    assert_eq!(read_test_samples(&output), read_test_samples(&input));
+    for path in [&input, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn gate_with_and_without_link() {
+    // Speech level on the left for a tenth of a second, then noise at -60 dB. The right channel
+    // is noise throughout.
+    let samples: Vec<f64> = (0..48000)
+        .flat_map(|frame| [if frame < 4800 { 0.5 } else { 0.001 }, 0.001])
+        .collect();
+    let settings = GateSettings {
+        range_db: -40.0,
+        ..Default::default()
+    };
+    let mut gated = samples.clone();
+    Gate::new(2, 48000, settings).process(&mut gated);
+    assert_eq!(gated[..2], [0.5, 0.001]);
+    assert!((gated[4798 * 2] - 0.5).abs() < 1e-9);
+    // -40 dB of range once the release has settled
+    assert!((gated[47999 * 2] / 0.00001 - 1.0).abs() < 0.05);
+    assert!((gated[47999 * 2 + 1] / 0.00001 - 1.0).abs() < 0.05);
+
+    let mut gated = samples.clone();
+    let unlinked = GateSettings {
+        link: false,
+        ..settings
+    };
+    Gate::new(2, 48000, unlinked).process(&mut gated);
+    assert!((gated[4798 * 2] - 0.5).abs() < 1e-9);
+    // The right channel has been closing since the start
+    assert!(gated[4798 * 2 + 1] < 0.0007);
+}
+
+#[test]
+pub fn dynamics_settings_from_str() {
+    let settings: CompressorSettings = "threshold=-20, ratio=4,link=off".parse().unwrap();
+    assert_eq!(
+        settings,
+        CompressorSettings {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            link: false,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        "".parse::<LimiterSettings>().unwrap(),
+        LimiterSettings::default()
+    );
+    let settings: GateSettings = "range=-inf,hold=0.2".parse().unwrap();
+    assert_eq!(settings.range_db, f64::NEG_INFINITY);
+    assert_eq!(settings.hold, 0.2);
+    assert!(matches!(
+        "knee=6".parse::<LimiterSettings>(),
+        Err(DynamicsError::UnknownSetting(key)) if key == "knee"
+    ));
+    assert!(matches!(
+        "ratio=lots".parse::<CompressorSettings>(),
+        Err(DynamicsError::InvalidValue { .. })
+    ));
+}
+
+#[test]
+pub fn split_at_markers_and_regions() {
+    let input = temp_path("split_markers.wav");
+    let output_dir =
+        std::env::temp_dir().join(format!("rwav_{}_split_markers", std::process::id()));
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let stem = input.file_stem().unwrap().to_string_lossy().to_string();
+    let (cue, mut adtl) = test_markers(&[(1, 10, "Intro"), (2, 40, "Verse/1")]);
+    let bext = BextChunk {
+        time_reference: 500,
+        ..Default::default()
+    };
+    let frames: Vec<f64> = (0..100).map(|i| i as f64 / 128.0).collect();
+    let write_input = |adtl: &AdtlChunk| {
+        let mut writer = SampleWriter::create(
+            &input,
+            SampleFormat::I16,
+            1,
+            48000,
+            None,
+            &[bext.to_chunk(), cue.to_chunk(), adtl.to_chunk()],
+        )
+        .unwrap();
+        writer.write_frames(&frames).unwrap();
+        writer.finish(&[]).unwrap();
+    };
+
+    write_input(&adtl);
+    let paths = split::split(&input, &output_dir, SplitMode::Markers).unwrap();
+    let names: Vec<String> = paths
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            format!("{stem}_01.wav"),
+            format!("{stem}_Intro.wav"),
+            format!("{stem}_Verse_1.wav")
+        ]
+    );
+    let original = data_bytes(&input);
+    assert_eq!(data_bytes(&paths[1]), original[10 * 2..40 * 2]);
+    let (cue_out, adtl_out, chunks) = read_marker_chunks(&paths[2]);
+    assert_eq!(cue_out.points, [CuePoint::new(2, 0)]);
+    assert_eq!(adtl_out.label(2), Some("Verse/1"));
+    let bext_out = chunks
+        .iter()
+        .find(|chunk| chunk.chunk_header.chunk_id == BextChunk::ID)
+        .map(|chunk| BextChunk::from_chunk(chunk).unwrap())
+        .unwrap();
+    assert_eq!(bext_out.time_reference, 540);
+
+    // Regions take over from the plain markers
+    adtl.regions.push(CueRegion::new(2, 20));
+    write_input(&adtl);
+    let paths = split::split(&input, &output_dir, SplitMode::Markers).unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(data_bytes(&paths[0]), original[40 * 2..60 * 2]);
+
+    write_test_wav(&input, test_fmt(1, 48000, 16), &frames);
+    let err = split::split(&input, &output_dir, SplitMode::Markers).unwrap_err();
+    assert!(matches!(err, EditError::NoMarkers));
+    std::fs::remove_dir_all(&output_dir).unwrap();
+    std::fs::remove_file(&input).unwrap();
+}
+
+#[test]
+pub fn split_at_silence() {
+    let input = temp_path("split_silence.wav");
+    let output_dir =
+        std::env::temp_dir().join(format!("rwav_{}_split_silence", std::process::id()));
+    std::fs::create_dir_all(&output_dir).unwrap();
+    // 0.5 s of sound, 1.5 s of silence, 0.5 s of sound and 0.3 s of silence
+    let mut samples = vec![0.25; 24000];
+    samples.extend(vec![0.0; 72000]);
+    samples.extend(vec![-0.25; 24000]);
+    samples.extend(vec![0.0; 14400]);
+    write_test_wav(&input, test_fmt(1, 48000, 24), &samples);
+
+    let settings = SilenceSplit {
+        threshold_db: -50.0,
+        min_silence: 1.0,
+        padding: 0.25,
+    };
+    let paths = split::split(&input, &output_dir, SplitMode::Silence(settings)).unwrap();
+    assert_eq!(paths.len(), 2);
+    let lengths: Vec<u64> = paths
+        .iter()
+        .map(|path| {
+            SampleReader::new(&mut WavFile::new(path))
+                .unwrap()
+                .num_frames
+        })
+        .collect();
+    assert_eq!(lengths, [24000 + 12000, 12000 + 24000 + 12000]);
+    let original = data_bytes(&input);
+    assert_eq!(data_bytes(&paths[1]), original[84000 * 3..132000 * 3]);
+
+    // The 0.3 s tail is too short to split at but still trimmed, with less padding than asked
+    let settings = SilenceSplit {
+        padding: 0.5,
+        ..settings
+    };
+    let paths = split::split(&input, &output_dir, SplitMode::Silence(settings)).unwrap();
+    let last = SampleReader::new(&mut WavFile::new(&paths[1])).unwrap();
+    assert_eq!(last.num_frames, 24000 + 24000 + 14400);
+    std::fs::remove_dir_all(&output_dir).unwrap();
+    std::fs::remove_file(&input).unwrap();
+}
+
+#[test]
+pub fn pipeline_streams_file_to_sinks() {
+    let input = temp_path("pipeline_in.wav");
+    let output = temp_path("pipeline_out.wav");
+    let samples: Vec<f64> = (0..20000)
+        .map(|index| ((index as f64 * 0.01).sin() * 16000.0).round() / 32768.0)
+        .collect();
+    write_test_wav(&input, test_fmt(2, 48000, 16), &samples);
+
+    // Unity gain keeps the stream exact, so it's written back bit for bit without dither
+    let (reader, mut sink) = pipeline::open_file_pipeline(&input, &output, None).unwrap();
+    let mut pipeline = Pipeline::new(reader);
+    pipeline.push(Gain::from_db(0.0)).unwrap();
+    assert!(pipeline.format().exact);
+    assert_eq!(pipeline.format().num_frames, Some(10000));
+    pipeline.sink(&mut sink).unwrap();
+    assert_eq!(pipeline.run().unwrap(), 10000);
+    assert_eq!(sink.report().frames, 10000);
+    assert_eq!(read_test_samples(&output), samples);
+
+    // One stream feeds every sink, a resampler's tail included
+    let mut wav_file = WavFile::open(&input).unwrap();
+    let reader = SampleReader::new(&mut wav_file).unwrap();
+    let mut loudness_meter = LoudnessMeter::new(2, 44100, None);
+    let mut stats_meter = StatsMeter::new(2, 44100, SampleFormat::I16, Default::default());
+    let mut pipeline = Pipeline::new(reader);
+    pipeline
+        .push(Resampler::new(2, 48000, 44100, ResampleQuality::Fast).unwrap())
+        .unwrap();
+    assert!(!pipeline.format().exact);
+    assert_eq!(pipeline.format().num_frames, Some(9188));
+    pipeline
+        .sink(&mut loudness_meter)
+        .unwrap()
+        .sink(&mut stats_meter)
+        .unwrap();
+    assert_eq!(pipeline.run().unwrap(), 9188);
+    // Too short for a gating block, but the true peak is measured
+    assert!((loudness_meter.finish().true_peak - linear_to_db(16000.0 / 32768.0)).abs() < 0.1);
+    assert_eq!(stats_meter.finish().frames, 9188);
+    for path in [&input, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn pipeline_negotiates_formats() {
+    let input = temp_path("pipeline_negotiate.wav");
+    write_test_wav(&input, test_fmt(2, 48000, 16), &[0.0; 200]);
+    let reader = || SampleReader::new(&mut WavFile::open(&input).unwrap()).unwrap();
+
+    // A filter designed for another rate is refused before any audio moves
+    let equalizer = Equalizer::new(2, 44100, &[FilterBand::RUMBLE]).unwrap();
+    let err = Pipeline::new(reader()).push(equalizer).err().unwrap();
+    assert!(matches!(err, PipelineError::Incompatible { ref stage, .. } if stage == "equalizer"));
+
+    // A mix changes the channel count every later stage sees
+    let mut pipeline = Pipeline::new(reader());
+    pipeline
+        .push(MixMatrix::preset(MixPreset::Mono, 2, None).unwrap())
+        .unwrap();
+    assert_eq!(pipeline.format().num_channels, 1);
+    let meter = LoudnessMeter::new(2, 48000, None);
+    assert!(matches!(
+        pipeline.sink(meter).err().unwrap(),
+        PipelineError::Incompatible { .. }
+    ));
+    assert!(matches!(pipeline.run(), Err(PipelineError::NoSink)));
+
+    // A fade needs the length of the stream it's given
+    let fade = Fade {
+        curve: FadeCurve::Linear,
+        length: FadeLength::Frames(10),
+    };
+    let fader = fade::Fader::new(2, 48000, 50, Some(fade), None);
+    assert!(Pipeline::new(reader()).push(fader).is_err());
+    std::fs::remove_file(&input).unwrap();
+}
+
+#[test]
+pub fn audio_session_queues_pipeline_output() {
+    let input = temp_path("pipeline_playback.wav");
+    let samples: Vec<f64> = (0..2000)
+        .map(|index| (index % 100) as f64 / 128.0)
+        .collect();
+    write_test_wav(&input, test_fmt(1, 44100, 8), &samples);
+    let description = |sample_rate| SourceDescription {
+        bits_per_sample: 16,
+        num_channels: 1,
+        sample_rate,
+    };
+
+    // Widening to the session's bit depth adds no dither
+    let mut session = AudioSession::new(0, description(44100), None);
+    let mut wav_file = WavFile::open(&input).unwrap();
+    let mut pipeline = Pipeline::new(SampleReader::new(&mut wav_file).unwrap());
+    pipeline.sink(&mut session).unwrap();
+    pipeline.run().unwrap();
+    let mut queued = Vec::new();
+    SampleFormat::I16.decode(session.queued(), &mut queued);
+    assert_eq!(queued, samples);
+
+    // Playing at another rate needs a resampler in front
+    let mut session = AudioSession::new(0, description(48000), None);
+    let mut wav_file = WavFile::open(&input).unwrap();
+    let mut pipeline = Pipeline::new(SampleReader::new(&mut wav_file).unwrap());
+    let err = pipeline
+        .sink(AudioSession::new(0, description(48000), None))
+        .err()
+        .unwrap();
+    assert!(matches!(err, PipelineError::Incompatible { ref stage, .. } if stage == "playback"));
+    pipeline
+        .push(Resampler::new(1, 44100, 48000, ResampleQuality::Fast).unwrap())
+        .unwrap()
+        .sink(&mut session)
+        .unwrap();
+    assert_eq!(pipeline.run().unwrap(), 2177);
+    assert_eq!(session.queued().len(), 2177 * 2);
+
+    // A stream too long to hand the device in one buffer fails before it's queued
+    let mut session = AudioSession::new(0, description(44100), None);
+    let format = StreamFormat {
+        num_channels: 1,
+        sample_rate: 44100,
+        channel_mask: None,
+        sample_format: SampleFormat::I16,
+        exact: true,
+        num_frames: Some(1 << 31),
+    };
+    let err = session.configure(format).unwrap_err();
+    assert!(matches!(err, PipelineError::TooLong { ref stage, .. } if stage == "playback"));
+    std::fs::remove_file(&input).unwrap();
+}
+
+#[test]
+pub fn effect_chain_parses_sox_style_tokens() {
+    let chain: EffectChain = "gain -3 highpass 80 norm -1 fade 0.5 0 1".parse().unwrap();
+    let effects: Vec<&Effect> = chain.effects().collect();
+    assert_eq!(
+        effects,
+        [
+            &Effect::Gain { db: -3.0 },
+            &Effect::Filter(FilterBand::HighPass {
+                frequency: 80.0,
+                q: std::f64::consts::FRAC_1_SQRT_2
+            }),
+            &Effect::Norm { dbfs: -1.0 },
+            &Effect::Fade {
+                curve: FadeCurve::Logarithmic,
+                fade_in: FadeLength::Seconds(0.5),
+                stop: None,
+                fade_out: Some(FadeLength::Seconds(1.0)),
+            },
+        ]
+    );
+
+    let chain: EffectChain = "fade q 100s 2 rate -h 44100 norm compress ratio=4,link=off"
+        .parse()
+        .unwrap();
+    let effects: Vec<&Effect> = chain.effects().collect();
+    assert_eq!(
+        effects[0],
+        &Effect::Fade {
+            curve: FadeCurve::EqualPower,
+            fade_in: FadeLength::Frames(100),
+            stop: Some(FadeLength::Seconds(2.0)),
+            fade_out: Some(FadeLength::Frames(100)),
+        }
+    );
+    assert_eq!(
+        effects[1],
+        &Effect::Rate {
+            sample_rate: 44100,
+            quality: ResampleQuality::Best
+        }
+    );
+    assert_eq!(effects[2], &Effect::Norm { dbfs: 0.0 });
+    let Effect::Compress(settings) = effects[3] else {
+        panic!("expected a compressor, got {:?}", effects[3]);
+    };
+    assert_eq!((settings.ratio, settings.link), (4.0, false));
+
+    // Errors point at the token they're about
+    let tokens = |chain: &str| -> Vec<String> { chain.split(' ').map(str::to_string).collect() };
+    let tokens_in = tokens("gain -3 highpas 80");
+    let err = EffectChain::parse(&tokens_in).unwrap_err();
+    assert!(matches!(err, ChainError::UnknownEffect { position: 2, .. }));
+    assert_eq!(
+        err.point_at(&tokens_in).unwrap(),
+        "gain -3 highpas 80\n        ^^^^^^^"
+    );
+    let err = EffectChain::parse(&tokens("highpass 80 norm 3")).unwrap_err();
+    assert!(matches!(
+        err,
+        ChainError::InvalidParameter {
+            position: 3,
+            effect: "norm",
+            ..
+        }
+    ));
+    let err = EffectChain::parse(&tokens("equalizer 1000 2 gain -1")).unwrap_err();
+    assert!(matches!(
+        err,
+        ChainError::MissingParameter {
+            position: 3,
+            effect: "equalizer",
+            ..
+        }
+    ));
+    let tokens_in = tokens("gain -1 channels");
+    let err = EffectChain::parse(&tokens_in).unwrap_err();
+    assert!(matches!(
+        err,
+        ChainError::MissingParameter { position: 3, .. }
+    ));
+    assert_eq!(
+        err.point_at(&tokens_in).unwrap(),
+        "gain -1 channels\n                 ^"
+    );
+    let err = EffectChain::parse(&tokens("limit ceiling=loud")).unwrap_err();
+    assert!(matches!(
+        err,
+        ChainError::InvalidSettings {
+            position: 1,
+            effect: "limit",
+            ..
+        }
+    ));
+}
+
+#[test]
+pub fn effect_chain_resolves_and_processes_files() {
+    let input = temp_path("chain_in.wav");
+    let output = temp_path("chain_out.wav");
+    let samples: Vec<f64> = (0..48000)
+        .flat_map(|frame| {
+            let sample = (frame as f64 * 0.03).sin() * 0.25;
+            [sample, -sample]
+        })
+        .collect();
+    write_test_wav(&input, test_fmt(2, 48000, 16), &samples);
+    let format = SampleReader::new(&mut WavFile::open(&input).unwrap())
+        .unwrap()
+        .format();
+
+    // Every effect is fitted to the format the one before it produces
+    let chain: EffectChain = "rate 16000 channels 1 fade 0.1 0.5".parse().unwrap();
+    let formats = chain.resolve(format).unwrap();
+    assert_eq!(formats[0].sample_rate, 16000);
+    assert_eq!(formats[1].num_channels, 1);
+    assert_eq!(formats[2].num_frames, Some(8000));
+    let chain: EffectChain = "rate 16000 lowpass 9000".parse().unwrap();
+    assert!(matches!(
+        chain.resolve(format),
+        Err(ChainError::Unsuitable {
+            position: 2,
+            effect: "lowpass",
+            ..
+        })
+    ));
+    let chain: EffectChain = "mix 1,0,0".parse().unwrap();
+    assert!(matches!(
+        chain.resolve(format),
+        Err(ChainError::Unsuitable { position: 0, .. })
+    ));
+
+    // norm measures the audio after the gain before it
+    let chain: EffectChain = "gain -12 norm -6 fade 0 0.5 0".parse().unwrap();
+    let report = effects::process_file(&input, &output, &chain, Some(SampleFormat::F32)).unwrap();
+    assert_eq!(report.frames, 24000);
+    assert!((linear_to_db(report.peak) + 6.0).abs() < 1e-6);
+    let reader = SampleReader::new(&mut WavFile::open(&output).unwrap()).unwrap();
+    assert_eq!(reader.format, SampleFormat::F32);
+
+    // Markers follow a fade's stop, chna goes once the channels change
+    let (cue, adtl) = test_markers(&[(1, 1000, "kept"), (2, 30000, "cut")]);
+    let mut writer = WavWriter::from_wav_file(WavFile::new(&input)).unwrap();
+    writer.set_chunk(cue.to_chunk());
+    writer.set_chunk(adtl.to_chunk());
+    writer.set_chunk(test_chna().to_chunk());
+    writer.write(&input).unwrap();
+    let chain: EffectChain = "fade 0 0.5 0 channels 1".parse().unwrap();
+    effects::process_file(&input, &output, &chain, None).unwrap();
+    let (cue, adtl, chunks) = read_marker_chunks(&output);
+    assert_eq!(cue.points.len(), 1);
+    assert_eq!(cue.points[0].sample_offset, 1000);
+    assert_eq!(adtl.label(2), None);
+    assert!(!chunks
+        .iter()
+        .any(|chunk| chunk.chunk_header.chunk_id == ChnaChunk::ID));
+    let chain: EffectChain = "gain -1".parse().unwrap();
+    effects::process_file(&input, &output, &chain, None).unwrap();
+    let (cue, _, chunks) = read_marker_chunks(&output);
+    assert_eq!(cue.points.len(), 2);
+    assert!(chunks
+        .iter()
+        .any(|chunk| chunk.chunk_header.chunk_id == ChnaChunk::ID));
+
+    // An empty chain copies the audio
+    let chain = EffectChain::default();
+    effects::process_file(&input, &output, &chain, None).unwrap();
+    assert_eq!(read_test_samples(&output), read_test_samples(&input));
+
+    write_test_wav(&input, test_fmt(2, 48000, 16), &[0.0; 200]);
+    let chain: EffectChain = "gain 6 norm".parse().unwrap();
+    let err = effects::process_file(&input, &output, &chain, None).unwrap_err();
+    assert!(matches!(
+        err,
+        ChainError::Unsuitable {
+            position: 2,
+            effect: "norm",
+            ..
+        }
+    ));
+    for path in [&input, &output] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn generator_signals() {
+    let description = SourceDescription {
+        bits_per_sample: 24,
+        num_channels: 2,
+        sample_rate: 48000,
+    };
+    let generate = |signal: &str, duration: f64, level_db: f64| {
+        let mut generator =
+            Generator::new(signal.parse().unwrap(), &description, duration, level_db).unwrap();
+        let mut samples = Vec::new();
+        let mut block = Vec::new();
+        while generator.read(4096, &mut block).unwrap() > 0 {
+            samples.extend_from_slice(&block);
+        }
+        samples
+    };
+    let rms = |samples: &[f64]| {
+        linear_to_db(
+            (samples.iter().map(|sample| sample * sample).sum::<f64>() / samples.len() as f64)
+                .sqrt(),
+        )
+    };
+    let peak = |samples: &[f64]| {
+        samples
+            .iter()
+            .fold(0.0f64, |peak, sample| peak.max(sample.abs()))
+    };
+
+    let sine = generate("sine:1000", 1.0, -6.0);
+    assert_eq!(sine.len(), 96000);
+    assert!((linear_to_db(peak(&sine)) + 6.0).abs() < 1e-6);
+    assert!((rms(&sine) + 6.0 + 3.0103).abs() < 1e-3);
+    assert!(sine.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+    let multitone = generate("multitone:100,1000,10000", 1.0, -6.0);
+    assert!(peak(&multitone) <= db_to_linear(-6.0));
+
+    // Noise is at its RMS level, and independent on each channel
+    for signal in ["white", "pink"] {
+        let noise = generate(signal, 10.0, -20.0);
+        assert!(
+            (rms(&noise) + 20.0).abs() < 0.2,
+            "{signal}: {}",
+            rms(&noise)
+        );
+        assert!(noise.chunks_exact(2).any(|frame| frame[0] != frame[1]));
+    }
+    // Pink noise has far more energy in its lowest octaves than white noise
+    let low_band = |signal: &str| {
+        let mut noise: Vec<f64> = generate(signal, 2.0, -20.0)
+            .into_iter()
+            .step_by(2)
+            .collect();
+        let mut lowpass = Equalizer::new(
+            1,
+            48000,
+            &[FilterBand::LowPass {
+                frequency: 200.0,
+                q: std::f64::consts::FRAC_1_SQRT_2,
+            }],
+        )
+        .unwrap();
+        lowpass.process(&mut noise);
+        rms(&noise)
+    };
+    assert!(low_band("pink") - low_band("white") > 10.0);
+
+    // A log sweep starts slow and ends fast
+    let sweep: Vec<f64> = generate("sweep:100-10000", 1.0, 0.0)
+        .into_iter()
+        .step_by(2)
+        .collect();
+    let crossings = |samples: &[f64]| {
+        samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count()
+    };
+    let (start, end) = (crossings(&sweep[..4800]), crossings(&sweep[43200..]));
+    assert!(end > start * 50, "{start} {end}");
+
+    // Impulses are on the grid, so they're written without dither
+    let impulse = generate("impulse", 0.01, 0.0);
+    assert_eq!(impulse[0], 8388607.0 / 8388608.0);
+    assert!(impulse[2..].iter().all(|&sample| sample == 0.0));
+    assert!(generate("silence", 0.5, 0.0)
+        .iter()
+        .all(|&sample| sample == 0.0));
+
+    assert!(matches!(
+        "sine:30000"
+            .parse::<Signal>()
+            .map(|signal| Generator::new(signal, &description, 1.0, 0.0)),
+        Ok(Err(GeneratorError::Frequency { .. }))
+    ));
+    for (num_channels, sample_rate) in [(0, 48000), (1, 0)] {
+        let description = SourceDescription {
+            bits_per_sample: 24,
+            num_channels,
+            sample_rate,
+        };
+        assert!(matches!(
+            Generator::new(Signal::WhiteNoise, &description, 1.0, 0.0),
+            Err(GeneratorError::EmptyFormat { .. })
+        ));
+    }
+    let description_12_bit = SourceDescription {
+        bits_per_sample: 12,
+        ..description
+    };
+    assert!(matches!(
+        Generator::new(Signal::WhiteNoise, &description_12_bit, 1.0, 0.0),
+        Err(GeneratorError::Sample(
+            SampleError::UnsupportedFormat { .. }
+        ))
+    ));
+    assert!(matches!(
+        "saw:3".parse::<Signal>(),
+        Err(GeneratorError::UnknownSignal(_))
+    ));
+    assert!(matches!(
+        "sweep:100".parse::<Signal>(),
+        Err(GeneratorError::InvalidParameters { .. })
+    ));
+    assert!("white:1".parse::<Signal>().is_err());
+}
+
+#[test]
+pub fn generator_writes_files_and_feeds_playback() {
+    let path = temp_path("generated.wav");
+    let description = SourceDescription {
+        bits_per_sample: 16,
+        num_channels: 1,
+        sample_rate: 44100,
+    };
+    let report = generator::generate_file(&path, Signal::Impulse, &description, 0.5, -6.0).unwrap();
+    assert_eq!(report.frames, 22050);
+    let samples = read_test_samples(&path);
+    assert_eq!(samples[0], (db_to_linear(-6.0) * 32768.0).round() / 32768.0);
+    assert!(samples[1..].iter().all(|&sample| sample == 0.0));
+    let reader = SampleReader::new(&mut WavFile::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        (reader.format, reader.sample_rate, reader.num_channels),
+        (SampleFormat::I16, 44100, 1)
+    );
+
+    let generator =
+        Generator::new(Signal::Sine { frequency: 440.0 }, &description, 0.1, -3.0).unwrap();
+    let mut session = AudioSession::new(0, description, None);
+    let mut pipeline = Pipeline::new(generator);
+    pipeline.sink(&mut session).unwrap();
+    assert_eq!(pipeline.run().unwrap(), 4410);
+    assert_eq!(session.queued().len(), 4410 * 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn diff_compares_headers_chunks_and_audio() {
+    let a = temp_path("diff_a.wav");
+    let b = temp_path("diff_b.wav");
+    let residual = temp_path("diff_residual.wav");
+    let samples: Vec<f64> = (0..2000)
+        .map(|index| ((index as f64 * 0.05).sin() * 12000.0).round() / 32768.0)
+        .collect();
+    let write = |path: &Path, fmt: FmtSubChunk, samples: &[f64], chunks: &[Chunk]| {
+        let mut data = Vec::new();
+        SampleFormat::from_fmt(&fmt)
+            .unwrap()
+            .encode(samples, &mut data);
+        let mut writer = WavWriter::new(fmt);
+        for chunk in chunks {
+            writer.set_chunk(chunk.clone());
+        }
+        writer.set_chunk(Chunk::new(*b"data", data));
+        writer.write(path).unwrap();
+    };
+    let notes = [Chunk::new(*b"note", b"take 1".to_vec())];
+    write(&a, test_fmt(2, 48000, 16), &samples, &notes);
+
+    // The same audio at a greater bit depth nulls, but the header doesn't match
+    write(&b, test_fmt(2, 48000, 24), &samples, &notes);
+    let report = diff::diff_files(&a, &b, DiffSettings::default(), None).unwrap();
+    let fields: Vec<&str> = report
+        .header_differences
+        .iter()
+        .map(|difference| difference.field)
+        .collect();
+    assert_eq!(fields, ["sample format", "block align", "byte rate"]);
+    assert!(report.chunk_differences.is_empty());
+    let audio = report.audio.unwrap();
+    assert_eq!(audio.frames, 1000);
+    assert_eq!(audio.first_difference, None);
+    assert_eq!(audio.max_difference, f64::NEG_INFINITY);
+    assert!(!report.is_match());
+
+    // One changed sample, a changed chunk and a new one
+    let mut changed = samples.clone();
+    changed[1501] += 64.0 / 32768.0;
+    let other_note = Chunk::new(*b"note", b"take 2".to_vec());
+    let extra = Chunk::new(*b"xtra", vec![0; 4]);
+    write(&b, test_fmt(2, 48000, 16), &changed, &[other_note, extra]);
+    let report = diff::diff_files(&a, &b, DiffSettings::default(), Some(&residual)).unwrap();
+    assert!(report.header_differences.is_empty());
+    assert_eq!(
+        report.chunk_differences,
+        [
+            ChunkDifference::Changed(*b"note"),
+            ChunkDifference::OnlyInB(*b"xtra")
+        ]
+    );
+    let audio = report.audio.unwrap();
+    assert_eq!(audio.first_difference, Some(750));
+    assert_eq!(audio.max_difference_frame, 750);
+    assert_eq!(audio.differing_samples, 1);
+    assert!((audio.max_difference - linear_to_db(64.0 / 32768.0)).abs() < 1e-9);
+    assert!((audio.residual_rms - linear_to_db(64.0 / 32768.0 / 2000f64.sqrt())).abs() < 1e-9);
+    let difference = read_test_samples(&residual);
+    assert_eq!(difference.len(), 2000);
+    assert_eq!(difference[1501], 64.0 / 32768.0);
+    assert_eq!(
+        difference.iter().filter(|&&sample| sample != 0.0).count(),
+        1
+    );
+
+    // Within the tolerance the audio matches
+    write(&b, test_fmt(2, 48000, 16), &changed, &notes);
+    let settings = DiffSettings {
+        tolerance_db: Some(-50.0),
+    };
+    let report = diff::diff_files(&a, &b, settings, None).unwrap();
+    assert!(report.is_match());
+
+    // A longer file differs where its extra audio isn't silent
+    let mut longer = samples.clone();
+    longer.extend([0.0, 0.0, 0.25, 0.0]);
+    write(&b, test_fmt(2, 48000, 16), &longer, &notes);
+    let report = diff::diff_files(&a, &b, DiffSettings::default(), None).unwrap();
+    assert_eq!(report.header_differences[0].field, "frames");
+    let audio = report.audio.unwrap();
+    assert_eq!((audio.frames, audio.first_difference), (1002, Some(1001)));
+
+    write(&b, test_fmt(1, 48000, 16), &samples, &[]);
+    let report = diff::diff_files(&a, &b, DiffSettings::default(), None).unwrap();
+    assert_eq!(report.audio, None);
+    assert_eq!(
+        report.chunk_differences,
+        [ChunkDifference::OnlyInA(*b"note")]
+    );
+    for path in [&a, &b, &residual] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+pub fn content_hash_ignores_metadata_and_md5_chunk_verifies() {
+    let plain = temp_path("hash_plain.wav");
+    let extensible = temp_path("hash_extensible.wav");
+    let with_md5 = temp_path("hash_md5.wav");
+    let processed = temp_path("hash_processed.wav");
+    let samples: Vec<f64> = (0..960)
+        .map(|index| ((index as f64 * 0.03).sin() * 1000.0).round() / 8388608.0)
+        .collect();
+
+    // Plain PCM with metadata, and WAVE_FORMAT_EXTENSIBLE with the default stereo mask
+    let mut data = Vec::new();
+    SampleFormat::I24.encode(&samples, &mut data);
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 24));
+    writer.set_chunk(Chunk::new(*b"iXML", b"<BWFXML/>".to_vec()));
+    writer.set_chunk(Chunk::new(*b"data", data.clone()));
+    writer.set_chunk(Chunk::new(*b"LIST", b"INFOINAM\x04\0\0\0take".to_vec()));
+    writer.write(&plain).unwrap();
+    write_extensible_wav(&extensible, 2, ChannelMask::STEREO.0, &samples);
+    let content_hash = hash::content_hash(&plain).unwrap();
+    assert_eq!(hash::content_hash(&extensible).unwrap(), content_hash);
+
+    // The same samples on the rear speakers
+    write_extensible_wav(&extensible, 2, 0x30, &samples);
+    assert_ne!(hash::content_hash(&extensible).unwrap(), content_hash);
+    let mut changed = samples.clone();
+    changed[501] += 1.0 / 8388608.0;
+    write_extensible_wav(&extensible, 2, ChannelMask::STEREO.0, &changed);
+    assert_ne!(hash::content_hash(&extensible).unwrap(), content_hash);
+
+    assert_eq!(hash::verify_md5(&plain).unwrap(), Md5Check::Missing);
+    let md5 = hash::write_md5(&plain, &with_md5).unwrap();
+    assert_eq!(md5.digest[..], Md5::digest(&data)[..]);
+    assert_eq!(hash::verify_md5(&with_md5).unwrap(), Md5Check::Match(md5));
+    assert_eq!(hash::content_hash(&with_md5).unwrap(), content_hash);
+
+    // Processing recomputes the MD5 chunk for the new audio
+    gain::apply_gain(&with_md5, &processed, GainMode::Fixed { db: -6.0 }).unwrap();
+    let Md5Check::Match(processed_md5) = hash::verify_md5(&processed).unwrap() else {
+        panic!("processed file's MD5 chunk doesn't match");
+    };
+    assert_ne!(processed_md5, md5);
+
+    // Even from a stale one after the audio, and files without one don't gain one
+    let mut writer = WavWriter::new(test_fmt(2, 48000, 24));
+    writer.set_chunk(Chunk::new(*b"data", data.clone()));
+    writer.set_chunk(Md5Chunk::default().to_chunk());
+    writer.write(&extensible).unwrap();
+    gain::apply_gain(&extensible, &processed, GainMode::Fixed { db: -6.0 }).unwrap();
+    assert_eq!(
+        hash::verify_md5(&processed).unwrap(),
+        Md5Check::Match(processed_md5)
+    );
+    gain::apply_gain(&plain, &processed, GainMode::Fixed { db: -6.0 }).unwrap();
+    assert_eq!(hash::verify_md5(&processed).unwrap(), Md5Check::Missing);
+
+    let (data_offset, _) = WavFile::open(&with_md5)
+        .unwrap()
+        .find_chunk(b"data")
+        .unwrap();
+    patch_file(&with_md5, data_offset + 100, &[0x55]);
+    let Md5Check::Mismatch { stored, computed } = hash::verify_md5(&with_md5).unwrap() else {
+        panic!("corrupted audio passed the MD5 check");
+    };
+    assert_eq!(stored, md5);
+    assert_ne!(computed, md5);
+
+    // Writing in place replaces the stale MD5 chunk
+    assert_eq!(hash::write_md5(&with_md5, &with_md5).unwrap(), computed);
+    assert_eq!(
+        hash::verify_md5(&with_md5).unwrap(),
+        Md5Check::Match(computed)
+    );
+    let md5_chunks = WavFile::new(&with_md5)
+        .filter(|chunk| chunk.chunk_header.chunk_id == *b"MD5 ")
+        .count();
+    assert_eq!(md5_chunks, 1);
+    for path in [&plain, &extensible, &with_md5, &processed] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
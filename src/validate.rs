@@ -0,0 +1,308 @@
+use std::fmt;
+
+use crate::{
+    samples::SampleFormat,
+    wav::{ChunkHeader, WavError, WavFile, WavHeader},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Unusual but harmless, e.g. trailing bytes after the RIFF chunk
+    Info,
+    /// Readers disagree on how to handle it, or it suggests a buggy writer
+    Warning,
+    /// The file can't be read correctly as is
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FindingKind {
+    RiffId,
+    WaveId,
+    FmtId,
+    FmtSize,
+    NoChannels,
+    NoSampleRate,
+    UnsupportedFormat,
+    BlockAlign,
+    ByteRate,
+    RiffSizeMismatch,
+    ChunkPastEof,
+    MissingDataChunk,
+    DuplicateDataChunk,
+    DataNotFrameAligned,
+    /// The fmt extension or the chunk list couldn't be read, so the checks stop short
+    Unreadable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub kind: FindingKind,
+    /// File offset of the offending field or chunk header
+    pub offset: u64,
+    pub description: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at 0x{:08x}: {}",
+            self.severity, self.offset, self.description
+        )
+    }
+}
+
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, severity: Severity, kind: FindingKind, offset: u64, description: String) {
+        self.findings.push(Finding {
+            severity,
+            kind,
+            offset,
+            description,
+        });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn contains(&self, kind: FindingKind) -> bool {
+        self.findings.iter().any(|finding| finding.kind == kind)
+    }
+}
+
+// Offsets of the header fields checked below
+const RIFF_SIZE_OFFSET: u64 = 4;
+const WAVE_ID_OFFSET: u64 = 8;
+const FMT_ID_OFFSET: u64 = 12;
+const FMT_SIZE_OFFSET: u64 = 16;
+const AUDIO_FORMAT_OFFSET: u64 = 20;
+const NUM_CHANNELS_OFFSET: u64 = 22;
+const SAMPLE_RATE_OFFSET: u64 = 24;
+const BYTE_RATE_OFFSET: u64 = 28;
+const BLOCK_ALIGN_OFFSET: u64 = 32;
+
+impl WavFile {
+    /// Checks the header fields and chunk layout against each other and the file length. Only
+    /// failing to read the file length is an error, anything unreadable in the file is a finding.
+    pub fn validate(&mut self) -> Result<ValidationReport, WavError> {
+        let mut report = ValidationReport {
+            findings: Vec::new(),
+        };
+        let header = self.header;
+        let fmt = header.fmt;
+        let file_len = self.handle.metadata()?.len();
+
+        if &header.chunk_id != b"RIFF" {
+            report.push(
+                Severity::Error,
+                FindingKind::RiffId,
+                0,
+                format!("File starts with {:?}, not RIFF", ascii(&header.chunk_id)),
+            );
+        }
+        if &header.format != b"WAVE" {
+            report.push(
+                Severity::Error,
+                FindingKind::WaveId,
+                WAVE_ID_OFFSET,
+                format!("RIFF form type is {:?}, not WAVE", ascii(&header.format)),
+            );
+        }
+        if &fmt.subchunk_1_id != b"fmt " {
+            report.push(
+                Severity::Error,
+                FindingKind::FmtId,
+                FMT_ID_OFFSET,
+                format!(
+                    "First chunk is {:?}, fmt must come first for this reader",
+                    ascii(&fmt.subchunk_1_id)
+                ),
+            );
+        }
+        if fmt.subchunk_1_size < 16 {
+            report.push(
+                Severity::Error,
+                FindingKind::FmtSize,
+                FMT_SIZE_OFFSET,
+                format!(
+                    "fmt chunk is {} bytes, at least 16 are required",
+                    fmt.subchunk_1_size
+                ),
+            );
+        }
+        if fmt.num_channels == 0 {
+            report.push(
+                Severity::Error,
+                FindingKind::NoChannels,
+                NUM_CHANNELS_OFFSET,
+                "num_channels is 0".to_string(),
+            );
+        }
+        if fmt.sample_rate == 0 {
+            report.push(
+                Severity::Error,
+                FindingKind::NoSampleRate,
+                SAMPLE_RATE_OFFSET,
+                "sample_rate is 0".to_string(),
+            );
+        }
+        match self.fmt_extension() {
+            Ok(extension) => {
+                if SampleFormat::from_extensible(&fmt, extension.as_ref()).is_err() {
+                    report.push(
+                        Severity::Warning,
+                        FindingKind::UnsupportedFormat,
+                        AUDIO_FORMAT_OFFSET,
+                        format!(
+                            "audio_format {} with {} bits per sample can't be decoded",
+                            fmt.audio_format, fmt.bits_per_sample
+                        ),
+                    );
+                }
+            }
+            Err(err) => report.push(
+                Severity::Error,
+                FindingKind::Unreadable,
+                std::mem::size_of::<WavHeader>() as u64,
+                format!("fmt extension can't be read: {err}"),
+            ),
+        }
+
+        let expected_block_align =
+            fmt.num_channels as u32 * (fmt.bits_per_sample as u32).div_ceil(8);
+        if fmt.block_align as u32 != expected_block_align {
+            report.push(
+                Severity::Error,
+                FindingKind::BlockAlign,
+                BLOCK_ALIGN_OFFSET,
+                format!(
+                    "block_align is {}, num_channels * bits_per_sample / 8 is {expected_block_align}",
+                    fmt.block_align
+                ),
+            );
+        }
+        let expected_byte_rate = fmt.sample_rate as u64 * fmt.block_align as u64;
+        if fmt.byte_rate as u64 != expected_byte_rate {
+            report.push(
+                Severity::Warning,
+                FindingKind::ByteRate,
+                BYTE_RATE_OFFSET,
+                format!(
+                    "byte_rate is {}, sample_rate * block_align is {expected_byte_rate}",
+                    fmt.byte_rate
+                ),
+            );
+        }
+
+        let riff_end = header.chunk_size as u64 + 8;
+        if riff_end > file_len {
+            report.push(
+                Severity::Error,
+                FindingKind::RiffSizeMismatch,
+                RIFF_SIZE_OFFSET,
+                format!("RIFF size covers {riff_end} bytes but the file is only {file_len} bytes"),
+            );
+        } else if riff_end < file_len {
+            report.push(
+                Severity::Info,
+                FindingKind::RiffSizeMismatch,
+                RIFF_SIZE_OFFSET,
+                format!(
+                    "RIFF size covers {riff_end} bytes, {} trailing bytes follow it",
+                    file_len - riff_end
+                ),
+            );
+        }
+
+        let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+        let fmt_end = header.chunks_offset();
+        if fmt_end > file_len {
+            report.push(
+                Severity::Error,
+                FindingKind::ChunkPastEof,
+                FMT_ID_OFFSET,
+                format!("fmt chunk ends at {fmt_end}, past the end of the file"),
+            );
+        }
+
+        let entries = match self.chunk_index() {
+            Ok(entries) => entries,
+            Err(err) => {
+                report.push(
+                    Severity::Error,
+                    FindingKind::Unreadable,
+                    fmt_end,
+                    format!("Chunk list can't be read: {err}"),
+                );
+                return Ok(report);
+            }
+        };
+        let mut num_data_chunks = 0;
+        for entry in entries {
+            let chunk_offset = entry.data_offset - header_size;
+            let chunk_size = entry.chunk_header.chunk_size as u64;
+            let chunk_id = ascii(&entry.chunk_header.chunk_id);
+            if entry.data_offset + chunk_size > file_len {
+                report.push(
+                    Severity::Error,
+                    FindingKind::ChunkPastEof,
+                    chunk_offset,
+                    format!(
+                        "{chunk_id:?} chunk claims {chunk_size} bytes, only {} remain in the file",
+                        file_len - entry.data_offset
+                    ),
+                );
+            }
+            if &entry.chunk_header.chunk_id != b"data" {
+                continue;
+            }
+            num_data_chunks += 1;
+            if num_data_chunks > 1 {
+                report.push(
+                    Severity::Warning,
+                    FindingKind::DuplicateDataChunk,
+                    chunk_offset,
+                    "Additional data chunk, only the first one is played".to_string(),
+                );
+            }
+            if fmt.block_align != 0 && !chunk_size.is_multiple_of(fmt.block_align as u64) {
+                report.push(
+                    Severity::Warning,
+                    FindingKind::DataNotFrameAligned,
+                    chunk_offset,
+                    format!(
+                        "data size {chunk_size} isn't a multiple of block_align {}",
+                        fmt.block_align
+                    ),
+                );
+            }
+        }
+        if num_data_chunks == 0 {
+            report.push(
+                Severity::Error,
+                FindingKind::MissingDataChunk,
+                fmt_end,
+                "File has no data chunk".to_string(),
+            );
+        }
+
+        Ok(report)
+    }
+}
+
+fn ascii(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).to_string()
+}
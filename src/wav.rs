@@ -1,6 +1,6 @@
 use std::{
     fs::{self, File},
-    io::Read,
+    io::{Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
@@ -11,21 +11,29 @@ use std::os::unix::fs::FileExt;
 use std::os::windows::fs::FileExt;
 
 use bytemuck::{Pod, Zeroable};
+use thiserror::Error;
+
+use crate::samples::WAVE_FORMAT_EXTENSIBLE;
 
 pub struct WavSample {}
 
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct WavHeader {
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
     pub chunk_id: [u8; 4],
     pub chunk_size: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
     pub format: [u8; 4],
     pub fmt: FmtSubChunk,
 }
 
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct FmtSubChunk {
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
     pub subchunk_1_id: [u8; 4],
     pub subchunk_1_size: u32,
     pub audio_format: u16,
@@ -36,6 +44,42 @@ pub struct FmtSubChunk {
     pub bits_per_sample: u16,
 }
 
+/// The 24 bytes WAVE_FORMAT_EXTENSIBLE appends to the fmt chunk, starting with cbSize
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Pod, Zeroable)]
+#[repr(C)]
+pub struct FmtExtension {
+    pub cb_size: u16,
+    pub valid_bits_per_sample: u16,
+    /// Speaker positions of the channels in order, see `channels::ChannelMask`
+    pub channel_mask: u32,
+    pub sub_format: [u8; 16],
+}
+
+impl FmtExtension {
+    /// KSDATAFORMAT_SUBTYPE GUIDs all share this tail after the format tag
+    const GUID_TAIL: [u8; 14] = [
+        0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+    ];
+
+    pub fn new(audio_format: u16, valid_bits_per_sample: u16, channel_mask: u32) -> Self {
+        let mut sub_format = [0u8; 16];
+        sub_format[..2].copy_from_slice(&audio_format.to_le_bytes());
+        sub_format[2..].copy_from_slice(&Self::GUID_TAIL);
+        FmtExtension {
+            cb_size: 22,
+            valid_bits_per_sample,
+            channel_mask,
+            sub_format,
+        }
+    }
+
+    /// Format tag of the sub format, e.g. WAVE_FORMAT_PCM, if it's a standard GUID
+    pub fn audio_format(&self) -> Option<u16> {
+        (self.sub_format[2..] == Self::GUID_TAIL)
+            .then(|| u16::from_le_bytes([self.sub_format[0], self.sub_format[1]]))
+    }
+}
+
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
 struct DataSubChunk {
@@ -47,98 +91,546 @@ pub struct WavFile {
     pub handle: File,
     pub offset: u64,
     pub header: WavHeader,
+    pub limits: ParseLimits,
+    /// Set when iteration stopped early because of a malformed or over-limit chunk
+    pub error: Option<WavError>,
+    budget: ChunkBudget,
+}
+
+/// Bounds on what the parser will hold in memory, so an untrusted size field can't trigger a
+/// huge allocation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Largest single chunk other than data that will be read into memory. The data chunk is
+    /// only bounded by the file length, so long recordings still open.
+    pub max_chunk_size: u64,
+    pub max_chunk_count: usize,
+    /// Combined size of every chunk other than data
+    pub max_metadata_size: u64,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_chunk_size: 1 << 30,
+            max_chunk_count: 1 << 16,
+            max_metadata_size: 64 << 20,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Checks a chunk header against the limits and the bytes left in the file before any of
+    /// its data is read
+    fn check_chunk(
+        &self,
+        budget: &mut ChunkBudget,
+        chunk_header: &ChunkHeader,
+        offset: u64,
+        available: u64,
+    ) -> Result<(), WavError> {
+        let chunk_id = String::from_utf8_lossy(&chunk_header.chunk_id).to_string();
+        let size = chunk_header.chunk_size as u64;
+        budget.chunk_count += 1;
+        if budget.chunk_count > self.max_chunk_count {
+            return Err(WavError::TooManyChunks(self.max_chunk_count));
+        }
+        if size > available {
+            return Err(WavError::ChunkPastEof {
+                chunk_id,
+                offset,
+                size,
+                available,
+            });
+        }
+        if &chunk_header.chunk_id != b"data" {
+            if size > self.max_chunk_size {
+                return Err(WavError::ChunkTooLarge {
+                    chunk_id,
+                    size,
+                    limit: self.max_chunk_size,
+                });
+            }
+            budget.metadata_size += size;
+            if budget.metadata_size > self.max_metadata_size {
+                return Err(WavError::MetadataTooLarge(self.max_metadata_size));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct ChunkBudget {
+    chunk_count: usize,
+    metadata_size: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum WavError {
+    #[error("IO error reading wav file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File is too short for a wav header! Only {0} bytes")]
+    TooShort(u64),
+    #[error("Chunk {chunk_id:?} at {offset} claims {size} bytes but only {available} remain!")]
+    ChunkPastEof {
+        chunk_id: String,
+        offset: u64,
+        size: u64,
+        available: u64,
+    },
+    #[error("Chunk {chunk_id:?} is {size} bytes, over the {limit} byte limit!")]
+    ChunkTooLarge {
+        chunk_id: String,
+        size: u64,
+        limit: u64,
+    },
+    #[error("File has more than {0} chunks!")]
+    TooManyChunks(usize),
+    #[error("Metadata chunks exceed the {0} byte limit!")]
+    MetadataTooLarge(u64),
+    #[error("Output would be {0} bytes, too large for a RIFF file!")]
+    TooLarge(u64),
 }
 
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct ChunkHeader {
+    #[cfg_attr(feature = "serde", serde(with = "crate::metadata::fourcc"))]
     pub chunk_id: [u8; 4],
     pub chunk_size: u32,
 }
 
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkIndexEntry {
+    pub chunk_header: ChunkHeader,
+    /// Offset of the chunk data, just past its header
+    pub data_offset: u64,
+}
+
 // #[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct Chunk {
     pub chunk_header: ChunkHeader,
     pub data: Vec<u8>,
 }
 
+impl Chunk {
+    pub fn new(chunk_id: [u8; 4], data: Vec<u8>) -> Self {
+        Chunk {
+            chunk_header: ChunkHeader {
+                chunk_id,
+                chunk_size: data.len() as u32,
+            },
+            data,
+        }
+    }
+
+    /// Size of the chunk on disk, including its header and the pad byte RIFF requires after
+    /// odd-sized chunks
+    pub fn padded_size(&self) -> u64 {
+        let size = self.data.len() as u64;
+        std::mem::size_of::<ChunkHeader>() as u64 + size + (size & 1)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.padded_size() as usize);
+        bytes.extend_from_slice(bytemuck::bytes_of(&self.chunk_header));
+        bytes.extend_from_slice(&self.data);
+        if self.data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
 impl WavHeader {
     pub fn parse(file_handle: &mut File) -> Option<WavHeader> {
-        let mut file_buffer = vec![0u8; std::mem::size_of::<WavHeader>()];
-        file_handle.read_exact(&mut file_buffer).unwrap();
-        let header = bytemuck::try_from_bytes::<WavHeader>(&file_buffer)
-            .expect("Unable to transmute wav header!");
-        Some(*header)
+        Self::read(file_handle).ok()
+    }
+
+    pub fn read(file_handle: &mut File) -> Result<WavHeader, WavError> {
+        let mut file_buffer = Vec::with_capacity(std::mem::size_of::<WavHeader>());
+        file_handle
+            .take(std::mem::size_of::<WavHeader>() as u64)
+            .read_to_end(&mut file_buffer)?;
+        Self::from_bytes(&file_buffer)
+    }
+
+    /// Reads the header from the start of `bytes`. Field values aren't checked here, see
+    /// `WavFile::validate`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<WavHeader, WavError> {
+        let header_size = std::mem::size_of::<WavHeader>();
+        if bytes.len() < header_size {
+            return Err(WavError::TooShort(bytes.len() as u64));
+        }
+        Ok(bytemuck::pod_read_unaligned(&bytes[..header_size]))
+    }
+
+    /// Offset of the first chunk after fmt. fmt chunks can be longer than the 16 bytes held in
+    /// `FmtSubChunk`, e.g. WAVE_FORMAT_EXTENSIBLE, so this follows the declared size.
+    pub fn chunks_offset(&self) -> u64 {
+        let fmt_size = self.fmt.subchunk_1_size.max(16) as u64;
+        12 + std::mem::size_of::<ChunkHeader>() as u64 + fmt_size + (fmt_size & 1)
     }
 }
 
 impl WavFile {
     pub fn new(path: &Path) -> Self {
-        let mut file_handle = fs::File::open(path).expect("Unable to read file!");
-        let header = WavHeader::parse(&mut file_handle).unwrap();
+        Self::open(path).expect("Unable to read file!")
+    }
+
+    pub fn open(path: &Path) -> Result<Self, WavError> {
+        Self::open_with_limits(path, ParseLimits::default())
+    }
 
-        WavFile {
+    pub fn open_with_limits(path: &Path, limits: ParseLimits) -> Result<Self, WavError> {
+        let mut file_handle = fs::File::open(path)?;
+        let header = WavHeader::read(&mut file_handle)?;
+
+        Ok(WavFile {
             handle: file_handle,
             offset: 0,
             header,
+            limits,
+            error: None,
+            budget: ChunkBudget::default(),
+        })
+    }
+
+    /// The WAVE_FORMAT_EXTENSIBLE part of the fmt chunk, if the file has one
+    pub fn fmt_extension(&mut self) -> Result<Option<FmtExtension>, WavError> {
+        let fmt = self.header.fmt;
+        let extension_size = std::mem::size_of::<FmtExtension>();
+        if fmt.audio_format != WAVE_FORMAT_EXTENSIBLE
+            || (fmt.subchunk_1_size as usize) < 16 + extension_size
+        {
+            return Ok(None);
+        }
+        let mut buff = vec![0u8; extension_size];
+        self.handle
+            .seek(SeekFrom::Start(std::mem::size_of::<WavHeader>() as u64))?;
+        self.handle.read_exact(&mut buff)?;
+        Ok(Some(bytemuck::pod_read_unaligned(&buff)))
+    }
+
+    /// Walks the chunk headers after the fmt chunk without reading any chunk data. Sizes are
+    /// reported as declared, even when they run past the end of the file.
+    pub fn chunk_index(&mut self) -> Result<Vec<ChunkIndexEntry>, WavError> {
+        let file_len = self.handle.metadata()?.len();
+        let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+        let mut offset = self.header.chunks_offset();
+        let mut entries = Vec::new();
+        let mut handle = &self.handle;
+        while offset + header_size <= file_len {
+            if entries.len() == self.limits.max_chunk_count {
+                return Err(WavError::TooManyChunks(self.limits.max_chunk_count));
+            }
+            let mut info_buff = [0u8; std::mem::size_of::<ChunkHeader>()];
+            handle.seek(SeekFrom::Start(offset))?;
+            handle.read_exact(&mut info_buff)?;
+            let chunk_header: ChunkHeader = bytemuck::pod_read_unaligned(&info_buff);
+            let chunk_size = chunk_header.chunk_size as u64;
+            entries.push(ChunkIndexEntry {
+                chunk_header,
+                data_offset: offset + header_size,
+            });
+            offset += header_size + chunk_size + (chunk_size & 1);
+        }
+        Ok(entries)
+    }
+
+    /// Reads the data of one chunk from the index, with the same checks as `next_chunk`. Both
+    /// draw on the same budget, so the limits hold across every chunk read from this file.
+    pub fn read_chunk(&mut self, entry: &ChunkIndexEntry) -> Result<Chunk, WavError> {
+        let file_len = self.handle.metadata()?.len();
+        let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+        let mut offset = entry.data_offset - header_size;
+        let handle = &self.handle;
+        read_next_chunk(
+            &self.limits,
+            &mut self.budget,
+            &mut offset,
+            file_len,
+            |offset, buf| Self::read_at(handle, offset, buf),
+        )?
+        .ok_or(WavError::TooShort(file_len))
+    }
+
+    /// Every chunk other than data, split into the ones before and after the first data chunk,
+    /// so they can be carried over when the audio is rewritten. An `MD5 ` chunk always leads,
+    /// since `SampleWriter` only digests the audio for one it's given up front.
+    pub fn metadata_chunks(&mut self) -> Result<(Vec<Chunk>, Vec<Chunk>), WavError> {
+        let mut leading = Vec::new();
+        let mut trailing = Vec::new();
+        let mut seen_data = false;
+        for entry in self.chunk_index()? {
+            if &entry.chunk_header.chunk_id == b"data" {
+                seen_data = true;
+            } else if seen_data && &entry.chunk_header.chunk_id != b"MD5 " {
+                trailing.push(self.read_chunk(&entry)?);
+            } else {
+                leading.push(self.read_chunk(&entry)?);
+            }
         }
+        Ok((leading, trailing))
     }
 
-    fn read_from_offset(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    /// Data offset and size of the first chunk with the given id
+    pub fn find_chunk(&mut self, chunk_id: &[u8; 4]) -> Option<(u64, u64)> {
+        self.chunk_index()
+            .ok()?
+            .into_iter()
+            .find(|entry| entry.chunk_header.chunk_id == *chunk_id)
+            .map(|entry| (entry.data_offset, entry.chunk_header.chunk_size as u64))
+    }
+
+    fn read_at(handle: &File, offset: u64, buf: &mut [u8]) -> Result<(), std::io::Error> {
         #[cfg(target_os = "windows")]
         {
-            let num_bytes = self.read_from_offset_nt(buf)?;
-            return Ok(num_bytes);
+            Self::read_at_nt(handle, offset, buf)
         }
 
         #[cfg(target_os = "macos")]
         {
-            self.read_from_offset_darwin(buf)?;
-            let num_bytes = buf.len();
-            return Ok(num_bytes);
+            Self::read_at_darwin(handle, offset, buf)
         }
     }
 
     #[inline(always)]
     #[cfg(target_os = "windows")]
-    fn read_from_offset_nt(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        let num_bytes = self.handle.seek_read(buf, self.offset)?;
-        Ok(num_bytes)
+    fn read_at_nt(handle: &File, offset: u64, mut buf: &mut [u8]) -> Result<(), std::io::Error> {
+        let mut offset = offset;
+        while !buf.is_empty() {
+            let num_bytes = handle.seek_read(buf, offset)?;
+            if num_bytes == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            buf = &mut buf[num_bytes..];
+            offset += num_bytes as u64;
+        }
+        Ok(())
     }
 
     #[inline(always)]
     #[cfg(target_os = "macos")]
-    fn read_from_offset_darwin(&mut self, buf: &mut [u8]) -> Result<(), std::io::Error> {
-        let num_bytes = self.handle.read_exact_at(buf, self.offset)?;
-        Ok(num_bytes)
+    fn read_at_darwin(handle: &File, offset: u64, buf: &mut [u8]) -> Result<(), std::io::Error> {
+        handle.read_exact_at(buf, offset)
+    }
+}
+
+impl WavFile {
+    /// Reads the next chunk, checking its size against the limits and the file length before
+    /// allocating for it. Returns `Ok(None)` at the end of the file; a trailing partial chunk
+    /// header is treated as the end.
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, WavError> {
+        if self.offset == 0 {
+            self.offset = self.header.chunks_offset();
+        }
+        let file_len = self.handle.metadata()?.len();
+        let handle = &self.handle;
+        read_next_chunk(
+            &self.limits,
+            &mut self.budget,
+            &mut self.offset,
+            file_len,
+            |offset, buf| Self::read_at(handle, offset, buf),
+        )
     }
 }
 
 impl Iterator for WavFile {
     type Item = Chunk;
 
+    /// Stops at the first malformed chunk, leaving the reason in `error`
     fn next(&mut self) -> Option<Self::Item> {
-        if self.offset == 0 {
-            self.offset = std::mem::size_of::<WavHeader>() as u64;
-        } else if self.offset >= self.handle.metadata().unwrap().len() {
+        if self.error.is_some() {
             return None;
         }
-        let mut info_buff = vec![0u8; std::mem::size_of::<ChunkHeader>()];
-        // Read the chunk id and size
-        self.read_from_offset(&mut (*info_buff)).unwrap();
-        let chunk_header = bytemuck::try_from_bytes::<ChunkHeader>(&info_buff)
-            .expect("Unable to transmute chunk info!");
-        self.offset = self.offset + std::mem::size_of::<ChunkHeader>() as u64;
+        match self.next_chunk() {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+/// Parses a wav file held in memory with the same checks and limits as `WavFile`
+pub fn parse_bytes(
+    bytes: &[u8],
+    limits: &ParseLimits,
+) -> Result<(WavHeader, Vec<Chunk>), WavError> {
+    let header = WavHeader::from_bytes(bytes)?;
+    let mut budget = ChunkBudget::default();
+    let mut chunks = Vec::new();
+    let mut offset = header.chunks_offset();
+    let read_at = |offset: u64, buf: &mut [u8]| {
+        let start = offset as usize;
+        buf.copy_from_slice(&bytes[start..start + buf.len()]);
+        Ok(())
+    };
+    while let Some(chunk) = read_next_chunk(
+        limits,
+        &mut budget,
+        &mut offset,
+        bytes.len() as u64,
+        read_at,
+    )? {
+        chunks.push(chunk);
+    }
+    Ok((header, chunks))
+}
 
-        let mut data_buffer = vec![0u8; chunk_header.chunk_size as usize];
-        self.read_from_offset(&mut data_buffer).unwrap();
+/// Reads the chunk at `offset` and moves `offset` past it, checking the chunk header against
+/// `limits` and the `file_len` before allocating for its data. `WavFile` and `parse_bytes` both
+/// parse through here, so fuzzing `parse_bytes` covers what real files go through. Returns
+/// `Ok(None)` at the end of the file; a trailing partial chunk header is treated as the end.
+fn read_next_chunk(
+    limits: &ParseLimits,
+    budget: &mut ChunkBudget,
+    offset: &mut u64,
+    file_len: u64,
+    mut read_at: impl FnMut(u64, &mut [u8]) -> Result<(), std::io::Error>,
+) -> Result<Option<Chunk>, WavError> {
+    let header_size = std::mem::size_of::<ChunkHeader>() as u64;
+    if offset.saturating_add(header_size) > file_len {
+        return Ok(None);
+    }
+    let mut info_buff = [0u8; std::mem::size_of::<ChunkHeader>()];
+    read_at(*offset, &mut info_buff)?;
+    let chunk_header: ChunkHeader = bytemuck::pod_read_unaligned(&info_buff);
+    let data_offset = *offset + header_size;
+    limits.check_chunk(budget, &chunk_header, *offset, file_len - data_offset)?;
 
-        self.offset = self.offset + chunk_header.chunk_size as u64;
+    let mut data = vec![0u8; chunk_header.chunk_size as usize];
+    read_at(data_offset, &mut data)?;
+    // Odd-sized chunks are followed by a pad byte that isn't counted in the chunk size
+    let chunk_size = chunk_header.chunk_size as u64;
+    *offset = data_offset + chunk_size + (chunk_size & 1);
+    Ok(Some(Chunk { chunk_header, data }))
+}
+
+/// Writes a wav file from a fmt chunk and an ordered list of chunks. Built from an existing
+/// `WavFile`, it rewrites the file with every chunk preserved, so metadata can be edited without
+/// touching the audio.
+pub struct WavWriter {
+    pub fmt: FmtSubChunk,
+    /// Written after `fmt` when set, making the fmt chunk 40 bytes
+    pub fmt_extension: Option<FmtExtension>,
+    pub chunks: Vec<Chunk>,
+}
 
-        Some(Chunk {
-            chunk_header: *chunk_header,
-            data: data_buffer,
+impl WavWriter {
+    pub fn new(fmt: FmtSubChunk) -> Self {
+        WavWriter {
+            fmt,
+            fmt_extension: None,
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Reads every chunk of `wav_file`, the audio included. Fails on the first malformed or
+    /// over-limit chunk rather than writing a file without it.
+    pub fn from_wav_file(mut wav_file: WavFile) -> Result<Self, WavError> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = wav_file.next_chunk()? {
+            chunks.push(chunk);
+        }
+        Ok(WavWriter {
+            fmt: wav_file.header.fmt,
+            fmt_extension: wav_file.fmt_extension()?,
+            chunks,
         })
     }
+
+    pub fn chunk(&self, chunk_id: &[u8; 4]) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_header.chunk_id == *chunk_id)
+    }
+
+    /// Replaces the first chunk with the same id, or inserts the chunk ahead of the data chunk
+    pub fn set_chunk(&mut self, chunk: Chunk) {
+        let chunk_id = chunk.chunk_header.chunk_id;
+        if let Some(existing) = self
+            .chunks
+            .iter_mut()
+            .find(|existing| existing.chunk_header.chunk_id == chunk_id)
+        {
+            *existing = chunk;
+            return;
+        }
+        let data_index = self
+            .chunks
+            .iter()
+            .position(|existing| existing.chunk_header.chunk_id == *b"data")
+            .unwrap_or(self.chunks.len());
+        self.chunks.insert(data_index, chunk);
+    }
+
+    pub fn remove_chunk(&mut self, chunk_id: &[u8; 4]) -> Option<Chunk> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_header.chunk_id == *chunk_id)?;
+        Some(self.chunks.remove(index))
+    }
+
+    fn fmt_size(&self) -> u32 {
+        let extension_size = match self.fmt_extension {
+            Some(_) => std::mem::size_of::<FmtExtension>(),
+            None => 0,
+        };
+        (16 + extension_size) as u32
+    }
+
+    pub fn riff_size(&self) -> u64 {
+        let fmt_size = 8 + self.fmt_size() as u64;
+        let chunks_size: u64 = self.chunks.iter().map(Chunk::padded_size).sum();
+        4 + fmt_size + chunks_size
+    }
+
+    /// Fails if the chunks don't fit in the 4 GiB a RIFF size can describe
+    pub fn header(&self) -> Result<WavHeader, WavError> {
+        let riff_size = self.riff_size();
+        if riff_size > u32::MAX as u64 {
+            return Err(WavError::TooLarge(riff_size + 8));
+        }
+        let mut fmt = self.fmt;
+        fmt.subchunk_1_id = *b"fmt ";
+        fmt.subchunk_1_size = self.fmt_size();
+        if self.fmt_extension.is_some() {
+            fmt.audio_format = WAVE_FORMAT_EXTENSIBLE;
+        }
+        Ok(WavHeader {
+            chunk_id: *b"RIFF",
+            chunk_size: riff_size as u32,
+            format: *b"WAVE",
+            fmt,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), WavError> {
+        writer.write_all(bytemuck::bytes_of(&self.header()?))?;
+        if let Some(extension) = &self.fmt_extension {
+            writer.write_all(bytemuck::bytes_of(extension))?;
+        }
+        for chunk in &self.chunks {
+            writer.write_all(&chunk.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), WavError> {
+        // Fail before `path` is created or truncated
+        self.header()?;
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        Ok(file.flush()?)
+    }
 }